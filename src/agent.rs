@@ -1,14 +1,42 @@
 use anyhow::{Context, Result};
+use reqwest::StatusCode;
 use serde::Deserialize;
 use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
 use tracing::{debug, error, info, warn};
 
-use crate::app::{AgentStatus, AppState, PlayerCommand, Song, SongStatus};
+use crate::api_keys::ApiKeys;
+use crate::app::{
+    AgentAction, AgentStatus, AppState, Backoff, BatchProgress, PlayerCommand, RepeatMode,
+    RequestPhase, RetryAction, Song, SongStatus, StopAfter, MAX_SPEED, MIN_SPEED,
+};
 use crate::config::Config;
 use crate::downloader;
-use crate::library::Library;
+use crate::library::{Library, LibraryEntry};
+use crate::llm::{self, LlmProvider, Message, ToolResult, ToolUse};
+
+/// Background downloads allowed to run at once.
+const MAX_CONCURRENT_DOWNLOADS: usize = 3;
+
+/// Queue entries downloaded ahead of the playing song.
+const PREFETCH_AHEAD: usize = 2;
+
+/// Retries for a rate-limited or overloaded API, waiting `API_RETRY_BASE`
+/// and doubling each time up to `API_RETRY_MAX` unless the API says how
+/// long. A `Retry-After` beyond `API_RETRY_AFTER_MAX` gives up instead.
+const MAX_API_RETRIES: u32 = 3;
+const API_RETRY_BASE: Duration = Duration::from_secs(2);
+const API_RETRY_MAX: Duration = Duration::from_secs(30);
+const API_RETRY_AFTER_MAX: Duration = Duration::from_secs(120);
+/// Wait before the one retry of a download that timed out.
+const DOWNLOAD_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// How long a key the API rejected is left out when there are others.
+const REJECTED_KEY_COOLDOWN: Duration = Duration::from_secs(600);
 
 const SYSTEM_PROMPT: &str = r#"You are the AI brain of vibeplayer, a TUI-based YouTube music player. Your job is to interpret user commands and control the player using tools.
 
@@ -19,17 +47,39 @@ Guidelines:
 - For song/artist names, use search_and_queue with good search queries
 - For vibe/mood requests, translate the mood into multiple specific search queries
 - When replacing the queue, pick 4-6 diverse but fitting search queries
-- Keep search queries specific: include artist names, song names, or descriptive terms like "chill lo-fi beats" rather than vague terms"#;
+- Keep search queries specific: include artist names, song names, or descriptive terms like "chill lo-fi beats" rather than vague terms
+- For questions about what's in the library or what's coming up, use describe_collection
+- "Most played" lists what the user listens to most; lean on it for requests like "play my favorites" and to bias vibe searches
+- When you pick songs, you may add one short sentence of text explaining why (e.g. "Picked these for a rainy-day focus mood"); it is shown to the user"#;
+
+/// Follow-up sent when the model answered with text only.
+const TOOL_REMINDER: &str =
+    "You must act using the tools. Call the tool(s) that carry out my previous request.";
+
+/// Sent with the tool results so the model sums them up instead of acting again.
+const CONFIRM_PROMPT: &str =
+    "Those are the results of your tool calls. Confirm to me in one short sentence what happened.";
+
+/// Songs named individually in a collection summary before falling back to a count.
+const SUMMARY_HIGHLIGHTS: usize = 3;
+
+/// Most played songs listed in the context, to lean picks toward them.
+const TOP_PLAYED: usize = 5;
 
 fn tool_definitions() -> Value {
     json!([
         {
             "name": "play_url",
-            "description": "Download and play a YouTube URL immediately. Use when the user provides a direct YouTube link.",
+            "description": "Download and play a YouTube URL immediately. Use when the user provides a direct YouTube link. A playlist link queues the playlist's songs instead.",
             "input_schema": {
                 "type": "object",
                 "properties": {
-                    "url": { "type": "string", "description": "YouTube URL to play" }
+                    "url": { "type": "string", "description": "YouTube URL to play" },
+                    "format": {
+                        "type": "string",
+                        "enum": downloader::AUDIO_FORMATS,
+                        "description": "Optional audio format for this song only. Omit to use the default."
+                    }
                 },
                 "required": ["url"]
             }
@@ -61,6 +111,11 @@ fn tool_definitions() -> Value {
                 "required": ["queries"]
             }
         },
+        {
+            "name": "clear_queue",
+            "description": "Remove every song from the queue without adding new ones. Use when the user wants to clear or empty the queue.",
+            "input_schema": { "type": "object", "properties": {} }
+        },
         {
             "name": "skip",
             "description": "Skip the currently playing song.",
@@ -76,6 +131,26 @@ fn tool_definitions() -> Value {
             "description": "Resume playback.",
             "input_schema": { "type": "object", "properties": {} }
         },
+        {
+            "name": "describe_collection",
+            "description": "Tell the user what's in their library or queue. Use for questions like 'what's in my library?' or 'what's coming up?'.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "what": {
+                        "type": "string",
+                        "enum": ["library", "queue", "all"],
+                        "description": "Which collection to describe"
+                    }
+                },
+                "required": ["what"]
+            }
+        },
+        {
+            "name": "download_all",
+            "description": "Download everything in the queue and library for offline listening.",
+            "input_schema": { "type": "object", "properties": {} }
+        },
         {
             "name": "set_volume",
             "description": "Set the playback volume.",
@@ -86,40 +161,551 @@ fn tool_definitions() -> Value {
                 },
                 "required": ["level"]
             }
+        },
+        {
+            "name": "mute",
+            "description": "Mute or unmute playback. Unmuting goes back to the volume from before muting.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "muted": { "type": "boolean" }
+                },
+                "required": ["muted"]
+            }
+        },
+        {
+            "name": "seek",
+            "description": "Jump to a point in the current song, e.g. 'skip to the chorus around 1:10' is seconds 70. Give exactly one of seconds or percent.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "seconds": { "type": "number", "description": "Position from the start, in seconds" },
+                    "percent": { "type": "number", "description": "Position as 0-100 percent of the song" }
+                }
+            }
+        },
+        {
+            "name": "set_speed",
+            "description": "Set the playback speed, e.g. 1.25 or 1.5 for podcasts and long mixes. 1.0 is normal; pitch changes with speed.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "speed": { "type": "number", "description": "Speed factor 0.5-2.0" }
+                },
+                "required": ["speed"]
+            }
+        },
+        {
+            "name": "set_repeat",
+            "description": "Set the repeat mode: 'one' loops the current song, 'all' loops everything played this session once the queue runs out, 'off' stops looping.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "mode": { "type": "string", "enum": ["off", "one", "all"] }
+                },
+                "required": ["mode"]
+            }
+        },
+        {
+            "name": "shuffle",
+            "description": "Turn shuffle on or off. When on, the next song is picked at random from the ready songs in the queue.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "enabled": { "type": "boolean" }
+                },
+                "required": ["enabled"]
+            }
+        },
+        {
+            "name": "stop_after_current",
+            "description": "Stop playback once the current song finishes instead of moving on to the next one, e.g. 'let this one finish then stop'. Set fade to fade the song out over its last few seconds. Pass enabled false to cancel.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "enabled": { "type": "boolean" },
+                    "fade": { "type": "boolean" }
+                },
+                "required": ["enabled"]
+            }
+        },
+        {
+            "name": "reorder_queue",
+            "description": "Reorder the queue without adding or removing songs, e.g. for a smoother energy or tempo progression. order lists every current queue position (as numbered in the context) exactly once, in the new play order.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "order": {
+                        "type": "array",
+                        "items": { "type": "integer", "minimum": 1 }
+                    }
+                },
+                "required": ["order"]
+            }
+        },
+        {
+            "name": "remove_from_queue",
+            "description": "Remove one song from the queue, e.g. 'drop that last sad one'. Give exactly one of position (as numbered in the context) or title; a title removes the first queued song whose title contains it, ignoring case.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "position": { "type": "integer", "minimum": 1 },
+                    "title": { "type": "string", "description": "Part of the song's title" }
+                }
+            }
+        },
+        {
+            "name": "move_in_queue",
+            "description": "Move one queued song to another position, e.g. 'play that one next'. Pick the song by exactly one of position (as numbered in the context) or title (first queued song whose title contains it, ignoring case). to is the new position; omit it to play the song next.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "position": { "type": "integer", "minimum": 1 },
+                    "title": { "type": "string", "description": "Part of the song's title" },
+                    "to": { "type": "integer", "minimum": 1, "description": "New position; 1 plays it next" }
+                }
+            }
+        },
+        {
+            "name": "favorite",
+            "description": "Mark the currently playing song as a favorite, e.g. 'save this one' or 'I love this'.",
+            "input_schema": { "type": "object", "properties": {} }
+        },
+        {
+            "name": "save_to_playlist",
+            "description": "Add the currently playing song to a named playlist, creating the playlist if it doesn't exist.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string", "description": "Playlist name" }
+                },
+                "required": ["name"]
+            }
+        },
+        {
+            "name": "create_playlist",
+            "description": "Create a new, empty named playlist.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string", "description": "Playlist name" }
+                },
+                "required": ["name"]
+            }
+        },
+        {
+            "name": "remove_from_playlist",
+            "description": "Remove the currently playing song from a named playlist.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string", "description": "Playlist name" }
+                },
+                "required": ["name"]
+            }
+        },
+        {
+            "name": "queue_library",
+            "description": "Queue every downloaded song in the library that isn't queued yet, and start playing if nothing is. Use for 'play my whole collection'. Keeps library order unless shuffle is true.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "shuffle": { "type": "boolean", "description": "Queue in random order" }
+                }
+            }
+        },
+        {
+            "name": "play_previous",
+            "description": "Go back to the previously played song.",
+            "input_schema": { "type": "object", "properties": {} }
+        },
+        {
+            "name": "play_album",
+            "description": "Queue the rest of the currently playing song's album, in track order. Use for requests like 'play the whole album'.",
+            "input_schema": { "type": "object", "properties": {} }
         }
     ])
 }
 
-#[derive(Debug, Deserialize)]
-struct ApiResponse {
-    content: Vec<ContentBlock>,
+/// Most results `search_and_queue` may queue at once.
+const MAX_SEARCH_COUNT: u32 = 5;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlayUrlInput {
+    url: String,
+    #[serde(default)]
+    format: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(tag = "type")]
-enum ContentBlock {
-    #[serde(rename = "text")]
-    Text { text: String },
-    #[serde(rename = "tool_use")]
-    ToolUse {
-        id: String,
-        name: String,
-        input: Value,
-    },
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SearchAndQueueInput {
+    query: String,
+    #[serde(default = "default_search_count")]
+    count: u32,
+}
+
+fn default_search_count() -> u32 {
+    3
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReplaceQueueInput {
+    queries: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DescribeCollectionInput {
+    what: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SetVolumeInput {
+    level: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MuteInput {
+    muted: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QueueLibraryInput {
+    #[serde(default)]
+    shuffle: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SeekInput {
+    seconds: Option<f64>,
+    percent: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SetSpeedInput {
+    speed: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SetRepeatInput {
+    mode: RepeatMode,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ShuffleInput {
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StopAfterCurrentInput {
+    enabled: bool,
+    #[serde(default)]
+    fade: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReorderQueueInput {
+    order: Vec<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RemoveFromQueueInput {
+    position: Option<usize>,
+    title: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MoveInQueueInput {
+    position: Option<usize>,
+    title: Option<String>,
+    to: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlaylistInput {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoInput {}
+
+/// Where a `seek` call asked to go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SeekTarget {
+    Seconds(Duration),
+    Percent(f64),
+}
+
+/// Which queued song a `remove_from_queue` or `move_in_queue` call meant.
+#[derive(Debug, Clone, PartialEq)]
+enum QueueTarget {
+    /// 0-based queue index
+    Position(usize),
+    Title(String),
+}
+
+impl QueueTarget {
+    /// From a tool's 1-based `position` or `title`, exactly one of which
+    /// must be given.
+    fn parse(position: Option<usize>, title: Option<String>) -> Result<Self> {
+        match (position, title) {
+            (Some(pos), None) => {
+                anyhow::ensure!(pos > 0, "queue positions start at 1, got {}", pos);
+                Ok(QueueTarget::Position(pos - 1))
+            }
+            (None, Some(title)) => {
+                let title = title.trim().to_string();
+                anyhow::ensure!(!title.is_empty(), "title is empty");
+                Ok(QueueTarget::Title(title))
+            }
+            _ => anyhow::bail!("give exactly one of position or title"),
+        }
+    }
+
+    /// Index of the queued song meant, if there is one.
+    fn find(&self, state: &AppState) -> Option<usize> {
+        match self {
+            QueueTarget::Position(index) => (*index < state.queue.len()).then_some(*index),
+            QueueTarget::Title(title) => state.find_queued(title),
+        }
+    }
+
+    /// Reply for when `find` came up empty.
+    fn not_found(&self, state: &AppState) -> String {
+        match self {
+            QueueTarget::Position(index) => format!(
+                "There's no song at position {}; the queue has {}.",
+                index + 1,
+                state.queue.len()
+            ),
+            QueueTarget::Title(title) => format!("No queued song matches {}.", title),
+        }
+    }
+}
+
+/// A tool call whose input has been checked against its schema in
+/// `tool_definitions`, so handlers never run on missing or mistyped fields.
+#[derive(Debug, Clone, PartialEq)]
+enum ToolCall {
+    PlayUrl { url: String, format: Option<String> },
+    SearchAndQueue { query: String, count: u32 },
+    ReplaceQueue { queries: Vec<String> },
+    DownloadAll,
+    ClearQueue,
+    Skip,
+    Pause,
+    Resume,
+    DescribeCollection { what: String },
+    SetVolume { level: u8 },
+    Mute { muted: bool },
+    SetSpeed { speed: f32 },
+    Seek { target: SeekTarget },
+    QueueLibrary { shuffle: bool },
+    SetRepeat { mode: RepeatMode },
+    Shuffle { enabled: bool },
+    StopAfterCurrent { enabled: bool, fade: bool },
+    /// 0-based queue indices in the new order
+    ReorderQueue { order: Vec<usize> },
+    RemoveFromQueue { target: QueueTarget },
+    /// `to` is a 0-based queue index
+    MoveInQueue { target: QueueTarget, to: usize },
+    SaveToPlaylist { name: String },
+    CreatePlaylist { name: String },
+    RemoveFromPlaylist { name: String },
+    Favorite,
+    PlayAlbum,
+    PlayPrevious,
+}
+
+impl ToolCall {
+    fn parse(name: &str, input: Value) -> Result<Self> {
+        fn fields<T: serde::de::DeserializeOwned>(input: Value) -> Result<T> {
+            Ok(serde_json::from_value(input)?)
+        }
+
+        let call = match name {
+            "play_url" => {
+                let PlayUrlInput { url, format } = fields(input)?;
+                anyhow::ensure!(url.starts_with("http"), "url must be a link, got {:?}", url);
+                if let Some(ref f) = format {
+                    anyhow::ensure!(
+                        downloader::AUDIO_FORMATS.contains(&f.as_str()),
+                        "unsupported format {:?}",
+                        f
+                    );
+                }
+                ToolCall::PlayUrl { url, format }
+            }
+            "search_and_queue" => {
+                let SearchAndQueueInput { query, count } = fields(input)?;
+                anyhow::ensure!(!query.trim().is_empty(), "query is empty");
+                anyhow::ensure!(
+                    (1..=MAX_SEARCH_COUNT).contains(&count),
+                    "count must be 1-{}, got {}",
+                    MAX_SEARCH_COUNT,
+                    count
+                );
+                ToolCall::SearchAndQueue { query, count }
+            }
+            "replace_queue" => {
+                let ReplaceQueueInput { queries } = fields(input)?;
+                anyhow::ensure!(
+                    !queries.is_empty() && queries.iter().all(|q| !q.trim().is_empty()),
+                    "queries must be a non-empty list of non-empty searches"
+                );
+                ToolCall::ReplaceQueue { queries }
+            }
+            "describe_collection" => {
+                let DescribeCollectionInput { what } = fields(input)?;
+                anyhow::ensure!(
+                    matches!(what.as_str(), "library" | "queue" | "all"),
+                    "what must be library, queue or all, got {:?}",
+                    what
+                );
+                ToolCall::DescribeCollection { what }
+            }
+            "set_volume" => {
+                let SetVolumeInput { level } = fields(input)?;
+                anyhow::ensure!(level <= 100, "level must be 0-100, got {}", level);
+                ToolCall::SetVolume { level: level as u8 }
+            }
+            "mute" => {
+                let MuteInput { muted } = fields(input)?;
+                ToolCall::Mute { muted }
+            }
+            "set_speed" => {
+                let SetSpeedInput { speed } = fields(input)?;
+                anyhow::ensure!(
+                    (MIN_SPEED..=MAX_SPEED).contains(&speed),
+                    "speed must be {}-{}, got {}",
+                    MIN_SPEED,
+                    MAX_SPEED,
+                    speed
+                );
+                ToolCall::SetSpeed { speed }
+            }
+            "seek" => {
+                let target = match fields(input)? {
+                    SeekInput { seconds: Some(secs), percent: None } => {
+                        // Also rejects values too large for a Duration
+                        let position = Duration::try_from_secs_f64(secs).map_err(|_| {
+                            anyhow::anyhow!("seconds must be >= 0, got {}", secs)
+                        })?;
+                        SeekTarget::Seconds(position)
+                    }
+                    SeekInput { seconds: None, percent: Some(percent) } => {
+                        let valid = (0.0..=100.0).contains(&percent);
+                        anyhow::ensure!(valid, "percent must be 0-100, got {}", percent);
+                        SeekTarget::Percent(percent)
+                    }
+                    _ => anyhow::bail!("give exactly one of seconds or percent"),
+                };
+                ToolCall::Seek { target }
+            }
+            "queue_library" => {
+                let QueueLibraryInput { shuffle } = fields(input)?;
+                ToolCall::QueueLibrary { shuffle }
+            }
+            "set_repeat" => {
+                let SetRepeatInput { mode } = fields(input)?;
+                ToolCall::SetRepeat { mode }
+            }
+            "shuffle" => {
+                let ShuffleInput { enabled } = fields(input)?;
+                ToolCall::Shuffle { enabled }
+            }
+            "stop_after_current" => {
+                let StopAfterCurrentInput { enabled, fade } = fields(input)?;
+                ToolCall::StopAfterCurrent { enabled, fade }
+            }
+            "reorder_queue" => {
+                let ReorderQueueInput { order } = fields(input)?;
+                anyhow::ensure!(
+                    !order.contains(&0),
+                    "queue positions start at 1, got {:?}",
+                    order
+                );
+                ToolCall::ReorderQueue {
+                    order: order.into_iter().map(|pos| pos - 1).collect(),
+                }
+            }
+            "remove_from_queue" => {
+                let RemoveFromQueueInput { position, title } = fields(input)?;
+                ToolCall::RemoveFromQueue { target: QueueTarget::parse(position, title)? }
+            }
+            "move_in_queue" => {
+                let MoveInQueueInput { position, title, to } = fields(input)?;
+                let to = to.unwrap_or(1);
+                anyhow::ensure!(to > 0, "queue positions start at 1, got {}", to);
+                ToolCall::MoveInQueue {
+                    target: QueueTarget::parse(position, title)?,
+                    to: to - 1,
+                }
+            }
+            "save_to_playlist" | "create_playlist" | "remove_from_playlist" => {
+                let PlaylistInput { name: playlist } = fields(input)?;
+                let playlist = playlist.trim().to_string();
+                anyhow::ensure!(!playlist.is_empty(), "name is empty");
+                match name {
+                    "save_to_playlist" => ToolCall::SaveToPlaylist { name: playlist },
+                    "create_playlist" => ToolCall::CreatePlaylist { name: playlist },
+                    _ => ToolCall::RemoveFromPlaylist { name: playlist },
+                }
+            }
+            "download_all" | "clear_queue" | "skip" | "pause" | "resume" | "play_album"
+            | "play_previous" | "favorite" => {
+                let NoInput {} = fields(input)?;
+                match name {
+                    "download_all" => ToolCall::DownloadAll,
+                    "play_album" => ToolCall::PlayAlbum,
+                    "play_previous" => ToolCall::PlayPrevious,
+                    "favorite" => ToolCall::Favorite,
+                    "clear_queue" => ToolCall::ClearQueue,
+                    "skip" => ToolCall::Skip,
+                    "pause" => ToolCall::Pause,
+                    _ => ToolCall::Resume,
+                }
+            }
+            other => anyhow::bail!("unknown tool {}", other),
+        };
+        Ok(call)
+    }
 }
 
 pub struct Agent {
     client: reqwest::Client,
     config: Arc<Config>,
+    api_keys: ApiKeys,
+    provider: Box<dyn LlmProvider>,
     library: Arc<Mutex<Library>>,
+    download_slots: Arc<Semaphore>,
 }
 
 impl Agent {
     pub fn new(config: Arc<Config>, library: Arc<Mutex<Library>>) -> Self {
         Self {
             client: reqwest::Client::new(),
+            api_keys: ApiKeys::new(config.api_keys.clone()),
+            provider: llm::provider(&config),
             config,
             library,
+            download_slots: Arc::new(Semaphore::new(MAX_CONCURRENT_DOWNLOADS)),
         }
     }
 
@@ -129,100 +715,262 @@ impl Agent {
         state: &Arc<Mutex<AppState>>,
     ) -> Result<()> {
         info!(%input, "agent handling input");
+        state.lock().unwrap().begin_request();
+
+        if let Some(direct) = DirectInput::classify(input, self.config.direct_search) {
+            return self.handle_direct(direct, state).await;
+        }
 
         // 1. Snapshot state
         let context = {
             let s = state.lock().unwrap();
-            build_context(&s)
+            build_context(&s, &self.library.lock().unwrap())
         };
         debug!(%context, "agent context snapshot");
 
         // 2. Mark as thinking
-        state.lock().unwrap().agent_status = AgentStatus::Thinking;
+        {
+            let mut s = state.lock().unwrap();
+            s.agent_status = AgentStatus::Thinking;
+            s.agent_reply = None;
+        }
         info!("agent status: thinking");
 
-        // 3. Call Claude API
-        info!(model = %self.config.model, "calling Claude API");
-        let tool_calls = self.call_api(input, &context).await?;
-        info!(count = tool_calls.len(), "received tool calls from API");
+        // 3. Call the model
+        info!(model = %self.config.model, api = self.provider.name(), "calling the model");
+        let mut messages = vec![Message::User(input.to_string())];
+        let (mut rationale, mut tool_calls) =
+            self.call_api(&messages, &context, false, state).await?;
+        if tool_calls.is_empty() && self.config.retry_empty_tool_calls {
+            // One reminder only; if it still just talks, show what it said
+            info!("no tool calls, re-prompting once");
+            messages.push(Message::Assistant {
+                text: Some(rationale.clone().unwrap_or_else(|| "(no response)".to_string())),
+                tool_calls: Vec::new(),
+            });
+            messages.push(Message::User(TOOL_REMINDER.to_string()));
+            (rationale, tool_calls) = self.call_api(&messages, &context, false, state).await?;
+        }
+        info!(count = tool_calls.len(), ?rationale, "received tool calls from API");
+        {
+            let mut s = state.lock().unwrap();
+            s.agent_reply = rationale.clone();
+            s.log_action(AgentAction {
+                request: input.to_string(),
+                tools: tool_calls.iter().map(|call| call.name.clone()).collect(),
+                rationale: rationale.clone(),
+            });
+        }
+
+        // 4. Execute tool calls, stopping at the first failure
+        let mut results = Vec::new();
+        let mut failure = None;
+        for (done, call) in tool_calls.iter().enumerate() {
+            if failure.is_some() {
+                results.push(tool_result(call, "not run: an earlier tool failed", true));
+                continue;
+            }
+            info!(tool = %call.name, input = %call.input, "executing tool call");
+            let (queued_before, reply_before) = {
+                let mut s = state.lock().unwrap();
+                s.agent_status = AgentStatus::Acting(call.name.clone());
+                s.set_request_phase(RequestPhase::Acting { done, total: tool_calls.len() });
+                (s.queue.len(), s.agent_reply.clone())
+            };
+            match self.execute_tool(&call.name, call.input.clone(), state).await {
+                Ok(()) => {
+                    info!(tool = %call.name, "tool call completed");
+                    let s = state.lock().unwrap();
+                    let outcome = tool_outcome(&s, queued_before, &reply_before);
+                    results.push(tool_result(call, &outcome, false));
+                }
+                Err(e) => {
+                    warn!(tool = %call.name, ?e, "tool call failed");
+                    results.push(tool_result(call, &format!("{:#}", e), true));
+                    failure = Some(e);
+                }
+            }
+        }
+
+        // 5. Done; the progress bar stays up until the first song is ready
+        state.lock().unwrap().request_tools_done();
+
+        // 6. Let the model confirm what happened, in its own words
+        if !tool_calls.is_empty() {
+            messages.push(Message::Assistant { text: rationale, tool_calls });
+            messages.push(Message::ToolResults {
+                results,
+                prompt: CONFIRM_PROMPT.to_string(),
+            });
 
-        // 4. Execute tool calls
-        for (name, input_val) in &tool_calls {
-            info!(tool = %name, input = %input_val, "executing tool call");
-            state.lock().unwrap().agent_status =
-                AgentStatus::Acting(name.clone());
-            self.execute_tool(name, input_val.clone(), state).await?;
-            info!(tool = %name, "tool call completed");
+            let context = {
+                let s = state.lock().unwrap();
+                build_context(&s, &self.library.lock().unwrap())
+            };
+            match self.call_api(&messages, &context, true, state).await {
+                Ok((Some(reply), _)) => {
+                    info!(%reply, "agent confirmation");
+                    state.lock().unwrap().agent_reply = Some(reply);
+                }
+                Ok((None, _)) => debug!("no confirmation text, keeping the tool replies"),
+                Err(e) => warn!(?e, "confirmation round trip failed, keeping the tool replies"),
+            }
         }
 
-        // 5. Done
         state.lock().unwrap().agent_status = AgentStatus::Idle;
         info!("agent status: idle");
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Play a pasted link, or the top result for a search, without the model.
+    async fn handle_direct(&self, direct: DirectInput, state: &Arc<Mutex<AppState>>) -> Result<()> {
+        let url = match direct {
+            DirectInput::Url(url) => {
+                info!(%url, "bare url, playing directly");
+                url
+            }
+            DirectInput::Search(query) => {
+                info!(%query, "direct search");
+                {
+                    let mut s = state.lock().unwrap();
+                    s.agent_status = AgentStatus::Acting("search".to_string());
+                    s.agent_reply = None;
+                }
+                let results = downloader::search_youtube(&query, 1, &self.config).await?;
+                let Some(first) = results.into_iter().next() else {
+                    let mut s = state.lock().unwrap();
+                    s.agent_status = AgentStatus::Idle;
+                    s.request = None;
+                    s.agent_reply = Some(format!("No results for {}.", query));
+                    return Ok(());
+                };
+                first.url
+            }
+        };
+
+        {
+            let mut s = state.lock().unwrap();
+            s.agent_status = AgentStatus::Acting("play_url".to_string());
+            s.set_request_phase(RequestPhase::Acting { done: 0, total: 1 });
+        }
+        self.execute_tool("play_url", json!({ "url": url }), state).await?;
+        let mut s = state.lock().unwrap();
+        s.agent_status = AgentStatus::Idle;
+        s.request_tools_done();
         Ok(())
     }
 
+    /// One request to the configured provider. With `confirming` the model
+    /// may only answer in text, which is how tool results get summed up.
     async fn call_api(
         &self,
-        user_input: &str,
+        messages: &[Message],
         context: &str,
-    ) -> Result<Vec<(String, Value)>> {
-        let body = json!({
-            "model": self.config.model,
-            "max_tokens": 1024,
-            "system": format!("{}\n\nCurrent state:\n{}", SYSTEM_PROMPT, context),
-            "tools": tool_definitions(),
-            "messages": [
-                { "role": "user", "content": user_input }
-            ]
-        });
+        confirming: bool,
+        state: &Arc<Mutex<AppState>>,
+    ) -> Result<(Option<String>, Vec<ToolUse>)> {
+        let system = format!("{}\n\nCurrent state:\n{}", SYSTEM_PROMPT, context);
+        let body = self.provider.body(
+            &self.config.model,
+            &system,
+            &tool_definitions(),
+            messages,
+            confirming,
+        );
+        let api = self.provider.name();
 
-        debug!("sending API request");
-        let resp = self
-            .client
-            .post("https://api.anthropic.com/v1/messages")
-            .header("x-api-key", &self.config.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .header("content-type", "application/json")
-            .json(&body)
-            .send()
-            .await
-            .context("Failed to reach Claude API")?;
+        let mut attempt = 0;
+        // Each key gets one go per request; after that limits are waited out
+        let mut switches = 0;
+        let resp = loop {
+            let (key_index, api_key) = self.api_keys.pick(Instant::now());
+            debug!(attempt, key_index, "sending API request");
+            let resp = self
+                .provider
+                .request(&self.client, api_key)
+                .json(&body)
+                .send()
+                .await
+                .with_context(|| format!("Failed to reach the {}", api))?;
+
+            let status = resp.status();
+            info!(%status, "API response received");
+            // Honor retry-after when the API sends one, else back off exponentially
+            let wait = resp
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or_else(|| (API_RETRY_BASE * 2u32.pow(attempt)).min(API_RETRY_MAX));
+
+            // A limit or rejection is per key: try another one before waiting
+            let rejected = status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN;
+            if (rejected || status == StatusCode::TOO_MANY_REQUESTS)
+                && switches + 1 < self.api_keys.count()
+            {
+                let now = Instant::now();
+                let cooldown = if rejected { REJECTED_KEY_COOLDOWN } else { wait };
+                if self.api_keys.rotate(key_index, now + cooldown, now) {
+                    warn!(%status, key_index, ?cooldown, "switching to the next API key");
+                    switches += 1;
+                    continue;
+                }
+            }
+
+            let retryable = status == StatusCode::TOO_MANY_REQUESTS
+                || status == StatusCode::SERVICE_UNAVAILABLE
+                || status.as_u16() == 529;
+            if !retryable || attempt >= MAX_API_RETRIES {
+                break resp;
+            }
+            // Retrying sooner than asked would only be refused again
+            if wait > API_RETRY_AFTER_MAX {
+                warn!(%status, ?wait, api, "model API asks to wait too long, giving up");
+                break resp;
+            }
+            attempt += 1;
+            warn!(%status, ?wait, attempt, api, "model API busy, retrying");
+            let reason = if status == StatusCode::TOO_MANY_REQUESTS {
+                "rate limited"
+            } else {
+                "API overloaded"
+            };
+            state.lock().unwrap().backoff = Some(Backoff {
+                reason: reason.to_string(),
+                until: Instant::now() + wait,
+            });
+            tokio::time::sleep(wait).await;
+            state.lock().unwrap().backoff = None;
+        };
 
         let status = resp.status();
-        info!(%status, "API response received");
 
         if !status.is_success() {
             let err_text = resp.text().await.unwrap_or_default();
-            error!(%status, %err_text, "Claude API error");
-            anyhow::bail!("Claude API error ({}): {}", status, err_text);
+            error!(%status, %err_text, api, "model API error");
+            anyhow::bail!("{} error ({}): {}", api, status, err_text);
         }
 
         let raw_body = resp.text().await.context("Failed to read API response body")?;
         debug!(body_len = raw_body.len(), "API response body received");
 
-        let api_resp: ApiResponse = serde_json::from_str(&raw_body)
-            .context("Failed to parse API response JSON")?;
+        let reply = self.provider.parse(&raw_body)?;
+        for call in &reply.tool_calls {
+            info!(tool = %call.name, input = %call.input, "parsed tool call from response");
+        }
+        if let Some(text) = &reply.text {
+            debug!(%text, "LLM text response (rationale)");
+        }
 
-        let tool_calls: Vec<(String, Value)> = api_resp
-            .content
-            .into_iter()
-            .filter_map(|block| match block {
-                ContentBlock::ToolUse { name, input, .. } => {
-                    info!(tool = %name, %input, "parsed tool call from response");
-                    Some((name, input))
-                }
-                ContentBlock::Text { text } => {
-                    debug!(%text, "LLM text response (non-tool)");
-                    None
-                }
-            })
-            .collect();
-
-        if tool_calls.is_empty() {
+        if reply.tool_calls.is_empty() && !confirming {
             warn!("API returned no tool calls — LLM may have responded with text only");
         }
 
-        Ok(tool_calls)
+        Ok((reply.text, reply.tool_calls))
     }
 
     async fn execute_tool(
@@ -231,31 +979,48 @@ impl Agent {
         input: Value,
         state: &Arc<Mutex<AppState>>,
     ) -> Result<()> {
-        match name {
-            "play_url" => {
-                let url = input["url"].as_str().unwrap_or_default().to_string();
+        // Rejected calls fail like any other, so the model hears about it
+        let call = ToolCall::parse(name, input)
+            .map_err(|e| anyhow::anyhow!("invalid arguments for {}: {:#}", name, e))?;
 
-                // Check library for cached entry
-                {
-                    let lib = self.library.lock().unwrap();
-                    if let Some(entry) = lib.find_by_url(&url) {
-                        let cached_path = self.config.cache_dir.join(&entry.file_path);
-                        if cached_path.exists() {
-                            info!(%url, title = %entry.title, "using cached library entry");
-                            let mut s = state.lock().unwrap();
-                            s.pending_commands.push(PlayerCommand::PlayFile {
-                                path: cached_path,
-                                title: entry.title.clone(),
-                                artist: entry.artist.clone(),
-                                url: url.clone(),
-                                duration_secs: entry.duration_secs,
-                            });
-                            return Ok(());
-                        }
-                    }
+        match call {
+            ToolCall::PlayUrl { url, .. } if downloader::is_playlist_url(&url) => {
+                let limit = self.config.max_playlist_items;
+                info!(%url, limit, "play_url: queueing playlist");
+                state.lock().unwrap().status_message = Some("Loading playlist...".to_string());
+                let listed = downloader::expand_playlist(&url, limit, &self.config).await;
+                state.lock().unwrap().status_message = None;
+                let results = listed?;
+                for result in &results {
+                    self.enqueue_result(result, state);
+                }
+                state.lock().unwrap().agent_reply = Some(match results.len() {
+                    0 => "That playlist is empty.".to_string(),
+                    n if n >= limit => format!("Queued the first {} songs from the playlist.", n),
+                    n => format!("Queued {} songs from the playlist.", n),
+                });
+            }
+
+            ToolCall::PlayUrl { url, format } => {
+                let generation = state.lock().unwrap().begin_play_request();
+
+                if let Some((path, entry)) = self.cached_entry(&url, format.as_deref()) {
+                    info!(%url, title = %entry.title, "using cached library entry");
+                    let mut s = state.lock().unwrap();
+                    s.pending_commands.push(PlayerCommand::PlayFile {
+                        generation,
+                        path,
+                        thumbnail: entry.thumbnail_path(&self.config.cache_dir),
+                        title: entry.title,
+                        artist: entry.artist,
+                        url: url.clone(),
+                        duration_secs: entry.duration_secs,
+                        chapters: entry.chapters,
+                    });
+                    return Ok(());
                 }
 
-                info!(%url, "play_url: downloading");
+                info!(%url, ?format, "play_url: downloading");
                 {
                     let mut s = state.lock().unwrap();
                     s.status_message = Some("Downloading...".to_string());
@@ -264,116 +1029,52 @@ impl Agent {
                 let state_clone = state.clone();
                 let library = self.library.clone();
                 tokio::spawn(async move {
-                    match downloader::download_song(&url, &config).await {
+                    let result =
+                        downloader::download_song(&url, &config, format.as_deref(), |_| {}).await;
+                    match result {
                         Ok((path, meta)) => {
                             info!(%url, title = %meta.title, "download complete, queueing playback");
                             persist_to_library(&library, &meta, &url, &config, &state_clone);
                             let mut s = state_clone.lock().unwrap();
                             s.status_message = None;
                             s.pending_commands.push(PlayerCommand::PlayFile {
+                                generation,
                                 path,
                                 title: meta.title,
                                 artist: meta.artist,
                                 url: url.clone(),
                                 duration_secs: meta.duration_secs,
+                                chapters: meta.chapters,
+                                thumbnail: meta
+                                    .thumbnail
+                                    .map(|name| config.cache_dir.join(name)),
                             });
                         }
                         Err(e) => {
                             error!(%url, ?e, "download failed");
                             let mut s = state_clone.lock().unwrap();
-                            s.status_message = Some(format!("Download error: {}", e));
+                            s.status_message = None;
+                            s.request = None;
+                            s.report_error(format!("Download error: {}", e));
+                            let reason = downloader::failure_reason(&e);
+                            s.record_failure(url.clone(), reason, RetryAction::PlayUrl { url });
                         }
                     }
                 });
             }
 
-            "search_and_queue" => {
-                let query = input["query"].as_str().unwrap_or_default().to_string();
-                let count = input["count"].as_u64().unwrap_or(3) as u32;
+            ToolCall::SearchAndQueue { query, count } => {
                 info!(%query, %count, "search_and_queue");
 
-                let results = downloader::search_youtube(&query, count).await?;
+                let results = downloader::search_youtube(&query, count, &self.config).await?;
                 info!(results_count = results.len(), "search returned results");
 
-                let config = self.config.clone();
-                let state_clone = state.clone();
-
-                for result in results {
-                    // Check library for cached entry
-                    let cached = {
-                        let lib = self.library.lock().unwrap();
-                        lib.find_by_url(&result.url).and_then(|entry| {
-                            let cached_path = config.cache_dir.join(&entry.file_path);
-                            if cached_path.exists() {
-                                Some((cached_path, entry.title.clone(), entry.artist.clone(), entry.duration_secs))
-                            } else {
-                                None
-                            }
-                        })
-                    };
-
-                    if let Some((path, title, artist, duration_secs)) = cached {
-                        info!(url = %result.url, %title, "using cached library entry");
-                        let mut s = state_clone.lock().unwrap();
-                        let mut song = Song::new_queued(&title, &artist, &result.url);
-                        song.file_path = Some(path);
-                        song.duration = Some(Duration::from_secs_f64(duration_secs));
-                        song.status = SongStatus::Ready;
-                        s.queue.push(song);
-                        continue;
-                    }
-
-                    info!(title = %result.title, url = %result.url, "queueing song for download");
-                    {
-                        let mut s = state_clone.lock().unwrap();
-                        let mut song = Song::new_queued(
-                            &result.title,
-                            "",
-                            &result.url,
-                        );
-                        song.status = SongStatus::Downloading;
-                        s.queue.push(song);
-                    }
-
-                    let url = result.url.clone();
-                    let cfg = config.clone();
-                    let st = state_clone.clone();
-                    let library = self.library.clone();
-                    tokio::spawn(async move {
-                        info!(%url, "starting background download");
-                        match downloader::download_song(&url, &cfg).await {
-                            Ok((path, meta)) => {
-                                info!(%url, title = %meta.title, "download complete");
-                                persist_to_library(&library, &meta, &url, &cfg, &st);
-                                let mut s = st.lock().unwrap();
-                                if let Some(song) =
-                                    s.queue.iter_mut().find(|s| s.url == url)
-                                {
-                                    song.title = meta.title;
-                                    song.artist = meta.artist;
-                                    song.file_path = Some(path);
-                                    song.duration =
-                                        Some(Duration::from_secs_f64(meta.duration_secs));
-                                    song.status = SongStatus::Ready;
-                                }
-                            }
-                            Err(e) => {
-                                error!(%url, ?e, "download failed");
-                            }
-                        }
-                    });
+                for result in &results {
+                    self.enqueue_result(result, state);
                 }
             }
 
-            "replace_queue" => {
-                let queries: Vec<String> = input["queries"]
-                    .as_array()
-                    .map(|arr| {
-                        arr.iter()
-                            .filter_map(|v| v.as_str().map(String::from))
-                            .collect()
-                    })
-                    .unwrap_or_default();
+            ToolCall::ReplaceQueue { queries } => {
                 info!(?queries, "replace_queue");
 
                 {
@@ -384,111 +1085,704 @@ impl Agent {
 
                 for query in queries {
                     info!(%query, "searching for queue replacement");
-                    let results = downloader::search_youtube(&query, 2).await?;
+                    let results = downloader::search_youtube(&query, 2, &self.config).await?;
                     info!(count = results.len(), %query, "search results");
 
-                    let config = self.config.clone();
-                    let state_clone = state.clone();
-
-                    for result in results {
-                        // Check library for cached entry
-                        let cached = {
-                            let lib = self.library.lock().unwrap();
-                            lib.find_by_url(&result.url).and_then(|entry| {
-                                let cached_path = config.cache_dir.join(&entry.file_path);
-                                if cached_path.exists() {
-                                    Some((cached_path, entry.title.clone(), entry.artist.clone(), entry.duration_secs))
-                                } else {
-                                    None
-                                }
-                            })
-                        };
-
-                        if let Some((path, title, artist, duration_secs)) = cached {
-                            info!(url = %result.url, %title, "using cached library entry");
-                            let mut s = state_clone.lock().unwrap();
-                            let mut song = Song::new_queued(&title, &artist, &result.url);
-                            song.file_path = Some(path);
-                            song.duration = Some(Duration::from_secs_f64(duration_secs));
-                            song.status = SongStatus::Ready;
-                            s.queue.push(song);
-                            continue;
-                        }
-
-                        info!(title = %result.title, url = %result.url, "queueing song for download");
-                        {
-                            let mut s = state_clone.lock().unwrap();
-                            let mut song = Song::new_queued(
-                                &result.title,
-                                "",
-                                &result.url,
-                            );
-                            song.status = SongStatus::Downloading;
-                            s.queue.push(song);
-                        }
-
-                        let url = result.url.clone();
-                        let cfg = config.clone();
-                        let st = state_clone.clone();
-                        let library = self.library.clone();
-                        tokio::spawn(async move {
-                            info!(%url, "starting background download");
-                            match downloader::download_song(&url, &cfg).await {
-                                Ok((path, meta)) => {
-                                    info!(%url, title = %meta.title, "download complete");
-                                    persist_to_library(&library, &meta, &url, &cfg, &st);
-                                    let mut s = st.lock().unwrap();
-                                    if let Some(song) =
-                                        s.queue.iter_mut().find(|s| s.url == url)
-                                    {
-                                        song.title = meta.title;
-                                        song.artist = meta.artist;
-                                        song.file_path = Some(path);
-                                        song.duration = Some(Duration::from_secs_f64(
-                                            meta.duration_secs,
-                                        ));
-                                        song.status = SongStatus::Ready;
-                                    }
-                                }
-                                Err(e) => {
-                                    error!(%url, ?e, "download failed");
-                                }
-                            }
-                        });
+                    for result in &results {
+                        self.enqueue_result(result, state);
                     }
                 }
             }
 
-            "skip" => {
+            ToolCall::DownloadAll => {
+                info!("tool: download_all");
+                let count = self.warm_cache(state);
+                state.lock().unwrap().agent_reply = Some(match count {
+                    None => "An offline download is already running.".to_string(),
+                    Some(0) => "Everything is already downloaded.".to_string(),
+                    Some(count) => format!("Downloading {} songs for offline listening.", count),
+                });
+            }
+
+            ToolCall::ClearQueue => {
+                let mut s = state.lock().unwrap();
+                let removed = s.clear_queue();
+                info!(removed, "tool: clear_queue");
+                s.agent_reply = Some(if removed == 0 {
+                    "The queue is already empty".to_string()
+                } else {
+                    format!("Cleared {} songs from the queue", removed)
+                });
+            }
+
+            ToolCall::Skip => {
                 info!("tool: skip");
                 state.lock().unwrap().pending_commands.push(PlayerCommand::Skip);
             }
 
-            "pause" => {
+            ToolCall::Pause => {
                 info!("tool: pause");
                 state.lock().unwrap().pending_commands.push(PlayerCommand::Pause);
             }
 
-            "resume" => {
+            ToolCall::Resume => {
                 info!("tool: resume");
                 state.lock().unwrap().pending_commands.push(PlayerCommand::Resume);
             }
 
-            "set_volume" => {
-                let level = input["level"].as_u64().unwrap_or(70) as u8;
+            ToolCall::DescribeCollection { what } => {
+                info!(%what, "tool: describe_collection");
+                let mut s = state.lock().unwrap();
+                let reply = match what.as_str() {
+                    "library" => summarize_songs("library", &s.library),
+                    "queue" => summarize_songs("queue", &s.queue),
+                    _ => format!(
+                        "{} {}",
+                        summarize_songs("library", &s.library),
+                        summarize_songs("queue", &s.queue)
+                    ),
+                };
+                s.agent_reply = Some(reply);
+            }
+
+            ToolCall::SetVolume { level } => {
                 info!(level, "tool: set_volume");
                 state.lock().unwrap().pending_commands.push(PlayerCommand::SetVolume(level));
             }
 
-            other => {
-                warn!(tool = %other, "unknown tool call received");
+            ToolCall::Mute { muted } => {
+                info!(muted, "tool: mute");
+                state.lock().unwrap().pending_commands.push(PlayerCommand::Mute(muted));
+            }
+
+            ToolCall::QueueLibrary { shuffle } => {
+                info!(shuffle, "tool: queue_library");
+                state
+                    .lock()
+                    .unwrap()
+                    .pending_commands
+                    .push(PlayerCommand::QueueLibrary { shuffle });
+            }
+
+            ToolCall::Seek { target } => {
+                info!(?target, "tool: seek");
+                let mut s = state.lock().unwrap();
+                let Some(ref np) = s.current else {
+                    s.agent_reply = Some("Nothing is playing to seek in.".to_string());
+                    return Ok(());
+                };
+                let position = match target {
+                    SeekTarget::Seconds(position) => position,
+                    SeekTarget::Percent(percent) => match np.song.known_duration() {
+                        Some(duration) => duration.mul_f64(percent / 100.0),
+                        None => {
+                            let reply = "This song's length is unknown; seek by seconds instead.";
+                            s.agent_reply = Some(reply.to_string());
+                            return Ok(());
+                        }
+                    },
+                };
+                s.pending_commands.push(PlayerCommand::Seek(position));
+            }
+
+            ToolCall::SetSpeed { speed } => {
+                info!(speed, "tool: set_speed");
+                state.lock().unwrap().pending_commands.push(PlayerCommand::SetSpeed(speed));
+            }
+
+            ToolCall::SetRepeat { mode } => {
+                info!(mode = mode.label(), "tool: set_repeat");
+                state.lock().unwrap().repeat = mode;
+            }
+
+            ToolCall::Shuffle { enabled } => {
+                info!(enabled, "tool: shuffle");
+                state.lock().unwrap().shuffle = enabled;
+            }
+
+            ToolCall::StopAfterCurrent { enabled, fade } => {
+                info!(enabled, fade, "tool: stop_after_current");
+                state.lock().unwrap().stop_after_current = enabled.then_some(StopAfter { fade });
+            }
+
+            ToolCall::ReorderQueue { order } => {
+                info!(?order, "tool: reorder_queue");
+                let mut s = state.lock().unwrap();
+                if !s.reorder_queue(&order) {
+                    // The queue may have changed since the model saw it
+                    warn!(?order, len = s.queue.len(), "reorder_queue: not a permutation");
+                    s.agent_reply = Some(format!(
+                        "Couldn't reorder: that wasn't an ordering of the {} queued songs.",
+                        s.queue.len()
+                    ));
+                }
+            }
+
+            ToolCall::RemoveFromQueue { target } => {
+                info!(?target, "tool: remove_from_queue");
+                let mut s = state.lock().unwrap();
+                let removed = target.find(&s).and_then(|i| s.remove_queued(i));
+                s.agent_reply = Some(match removed {
+                    Some(song) => format!("Removed {} from the queue.", song.title),
+                    None => target.not_found(&s),
+                });
+            }
+
+            ToolCall::MoveInQueue { target, to } => {
+                info!(?target, to, "tool: move_in_queue");
+                let mut s = state.lock().unwrap();
+                let Some(from) = target.find(&s) else {
+                    s.agent_reply = Some(target.not_found(&s));
+                    return Ok(());
+                };
+                // Past the end means last
+                let to = to.min(s.queue.len() - 1);
+                s.move_queued(from, to);
+                let order: Vec<&str> = s
+                    .queue
+                    .iter()
+                    .take(SUMMARY_HIGHLIGHTS)
+                    .map(|song| song.title.as_str())
+                    .collect();
+                let more = s.queue.len().saturating_sub(SUMMARY_HIGHLIGHTS);
+                let mut reply = format!(
+                    "Moved {} to position {}. Up next: {}",
+                    s.queue[to].title,
+                    to + 1,
+                    order.join(", ")
+                );
+                if more > 0 {
+                    reply.push_str(&format!(" and {} more", more));
+                }
+                reply.push('.');
+                s.agent_reply = Some(reply);
+            }
+
+            ToolCall::SaveToPlaylist { name } => {
+                info!(%name, "tool: save_to_playlist");
+                self.save_current_to_playlist(&name, state);
+            }
+
+            ToolCall::CreatePlaylist { name } => {
+                info!(%name, "tool: create_playlist");
+                let (created, summaries) = {
+                    let mut lib = self.library.lock().unwrap();
+                    (lib.create_playlist(&name)?, lib.playlist_summaries())
+                };
+                let mut s = state.lock().unwrap();
+                s.playlists = summaries;
+                s.clamp_cursors();
+                s.agent_reply = Some(if created {
+                    format!("Created playlist {}.", name)
+                } else {
+                    format!("There's already a playlist called {}.", name)
+                });
+            }
+
+            ToolCall::RemoveFromPlaylist { name } => {
+                info!(%name, "tool: remove_from_playlist");
+                let current = state
+                    .lock()
+                    .unwrap()
+                    .current
+                    .as_ref()
+                    .map(|np| (np.song.url.clone(), np.song.title.clone()));
+                let Some((url, title)) = current else {
+                    state.lock().unwrap().agent_reply =
+                        Some("Nothing is playing to remove.".to_string());
+                    return Ok(());
+                };
+                let (removed, summaries) = {
+                    let mut lib = self.library.lock().unwrap();
+                    let video_id = lib.find_by_url(&url).map(|e| e.video_id.clone());
+                    let removed = match video_id {
+                        Some(id) => lib.remove_from_playlist(&name, &id)?,
+                        None => false,
+                    };
+                    (removed, lib.playlist_summaries())
+                };
+                let mut s = state.lock().unwrap();
+                s.playlists = summaries;
+                s.clamp_cursors();
+                s.agent_reply = Some(if removed {
+                    format!("Removed {} from {}.", title, name)
+                } else {
+                    format!("{} isn't in {}.", title, name)
+                });
+            }
+
+            ToolCall::Favorite => {
+                info!("tool: favorite");
+                let mut s = state.lock().unwrap();
+                let Some((url, title)) =
+                    s.current.as_ref().map(|np| (np.song.url.clone(), np.song.title.clone()))
+                else {
+                    s.agent_reply = Some("Nothing is playing to favorite.".to_string());
+                    return Ok(());
+                };
+                let saved = self.library.lock().unwrap().set_favorite(&url, true)?;
+                s.agent_reply = Some(if saved {
+                    s.mark_favorite(&url, true);
+                    format!("Added {} to favorites.", title)
+                } else {
+                    format!("{} isn't in the library, so it can't be a favorite.", title)
+                });
+            }
+
+            ToolCall::PlayPrevious => {
+                info!("tool: play_previous");
+                state.lock().unwrap().pending_commands.push(PlayerCommand::Previous);
             }
+
+            ToolCall::PlayAlbum => {
+                let current = state.lock().unwrap().current.as_ref().map(|np| np.song.clone());
+                let Some(song) = current else {
+                    state.lock().unwrap().agent_reply =
+                        Some("Nothing is playing, so there's no album to play.".to_string());
+                    return Ok(());
+                };
+                let entry = self.library.lock().unwrap().find_by_url(&song.url).cloned();
+                let Some(album) = entry.as_ref().and_then(|e| e.album.clone()) else {
+                    anyhow::bail!("don't know which album {:?} is from", song.title);
+                };
+                let query = album_query(&song, &album, &self.config.unknown_artist);
+                info!(%query, "tool: play_album");
+
+                state.lock().unwrap().status_message = Some("Loading album...".to_string());
+                let listed = match downloader::find_album(&query, &self.config).await {
+                    Ok(Some(url)) => {
+                        let limit = self.config.max_playlist_items;
+                        downloader::expand_playlist(&url, limit, &self.config).await.map(Some)
+                    }
+                    Ok(None) => Ok(None),
+                    Err(e) => Err(e),
+                };
+                state.lock().unwrap().status_message = None;
+                let Some(tracks) = listed? else {
+                    state.lock().unwrap().agent_reply =
+                        Some(format!("Couldn't find the album {}.", album));
+                    return Ok(());
+                };
+
+                // The rest of the album, in order, when the playing track is on it
+                let start = entry
+                    .and_then(|e| tracks.iter().position(|t| t.url.contains(&e.video_id)))
+                    .map_or(0, |i| i + 1);
+                for track in &tracks[start..] {
+                    self.enqueue_result(track, state);
+                }
+                state.lock().unwrap().agent_reply = Some(match tracks.len() - start {
+                    0 => format!("Nothing left to queue from {}.", album),
+                    n => format!("Queued {} tracks from {}.", n, album),
+                });
+            }
+
         }
 
         Ok(())
     }
+
+    /// The library entry for `url` and its cached file, if that file is
+    /// still there (and in `format`, when one is asked for). Takes and
+    /// releases the library lock, so don't hold the state lock around it.
+    fn cached_entry(&self, url: &str, format: Option<&str>) -> Option<(PathBuf, LibraryEntry)> {
+        let lib = self.library.lock().unwrap();
+        let entry = lib.find_by_url(url)?;
+        let cached_path = self.config.cache_dir.join(&entry.file_path);
+        let format_matches = format.is_none_or(|f| entry.file_path.ends_with(&format!(".{}", f)));
+        (downloader::is_valid_cached_file(&cached_path) && format_matches)
+            .then(|| (cached_path, entry.clone()))
+    }
+
+    /// Make sure the next `PREFETCH_AHEAD` queue entries are ready or
+    /// downloading, so auto-advance doesn't stop at one that was only queued.
+    /// Returns how many downloads were started.
+    pub fn prefetch_next(&self, state: &Arc<Mutex<AppState>>) -> usize {
+        let upcoming: Vec<String> = {
+            let s = state.lock().unwrap();
+            s.queue
+                .iter()
+                .take(PREFETCH_AHEAD)
+                .filter(|song| {
+                    song.status == SongStatus::Queued && !s.downloads_in_flight.contains(&song.url)
+                })
+                .map(|song| song.url.clone())
+                .collect()
+        };
+
+        let mut started = 0;
+        for url in upcoming {
+            let cached = self.cached_entry(&url, None);
+            {
+                let mut s = state.lock().unwrap();
+                let Some(song) = s.queue.iter_mut().find(|s| s.url == url) else {
+                    continue;
+                };
+                match cached {
+                    Some((path, entry)) => {
+                        debug!(%url, "prefetch: already cached");
+                        song.file_path = Some(path);
+                        song.duration = Some(Duration::from_secs_f64(entry.duration_secs));
+                        song.thumbnail = entry.thumbnail_path(&self.config.cache_dir);
+                        song.chapters = entry.chapters;
+                        song.status = SongStatus::Ready;
+                        continue;
+                    }
+                    None => song.status = SongStatus::Downloading,
+                }
+            }
+            info!(%url, "prefetching upcoming song");
+            self.spawn_download(url, None, state);
+            started += 1;
+        }
+        started
+    }
+
+    /// Add the playing song to playlist `name`. Only downloaded songs have a
+    /// library entry to point at, so previews and unknown URLs are refused.
+    fn save_current_to_playlist(&self, name: &str, state: &Arc<Mutex<AppState>>) {
+        let current = state
+            .lock()
+            .unwrap()
+            .current
+            .as_ref()
+            .map(|np| (np.song.url.clone(), np.song.title.clone()));
+        let Some((url, title)) = current else {
+            state.lock().unwrap().agent_reply = Some("Nothing is playing to save.".to_string());
+            return;
+        };
+
+        // Saving is what keeps a download when persist_downloads is off
+        let unsaved = state.lock().unwrap().unsaved_downloads.remove(&url);
+
+        // Release the library before touching state again
+        let (saved, summaries, added) = {
+            let mut lib = self.library.lock().unwrap();
+            let mut added = None;
+            if let Some(entry) = unsaved {
+                info!(%url, "adding saved download to the library");
+                let song = Song::cached(&entry, &self.config.cache_dir);
+                match lib.add(entry) {
+                    Ok(_) => added = song,
+                    Err(e) => warn!(?e, %url, "failed to add saved download to the library"),
+                }
+            }
+            let video_id = lib.find_by_url(&url).map(|e| e.video_id.clone());
+            let saved = video_id.map(|id| lib.add_to_playlist(name, &id));
+            (saved, lib.playlist_summaries(), added)
+        };
+
+        let mut s = state.lock().unwrap();
+        if let Some(song) = added {
+            if !s.library.iter().any(|other| other.url == song.url) {
+                s.library.push(song);
+            }
+        }
+        match saved {
+            Some(Ok(added)) => {
+                s.playlists = summaries;
+                s.clamp_cursors();
+                s.agent_reply = Some(if added {
+                    format!("Saved {} to {}.", title, name)
+                } else {
+                    format!("{} is already in {}.", title, name)
+                });
+            }
+            Some(Err(e)) => {
+                warn!(?e, %name, "failed to save playlist");
+                s.report_warning(format!("Couldn't save playlist: {:#}", e));
+            }
+            None => {
+                s.agent_reply = Some(format!("{} isn't in the library, so it can't be saved.", title));
+            }
+        }
+    }
+
+    /// Queue a search result: straight from the cache when the library has
+    /// it, otherwise as a background download.
+    fn enqueue_result(&self, result: &downloader::SearchResult, state: &Arc<Mutex<AppState>>) {
+        // Check library for cached entry
+        let cached = {
+            let lib = self.library.lock().unwrap();
+            lib.find_by_url(&result.url).and_then(|entry| {
+                let cached_path = self.config.cache_dir.join(&entry.file_path);
+                downloader::is_valid_cached_file(&cached_path).then(|| (cached_path, entry.clone()))
+            })
+        };
+
+        if let Some((path, entry)) = cached {
+            info!(url = %result.url, title = %entry.title, "using cached library entry");
+            let mut s = state.lock().unwrap();
+            let mut song = Song::new_queued(&entry.title, &entry.artist, &result.url);
+            song.file_path = Some(path);
+            song.duration = Some(Duration::from_secs_f64(entry.duration_secs));
+            song.thumbnail = entry.thumbnail_path(&self.config.cache_dir);
+            song.chapters = entry.chapters;
+            song.status = SongStatus::Ready;
+            s.queue.push(song);
+            return;
+        }
+
+        info!(title = %result.title, url = %result.url, "queueing song for download");
+        {
+            let mut s = state.lock().unwrap();
+            let mut song = Song::new_queued(
+                &result.title,
+                &self.config.unknown_artist,
+                &result.url,
+            );
+            song.duration = result.duration_secs.map(Duration::from_secs_f64);
+            song.status = SongStatus::Downloading;
+            s.queue.push(song);
+        }
+
+        self.spawn_download(result.url.clone(), None, state);
+    }
+
+    /// Download `url` in the background, at most `MAX_CONCURRENT_DOWNLOADS`
+    /// at a time. On success the song is persisted to the library and its
+    /// queue entry (if still queued) marked ready. The task yields whether
+    /// the download succeeded.
+    fn spawn_download(
+        &self,
+        url: String,
+        format: Option<String>,
+        state: &Arc<Mutex<AppState>>,
+    ) -> JoinHandle<bool> {
+        state.lock().unwrap().downloads_in_flight.insert(url.clone());
+
+        let cfg = self.config.clone();
+        let st = state.clone();
+        let library = self.library.clone();
+        let slots = self.download_slots.clone();
+        tokio::spawn(async move {
+            // The semaphore is never closed, so acquiring can't fail
+            let _permit = slots.acquire_owned().await.ok();
+            info!(%url, "starting background download");
+            let on_progress = |fraction| st.lock().unwrap().set_download_progress(&url, fraction);
+            let mut result =
+                downloader::download_song(&url, &cfg, format.as_deref(), on_progress).await;
+            // A hang is often a passing network problem; give it one more go
+            // once it's had a moment to clear
+            if result.as_ref().is_err_and(downloader::is_timeout) {
+                warn!(%url, delay = ?DOWNLOAD_RETRY_DELAY, "download timed out, retrying once");
+                st.lock().unwrap().backoff = Some(Backoff {
+                    reason: "download timed out".to_string(),
+                    until: Instant::now() + DOWNLOAD_RETRY_DELAY,
+                });
+                tokio::time::sleep(DOWNLOAD_RETRY_DELAY).await;
+                st.lock().unwrap().backoff = None;
+                result =
+                    downloader::download_song(&url, &cfg, format.as_deref(), on_progress).await;
+            }
+            st.lock().unwrap().downloads_in_flight.remove(&url);
+
+            match result {
+                Ok((path, meta)) => {
+                    info!(%url, title = %meta.title, "download complete");
+                    persist_to_library(&library, &meta, &url, &cfg, &st);
+                    let mut s = st.lock().unwrap();
+                    // Removed from the queue while downloading: it stays removed
+                    if let Some(song) = s.queue.iter_mut().find(|s| s.url == url) {
+                        song.title = meta.title;
+                        song.artist = meta.artist;
+                        song.file_path = Some(path);
+                        song.duration = Some(Duration::from_secs_f64(meta.duration_secs));
+                        song.chapters = meta.chapters;
+                        song.thumbnail = meta.thumbnail.map(|name| cfg.cache_dir.join(name));
+                        song.status = SongStatus::Ready;
+                        song.download_progress = None;
+                    }
+                    true
+                }
+                Err(e) => {
+                    error!(%url, ?e, "download failed");
+                    let mut s = st.lock().unwrap();
+                    if let Some(hint) = downloader::storage_hint(&e) {
+                        s.report_error(format!("Download failed: {}", hint));
+                    }
+                    let reason = downloader::failure_reason(&e);
+                    let mut item = url.clone();
+                    if let Some(song) = s.queue.iter_mut().find(|s| s.url == url) {
+                        song.status = SongStatus::Failed(reason.clone());
+                        song.download_progress = None;
+                        item = song.title.clone();
+                    }
+                    s.record_failure(item, reason, RetryAction::Download { url: url.clone() });
+                    false
+                }
+            }
+        })
+    }
+
+    /// Download `url` again: its failed queue entry, or a new one at the
+    /// end of the queue if it was removed. Returns `false` if it's queued
+    /// and hasn't failed, so there's nothing to retry.
+    pub fn retry_download(&self, url: &str, state: &Arc<Mutex<AppState>>) -> bool {
+        {
+            let mut s = state.lock().unwrap();
+            match s.queue.iter_mut().find(|song| song.url == url) {
+                Some(song) if matches!(song.status, SongStatus::Failed(_)) => {
+                    info!(%url, title = %song.title, "retrying failed download");
+                    song.status = SongStatus::Downloading;
+                }
+                Some(_) => return false,
+                None => {
+                    info!(%url, "retrying download of a song no longer queued");
+                    s.queue.push(Song::new_downloading(url));
+                }
+            }
+            // Retried from the queue: its tray entry is handled
+            s.failures
+                .retain(|f| !matches!(&f.retry, RetryAction::Download { url: u } if u == url));
+        }
+        self.spawn_download(url.to_string(), None, state);
+        true
+    }
+
+    /// Play `url` as if it had been pasted.
+    pub async fn play_url(&self, url: &str, state: &Arc<Mutex<AppState>>) -> Result<()> {
+        self.execute_tool("play_url", json!({ "url": url }), state).await
+    }
+
+    /// Make sure everything in the queue and library is downloaded, with a
+    /// combined progress indicator. Returns how many downloads were started,
+    /// or `None` if a batch is already running.
+    pub fn warm_cache(&self, state: &Arc<Mutex<AppState>>) -> Option<usize> {
+        let entries = self.library.lock().unwrap().entries().to_vec();
+        // Checked and claimed under one lock so two requests can't both start
+        let items = {
+            let mut s = state.lock().unwrap();
+            if s.cache_warm.is_some() {
+                info!("cache warm-up already running");
+                return None;
+            }
+            let items =
+                uncached_items(&s.queue, &entries, &self.config.cache_dir, &s.downloads_in_flight);
+            if !items.is_empty() {
+                s.cache_warm = Some(BatchProgress {
+                    total: items.len(),
+                    ..Default::default()
+                });
+            }
+            items
+        };
+        info!(count = items.len(), "warming cache");
+        if items.is_empty() {
+            return Some(0);
+        }
+
+        let total = items.len();
+
+        let handles: Vec<_> = items
+            .into_iter()
+            .map(|item| self.spawn_download(item.url, item.audio_format, state))
+            .collect();
+
+        let st = state.clone();
+        tokio::spawn(async move {
+            for handle in handles {
+                let ok = handle.await.unwrap_or(false);
+                let mut s = st.lock().unwrap();
+                if let Some(ref mut progress) = s.cache_warm {
+                    if ok {
+                        progress.done += 1;
+                    } else {
+                        progress.failed += 1;
+                    }
+                }
+            }
+            let mut s = st.lock().unwrap();
+            if let Some(progress) = s.cache_warm.take() {
+                info!(done = progress.done, failed = progress.failed, "cache warm-up finished");
+                if progress.failed > 0 {
+                    s.report_warning(format!(
+                        "Offline download: {} of {} songs failed",
+                        progress.failed, progress.total
+                    ));
+                }
+            }
+        });
+
+        Some(total)
+    }
 }
 
+/// Something the cache is missing, with the format it was originally
+/// downloaded in.
+#[derive(Debug, Clone, PartialEq)]
+struct CacheItem {
+    url: String,
+    audio_format: Option<String>,
+}
+
+/// Queue songs that aren't ready plus library entries whose file is gone,
+/// deduplicated by URL and skipping downloads already in flight.
+fn uncached_items(
+    queue: &[Song],
+    entries: &[LibraryEntry],
+    cache_dir: &Path,
+    in_flight: &HashSet<String>,
+) -> Vec<CacheItem> {
+    let mut seen = HashSet::new();
+    let mut items = Vec::new();
+
+    let queued = queue
+        .iter()
+        .filter(|song| {
+            song.status != SongStatus::Ready
+                || !song.file_path.as_ref().is_some_and(|p| downloader::is_valid_cached_file(p))
+        })
+        .map(|song| CacheItem {
+            url: song.url.clone(),
+            audio_format: None,
+        });
+    let library = entries
+        .iter()
+        .filter(|entry| !downloader::is_valid_cached_file(&cache_dir.join(&entry.file_path)))
+        .map(|entry| CacheItem {
+            url: entry.url.clone(),
+            audio_format: entry.audio_format.clone(),
+        });
+
+    for item in queued.chain(library) {
+        if !in_flight.contains(&item.url) && seen.insert(item.url.clone()) {
+            items.push(item);
+        }
+    }
+    items
+}
+
+/// Input that skips the model. A YouTube video link always does; with
+/// `direct_search` on, so does any other link or plain text.
+#[derive(Debug, PartialEq)]
+enum DirectInput {
+    Url(String),
+    Search(String),
+}
+
+impl DirectInput {
+    fn classify(input: &str, direct_search: bool) -> Option<Self> {
+        let input = input.trim();
+        if downloader::is_youtube_url(input) {
+            return Some(DirectInput::Url(input.to_string()));
+        }
+        if !direct_search || input.is_empty() {
+            return None;
+        }
+        let is_url = (input.starts_with("https://") || input.starts_with("http://"))
+            && !input.contains(char::is_whitespace);
+        Some(if is_url {
+            DirectInput::Url(input.to_string())
+        } else {
+            DirectInput::Search(input.to_string())
+        })
+    }
+}
+
+/// Serializes `persist_to_library`; taken before the state lock.
+static PERSIST_LOCK: Mutex<()> = Mutex::new(());
+
 fn persist_to_library(
     library: &Arc<Mutex<Library>>,
     meta: &downloader::SongMeta,
@@ -502,33 +1796,153 @@ fn persist_to_library(
         artist: meta.artist.clone(),
         url: url.to_string(),
         duration_secs: meta.duration_secs,
-        file_path: format!("{}.mp3", meta.video_id),
+        file_path: meta.file_name(),
         downloaded_at: chrono::Utc::now().to_rfc3339(),
+        audio_format: meta.audio_format.clone(),
+        last_played: None,
+        play_count: 0,
+        favorite: false,
+        chapters: meta.chapters.clone(),
+        album: meta.album.clone(),
+        thumbnail: meta.thumbnail.clone(),
     };
-    if let Err(e) = library.lock().unwrap().add(entry) {
-        warn!(?e, "failed to persist library entry");
+    if !config.persist_downloads {
+        debug!(%url, "persist_downloads off, keeping download out of the library");
+        state.lock().unwrap().unsaved_downloads.insert(url.to_string(), entry);
+        return;
     }
+    // Held until the panel is updated too, so concurrent downloads can't
+    // leave it and library.json disagreeing. The state lock isn't held over
+    // the library's disk work, which would stall the UI.
+    let _persisting = PERSIST_LOCK.lock().unwrap();
+    let playing = {
+        let s = state.lock().unwrap();
+        s.current.as_ref().map(|np| np.song.url.clone())
+    };
+    let downloaded_at = entry.downloaded_at.clone();
+    let saved = {
+        let mut lib = library.lock().unwrap();
+        lib.add(entry).and_then(|mut evicted| {
+            if let Some(max) = config.max_cache_bytes {
+                let keep: Vec<&str> =
+                    [Some(url), playing.as_deref()].into_iter().flatten().collect();
+                evicted.extend(lib.evict_to_fit(&config.cache_dir, max, &keep)?);
+            }
+            Ok(evicted)
+        })
+    };
 
     // Also add to the in-memory library panel (deduplicate by URL)
     let mut s = state.lock().unwrap();
+    match saved {
+        Ok(evicted) => {
+            s.library.retain(|song| !evicted.iter().any(|e| e.url == song.url));
+            // Queued songs whose file was evicted need downloading again
+            for song in s.queue.iter_mut() {
+                if song.status == SongStatus::Ready && evicted.iter().any(|e| e.url == song.url) {
+                    song.status = SongStatus::Queued;
+                    song.file_path = None;
+                }
+            }
+            s.clamp_cursors();
+        }
+        Err(e) => {
+            warn!(?e, "failed to persist library entry");
+            let reason = downloader::storage_hint(&e)
+                .map(String::from)
+                .unwrap_or_else(|| e.to_string());
+            s.report_warning(format!("Couldn't save library: {}", reason));
+        }
+    }
     if !s.library.iter().any(|song| song.url == url) {
         let mut song = Song::new_queued(&meta.title, &meta.artist, url);
-        song.file_path = Some(config.cache_dir.join(format!("{}.mp3", meta.video_id)));
+        song.file_path = Some(config.cache_dir.join(meta.file_name()));
         song.duration = Some(Duration::from_secs_f64(meta.duration_secs));
+        song.chapters = meta.chapters.clone();
+        song.thumbnail = meta.thumbnail.as_ref().map(|name| config.cache_dir.join(name));
+        song.downloaded_at = Some(downloaded_at);
         song.status = SongStatus::Ready;
         s.library.push(song);
         info!(title = %meta.title, "added song to library panel");
     }
 }
 
-fn build_context(state: &AppState) -> String {
+/// Search for the album `song` belongs to, by its artist when known.
+fn album_query(song: &Song, album: &str, unknown_artist: &str) -> String {
+    if song.artist == unknown_artist {
+        album.to_string()
+    } else {
+        format!("{} {}", song.artist, album)
+    }
+}
+
+/// One or two sentences describing a collection, naming at most
+/// `SUMMARY_HIGHLIGHTS` songs so large libraries stay readable.
+fn summarize_songs(label: &str, songs: &[Song]) -> String {
+    let titles: Vec<&str> = songs
+        .iter()
+        .take(SUMMARY_HIGHLIGHTS)
+        .map(|s| s.title.as_str())
+        .collect();
+    let downloading = songs
+        .iter()
+        .filter(|s| s.status == SongStatus::Downloading)
+        .count();
+
+    let mut summary = match songs.len() {
+        0 => return format!("Your {} is empty.", label),
+        1 => format!("Your {} has one song: {}.", label, titles[0]),
+        n if n <= SUMMARY_HIGHLIGHTS => {
+            format!("Your {} has {} songs: {}.", label, n, titles.join(", "))
+        }
+        n => format!(
+            "Your {} has {} songs, including {} and {} more.",
+            label,
+            n,
+            titles.join(", "),
+            n - SUMMARY_HIGHLIGHTS
+        ),
+    };
+    if downloading > 0 {
+        summary.push_str(&format!(" {} still downloading.", downloading));
+    }
+    summary
+}
+
+/// The result answering `call`.
+fn tool_result(call: &ToolUse, content: &str, is_error: bool) -> ToolResult {
+    ToolResult {
+        id: call.id.clone(),
+        content: content.to_string(),
+        is_error,
+    }
+}
+
+/// What a successful tool did, for the model: the reply it set for the user
+/// if any, otherwise how the queue changed.
+fn tool_outcome(state: &AppState, queued_before: usize, reply_before: &Option<String>) -> String {
+    if state.agent_reply != *reply_before {
+        if let Some(reply) = &state.agent_reply {
+            return reply.clone();
+        }
+    }
+    match state.queue.len().checked_sub(queued_before) {
+        Some(0) | None => "done".to_string(),
+        Some(added) => format!("queued {} songs, {} in the queue now", added, state.queue.len()),
+    }
+}
+
+fn build_context(state: &AppState, library: &Library) -> String {
     let mut ctx = String::new();
 
     if let Some(ref np) = state.current {
-        ctx.push_str(&format!(
-            "Now playing: {} - {}\n",
-            np.song.title, np.song.artist
-        ));
+        match np.song.known_artist(&state.config.unknown_artist) {
+            Some(artist) => ctx.push_str(&format!(
+                "Now playing: {} - {}\n",
+                np.song.title, artist
+            )),
+            None => ctx.push_str(&format!("Now playing: {}\n", np.song.title)),
+        }
     } else {
         ctx.push_str("Now playing: nothing\n");
     }
@@ -542,21 +1956,52 @@ fn build_context(state: &AppState) -> String {
         }
     }
 
+    let top: Vec<String> = library
+        .most_played(TOP_PLAYED)
+        .into_iter()
+        .map(|entry| format!("{} ({} plays)", entry.title, entry.play_count))
+        .collect();
+    if !top.is_empty() {
+        ctx.push_str(&format!("Most played: {}\n", top.join(", ")));
+    }
+
     if state.queue.is_empty() {
         ctx.push_str("Queue: empty\n");
     } else {
         ctx.push_str("Queue:\n");
         for (i, song) in state.queue.iter().enumerate() {
-            ctx.push_str(&format!(
-                "  {}. {} ({:?})\n",
-                i + 1,
-                song.title,
-                song.status
-            ));
+            match &song.status {
+                // Spelled out so the model can search for another upload
+                SongStatus::Failed(reason) => ctx.push_str(&format!(
+                    "  {}. {} (download failed: {})\n",
+                    i + 1,
+                    song.title,
+                    reason
+                )),
+                status => ctx.push_str(&format!("  {}. {} ({:?})\n", i + 1, song.title, status)),
+            }
+        }
+    }
+
+    if let Some(last) = state.action_log.last() {
+        ctx.push_str(&format!(
+            "Last request: \"{}\" -> {}",
+            last.request,
+            last.tools.join(", ")
+        ));
+        if let Some(ref why) = last.rationale {
+            ctx.push_str(&format!(" (because: {})", why));
         }
+        ctx.push('\n');
     }
 
-    ctx.push_str(&format!("Volume: {}\n", state.volume));
+    match state.muted_from {
+        Some(level) => ctx.push_str(&format!("Volume: muted (was {})\n", level)),
+        None => ctx.push_str(&format!("Volume: {}\n", state.volume)),
+    }
+    if state.speed != 1.0 {
+        ctx.push_str(&format!("Speed: {}x\n", state.speed));
+    }
     ctx.push_str(&format!(
         "Paused: {}\n",
         if state.paused { "yes" } else { "no" }
@@ -564,3 +2009,450 @@ fn build_context(state: &AppState) -> String {
 
     ctx
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FileConfig;
+    use crate::test_util::{self, FakeApi, TempDir};
+
+    fn meta(video_id: &str) -> downloader::SongMeta {
+        downloader::SongMeta {
+            title: format!("Song {}", video_id),
+            artist: "Artist".to_string(),
+            duration_secs: 180.0,
+            video_id: video_id.to_string(),
+            audio_format: None,
+            extension: "mp3".to_string(),
+            chapters: Vec::new(),
+            album: None,
+            thumbnail: None,
+        }
+    }
+
+    /// Prints metadata for video `abc123`, and downloads write a stub file
+    /// named by `-o` in the `--audio-format` asked for.
+    const FAKE_DOWNLOAD: &str = r#"
+case "$*" in *--no-download*) printf 'Title\nArtist\n200\nabc123\nNA\nNA\n'; exit 0;; esac
+while [ $# -gt 0 ]; do
+    case "$1" in --audio-format) fmt=$2; shift;; -o) out=$2; shift;; esac
+    shift
+done
+out=$(echo "$out" | sed "s/%(id)s/abc123/; s/%(ext)s/$fmt/")
+head -c 8192 /dev/zero > "$out"
+"#;
+
+    /// An agent with an empty library, and the state it acts on.
+    fn agent(dir: &TempDir, file: FileConfig) -> (Agent, Arc<Mutex<AppState>>) {
+        let config = Arc::new(test_util::config(dir.path(), file));
+        let library = Library::load(config.library_path.clone(), None, false).unwrap();
+        let state = Arc::new(Mutex::new(AppState::new(config.clone())));
+        (Agent::new(config, Arc::new(Mutex::new(library))), state)
+    }
+
+    /// An agent talking to `api`, with `file` for everything else.
+    fn api_agent(dir: &TempDir, api: &FakeApi, file: FileConfig) -> (Agent, Arc<Mutex<AppState>>) {
+        agent(dir, FileConfig { base_url: Some(api.base_url.clone()), ..file })
+    }
+
+    /// A Messages API response with `text` and then `tools` as `(name, input)`.
+    fn anthropic_reply(text: &str, tools: &[(&str, Value)]) -> (u16, String) {
+        let mut content = vec![json!({ "type": "text", "text": text })];
+        content.extend(tools.iter().enumerate().map(|(i, (name, input))| {
+            json!({ "type": "tool_use", "id": format!("tool_{}", i), "name": name, "input": input })
+        }));
+        (200, json!({ "content": content }).to_string())
+    }
+
+    #[test]
+    fn context_leaves_out_the_unknown_artist() {
+        let dir = TempDir::new("unknown-artist");
+        let file = FileConfig {
+            unknown_artist: Some("Various".to_string()),
+            ..FileConfig::default()
+        };
+        let config = Arc::new(test_util::config(dir.path(), file));
+        let library = Library::load(config.library_path.clone(), None, false).unwrap();
+        let mut state = AppState::new(config);
+        let now_playing = |state: &mut AppState, artist: &str| {
+            state.current = None;
+            state.start_playing(Song::new_queued("Title", artist, "https://youtu.be/a"));
+            build_context(state, &library).lines().next().unwrap().to_string()
+        };
+
+        assert_eq!(now_playing(&mut state, "Various"), "Now playing: Title");
+        assert_eq!(now_playing(&mut state, ""), "Now playing: Title");
+        assert_eq!(now_playing(&mut state, "Daft Punk"), "Now playing: Title - Daft Punk");
+    }
+
+    #[test]
+    fn summaries_name_a_few_songs_of_a_large_library() {
+        let songs: Vec<Song> = ["A", "B", "C", "D", "E"]
+            .iter()
+            .map(|title| Song::new_queued(title, "Artist", "https://youtu.be/x"))
+            .collect();
+
+        assert_eq!(summarize_songs("library", &[]), "Your library is empty.");
+        assert_eq!(summarize_songs("queue", &songs[..1]), "Your queue has one song: A.");
+        assert_eq!(summarize_songs("library", &songs[..3]), "Your library has 3 songs: A, B, C.");
+        assert_eq!(
+            summarize_songs("library", &songs),
+            "Your library has 5 songs, including A, B, C and 2 more."
+        );
+        let downloading = [songs[0].clone(), Song::new_downloading("https://youtu.be/y")];
+        assert!(summarize_songs("queue", &downloading).ends_with(" 1 still downloading."));
+    }
+
+    #[test]
+    fn uncached_items_are_what_the_cache_is_missing() {
+        let dir = TempDir::new("uncached");
+        let cached = |id: &str, bytes: usize| {
+            let path = dir.path().join(format!("{}.mp3", id));
+            std::fs::write(&path, vec![0u8; bytes]).unwrap();
+            let mut song = Song::new_queued(id, "Artist", &test_util::library_entry(id).url);
+            song.file_path = Some(path);
+            song.status = SongStatus::Ready;
+            song
+        };
+        let queued = |id: &str| Song::new_queued(id, "Artist", &test_util::library_entry(id).url);
+        // Ready, truncated, not downloaded, downloading now, and also in the library
+        let queue = [cached("a", 8192), cached("b", 10), queued("c"), queued("d"), queued("f")];
+        cached("e", 8192);
+        let mut missing = test_util::library_entry("f");
+        missing.audio_format = Some("flac".to_string());
+        let entries = [test_util::library_entry("e"), missing];
+        let in_flight = HashSet::from([test_util::library_entry("d").url]);
+
+        let items = uncached_items(&queue, &entries, dir.path(), &in_flight);
+        let item = |id: &str, audio_format: Option<&str>| CacheItem {
+            url: test_util::library_entry(id).url,
+            audio_format: audio_format.map(String::from),
+        };
+        // The queue's copy of "f" comes first and wins
+        assert_eq!(items, [item("b", None), item("c", None), item("f", None)]);
+        assert!(uncached_items(&[], &entries[1..], dir.path(), &HashSet::new())
+            .contains(&item("f", Some("flac"))));
+    }
+
+    #[test]
+    fn warm_cache_does_not_start_a_second_batch() {
+        let dir = TempDir::new("warm-twice");
+        let config = Arc::new(test_util::config(dir.path(), FileConfig::default()));
+        let library = Library::load(config.library_path.clone(), None, false).unwrap();
+        let agent = Agent::new(config.clone(), Arc::new(Mutex::new(library)));
+        let state = Arc::new(Mutex::new(AppState::new(config)));
+
+        assert_eq!(agent.warm_cache(&state), Some(0));
+        let running = BatchProgress { total: 3, ..Default::default() };
+        state.lock().unwrap().cache_warm = Some(running);
+        assert_eq!(agent.warm_cache(&state), None);
+        assert_eq!(state.lock().unwrap().cache_warm.unwrap().total, 3);
+    }
+
+    #[tokio::test]
+    async fn unpersisted_download_plays_without_a_library_entry() {
+        let dir = TempDir::new("no-persist");
+        let yt_dlp = test_util::fake_yt_dlp(dir.path(), FAKE_DOWNLOAD);
+        let file = FileConfig {
+            yt_dlp_path: Some(yt_dlp.display().to_string()),
+            persist_downloads: Some(false),
+            ..FileConfig::default()
+        };
+        let config = Arc::new(test_util::config(dir.path(), file));
+        let library = Library::load(config.library_path.clone(), None, false).unwrap();
+        let library = Arc::new(Mutex::new(library));
+        let agent = Agent::new(config.clone(), library.clone());
+        let state = Arc::new(Mutex::new(AppState::new(config.clone())));
+        let url = "https://www.youtube.com/watch?v=abc123";
+
+        agent.execute_tool("play_url", json!({ "url": url }), &state).await.unwrap();
+        let command = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Some(command) = state.lock().unwrap().pending_commands.pop() {
+                    return command;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let PlayerCommand::PlayFile { path, .. } = command else {
+            panic!("expected a play, got {:?}", command);
+        };
+        assert!(path.is_file());
+        assert!(library.lock().unwrap().entries().is_empty());
+        let s = state.lock().unwrap();
+        assert!(s.library.is_empty());
+        assert!(s.unsaved_downloads.contains_key(url));
+    }
+
+    #[tokio::test]
+    async fn format_override_reaches_file_and_library_entry() {
+        let dir = TempDir::new("format-override");
+        let yt_dlp = test_util::fake_yt_dlp(dir.path(), FAKE_DOWNLOAD);
+        let file = FileConfig {
+            yt_dlp_path: Some(yt_dlp.display().to_string()),
+            ..FileConfig::default()
+        };
+        let config = Arc::new(test_util::config(dir.path(), file));
+        let url = "https://www.youtube.com/watch?v=abc123";
+
+        let (path, meta) = downloader::download_song(url, &config, Some("flac"), |_| {})
+            .await
+            .unwrap();
+        assert_eq!(path, config.cache_dir.join("abc123.flac"));
+        assert!(path.is_file());
+
+        let library = Library::load(config.library_path.clone(), None, false).unwrap();
+        let library = Arc::new(Mutex::new(library));
+        let state = Arc::new(Mutex::new(AppState::new(config.clone())));
+        persist_to_library(&library, &meta, url, &config, &state);
+        let library = library.lock().unwrap();
+        let entry = library.find_by_url(url).unwrap();
+        assert_eq!(entry.file_path, "abc123.flac");
+        assert_eq!(entry.audio_format.as_deref(), Some("flac"));
+    }
+
+    #[test]
+    fn album_query_names_the_artist_when_known() {
+        let song = Song::new_queued("Money", "Pink Floyd", "https://youtu.be/x");
+        assert_eq!(
+            album_query(&song, "The Dark Side of the Moon", "Unknown"),
+            "Pink Floyd The Dark Side of the Moon"
+        );
+        let song = Song::new_queued("Money", "Unknown", "https://youtu.be/x");
+        assert_eq!(
+            album_query(&song, "The Dark Side of the Moon", "Unknown"),
+            "The Dark Side of the Moon"
+        );
+        assert_eq!(
+            downloader::album_search_url("Pink Floyd The Wall"),
+            "https://music.youtube.com/search?q=Pink+Floyd+The+Wall#albums"
+        );
+    }
+
+    #[test]
+    fn concurrent_persists_keep_library_and_panel_in_step() {
+        let dir = TempDir::new("persist");
+        let config = Arc::new(test_util::config(dir.path(), FileConfig::default()));
+        let library = Library::load(config.library_path.clone(), None, false).unwrap();
+        let library = Arc::new(Mutex::new(library));
+        let state = Arc::new(Mutex::new(AppState::new(config.clone())));
+
+        // Every song finishes twice, as a re-download would
+        let threads: Vec<_> = (0..32)
+            .map(|i| {
+                let (library, state, config) = (library.clone(), state.clone(), config.clone());
+                std::thread::spawn(move || {
+                    let id = format!("vid{}", i % 16);
+                    let url = format!("https://www.youtube.com/watch?v={}", id);
+                    persist_to_library(&library, &meta(&id), &url, &config, &state);
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut lib = library.lock().unwrap();
+        let mut saved: Vec<String> = lib.entries().iter().map(|e| e.url.clone()).collect();
+        let mut panel: Vec<String> =
+            state.lock().unwrap().library.iter().map(|song| song.url.clone()).collect();
+        saved.sort();
+        panel.sort();
+        assert_eq!(saved.len(), 16);
+        assert_eq!(saved, panel);
+
+        lib.save_if_dirty().unwrap();
+        let reloaded = Library::load(config.library_path.clone(), None, false).unwrap();
+        assert_eq!(reloaded.entries().len(), 16);
+    }
+
+    #[tokio::test]
+    async fn rationale_is_logged_and_the_tools_still_run() {
+        let dir = TempDir::new("rationale");
+        let api = FakeApi::start(vec![
+            anthropic_reply("Quieter for focus.", &[("set_volume", json!({ "level": 40 }))]),
+            anthropic_reply("Volume is at 40.", &[]),
+        ]);
+        let (agent, state) = api_agent(&dir, &api, FileConfig::default());
+
+        agent.handle_input("turn it down a bit", &state).await.unwrap();
+
+        let s = state.lock().unwrap();
+        let action = s.action_log.last().unwrap();
+        assert_eq!(action.request, "turn it down a bit");
+        assert_eq!(action.tools, ["set_volume"]);
+        assert_eq!(action.rationale.as_deref(), Some("Quieter for focus."));
+        assert!(matches!(s.pending_commands[..], [PlayerCommand::SetVolume(40)]));
+        assert_eq!(s.agent_reply.as_deref(), Some("Volume is at 40."));
+        // The confirmation round trip may only answer in text
+        let requests = api.requests();
+        assert_eq!(requests[0].header("x-api-key"), Some("test-key"));
+        assert!(requests[0].body.get("tool_choice").is_none());
+        assert_eq!(requests[1].body["tool_choice"], json!({ "type": "none" }));
+    }
+
+    #[tokio::test]
+    async fn a_rate_limited_key_hands_over_to_the_next() {
+        let dir = TempDir::new("key-rotation");
+        let api = FakeApi::start(vec![
+            (429, r#"{"error":"rate limited"}"#.to_string()),
+            anthropic_reply("", &[("set_volume", json!({ "level": 40 }))]),
+            anthropic_reply("Done.", &[]),
+        ]);
+        let file = FileConfig {
+            api_keys: Some(vec!["second-key".to_string()]),
+            ..FileConfig::default()
+        };
+        let (agent, state) = api_agent(&dir, &api, file);
+
+        agent.handle_input("quieter", &state).await.unwrap();
+
+        let keys: Vec<String> = api
+            .requests()
+            .iter()
+            .map(|request| request.header("x-api-key").unwrap().to_string())
+            .collect();
+        // No waiting on the limited key; the next request stays on the new one
+        assert_eq!(keys, ["test-key", "second-key", "second-key"]);
+        assert!(state.lock().unwrap().backoff.is_none());
+    }
+
+    #[test]
+    fn links_and_searches_skip_the_model_as_configured() {
+        let video = "https://youtu.be/abc123";
+        let url = |u: &str| Some(DirectInput::Url(u.to_string()));
+        // Video links always go straight to play_url, trimmed
+        assert_eq!(DirectInput::classify(&format!("  {} ", video), false), url(video));
+        assert_eq!(DirectInput::classify("chill lo-fi beats", false), None);
+        assert_eq!(DirectInput::classify("https://example.com/a.mp3", false), None);
+
+        assert_eq!(
+            DirectInput::classify("chill lo-fi beats", true),
+            Some(DirectInput::Search("chill lo-fi beats".to_string()))
+        );
+        let other = "https://soundcloud.com/artist/track";
+        assert_eq!(DirectInput::classify(other, true), url(other));
+        assert_eq!(DirectInput::classify("   ", true), None);
+    }
+
+    #[tokio::test]
+    async fn a_rejected_tool_call_is_reported_to_the_model_as_an_error() {
+        let dir = TempDir::new("rejected-call");
+        let api = FakeApi::start(vec![
+            anthropic_reply("", &[("set_volume", json!({ "level": 150 })), ("skip", json!({}))]),
+            anthropic_reply("That volume is out of range.", &[]),
+        ]);
+        let (agent, state) = api_agent(&dir, &api, FileConfig::default());
+        let volume = state.lock().unwrap().volume;
+
+        let err = agent.handle_input("max it out", &state).await.unwrap_err();
+        assert!(err.to_string().contains("invalid arguments for set_volume"), "{:#}", err);
+
+        let requests = api.requests();
+        assert_eq!(requests.len(), 2);
+        let messages = requests[1].body["messages"].as_array().unwrap();
+        let results = messages.last().unwrap()["content"].as_array().unwrap();
+        assert_eq!(results[0]["is_error"], true);
+        let content = results[0]["content"].as_str().unwrap();
+        assert!(content.starts_with("invalid arguments for set_volume"), "{}", content);
+        // Nothing after it runs
+        assert_eq!(results[1]["is_error"], true);
+        assert_eq!(state.lock().unwrap().volume, volume);
+    }
+
+    #[tokio::test]
+    async fn a_reply_without_tools_is_reprompted_once() {
+        let dir = TempDir::new("reprompt");
+        let api = FakeApi::start(vec![
+            anthropic_reply("What kind of jazz?", &[]),
+            anthropic_reply("Still not sure what you mean.", &[]),
+        ]);
+        let (agent, state) = api_agent(&dir, &api, FileConfig::default());
+
+        agent.handle_input("something jazzy", &state).await.unwrap();
+
+        let requests = api.requests();
+        assert_eq!(requests.len(), 2);
+        let messages = requests[1].body["messages"].as_array().unwrap();
+        assert_eq!(messages.last().unwrap()["content"], TOOL_REMINDER);
+        let reply = state.lock().unwrap().agent_reply.clone();
+        assert_eq!(reply.as_deref(), Some("Still not sure what you mean."));
+
+        let dir = TempDir::new("no-reprompt");
+        let api = FakeApi::start(vec![anthropic_reply("What kind of jazz?", &[])]);
+        let file = FileConfig { retry_empty_tool_calls: Some(false), ..FileConfig::default() };
+        let (agent, state) = api_agent(&dir, &api, file);
+        agent.handle_input("something jazzy", &state).await.unwrap();
+        assert_eq!(api.requests().len(), 1);
+        let reply = state.lock().unwrap().agent_reply.clone();
+        assert_eq!(reply.as_deref(), Some("What kind of jazz?"));
+    }
+
+    #[test]
+    fn malformed_tool_inputs_are_rejected() {
+        let rejected = [
+            ("play_url", json!({ "url": "not a link" })),
+            ("play_url", json!({ "url": "https://youtu.be/a", "format": "ogg" })),
+            ("search_and_queue", json!({ "query": " ", "count": 1 })),
+            ("search_and_queue", json!({ "query": "lofi", "count": 0 })),
+            ("replace_queue", json!({ "queries": [] })),
+            ("describe_collection", json!({ "what": "everything" })),
+            ("set_volume", json!({ "level": 101 })),
+            ("set_volume", json!({ "level": "loud" })),
+            ("set_speed", json!({ "speed": 10.0 })),
+            ("seek", json!({ "seconds": -1.0 })),
+            ("seek", json!({ "seconds": 10.0, "percent": 50.0 })),
+            ("seek", json!({ "percent": 150.0 })),
+            ("reorder_queue", json!({ "order": [0, 1] })),
+            ("remove_from_queue", json!({})),
+            ("remove_from_queue", json!({ "position": 0 })),
+            ("move_in_queue", json!({ "title": "a", "to": 0 })),
+            ("save_to_playlist", json!({ "name": "  " })),
+            ("skip", json!({ "extra": true })),
+            ("mute", json!({})),
+            ("launch_rockets", json!({})),
+        ];
+        for (name, input) in rejected {
+            let parsed = ToolCall::parse(name, input.clone());
+            assert!(parsed.is_err(), "{} {} parsed as {:?}", name, input, parsed);
+        }
+
+        // Positions the model gives are 1-based
+        assert_eq!(
+            ToolCall::parse("move_in_queue", json!({ "position": 3, "to": 1 })).unwrap(),
+            ToolCall::MoveInQueue { target: QueueTarget::Position(2), to: 0 }
+        );
+        assert_eq!(
+            ToolCall::parse("set_volume", json!({ "level": 40 })).unwrap(),
+            ToolCall::SetVolume { level: 40 }
+        );
+    }
+
+    #[tokio::test]
+    async fn clear_queue_reports_how_many_went() {
+        let dir = TempDir::new("clear-queue");
+        let (agent, state) = agent(&dir, FileConfig::default());
+        {
+            let mut s = state.lock().unwrap();
+            for id in ["a", "b"] {
+                s.queue.push(Song::new_queued(id, "Artist", &format!("https://youtu.be/{}", id)));
+            }
+            s.queue_cursor = 1;
+        }
+
+        agent.execute_tool("clear_queue", json!({}), &state).await.unwrap();
+        {
+            let s = state.lock().unwrap();
+            assert!(s.queue.is_empty());
+            assert_eq!(s.queue_cursor, 0);
+            assert_eq!(s.agent_reply.as_deref(), Some("Cleared 2 songs from the queue"));
+        }
+
+        agent.execute_tool("clear_queue", json!({}), &state).await.unwrap();
+        let reply = state.lock().unwrap().agent_reply.clone();
+        assert_eq!(reply.as_deref(), Some("The queue is already empty"));
+    }
+}
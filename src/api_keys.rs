@@ -0,0 +1,103 @@
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// The configured API keys, used one at a time. When the key in use is rate
+/// limited or rejected it cools down and the next one takes over; with a
+/// single key this never changes anything.
+pub struct ApiKeys {
+    keys: Vec<String>,
+    rotation: Mutex<Rotation>,
+}
+
+struct Rotation {
+    current: usize,
+    /// Per key: when it may be used again, if it's cooling down
+    cooling_until: Vec<Option<Instant>>,
+}
+
+impl ApiKeys {
+    /// `keys` must not be empty; `Config::load` refuses to start without one.
+    pub fn new(keys: Vec<String>) -> Self {
+        let rotation = Rotation {
+            current: 0,
+            cooling_until: vec![None; keys.len()],
+        };
+        Self {
+            keys,
+            rotation: Mutex::new(rotation),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Index and value of the key to send with: the current one unless it's
+    /// cooling down, then the next one that isn't. If every key is, the one
+    /// that comes back first.
+    pub fn pick(&self, now: Instant) -> (usize, &str) {
+        let mut rotation = self.rotation.lock().unwrap();
+        let index = rotation.next_ready(now).unwrap_or_else(|| {
+            (0..self.keys.len())
+                .min_by_key(|&i| rotation.cooling_until[i])
+                .unwrap_or(0)
+        });
+        rotation.current = index;
+        (index, &self.keys[index])
+    }
+
+    /// Rest key `index` until `until` and switch to the next key that isn't
+    /// cooling down. Returns `false` if there's none to switch to.
+    pub fn rotate(&self, index: usize, until: Instant, now: Instant) -> bool {
+        let mut rotation = self.rotation.lock().unwrap();
+        rotation.cooling_until[index] = Some(until);
+        match rotation.next_ready(now) {
+            Some(next) => {
+                rotation.current = next;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Rotation {
+    /// First key from `current` on, wrapping around, that isn't cooling down.
+    fn next_ready(&self, now: Instant) -> Option<usize> {
+        let len = self.cooling_until.len();
+        (0..len)
+            .map(|offset| (self.current + offset) % len)
+            .find(|&i| self.cooling_until[i].is_none_or(|until| until <= now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn limited_keys_rest_while_the_others_take_over() {
+        let keys = ApiKeys::new(vec!["a".to_string(), "b".to_string()]);
+        let now = Instant::now();
+        let later = now + Duration::from_secs(30);
+        assert_eq!(keys.pick(now), (0, "a"));
+
+        assert!(keys.rotate(0, later, now));
+        assert_eq!(keys.pick(now), (1, "b"));
+        // Both resting: the one back first, and no more switching
+        assert!(!keys.rotate(1, later + Duration::from_secs(30), now));
+        assert_eq!(keys.pick(now), (0, "a"));
+        // Once rested, a key is picked again
+        assert_eq!(keys.pick(later), (0, "a"));
+    }
+
+    #[test]
+    fn a_single_key_never_rotates() {
+        let keys = ApiKeys::new(vec!["only".to_string()]);
+        let now = Instant::now();
+        assert!(!keys.rotate(0, now + Duration::from_secs(30), now));
+        assert_eq!(keys.pick(now), (0, "only"));
+    }
+}
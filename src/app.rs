@@ -1,7 +1,18 @@
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use rand::rngs::StdRng;
+use rand::seq::{IndexedRandom, SliceRandom};
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
 use crate::audio_analysis::AudioFeatures;
+use crate::config::{Config, ReplayLimit};
+use crate::downloader::{self, Chapter};
+use crate::library::LibraryEntry;
+use crate::ui::album_art::AlbumArt;
 use crate::ui::visualizer::MatrixRain;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -9,8 +20,12 @@ pub enum SongStatus {
     Queued,
     Downloading,
     Ready,
+    #[allow(dead_code)]
     Playing,
+    #[allow(dead_code)]
     Played,
+    /// The download errored; holds a one-line reason
+    Failed(String),
 }
 
 #[derive(Debug, Clone)]
@@ -21,6 +36,17 @@ pub struct Song {
     pub file_path: Option<PathBuf>,
     pub status: SongStatus,
     pub duration: Option<Duration>,
+    /// Chapter markers, sorted by start; empty for most tracks
+    pub chapters: Vec<Chapter>,
+    /// Download fraction (0–1) while `Downloading`, once yt-dlp reports one
+    pub download_progress: Option<f32>,
+    /// Cover image shown in now playing, if one was downloaded
+    pub thumbnail: Option<PathBuf>,
+    /// Mirrors the library entry's flag
+    pub favorite: bool,
+    /// Library songs only, mirrored from their entry for sorting
+    pub downloaded_at: Option<String>,
+    pub play_count: u32,
 }
 
 impl Song {
@@ -32,7 +58,32 @@ impl Song {
             file_path: None,
             status: SongStatus::Queued,
             duration: None,
+            chapters: Vec::new(),
+            download_progress: None,
+            thumbnail: None,
+            favorite: false,
+            downloaded_at: None,
+            play_count: 0,
+        }
+    }
+
+    /// A ready-to-play song for a library entry, or `None` if its cached
+    /// file is gone or truncated.
+    pub fn cached(entry: &LibraryEntry, cache_dir: &Path) -> Option<Self> {
+        let cached_path = cache_dir.join(&entry.file_path);
+        if !downloader::is_valid_cached_file(&cached_path) {
+            return None;
         }
+        let mut song = Song::new_queued(&entry.title, &entry.artist, &entry.url);
+        song.file_path = Some(cached_path);
+        song.duration = Some(Duration::from_secs_f64(entry.duration_secs));
+        song.chapters = entry.chapters.clone();
+        song.thumbnail = entry.thumbnail_path(cache_dir);
+        song.favorite = entry.favorite;
+        song.downloaded_at = Some(entry.downloaded_at.clone());
+        song.play_count = entry.play_count;
+        song.status = SongStatus::Ready;
+        Some(song)
     }
 
     pub fn new_downloading(url: &str) -> Self {
@@ -43,6 +94,26 @@ impl Song {
             file_path: None,
             status: SongStatus::Downloading,
             duration: None,
+            chapters: Vec::new(),
+            download_progress: None,
+            thumbnail: None,
+            favorite: false,
+            downloaded_at: None,
+            play_count: 0,
+        }
+    }
+
+    /// Duration if the metadata actually provided one (yt-dlp can report 0).
+    pub fn known_duration(&self) -> Option<Duration> {
+        self.duration.filter(|d| *d > Duration::ZERO)
+    }
+
+    /// The artist to show next to the title, or `None` when it's unknown.
+    pub fn known_artist(&self, unknown: &str) -> Option<&str> {
+        if self.artist.is_empty() || self.artist == unknown {
+            None
+        } else {
+            Some(&self.artist)
         }
     }
 }
@@ -56,15 +127,359 @@ pub struct NowPlaying {
 }
 
 impl NowPlaying {
+    /// Wall-clock time since the track started, minus time spent paused.
     pub fn elapsed(&self) -> Duration {
-        if let Some(paused_at) = self.paused_at {
-            self.paused_elapsed + (paused_at - self.started_at) - self.paused_elapsed
+        let until = self.paused_at.unwrap_or_else(Instant::now);
+        until
+            .saturating_duration_since(self.started_at)
+            .saturating_sub(self.paused_elapsed)
+    }
+
+    pub fn pause(&mut self, now: Instant) {
+        self.paused_at.get_or_insert(now);
+    }
+
+    /// Fold the pause that just ended into `paused_elapsed`.
+    pub fn resume(&mut self, now: Instant) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.paused_elapsed += now.saturating_duration_since(paused_at);
+        }
+    }
+
+    /// One-line summary for pasting into chat, e.g.
+    /// `🎵 Title — Artist (url) at 1:23`. The timestamp is left off at the start.
+    pub fn share_text(&self, position: Duration) -> String {
+        let song = &self.song;
+        let mut text = format!("\u{1f3b5} {} \u{2014} {} ({})", song.title, song.artist, song.url);
+        let secs = position.as_secs();
+        if secs > 0 {
+            text.push_str(&format!(" at {}:{:02}", secs / 60, secs % 60));
+        }
+        text
+    }
+}
+
+/// How long an error stays on screen before it clears itself.
+pub const ERROR_DISPLAY_TIME: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// An error shown in the status bar until it expires. Kept apart from
+/// `status_message` so transient info can't overwrite it.
+#[derive(Debug, Clone)]
+pub struct ErrorNotice {
+    pub message: String,
+    pub severity: Severity,
+    /// Wall-clock time for display
+    pub timestamp: String,
+    pub raised_at: Instant,
+}
+
+impl ErrorNotice {
+    pub fn new(message: impl Into<String>, severity: Severity) -> Self {
+        Self {
+            message: message.into(),
+            severity,
+            timestamp: chrono::Local::now().format("%H:%M:%S").to_string(),
+            raised_at: Instant::now(),
+        }
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.raised_at) >= ERROR_DISPLAY_TIME
+    }
+}
+
+/// Failures kept in the tray; older ones are dropped.
+pub const FAILURE_TRAY_LIMIT: usize = 10;
+
+/// How to try a failed operation again.
+#[derive(Debug, Clone)]
+pub enum RetryAction {
+    /// Download into the queue again
+    Download { url: String },
+    /// Download (if needed) and play right away
+    PlayUrl { url: String },
+    /// Send the same request to the agent
+    AskAgent { input: String },
+    /// Open the downloaded file again
+    Play { song: Song },
+}
+
+impl RetryAction {
+    /// Two failures with the same target are one tray entry.
+    fn same_target(&self, other: &RetryAction) -> bool {
+        match (self, other) {
+            (RetryAction::Download { url: a }, RetryAction::Download { url: b })
+            | (RetryAction::PlayUrl { url: a }, RetryAction::PlayUrl { url: b })
+            | (RetryAction::AskAgent { input: a }, RetryAction::AskAgent { input: b }) => a == b,
+            (RetryAction::Play { song: a }, RetryAction::Play { song: b }) => a.url == b.url,
+            _ => false,
+        }
+    }
+}
+
+/// An entry in the recently-failed tray.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    /// What failed: a song title, link or request
+    pub item: String,
+    /// One line, for people
+    pub reason: String,
+    pub retry: RetryAction,
+    /// Wall-clock time for display
+    pub timestamp: String,
+}
+
+/// A track ending sooner than this after it started is suspicious.
+pub const MIN_PLAUSIBLE_PLAY: Duration = Duration::from_secs(1);
+/// Suspicious endings in a row before auto-advance gives up.
+pub const MAX_RAPID_ADVANCES: u32 = 3;
+
+/// Detects auto-advance spinning through tracks that end immediately
+/// (broken files, zero-length streams) instead of busy-looping.
+#[derive(Debug, Default)]
+pub struct RapidAdvanceGuard {
+    consecutive_short: u32,
+}
+
+impl RapidAdvanceGuard {
+    /// Record how long the track that just ended played for. Returns `true`
+    /// once auto-advance should stop.
+    pub fn record(&mut self, played: Duration) -> bool {
+        if played < MIN_PLAUSIBLE_PLAY {
+            self.consecutive_short += 1;
         } else {
-            self.started_at.elapsed() - self.paused_elapsed
+            self.consecutive_short = 0;
         }
+        self.consecutive_short >= MAX_RAPID_ADVANCES
+    }
+
+    pub fn reset(&mut self) {
+        self.consecutive_short = 0;
     }
 }
 
+/// Keeps auto-advance from replaying one track too often, e.g. when the
+/// agent keeps re-queueing it into a short queue.
+#[derive(Debug, Default)]
+pub struct ReplayThrottle {
+    /// Recent auto-advance start times per URL
+    plays: HashMap<String, Vec<Instant>>,
+}
+
+impl ReplayThrottle {
+    /// Whether `url` may play again under `limit` (always, without one).
+    pub fn allows(&self, url: &str, now: Instant, limit: Option<ReplayLimit>) -> bool {
+        let Some(limit) = limit else {
+            return true;
+        };
+        let recent = self.plays.get(url).map_or(0, |times| {
+            times
+                .iter()
+                .filter(|&&t| now.duration_since(t) < limit.window)
+                .count()
+        });
+        recent < limit.max_plays as usize
+    }
+
+    pub fn record(&mut self, url: &str, now: Instant, limit: Option<ReplayLimit>) {
+        let Some(limit) = limit else {
+            return;
+        };
+        let times = self.plays.entry(url.to_string()).or_default();
+        times.retain(|&t| now.duration_since(t) < limit.window);
+        times.push(now);
+    }
+}
+
+/// Seconds moved per column away from the playhead on Shift+click.
+pub const FINE_SEEK_STEP: Duration = Duration::from_secs(1);
+
+/// Map a progress-bar column to a track position, using the column's center
+/// so wide bars resolve to sub-second positions.
+pub fn column_to_position(column: u16, col_start: u16, col_end: u16, duration: Duration) -> Duration {
+    let width = col_end.saturating_sub(col_start).max(1) as f64;
+    let frac = ((column.saturating_sub(col_start) as f64 + 0.5) / width).clamp(0.0, 1.0);
+    Duration::from_secs_f64(frac * duration.as_secs_f64())
+}
+
+/// Fine seek: each column right/left of the playhead moves `FINE_SEEK_STEP`
+/// forward/back from the current position.
+pub fn fine_seek_position(
+    column: u16,
+    col_start: u16,
+    col_end: u16,
+    position: Duration,
+    duration: Duration,
+) -> Duration {
+    let width = col_end.saturating_sub(col_start).max(1) as f64;
+    let progress = (position.as_secs_f64() / duration.as_secs_f64()).clamp(0.0, 1.0);
+    let playhead = col_start as f64 + progress * width;
+    let offset = column as f64 - playhead.floor();
+    let target = position.as_secs_f64() + offset * FINE_SEEK_STEP.as_secs_f64();
+    Duration::from_secs_f64(target.clamp(0.0, duration.as_secs_f64()))
+}
+
+/// Within this long after a chapter starts, "previous chapter" goes to the
+/// one before rather than back to its start.
+pub const CHAPTER_RESTART_GRACE: Duration = Duration::from_secs(3);
+
+/// Start of the first chapter after `position`, if any.
+pub fn next_chapter(chapters: &[Chapter], position: Duration) -> Option<Duration> {
+    let pos = position.as_secs_f64();
+    chapters
+        .iter()
+        .find(|c| c.start_secs > pos + 0.5)
+        .map(|c| Duration::from_secs_f64(c.start_secs))
+}
+
+/// Start of the current chapter, or of the one before when `position` is
+/// still within `CHAPTER_RESTART_GRACE` of the current one's start.
+pub fn previous_chapter(chapters: &[Chapter], position: Duration) -> Option<Duration> {
+    if chapters.is_empty() {
+        return None;
+    }
+    let cutoff = position.saturating_sub(CHAPTER_RESTART_GRACE).as_secs_f64();
+    let start = chapters
+        .iter()
+        .rev()
+        .find(|c| c.start_secs < cutoff)
+        .map_or(0.0, |c| c.start_secs);
+    Some(Duration::from_secs_f64(start))
+}
+
+/// How far before the end `l` ("jump to live") lands.
+pub const LIVE_EDGE_BUFFER: Duration = Duration::from_secs(10);
+
+/// Seek target for jumping to the live edge: `buffer` before the end, or the
+/// start of tracks shorter than that. `None` when the duration is unknown.
+pub fn live_edge_position(duration: Option<Duration>, buffer: Duration) -> Option<Duration> {
+    duration.map(|d| d.saturating_sub(buffer))
+}
+
+/// A volume change eased in over `duration` instead of jumping.
+#[derive(Debug, Clone, Copy)]
+pub struct VolumeRamp {
+    from: u8,
+    to: u8,
+    start: Instant,
+    duration: Duration,
+}
+
+impl VolumeRamp {
+    pub fn new(from: u8, to: u8, start: Instant, duration: Duration) -> Self {
+        Self { from, to, start, duration }
+    }
+
+    /// Linearly interpolated level at `now`.
+    pub fn level_at(&self, now: Instant) -> u8 {
+        let t = (now.duration_since(self.start).as_secs_f64() / self.duration.as_secs_f64())
+            .clamp(0.0, 1.0);
+        let level = self.from as f64 + (self.to as f64 - self.from as f64) * t;
+        level.round() as u8
+    }
+
+    pub fn is_done(&self, now: Instant) -> bool {
+        now.duration_since(self.start) >= self.duration
+    }
+}
+
+/// Aggregate progress of a batch of downloads.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BatchProgress {
+    pub total: usize,
+    pub done: usize,
+    pub failed: usize,
+}
+
+/// A wait before retrying something, shown as a countdown.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    /// Why we're waiting, e.g. "rate limited"
+    pub reason: String,
+    pub until: Instant,
+}
+
+/// `"rate limited, retrying in 3s..."`. Seconds round up so the countdown
+/// never shows 0 while still waiting.
+pub fn countdown_text(reason: &str, until: Instant, now: Instant) -> String {
+    let left = until.saturating_duration_since(now);
+    if left.is_zero() {
+        return format!("{}, retrying...", reason);
+    }
+    let secs = left.as_millis().div_ceil(1000);
+    format!("{}, retrying in {}s...", reason, secs)
+}
+
+/// What auto-advance does when a track ends.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    /// Replay the current track.
+    One,
+    /// Requeue everything played this session once the queue runs dry.
+    All,
+}
+
+impl RepeatMode {
+    pub fn cycle(self) -> Self {
+        match self {
+            RepeatMode::Off => RepeatMode::One,
+            RepeatMode::One => RepeatMode::All,
+            RepeatMode::All => RepeatMode::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            RepeatMode::Off => "off",
+            RepeatMode::One => "one",
+            RepeatMode::All => "all",
+        }
+    }
+}
+
+/// A pending "stop after this track"; takes precedence over the repeat mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StopAfter {
+    /// Fade out over the last `STOP_FADE` of the track
+    pub fade: bool,
+}
+
+pub const STOP_FADE: Duration = Duration::from_secs(5);
+
+/// Playback speed range and the step the speed keys move by.
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 2.0;
+pub const SPEED_STEP: f32 = 0.25;
+
+/// Destructive actions that wait for a `y` before running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfirmAction {
+    ClearQueue,
+    /// Restore the last session's queue and track; `songs` counts both
+    ResumeSession { songs: usize },
+}
+
+/// Actions kept in `AppState::action_log`; older ones are dropped.
+pub const ACTION_LOG_LIMIT: usize = 20;
+
+/// One handled request: what the user asked, which tools ran, and the
+/// agent's stated reason for its picks, if it gave one.
+#[derive(Debug, Clone)]
+pub struct AgentAction {
+    pub request: String,
+    pub tools: Vec<String>,
+    pub rationale: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum AgentStatus {
     Idle,
@@ -72,6 +487,58 @@ pub enum AgentStatus {
     Acting(String),
 }
 
+/// Stage of a request on its way from the input bar to sound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestPhase {
+    Thinking,
+    /// Running tool calls, `done` of `total` finished
+    Acting { done: usize, total: usize },
+    /// Tools are done; waiting for the first song to download
+    Downloading,
+}
+
+/// Share of a request's progress bar each phase covers. Thinking is
+/// usually the longest wait, but a download can be longer still.
+const THINKING_WEIGHT: f32 = 0.3;
+const ACTING_WEIGHT: f32 = 0.2;
+
+impl RequestPhase {
+    /// Overall progress (0–1) at the start of this phase, or part way
+    /// through it: `download` is the first song's download fraction.
+    pub fn fraction(self, download: f32) -> f32 {
+        match self {
+            RequestPhase::Thinking => 0.0,
+            RequestPhase::Acting { done, total } => {
+                THINKING_WEIGHT + ACTING_WEIGHT * done as f32 / total.max(1) as f32
+            }
+            RequestPhase::Downloading => {
+                let download_weight = 1.0 - THINKING_WEIGHT - ACTING_WEIGHT;
+                THINKING_WEIGHT + ACTING_WEIGHT + download_weight * download.clamp(0.0, 1.0)
+            }
+        }
+    }
+
+    pub fn label(self) -> String {
+        match self {
+            RequestPhase::Thinking => "thinking".to_string(),
+            RequestPhase::Acting { done, total } => format!("step {}/{}", done + 1, total),
+            RequestPhase::Downloading => "downloading".to_string(),
+        }
+    }
+}
+
+/// A request in flight, until its first song is ready or starts playing.
+#[derive(Debug, Clone)]
+pub struct RequestProgress {
+    pub phase: RequestPhase,
+    /// Queue and track before the request, to tell what it added
+    queued_before: HashSet<String>,
+    playing_before: Option<String>,
+    generation_before: u64,
+    /// Songs the request queued that aren't ready yet
+    awaiting: Vec<String>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum InputMode {
     Normal,
@@ -84,11 +551,132 @@ pub enum FocusedPanel {
     Queue,
 }
 
+/// What the library panel lists.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LibraryView {
+    #[default]
+    Songs,
+    Playlists,
+}
+
+/// Order of the library panel's songs; the library itself keeps download
+/// order.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LibrarySort {
+    #[default]
+    Added,
+    Title,
+    Artist,
+    Recent,
+    MostPlayed,
+}
+
+impl LibrarySort {
+    pub fn next(self) -> Self {
+        match self {
+            LibrarySort::Added => LibrarySort::Title,
+            LibrarySort::Title => LibrarySort::Artist,
+            LibrarySort::Artist => LibrarySort::Recent,
+            LibrarySort::Recent => LibrarySort::MostPlayed,
+            LibrarySort::MostPlayed => LibrarySort::Added,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LibrarySort::Added => "added",
+            LibrarySort::Title => "title",
+            LibrarySort::Artist => "artist",
+            LibrarySort::Recent => "newest",
+            LibrarySort::MostPlayed => "most played",
+        }
+    }
+
+    /// Reorder `indices` into `songs` in place; ties keep their order.
+    pub fn apply(self, songs: &[Song], indices: &mut [usize]) {
+        let title = |i: &usize| songs[*i].title.to_lowercase();
+        match self {
+            LibrarySort::Added => {}
+            LibrarySort::Title => indices.sort_by_cached_key(title),
+            LibrarySort::Artist => {
+                indices.sort_by_cached_key(|i| (songs[*i].artist.to_lowercase(), title(i)))
+            }
+            // Newest first; songs without a date last
+            LibrarySort::Recent => indices.sort_by(|a, b| {
+                songs[*b].downloaded_at.cmp(&songs[*a].downloaded_at)
+            }),
+            LibrarySort::MostPlayed => {
+                indices.sort_by(|a, b| songs[*b].play_count.cmp(&songs[*a].play_count))
+            }
+        }
+    }
+}
+
+/// Bounds and step for resizing the visualizer with `[` / `]`.
+pub const MIN_VISUALIZER_PERCENT: u16 = 30;
+pub const MAX_VISUALIZER_PERCENT: u16 = 100;
+const VISUALIZER_STEP: u16 = 10;
+
+/// How the sidebar divides its height between library and queue.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SidebarSplit {
+    /// The configured library/queue percentages
+    #[default]
+    Balanced,
+    LibraryHeavy,
+    QueueHeavy,
+}
+
+impl SidebarSplit {
+    pub fn next(self) -> Self {
+        match self {
+            SidebarSplit::Balanced => SidebarSplit::LibraryHeavy,
+            SidebarSplit::LibraryHeavy => SidebarSplit::QueueHeavy,
+            SidebarSplit::QueueHeavy => SidebarSplit::Balanced,
+        }
+    }
+}
+
+/// Where the visualizer wave is drawn from.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WaveStyle {
+    /// A band mirrored around the vertical center
+    #[default]
+    Mirror,
+    /// Bars growing up from the bottom edge
+    Bottom,
+    /// Bars hanging down from the top edge
+    Top,
+}
+
+impl WaveStyle {
+    pub fn next(self) -> Self {
+        match self {
+            WaveStyle::Mirror => WaveStyle::Bottom,
+            WaveStyle::Bottom => WaveStyle::Top,
+            WaveStyle::Top => WaveStyle::Mirror,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct InputState {
     pub text: String,
     pub cursor: usize,
     pub mode: InputMode,
+    /// Tab-completion in progress; any edit ends it
+    completion: Option<Completion>,
+}
+
+/// Matches for the fragment that was typed when Tab was first pressed.
+#[derive(Debug, Clone)]
+struct Completion {
+    /// Byte range in `text` the current match occupies
+    start: usize,
+    end: usize,
+    matches: Vec<String>,
+    index: usize,
 }
 
 impl Default for InputState {
@@ -97,17 +685,93 @@ impl Default for InputState {
             text: String::new(),
             cursor: 0,
             mode: InputMode::Normal,
+            completion: None,
+        }
+    }
+}
+
+/// Live filter over the library panel, separate from the agent input.
+#[derive(Debug, Clone, Default)]
+pub struct LibraryFilter {
+    pub input: InputState,
+    /// Typing goes to the filter until Enter or Esc
+    pub editing: bool,
+}
+
+/// Indices of the songs whose title or artist contains `query`, ignoring
+/// case. An empty query keeps everything.
+pub fn filter_songs(songs: &[Song], query: &str) -> Vec<usize> {
+    let query = query.trim().to_lowercase();
+    songs
+        .iter()
+        .enumerate()
+        .filter(|(_, song)| {
+            query.is_empty()
+                || song.title.to_lowercase().contains(&query)
+                || song.artist.to_lowercase().contains(&query)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Candidates completing the end of `input`: each one starts with some
+/// trailing run of whole words (longest run first). Returns the byte offset
+/// of the fragment that matched and the matching candidates.
+pub fn completions(input: &str, candidates: &[String]) -> Option<(usize, Vec<String>)> {
+    let word_starts = input
+        .char_indices()
+        .filter(|&(i, c)| !c.is_whitespace() && (i == 0 || input[..i].ends_with(char::is_whitespace)))
+        .map(|(i, _)| i);
+    for start in word_starts {
+        let fragment = input[start..].to_lowercase();
+        let matches: Vec<String> = candidates
+            .iter()
+            .filter(|c| c.to_lowercase().starts_with(&fragment) && c.len() > fragment.len())
+            .cloned()
+            .collect();
+        if !matches.is_empty() {
+            return Some((start, matches));
         }
     }
+    None
 }
 
 impl InputState {
     pub fn insert(&mut self, c: char) {
+        self.completion = None;
         self.text.insert(self.cursor, c);
         self.cursor += c.len_utf8();
     }
 
+    /// Complete the input up to the cursor from `candidates`, replacing the
+    /// rest of the word under the cursor; repeated calls cycle through the
+    /// matches. Returns `false` if nothing matched.
+    pub fn autocomplete(&mut self, candidates: &[String]) -> bool {
+        let mut completion = match self.completion.take() {
+            Some(mut c) => {
+                c.index = (c.index + 1) % c.matches.len();
+                c
+            }
+            None => {
+                let before = &self.text[..self.cursor];
+                let Some((start, matches)) = completions(before, candidates) else {
+                    return false;
+                };
+                let after = &self.text[self.cursor..];
+                let word_end = after.find(char::is_whitespace).unwrap_or(after.len());
+                Completion { start, end: self.cursor + word_end, matches, index: 0 }
+            }
+        };
+        let completed = &completion.matches[completion.index];
+        self.text.replace_range(completion.start..completion.end, completed);
+        completion.end = completion.start + completed.len();
+        self.cursor = completion.end;
+        self.completion = Some(completion);
+        true
+    }
+
     pub fn backspace(&mut self) {
+        self.completion = None;
         if self.cursor > 0 {
             let prev = self.text[..self.cursor]
                 .char_indices()
@@ -120,6 +784,7 @@ impl InputState {
     }
 
     pub fn clear(&mut self) {
+        self.completion = None;
         self.text.clear();
         self.cursor = 0;
     }
@@ -134,79 +799,821 @@ impl InputState {
 /// Command from agent to the main loop (which owns the player)
 #[derive(Debug, Clone)]
 pub enum PlayerCommand {
+    /// `generation` is `AppState::play_generation` when the play was asked
+    /// for; a newer request makes it stale.
     PlayFile {
+        generation: u64,
         path: PathBuf,
         title: String,
         artist: String,
         url: String,
         duration_secs: f64,
+        chapters: Vec<Chapter>,
+        thumbnail: Option<PathBuf>,
+    },
+    /// Play a temporary preview clip, deleting the file once it's open.
+    /// `generation` works as for `PlayFile`.
+    PlayPreview {
+        generation: u64,
+        path: PathBuf,
+        title: String,
+        url: String,
     },
     Skip,
     Pause,
     Resume,
+    Previous,
     SetVolume(u8),
+    /// Mute (`true`) or unmute; a no-op when already so
+    Mute(bool),
+    SetSpeed(f32),
+    /// Jump to a position in the current track, clamped to its length
+    Seek(Duration),
+    /// Queue the whole library and start playing if nothing is
+    QueueLibrary { shuffle: bool },
 }
 
 pub struct AppState {
+    pub config: Arc<Config>,
     pub queue: Vec<Song>,
     pub library: Vec<Song>,
     pub current: Option<NowPlaying>,
     pub input: InputState,
     pub agent_status: AgentStatus,
+    /// Conversational reply from the agent, shown under the input bar
+    pub agent_reply: Option<String>,
+    /// Recent agent actions, oldest first
+    pub action_log: Vec<AgentAction>,
+    /// Target volume; the sink may still be fading towards it
     pub volume: u8,
+    pub volume_ramp: Option<VolumeRamp>,
+    /// Level to go back to; `Some` while muted, with `volume` at 0
+    pub muted_from: Option<u8>,
+    /// Playback speed factor, 1.0 being normal
+    pub speed: f32,
     pub paused: bool,
+    /// False when running on `NullPlayer` because no output device opened
+    pub audio_available: bool,
     pub audio_features: AudioFeatures,
     pub matrix_rain: MatrixRain,
+    /// Rendered cover of the current track
+    pub album_art: AlbumArt,
     pub should_quit: bool,
     pub pending_commands: Vec<PlayerCommand>,
-    /// Status message shown in the visualizer area (buffering, errors, etc.)
+    /// Status message shown in the visualizer area (buffering, loading, etc.)
     pub status_message: Option<String>,
+    /// Action awaiting confirmation; its prompt replaces the key hints
+    pub pending_confirm: Option<ConfirmAction>,
+    /// Most recent error, shown in the status bar until it expires
+    pub error: Option<ErrorNotice>,
+    /// The agent request being worked on, for the input bar's progress bar
+    pub request: Option<RequestProgress>,
+    /// Recent failures that can be retried, oldest first
+    pub failures: Vec<Failure>,
+    /// Failure tray clickable region: (first row, col_start, col_end, rows),
+    /// one row per failure, newest first
+    pub failure_tray_area: Option<(u16, u16, u16, u16)>,
     pub focused_panel: FocusedPanel,
+    pub sidebar_split: SidebarSplit,
+    /// Share of the left column given to the visualizer; now playing gets the rest
+    pub visualizer_percent: u16,
+    /// Index into `library`; kept on a song the filter shows
     pub library_cursor: usize,
+    pub library_filter: Option<LibraryFilter>,
+    pub library_sort: LibrarySort,
+    pub library_view: LibraryView,
+    /// Playlist names and lengths, mirrored from the library
+    pub playlists: Vec<(String, usize)>,
+    pub playlist_cursor: usize,
     pub queue_cursor: usize,
     pub playback_position: Duration,
     /// Progress bar clickable region: (row, col_start, col_end)
     pub progress_bar_area: Option<(u16, u16, u16)>,
+    /// Seek target under the mouse while hovering the progress bar
+    pub seek_hover: Option<Duration>,
+    /// Whether the terminal window has focus (terminals that don't report
+    /// focus changes stay `true`).
+    pub terminal_focused: bool,
+    /// Main-loop iteration counter, drives UI animations that must keep
+    /// moving even when the visualizer is idle (spinners).
+    pub ui_tick: u64,
+    /// Main visualizer hidden; a mini meter shows in the status bar instead
+    pub visualizer_hidden: bool,
+    pub wave_style: WaveStyle,
+    pub advance_guard: RapidAdvanceGuard,
+    pub replay_throttle: ReplayThrottle,
+    /// End of the silent gap before auto-advancing, once the track has ended
+    pub gap_until: Option<Instant>,
+    pub repeat: RepeatMode,
+    /// Bumped by every explicit play request, so a slow download finishing
+    /// late can't override a track picked after it
+    pub play_generation: u64,
+    pub shuffle: bool,
+    /// Armed by the agent: end playback when the current track finishes
+    /// instead of advancing
+    pub stop_after_current: Option<StopAfter>,
+    /// Picks shuffled songs; seeded from `config.shuffle_seed` when set
+    rng: StdRng,
+    /// Tracks that finished this session, oldest first; what `RepeatMode::All`
+    /// requeues.
+    pub played: Vec<Song>,
+    /// Recently replaced tracks, newest last, capped at `config.history_limit`
+    pub history: Vec<Song>,
+    /// URLs with a download task currently running
+    pub downloads_in_flight: HashSet<String>,
+    /// Downloads kept out of the library (`persist_downloads` off), by URL.
+    /// Saving one to a playlist adds it; the rest are deleted on quit.
+    pub unsaved_downloads: HashMap<String, LibraryEntry>,
+    /// Progress of an offline "download all" batch, if one is running
+    pub cache_warm: Option<BatchProgress>,
+    /// Set while waiting to retry after a failure
+    pub backoff: Option<Backoff>,
 }
 
 impl AppState {
-    pub fn new() -> Self {
+    pub fn new(config: Arc<Config>) -> Self {
+        let input = InputState {
+            mode: if config.start_in_input_mode {
+                InputMode::Editing
+            } else {
+                InputMode::Normal
+            },
+            ..InputState::default()
+        };
+        let visualizer_hidden = !config.show_visualizer;
+        let volume = config.default_volume;
+        let rng = config.shuffle_seed.map_or_else(StdRng::from_os_rng, StdRng::seed_from_u64);
         Self {
+            config,
             queue: Vec::new(),
             library: Vec::new(),
             current: None,
-            input: InputState::default(),
+            input,
             agent_status: AgentStatus::Idle,
-            volume: 70,
+            agent_reply: None,
+            action_log: Vec::new(),
+            volume,
+            muted_from: None,
+            speed: 1.0,
+            volume_ramp: None,
             paused: false,
+            audio_available: true,
             audio_features: AudioFeatures::default(),
             matrix_rain: MatrixRain::new(80, 24),
+            album_art: AlbumArt::default(),
             should_quit: false,
             pending_commands: Vec::new(),
             status_message: None,
+            pending_confirm: None,
+            error: None,
+            request: None,
+            failures: Vec::new(),
+            failure_tray_area: None,
             focused_panel: FocusedPanel::Library,
+            sidebar_split: SidebarSplit::Balanced,
+            visualizer_percent: MAX_VISUALIZER_PERCENT,
             library_cursor: 0,
+            library_filter: None,
+            library_sort: LibrarySort::default(),
+            library_view: LibraryView::Songs,
+            playlists: Vec::new(),
+            playlist_cursor: 0,
             queue_cursor: 0,
             playback_position: Duration::ZERO,
             progress_bar_area: None,
+            seek_hover: None,
+            terminal_focused: true,
+            ui_tick: 0,
+            visualizer_hidden,
+            wave_style: WaveStyle::default(),
+            advance_guard: RapidAdvanceGuard::default(),
+            replay_throttle: ReplayThrottle::default(),
+            gap_until: None,
+            repeat: RepeatMode::Off,
+            play_generation: 0,
+            shuffle: false,
+            stop_after_current: None,
+            rng,
+            played: Vec::new(),
+            history: Vec::new(),
+            downloads_in_flight: HashSet::new(),
+            unsaved_downloads: HashMap::new(),
+            cache_warm: None,
+            backoff: None,
+        }
+    }
+
+    pub fn report_error(&mut self, message: impl Into<String>) {
+        self.error = Some(ErrorNotice::new(message, Severity::Error));
+    }
+
+    pub fn report_warning(&mut self, message: impl Into<String>) {
+        self.error = Some(ErrorNotice::new(message, Severity::Warning));
+    }
+
+    pub fn begin_request(&mut self) {
+        self.request = Some(RequestProgress {
+            phase: RequestPhase::Thinking,
+            queued_before: self.queue.iter().map(|song| song.url.clone()).collect(),
+            playing_before: self.current.as_ref().map(|np| np.song.url.clone()),
+            generation_before: self.play_generation,
+            awaiting: Vec::new(),
+        });
+    }
+
+    pub fn set_request_phase(&mut self, phase: RequestPhase) {
+        if let Some(ref mut request) = self.request {
+            request.phase = phase;
+        }
+    }
+
+    /// The request's tools have run: wait for what they queued or started,
+    /// or finish now if they didn't download anything.
+    pub fn request_tools_done(&mut self) {
+        let Some(ref mut request) = self.request else {
+            return;
+        };
+        request.awaiting = self
+            .queue
+            .iter()
+            .filter(|song| !request.queued_before.contains(&song.url))
+            .filter(|song| song.status != SongStatus::Ready)
+            .map(|song| song.url.clone())
+            .collect();
+        let play_pending = self.play_generation != request.generation_before;
+        if request.awaiting.is_empty() && !play_pending {
+            self.request = None;
+        } else {
+            request.phase = RequestPhase::Downloading;
+        }
+        self.update_request();
+    }
+
+    /// End a downloading request once a new track plays, one of its songs
+    /// is ready, or all of them failed or were removed.
+    pub fn update_request(&mut self) {
+        let Some(ref request) = self.request else {
+            return;
+        };
+        if request.phase != RequestPhase::Downloading {
+            return;
+        }
+        let playing = self.current.as_ref().map(|np| &np.song.url);
+        let started = playing.is_some() && playing != request.playing_before.as_ref();
+        let awaited: Vec<&Song> = self
+            .queue
+            .iter()
+            .filter(|song| request.awaiting.contains(&song.url))
+            .collect();
+        let ready = awaited.iter().any(|song| song.status == SongStatus::Ready);
+        let play_pending = self.play_generation != request.generation_before;
+        let gave_up = !play_pending
+            && awaited.iter().all(|song| matches!(song.status, SongStatus::Failed(_)));
+        if started || ready || gave_up {
+            self.request = None;
+        }
+    }
+
+    /// Overall progress of the request in flight, if there is one.
+    pub fn request_fraction(&self) -> Option<f32> {
+        let request = self.request.as_ref()?;
+        let download = self
+            .queue
+            .iter()
+            .filter(|song| request.awaiting.contains(&song.url))
+            .filter_map(|song| song.download_progress)
+            .fold(0.0, f32::max);
+        Some(request.phase.fraction(download))
+    }
+
+    /// Add a failure to the tray, replacing an older one for the same thing.
+    pub fn record_failure(
+        &mut self,
+        item: impl Into<String>,
+        reason: impl Into<String>,
+        retry: RetryAction,
+    ) {
+        self.failures.retain(|f| !f.retry.same_target(&retry));
+        self.failures.push(Failure {
+            item: item.into(),
+            reason: reason.into(),
+            retry,
+            timestamp: chrono::Local::now().format("%H:%M:%S").to_string(),
+        });
+        let excess = self.failures.len().saturating_sub(FAILURE_TRAY_LIMIT);
+        self.failures.drain(..excess);
+    }
+
+    /// Take the failure shown `row` rows from the top of the tray (newest
+    /// first) out of it, to retry.
+    pub fn take_failure(&mut self, row: usize) -> Option<Failure> {
+        let index = self.failures.len().checked_sub(row + 1)?;
+        Some(self.failures.remove(index))
+    }
+
+    /// Tray row under (`column`, `row`), if any.
+    pub fn failure_row_at(&self, column: u16, row: u16) -> Option<usize> {
+        let (first_row, col_start, col_end, rows) = self.failure_tray_area?;
+        if row < first_row || row >= first_row + rows || column < col_start || column >= col_end {
+            return None;
+        }
+        Some((row - first_row) as usize)
+    }
+
+    /// Drop the current error once it has been visible long enough.
+    pub fn expire_error(&mut self, now: Instant) {
+        if self.error.as_ref().is_some_and(|e| e.is_expired(now)) {
+            self.error = None;
+        }
+    }
+
+    /// Where a click at (`column`, `row`) would seek to, if it's on the
+    /// progress bar of a track with a known duration.
+    pub fn seek_target_at(&self, column: u16, row: u16, fine: bool) -> Option<Duration> {
+        let (bar_row, col_start, col_end) = self.progress_bar_area?;
+        let np = self.current.as_ref()?;
+        if row != bar_row || column < col_start || column >= col_end {
+            return None;
+        }
+        // Absolute seeks need a length to be relative to
+        let duration = np.song.known_duration()?;
+        Some(if fine {
+            fine_seek_position(column, col_start, col_end, self.playback_position, duration)
+        } else {
+            column_to_position(column, col_start, col_end, duration)
+        })
+    }
+
+    pub fn log_action(&mut self, action: AgentAction) {
+        self.action_log.push(action);
+        if self.action_log.len() > ACTION_LOG_LIMIT {
+            self.action_log.remove(0);
+        }
+    }
+
+    /// Set the target volume, fading to it when `config.volume_fade` is set.
+    /// Any explicit level unmutes. Returns the level the sink should be at
+    /// right now.
+    pub fn change_volume(&mut self, level: u8, now: Instant) -> u8 {
+        self.muted_from = None;
+        let from = self.volume_ramp.map_or(self.volume, |r| r.level_at(now));
+        self.volume = level;
+        let fade = self.config.volume_fade;
+        if fade.is_zero() || from == level {
+            self.volume_ramp = None;
+            return level;
+        }
+        self.volume_ramp = Some(VolumeRamp::new(from, level, now, fade));
+        from
+    }
+
+    /// The level the user picked: what unmuting goes back to.
+    pub fn audible_volume(&self) -> u8 {
+        self.muted_from.unwrap_or(self.volume)
+    }
+
+    /// Clamp `speed` to the supported range and make it current. Returns
+    /// the factor the player should use.
+    pub fn set_speed(&mut self, speed: f32) -> f32 {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        self.speed
+    }
+
+    /// Mute or unmute. Returns the level to set the sink to, or `None` when
+    /// it was already muted (or not).
+    pub fn set_muted(&mut self, mute: bool, now: Instant) -> Option<u8> {
+        match (mute, self.muted_from) {
+            (true, None) => {
+                let level = self.volume;
+                let sink = self.change_volume(0, now);
+                self.muted_from = Some(level);
+                Some(sink)
+            }
+            (false, Some(level)) => Some(self.change_volume(level, now)),
+            _ => None,
         }
     }
 
+    /// Sink level for this tick while a fade is running.
+    pub fn tick_volume_ramp(&mut self, now: Instant) -> Option<u8> {
+        let ramp = self.volume_ramp?;
+        if ramp.is_done(now) {
+            self.volume_ramp = None;
+        }
+        Some(ramp.level_at(now))
+    }
+
+    /// Move the queued song at `from` to `to`, shifting the ones between.
+    /// Returns `false` if either index is out of range. Downloads find their
+    /// entry by URL, so reordering never confuses them.
+    pub fn move_queued(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.queue.len() || to >= self.queue.len() {
+            return false;
+        }
+        let song = self.queue.remove(from);
+        self.queue.insert(to, song);
+        true
+    }
+
+    /// Rearrange the queue so position `i` holds the song now at `order[i]`.
+    /// Returns `false`, leaving the queue alone, unless `order` names every
+    /// current index exactly once. The cursor stays on its song.
+    pub fn reorder_queue(&mut self, order: &[usize]) -> bool {
+        let len = self.queue.len();
+        let mut seen = vec![false; len];
+        let is_permutation = order.len() == len
+            && order.iter().all(|&i| i < len && !std::mem::replace(&mut seen[i], true));
+        if !is_permutation {
+            return false;
+        }
+        let mut songs: Vec<Option<Song>> = self.queue.drain(..).map(Some).collect();
+        self.queue = order.iter().filter_map(|&i| songs[i].take()).collect();
+        if let Some(pos) = order.iter().position(|&i| i == self.queue_cursor) {
+            self.queue_cursor = pos;
+        }
+        true
+    }
+
+    /// Record yt-dlp's progress for the queued download of `url`. It starts
+    /// over for each stream and post-processing step, so the shown value
+    /// only ever grows.
+    pub fn set_download_progress(&mut self, url: &str, fraction: f32) {
+        let fraction = fraction.clamp(0.0, 1.0);
+        for song in self.queue.iter_mut() {
+            if song.url == url && song.status == SongStatus::Downloading {
+                let shown = song.download_progress.unwrap_or(0.0);
+                song.download_progress = Some(fraction.max(shown));
+            }
+        }
+    }
+
+    /// Song under the cursor of the focused panel; none in the playlists view.
+    pub fn focused_song(&self) -> Option<&Song> {
+        match self.focused_panel {
+            FocusedPanel::Library if self.library_view == LibraryView::Playlists => None,
+            FocusedPanel::Library => self.selected_library_song(),
+            FocusedPanel::Queue => self.queue.get(self.queue_cursor),
+        }
+    }
+
+    /// Set the favorite flag on every copy of the song at `url`.
+    pub fn mark_favorite(&mut self, url: &str, favorite: bool) {
+        let current = self.current.as_mut().map(|np| &mut np.song);
+        for song in self.library.iter_mut().chain(self.queue.iter_mut()).chain(current) {
+            if song.url == url {
+                song.favorite = favorite;
+            }
+        }
+    }
+
+    /// Drop the song under the queue cursor.
+    pub fn remove_selected_queued(&mut self) -> Option<Song> {
+        self.remove_queued(self.queue_cursor)
+    }
+
+    /// Drop the queued song at `index`, if there is one.
+    pub fn remove_queued(&mut self, index: usize) -> Option<Song> {
+        if index >= self.queue.len() {
+            return None;
+        }
+        let song = self.queue.remove(index);
+        self.clamp_cursors();
+        Some(song)
+    }
+
+    /// Index of the first queued song whose title contains `query`,
+    /// ignoring case.
+    pub fn find_queued(&self, query: &str) -> Option<usize> {
+        let query = query.to_lowercase();
+        self.queue.iter().position(|song| song.title.to_lowercase().contains(&query))
+    }
+
+    /// Append every ready library song that isn't already queued or playing,
+    /// in library order unless `shuffle`. Returns how many were added.
+    pub fn enqueue_library(&mut self, shuffle: bool) -> usize {
+        let mut taken: HashSet<&str> = self.queue.iter().map(|song| song.url.as_str()).collect();
+        if let Some(ref np) = self.current {
+            taken.insert(&np.song.url);
+        }
+        let mut songs: Vec<Song> = self
+            .library
+            .iter()
+            .filter(|song| song.status == SongStatus::Ready && song.file_path.is_some())
+            .filter(|song| taken.insert(&song.url))
+            .cloned()
+            .collect();
+        if shuffle {
+            songs.shuffle(&mut self.rng);
+        }
+        let added = songs.len();
+        self.queue.extend(songs);
+        added
+    }
+
+    /// Empty the queue. Returns how many songs were removed.
+    pub fn clear_queue(&mut self) -> usize {
+        let removed = self.queue.len();
+        self.queue.clear();
+        self.clamp_cursors();
+        removed
+    }
+
+    /// Status-bar prompt for the pending confirmation, if any.
+    pub fn confirm_prompt(&self) -> Option<String> {
+        match self.pending_confirm? {
+            ConfirmAction::ClearQueue => Some(format!(
+                "Clear {} queued song{}? [y/N]",
+                self.queue.len(),
+                if self.queue.len() == 1 { "" } else { "s" }
+            )),
+            ConfirmAction::ResumeSession { songs } => Some(format!(
+                "Resume last session ({} song{})? [y/N]",
+                songs,
+                if songs == 1 { "" } else { "s" }
+            )),
+        }
+    }
+
+    /// Library titles and artists offered by Tab completion.
+    pub fn completion_candidates(&self) -> Vec<String> {
+        let mut candidates: Vec<String> = Vec::new();
+        for song in &self.library {
+            let artist = song.known_artist(&self.config.unknown_artist);
+            for c in std::iter::once(song.title.as_str()).chain(artist) {
+                if !candidates.iter().any(|existing| existing == c) {
+                    candidates.push(c.to_string());
+                }
+            }
+        }
+        candidates
+    }
+
+    /// Whether the visualizer should advance this tick. Playback itself is
+    /// unaffected; this only saves CPU while nobody is looking.
+    pub fn should_animate(&self) -> bool {
+        self.terminal_focused && self.current.is_some() && !self.paused
+    }
+
+    /// Whether auto-advance should keep waiting out `config.track_gap` after
+    /// the current track ended. The gap starts on the first call.
+    pub fn hold_for_gap(&mut self, now: Instant) -> bool {
+        let gap = self.config.track_gap;
+        if gap.is_zero() {
+            return false;
+        }
+        match self.gap_until {
+            None => {
+                self.gap_until = Some(now + gap);
+                true
+            }
+            Some(until) if now < until => true,
+            Some(_) => {
+                self.gap_until = None;
+                false
+            }
+        }
+    }
+
+    /// How long to crossfade for if it's time to start fading into the next
+    /// track, or `None` to let this one play out. Tracks shorter than twice
+    /// `config.crossfade` fade over half their length so they still get
+    /// heard; a fade is never longer than what's left to play. Nothing fades
+    /// in while paused.
+    pub fn crossfade_due(&self) -> Option<Duration> {
+        let fade = self.config.crossfade;
+        if fade.is_zero()
+            || self.paused
+            || self.stop_after_current.is_some()
+            || !self.has_next_auto_song()
+        {
+            return None;
+        }
+        let duration = self.current.as_ref()?.song.known_duration()?;
+        // Positions are in track time; the fade is in real time
+        let window = fade.min(duration.div_f32(self.speed) / 2);
+        let remaining = duration.saturating_sub(self.playback_position).div_f32(self.speed);
+        (remaining <= window && !remaining.is_zero()).then_some(remaining)
+    }
+
+    /// Whether `next_auto_song` would find something to play.
+    fn has_next_auto_song(&self) -> bool {
+        let now = Instant::now();
+        let limit = self.config.replay_limit;
+        let playable = |song: &Song| {
+            song.status == SongStatus::Ready && self.replay_throttle.allows(&song.url, now, limit)
+        };
+        let repeat_one = self.repeat == RepeatMode::One
+            && self.current.as_ref().is_some_and(|np| np.song.file_path.is_some());
+        let repeat_all = self.repeat == RepeatMode::All && self.played.iter().any(playable);
+        repeat_one || self.queue.iter().any(playable) || repeat_all
+    }
+
+    /// Next song for auto-advance: the first ready one, or a random one when
+    /// shuffling. Songs over the replay limit are passed over (left in the
+    /// queue) in favour of later ones.
     pub fn next_ready_song(&mut self) -> Option<Song> {
-        if let Some(pos) = self.queue.iter().position(|s| s.status == SongStatus::Ready) {
-            let song = self.queue.remove(pos);
-            self.clamp_cursors();
-            Some(song)
+        let now = Instant::now();
+        let limit = self.config.replay_limit;
+        let mut ready = self.queue.iter().enumerate().filter(|(_, s)| {
+            s.status == SongStatus::Ready && self.replay_throttle.allows(&s.url, now, limit)
+        });
+        let pos = if self.shuffle {
+            let ready: Vec<usize> = ready.map(|(i, _)| i).collect();
+            *ready.choose(&mut self.rng)?
         } else {
-            None
+            ready.next()?.0
+        };
+        let song = self.queue.remove(pos);
+        self.clamp_cursors();
+        self.replay_throttle.record(&song.url, now, limit);
+        Some(song)
+    }
+
+    /// Start a new explicit play request, superseding any still pending.
+    pub fn begin_play_request(&mut self) -> u64 {
+        self.play_generation += 1;
+        self.play_generation
+    }
+
+    /// Whether a play asked for at `generation` has since been overtaken by
+    /// a newer request.
+    pub fn play_superseded(&self, generation: u64) -> bool {
+        generation != self.play_generation
+    }
+
+    /// Make `song` the current track, remembering the one it replaces.
+    /// Replaying the same track (repeat one) isn't recorded again.
+    pub fn start_playing(&mut self, song: Song) {
+        if self.current.as_ref().is_some_and(|np| np.song.url != song.url) {
+            self.retire_current();
+        }
+        self.current = Some(NowPlaying {
+            song,
+            started_at: Instant::now(),
+            paused_elapsed: Duration::ZERO,
+            paused_at: None,
+        });
+        self.paused = false;
+    }
+
+    /// Play a preview clip in place of the current track, which goes back to
+    /// the front of the queue to pick up after it.
+    pub fn start_preview(&mut self, song: Song) {
+        let outgoing = self.current.take();
+        self.requeue(outgoing);
+        self.start_playing(song);
+    }
+
+    /// Sink level while fading out ahead of an armed stop, if in the fade
+    /// window. Needs a known duration.
+    pub fn stop_fade_level(&self) -> Option<u8> {
+        self.stop_after_current.filter(|stop| stop.fade)?;
+        let duration = self.current.as_ref()?.song.duration?;
+        // Positions are in track time; the fade is in real time
+        let remaining = duration.saturating_sub(self.playback_position).div_f32(self.speed);
+        if remaining >= STOP_FADE {
+            return None;
+        }
+        let level = self.volume as f32 * remaining.as_secs_f32() / STOP_FADE.as_secs_f32();
+        Some(level.round() as u8)
+    }
+
+    /// Called when the track ends: if a stop is armed, disarm it and end
+    /// playback. Returns whether it did.
+    pub fn stop_if_armed(&mut self) -> bool {
+        if self.stop_after_current.take().is_none() {
+            return false;
+        }
+        self.retire_current();
+        true
+    }
+
+    /// Move the current track into the history and the repeat-all list.
+    /// Previews have no file and can't be replayed, so they're dropped.
+    fn retire_current(&mut self) {
+        let Some(np) = self.current.take().filter(|np| np.song.file_path.is_some()) else {
+            return;
+        };
+        self.history.push(np.song.clone());
+        let excess = self.history.len().saturating_sub(self.config.history_limit);
+        self.history.drain(..excess);
+        self.played.push(np.song);
+    }
+
+    /// Drop the playing track for a skip; it goes to the history like one
+    /// that played out.
+    pub fn skip_current(&mut self) {
+        self.retire_current();
+    }
+
+    /// The track stepping back would play: the last one in the history.
+    pub fn previous_song(&self) -> Option<Song> {
+        self.history.last().cloned()
+    }
+
+    /// Step back one track once `previous_song` has started in place of
+    /// `outgoing`: pops the history and puts `outgoing` back at the front of
+    /// the queue, so repeated presses walk further back and `next` returns
+    /// to where you were.
+    pub fn stepped_back(&mut self, outgoing: Option<NowPlaying>) {
+        self.history.pop();
+        self.requeue(outgoing);
+    }
+
+    /// Put a track that was cut off back at the front of the queue, unless
+    /// there's no file to play it from.
+    fn requeue(&mut self, np: Option<NowPlaying>) {
+        if let Some(np) = np.filter(|np| np.song.file_path.is_some()) {
+            self.queue.insert(0, np.song);
+            self.clamp_cursors();
+        }
+    }
+
+    /// Song to follow the track that just ended, honoring the repeat mode.
+    pub fn next_auto_song(&mut self) -> Option<Song> {
+        if self.repeat == RepeatMode::One {
+            if let Some(np) = self.current.as_ref().filter(|np| np.song.file_path.is_some()) {
+                return Some(np.song.clone());
+            }
+        }
+        self.retire_current();
+        if let Some(song) = self.next_ready_song() {
+            return Some(song);
+        }
+        if self.repeat == RepeatMode::All && !self.played.is_empty() {
+            self.queue.append(&mut self.played);
+            return self.next_ready_song();
+        }
+        None
+    }
+
+    /// Pause or resume, keeping the current track's timing in step.
+    pub fn set_paused(&mut self, paused: bool, now: Instant) {
+        self.paused = paused;
+        if let Some(ref mut np) = self.current {
+            if paused {
+                np.pause(now);
+            } else {
+                np.resume(now);
+            }
+        }
+    }
+
+    /// Reset the current track's timing back to the start. Returns `false`
+    /// when nothing is playing so the caller can skip the seek.
+    pub fn restart_current(&mut self) -> bool {
+        let Some(ref mut np) = self.current else {
+            return false;
+        };
+        np.started_at = Instant::now();
+        np.paused_elapsed = Duration::ZERO;
+        np.paused_at = if self.paused { Some(np.started_at) } else { None };
+        self.playback_position = Duration::ZERO;
+        true
+    }
+
+    /// Library indices the panel lists, in order.
+    pub fn visible_library(&self) -> Vec<usize> {
+        let query = self.library_filter.as_ref().map_or("", |f| f.input.text.as_str());
+        let mut listed = filter_songs(&self.library, query);
+        self.library_sort.apply(&self.library, &mut listed);
+        listed
+    }
+
+    /// Count a play on the library's copy of `url`, for sorting.
+    pub fn count_library_play(&mut self, url: &str) {
+        if let Some(song) = self.library.iter_mut().find(|song| song.url == url) {
+            song.play_count = song.play_count.saturating_add(1);
+        }
+    }
+
+    /// The library song under the cursor, unless the filter hides it.
+    pub fn selected_library_song(&self) -> Option<&Song> {
+        let visible = self.visible_library();
+        visible.contains(&self.library_cursor).then(|| &self.library[self.library_cursor])
+    }
+
+    /// Move the library cursor onto the first listed song if the filter
+    /// hides the one it's on.
+    pub fn snap_library_cursor(&mut self) {
+        let visible = self.visible_library();
+        if !visible.contains(&self.library_cursor) {
+            if let Some(&first) = visible.first() {
+                self.library_cursor = first;
+            }
         }
     }
 
     pub fn move_cursor_up(&mut self) {
         match self.focused_panel {
+            FocusedPanel::Library if self.library_view == LibraryView::Playlists => {
+                self.playlist_cursor = self.playlist_cursor.saturating_sub(1);
+            }
             FocusedPanel::Library => {
-                if self.library_cursor > 0 {
-                    self.library_cursor -= 1;
+                let visible = self.visible_library();
+                let row = visible.iter().position(|&i| i == self.library_cursor);
+                if let Some(&prev) = row.and_then(|row| visible.get(row.checked_sub(1)?)) {
+                    self.library_cursor = prev;
                 }
             }
             FocusedPanel::Queue => {
@@ -219,9 +1626,16 @@ impl AppState {
 
     pub fn move_cursor_down(&mut self) {
         match self.focused_panel {
+            FocusedPanel::Library if self.library_view == LibraryView::Playlists => {
+                if !self.playlists.is_empty() {
+                    self.playlist_cursor = (self.playlist_cursor + 1).min(self.playlists.len() - 1);
+                }
+            }
             FocusedPanel::Library => {
-                if !self.library.is_empty() {
-                    self.library_cursor = (self.library_cursor + 1).min(self.library.len() - 1);
+                let visible = self.visible_library();
+                let row = visible.iter().position(|&i| i == self.library_cursor);
+                if let Some(&next) = row.and_then(|row| visible.get(row + 1)) {
+                    self.library_cursor = next;
                 }
             }
             FocusedPanel::Queue => {
@@ -240,12 +1654,24 @@ impl AppState {
         self.focused_panel = FocusedPanel::Queue;
     }
 
+    /// Grow (`grow`) or shrink the visualizer by one step, within bounds.
+    pub fn resize_visualizer(&mut self, grow: bool) {
+        let percent = if grow {
+            self.visualizer_percent + VISUALIZER_STEP
+        } else {
+            self.visualizer_percent.saturating_sub(VISUALIZER_STEP)
+        };
+        self.visualizer_percent = percent.clamp(MIN_VISUALIZER_PERCENT, MAX_VISUALIZER_PERCENT);
+    }
+
     pub fn clamp_cursors(&mut self) {
         if self.library.is_empty() {
             self.library_cursor = 0;
         } else {
             self.library_cursor = self.library_cursor.min(self.library.len() - 1);
+            self.snap_library_cursor();
         }
+        self.playlist_cursor = self.playlist_cursor.min(self.playlists.len().saturating_sub(1));
         if self.queue.is_empty() {
             self.queue_cursor = 0;
         } else {
@@ -253,3 +1679,527 @@ impl AppState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FileConfig;
+    use crate::test_util::{self, TempDir};
+
+    fn new_state(dir: &TempDir) -> AppState {
+        AppState::new(Arc::new(test_util::config(dir.path(), FileConfig::default())))
+    }
+
+    /// A downloaded song named `title`.
+    fn ready(title: &str) -> Song {
+        let mut song = Song::new_queued(title, "Artist", &format!("https://youtu.be/{}", title));
+        song.file_path = Some(PathBuf::from(format!("{}.mp3", title)));
+        song.status = SongStatus::Ready;
+        song
+    }
+
+    fn titles(songs: &[Song]) -> Vec<&str> {
+        songs.iter().map(|song| song.title.as_str()).collect()
+    }
+
+    #[test]
+    fn countdown_rounds_up_and_ends_at_retrying() {
+        let now = Instant::now();
+        let text = |left| countdown_text("rate limited", now + left, now);
+        assert_eq!(text(Duration::from_millis(2500)), "rate limited, retrying in 3s...");
+        assert_eq!(text(Duration::from_secs(3)), "rate limited, retrying in 3s...");
+        assert_eq!(text(Duration::from_millis(1)), "rate limited, retrying in 1s...");
+        assert_eq!(text(Duration::ZERO), "rate limited, retrying...");
+        // Past the target while the retry is still starting
+        assert_eq!(countdown_text("busy", now, now + Duration::from_secs(1)), "busy, retrying...");
+    }
+
+    #[test]
+    fn replay_throttle_counts_plays_inside_the_window() {
+        let limit = Some(ReplayLimit { max_plays: 2, window: Duration::from_secs(60) });
+        let start = Instant::now();
+        let mut throttle = ReplayThrottle::default();
+        assert!(throttle.allows("a", start, limit));
+
+        throttle.record("a", start, limit);
+        throttle.record("a", start + Duration::from_secs(10), limit);
+        assert!(!throttle.allows("a", start + Duration::from_secs(20), limit));
+        // Other tracks, and no limit at all, aren't affected
+        assert!(throttle.allows("b", start + Duration::from_secs(20), limit));
+        assert!(throttle.allows("a", start + Duration::from_secs(20), None));
+        // The first play ages out of the window
+        assert!(throttle.allows("a", start + Duration::from_secs(61), limit));
+    }
+
+    #[test]
+    fn filter_songs_matches_title_or_artist_ignoring_case() {
+        let mut songs = vec![ready("Blue Monday"), ready("Karma Police"), ready("Hey Jude")];
+        songs[1].artist = "Radiohead".to_string();
+
+        assert_eq!(filter_songs(&songs, "  "), [0, 1, 2]);
+        assert_eq!(filter_songs(&songs, "MONDAY"), [0]);
+        assert_eq!(filter_songs(&songs, "radio"), [1]);
+        assert_eq!(filter_songs(&songs, "e"), [0, 1, 2]);
+        assert!(filter_songs(&songs, "zeppelin").is_empty());
+    }
+
+    #[test]
+    fn reorder_queue_applies_a_permutation() {
+        let dir = TempDir::new("reorder");
+        let mut state = new_state(&dir);
+        state.queue = vec![ready("a"), ready("b"), ready("c")];
+        state.queue_cursor = 0;
+
+        assert!(state.reorder_queue(&[2, 0, 1]));
+        assert_eq!(titles(&state.queue), ["c", "a", "b"]);
+        // The cursor follows its song
+        assert_eq!(state.queue_cursor, 1);
+    }
+
+    #[test]
+    fn reorder_queue_rejects_anything_but_a_permutation() {
+        let dir = TempDir::new("reorder-invalid");
+        let mut state = new_state(&dir);
+        state.queue = vec![ready("a"), ready("b"), ready("c")];
+
+        for order in [&[0, 1][..], &[0, 1, 1], &[0, 1, 3], &[0, 1, 2, 0]] {
+            assert!(!state.reorder_queue(order), "{:?}", order);
+            assert_eq!(titles(&state.queue), ["a", "b", "c"]);
+        }
+    }
+
+    #[test]
+    fn animates_only_while_focused_and_playing() {
+        let dir = TempDir::new("animate");
+        let mut state = new_state(&dir);
+        assert!(!state.should_animate());
+
+        state.start_playing(ready("a"));
+        assert!(state.should_animate());
+        state.terminal_focused = false;
+        assert!(!state.should_animate());
+        state.terminal_focused = true;
+        state.paused = true;
+        assert!(!state.should_animate());
+    }
+
+    #[test]
+    fn errors_clear_once_shown_long_enough() {
+        let dir = TempDir::new("error-expiry");
+        let mut state = new_state(&dir);
+        state.report_error("Download error");
+        let raised = state.error.as_ref().unwrap().raised_at;
+
+        state.expire_error(raised + ERROR_DISPLAY_TIME - Duration::from_millis(1));
+        assert!(state.error.is_some());
+        state.expire_error(raised + ERROR_DISPLAY_TIME);
+        assert!(state.error.is_none());
+        // A clock reading from before it was raised doesn't expire it
+        state.report_warning("Config: bad value");
+        let raised = state.error.as_ref().unwrap().raised_at;
+        assert!(!state.error.as_ref().unwrap().is_expired(raised - Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn rapid_advance_trips_only_on_consecutive_short_plays() {
+        let short = MIN_PLAUSIBLE_PLAY - Duration::from_millis(1);
+        let mut guard = RapidAdvanceGuard::default();
+        for _ in 1..MAX_RAPID_ADVANCES {
+            assert!(!guard.record(short));
+        }
+        // A track that really played breaks the run
+        assert!(!guard.record(MIN_PLAUSIBLE_PLAY));
+        for _ in 1..MAX_RAPID_ADVANCES {
+            assert!(!guard.record(short));
+        }
+        assert!(guard.record(short));
+
+        guard.reset();
+        assert!(!guard.record(short));
+    }
+
+    #[test]
+    fn input_mode_starts_as_configured() {
+        let dir = TempDir::new("start-mode");
+        assert_eq!(new_state(&dir).input.mode, InputMode::Normal);
+        let file = FileConfig { start_in_input_mode: Some(true), ..FileConfig::default() };
+        let state = AppState::new(Arc::new(test_util::config(dir.path(), file)));
+        assert_eq!(state.input.mode, InputMode::Editing);
+    }
+
+    #[test]
+    fn wide_bars_seek_to_sub_second_positions() {
+        // 200 columns over 100s: half a second each, taken at the center
+        let duration = Duration::from_secs(100);
+        assert_eq!(column_to_position(10, 10, 210, duration), Duration::from_millis(250));
+        assert_eq!(column_to_position(11, 10, 210, duration), Duration::from_millis(750));
+        assert_eq!(column_to_position(209, 10, 210, duration), Duration::from_millis(99_750));
+
+        // Fine seeks step a second per column from the playhead at column 110
+        let position = Duration::from_secs(50);
+        let fine = |column| fine_seek_position(column, 10, 210, position, duration);
+        assert_eq!(fine(113), Duration::from_secs(53));
+        assert_eq!(fine(108), Duration::from_secs(48));
+        assert_eq!(fine(10), Duration::ZERO);
+    }
+
+    fn now_playing(song: Song, started_at: Instant) -> NowPlaying {
+        NowPlaying { song, started_at, paused_elapsed: Duration::ZERO, paused_at: None }
+    }
+
+    #[test]
+    fn request_phases_fill_the_bar_in_order() {
+        assert_eq!(RequestPhase::Thinking.fraction(0.5), 0.0);
+        let acting = |done| RequestPhase::Acting { done, total: 2 }.fraction(0.0);
+        assert_eq!(acting(0), THINKING_WEIGHT);
+        assert_eq!(acting(1), THINKING_WEIGHT + ACTING_WEIGHT / 2.0);
+        let downloading = |fraction| RequestPhase::Downloading.fraction(fraction);
+        assert_eq!(downloading(0.0), THINKING_WEIGHT + ACTING_WEIGHT);
+        assert_eq!(downloading(1.0), 1.0);
+        assert_eq!(downloading(7.0), 1.0);
+        assert_eq!(RequestPhase::Acting { done: 0, total: 0 }.fraction(0.0), THINKING_WEIGHT);
+        assert_eq!(RequestPhase::Acting { done: 1, total: 3 }.label(), "step 2/3");
+    }
+
+    #[test]
+    fn a_request_finishes_when_its_first_song_is_ready() {
+        let dir = TempDir::new("request-progress");
+        let mut state = new_state(&dir);
+        state.begin_request();
+        assert_eq!(state.request_fraction(), Some(0.0));
+
+        let mut song = Song::new_queued("a", "Artist", "https://youtu.be/a");
+        song.status = SongStatus::Downloading;
+        song.download_progress = Some(0.5);
+        state.queue.push(song);
+        state.request_tools_done();
+        let halfway = RequestPhase::Downloading.fraction(0.5);
+        assert_eq!(state.request_fraction(), Some(halfway));
+
+        state.queue[0].status = SongStatus::Ready;
+        state.update_request();
+        assert_eq!(state.request_fraction(), None);
+
+        // Nothing to download: done as soon as the tools are
+        state.begin_request();
+        state.request_tools_done();
+        assert!(state.request.is_none());
+    }
+
+    #[test]
+    fn the_failure_tray_keeps_one_entry_per_target() {
+        let dir = TempDir::new("failure-tray");
+        let mut state = new_state(&dir);
+        let download = |url: &str| RetryAction::Download { url: url.to_string() };
+        state.record_failure("a", "first", download("a"));
+        state.record_failure("b", "other", download("b"));
+        state.record_failure("a", "again", download("a"));
+        let items: Vec<&str> = state.failures.iter().map(|f| f.reason.as_str()).collect();
+        assert_eq!(items, ["other", "again"]);
+
+        for i in 0..FAILURE_TRAY_LIMIT + 3 {
+            state.record_failure(i.to_string(), "failed", download(&i.to_string()));
+        }
+        assert_eq!(state.failures.len(), FAILURE_TRAY_LIMIT);
+        // Row 0 is the newest
+        let newest = (FAILURE_TRAY_LIMIT + 2).to_string();
+        assert_eq!(state.take_failure(0).unwrap().item, newest);
+        assert!(state.take_failure(FAILURE_TRAY_LIMIT).is_none());
+    }
+
+    #[test]
+    fn an_armed_stop_fades_out_and_ends_playback_once() {
+        let dir = TempDir::new("stop-after-current");
+        let mut state = new_state(&dir);
+        let mut song = ready("a");
+        song.duration = Some(Duration::from_secs(100));
+        state.start_playing(song);
+        state.queue = vec![ready("b")];
+        state.volume = 80;
+        state.stop_after_current = Some(StopAfter { fade: true });
+
+        state.playback_position = Duration::from_secs(90);
+        assert_eq!(state.stop_fade_level(), None);
+        state.playback_position = Duration::from_millis(97_500);
+        assert_eq!(state.stop_fade_level(), Some(40));
+
+        assert!(state.stop_if_armed());
+        assert!(state.current.is_none());
+        assert_eq!(titles(&state.history), ["a"]);
+        // The queue waits for the next play
+        assert_eq!(titles(&state.queue), ["b"]);
+        assert!(!state.stop_if_armed());
+    }
+
+    #[test]
+    fn moving_a_queued_song_shifts_the_ones_between() {
+        let dir = TempDir::new("move-queued");
+        let mut state = new_state(&dir);
+        state.queue = vec![ready("a"), ready("b"), ready("c"), ready("d")];
+
+        assert!(state.move_queued(0, 2));
+        assert_eq!(titles(&state.queue), ["b", "c", "a", "d"]);
+        assert!(state.move_queued(3, 0));
+        assert_eq!(titles(&state.queue), ["d", "b", "c", "a"]);
+        assert!(state.move_queued(1, 1));
+        assert!(!state.move_queued(4, 0));
+        assert!(!state.move_queued(0, 4));
+        assert_eq!(titles(&state.queue), ["d", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn removing_the_last_queued_song_keeps_the_cursor_on_the_queue() {
+        let dir = TempDir::new("remove-queued");
+        let mut state = new_state(&dir);
+        state.queue = vec![ready("a"), ready("b"), ready("c")];
+        state.queue_cursor = 2;
+
+        assert_eq!(state.remove_selected_queued().unwrap().title, "c");
+        assert_eq!(state.queue_cursor, 1);
+        assert_eq!(state.remove_selected_queued().unwrap().title, "b");
+        assert_eq!(state.remove_selected_queued().unwrap().title, "a");
+        assert_eq!(state.queue_cursor, 0);
+        assert!(state.remove_selected_queued().is_none());
+    }
+
+    #[test]
+    fn enqueue_library_adds_each_ready_song_once() {
+        let dir = TempDir::new("enqueue-library");
+        let mut state = new_state(&dir);
+        let mut pending = ready("d");
+        pending.status = SongStatus::Downloading;
+        state.library = vec![ready("a"), ready("b"), ready("c"), pending, ready("e")];
+        state.queue = vec![ready("b")];
+        state.current = Some(now_playing(ready("c"), Instant::now()));
+
+        assert_eq!(state.enqueue_library(false), 2);
+        assert_eq!(titles(&state.queue), ["b", "a", "e"]);
+        // Everything ready is already queued
+        assert_eq!(state.enqueue_library(true), 0);
+        assert_eq!(state.queue.len(), 3);
+    }
+
+    /// Titles in the order a seeded shuffle plays `count` ready songs.
+    fn shuffled_order(dir: &TempDir, seed: u64, count: usize) -> Vec<String> {
+        let file = FileConfig { shuffle_seed: Some(seed), ..FileConfig::default() };
+        let mut state = AppState::new(Arc::new(test_util::config(dir.path(), file)));
+        state.shuffle = true;
+        state.queue = (0..count).map(|i| ready(&i.to_string())).collect();
+        state.queue_cursor = count - 1;
+        let mut order = Vec::new();
+        while let Some(song) = state.next_ready_song() {
+            assert!(state.queue.is_empty() || state.queue_cursor < state.queue.len());
+            order.push(song.title);
+        }
+        order
+    }
+
+    #[test]
+    fn seeded_shuffle_is_repeatable_and_plays_everything_once() {
+        let dir = TempDir::new("seeded-shuffle");
+        let order = shuffled_order(&dir, 7, 8);
+        assert_eq!(order, shuffled_order(&dir, 7, 8));
+        let in_order: Vec<String> = (0..8).map(|i| i.to_string()).collect();
+        assert_ne!(order, in_order);
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, in_order);
+    }
+
+    #[test]
+    fn share_text_names_the_song_and_where_it_is() {
+        let np = now_playing(ready("Intro"), Instant::now());
+        assert_eq!(
+            np.share_text(Duration::ZERO),
+            "\u{1f3b5} Intro \u{2014} Artist (https://youtu.be/Intro)"
+        );
+        assert_eq!(
+            np.share_text(Duration::from_millis(83_900)),
+            "\u{1f3b5} Intro \u{2014} Artist (https://youtu.be/Intro) at 1:23"
+        );
+        // Under a second still counts as the start
+        assert!(!np.share_text(Duration::from_millis(900)).contains(" at "));
+    }
+
+    #[test]
+    fn elapsed_leaves_out_every_pause() {
+        let secs = Duration::from_secs;
+        let start = Instant::now().checked_sub(secs(60)).unwrap();
+        let mut np = now_playing(ready("a"), start);
+
+        np.pause(start + secs(20));
+        assert_eq!(np.elapsed(), secs(20));
+        // Pausing again doesn't restart the pause
+        np.pause(start + secs(25));
+        np.resume(start + secs(50));
+        np.pause(start + secs(55));
+        assert_eq!(np.elapsed(), secs(25));
+        np.resume(start + secs(58));
+        // Resuming twice doesn't count the pause twice
+        np.resume(start + secs(59));
+        assert_eq!(np.paused_elapsed, secs(33));
+        assert!(np.elapsed() >= secs(27));
+
+        let dir = TempDir::new("elapsed-paused");
+        let mut state = new_state(&dir);
+        state.start_playing(ready("b"));
+        let now = Instant::now();
+        state.set_paused(true, now);
+        state.set_paused(false, now + secs(4));
+        assert_eq!(state.current.as_ref().unwrap().paused_elapsed, secs(4));
+    }
+
+    #[test]
+    fn chapter_jumps_step_between_starts() {
+        let chapters: Vec<Chapter> = [0.0, 60.0, 150.0]
+            .iter()
+            .map(|&start_secs| Chapter { start_secs, title: String::new() })
+            .collect();
+        let secs = Duration::from_secs;
+        assert_eq!(next_chapter(&chapters, secs(10)), Some(secs(60)));
+        // Just short of a start counts as already there
+        assert_eq!(next_chapter(&chapters, Duration::from_millis(59_800)), Some(secs(150)));
+        assert_eq!(next_chapter(&chapters, secs(200)), None);
+
+        // Well into a chapter goes back to its start, near the start to the one before
+        assert_eq!(previous_chapter(&chapters, secs(100)), Some(secs(60)));
+        assert_eq!(previous_chapter(&chapters, secs(62)), Some(secs(0)));
+        assert_eq!(previous_chapter(&chapters, secs(1)), Some(secs(0)));
+        assert_eq!(previous_chapter(&[], secs(100)), None);
+        assert_eq!(next_chapter(&[], secs(0)), None);
+    }
+
+    #[test]
+    fn live_edge_is_the_buffer_before_the_end() {
+        let buffer = LIVE_EDGE_BUFFER;
+        let live = |secs| live_edge_position(Some(Duration::from_secs(secs)), buffer);
+        assert_eq!(live(300), Some(Duration::from_secs(300) - buffer));
+        // Shorter than the buffer: back to the start rather than past the end
+        assert_eq!(live(5), Some(Duration::ZERO));
+        assert_eq!(live_edge_position(None, buffer), None);
+    }
+
+    fn input(text: &str, cursor: usize) -> InputState {
+        InputState { text: text.to_string(), cursor, ..InputState::default() }
+    }
+
+    #[test]
+    fn autocomplete_cycles_library_matches() {
+        let dir = TempDir::new("autocomplete");
+        let mut state = new_state(&dir);
+        let mut around = ready("Around the World");
+        around.artist = "Daft Punk".to_string();
+        let mut dance = ready("Dance Yrself Clean");
+        dance.artist = "LCD Soundsystem".to_string();
+        state.library = vec![around, dance];
+        let candidates = state.completion_candidates();
+
+        let mut input = input("play da", 7);
+        for expected in ["play Daft Punk", "play Dance Yrself Clean", "play Daft Punk"] {
+            assert!(input.autocomplete(&candidates));
+            assert_eq!(input.text, expected);
+            assert_eq!(input.cursor, input.text.len());
+        }
+        // Typing ends the cycle; the next Tab starts over from the new text
+        input.insert(' ');
+        assert!(!input.autocomplete(&candidates));
+    }
+
+    #[test]
+    fn autocomplete_completes_the_word_under_the_cursor() {
+        let candidates = vec!["Daft Punk".to_string(), "Dance Yrself Clean".to_string()];
+
+        let mut between = input("play da please", 7);
+        assert!(between.autocomplete(&candidates));
+        assert_eq!(between.text, "play Daft Punk please");
+        assert_eq!(between.cursor, "play Daft Punk".len());
+        // Cycling replaces only the match
+        assert!(between.autocomplete(&candidates));
+        assert_eq!(between.text, "play Dance Yrself Clean please");
+
+        // The rest of a word the cursor is inside is replaced too
+        let mut inside = input("play dafx please", 8);
+        assert!(inside.autocomplete(&candidates));
+        assert_eq!(inside.text, "play Daft Punk please");
+    }
+
+    /// What the player loop does with a finished play request.
+    fn land_play(state: &mut AppState, generation: u64, song: Song) {
+        if !state.play_superseded(generation) {
+            state.start_playing(song);
+        }
+    }
+
+    #[test]
+    fn stale_agent_play_loses_to_a_newer_pick() {
+        let dir = TempDir::new("play-generation");
+        let mut state = new_state(&dir);
+        state.queue = vec![ready("a"), ready("b")];
+
+        // The agent starts downloading while the current track ends
+        let agent = state.begin_play_request();
+        let next = state.next_auto_song().unwrap();
+        state.start_playing(next);
+        // The user picks a song before the download lands
+        let picked = state.begin_play_request();
+        land_play(&mut state, picked, ready("b"));
+        land_play(&mut state, agent, ready("agent"));
+
+        assert_eq!(state.current.as_ref().unwrap().song.title, "b");
+        assert_eq!(titles(&state.history), ["a"]);
+    }
+
+    #[test]
+    fn agent_play_replaces_an_auto_advanced_track() {
+        let dir = TempDir::new("play-generation-advance");
+        let mut state = new_state(&dir);
+        state.queue = vec![ready("a")];
+
+        let agent = state.begin_play_request();
+        let next = state.next_auto_song().unwrap();
+        state.start_playing(next);
+        land_play(&mut state, agent, ready("agent"));
+
+        assert_eq!(state.current.as_ref().unwrap().song.title, "agent");
+        assert_eq!(titles(&state.history), ["a"]);
+    }
+
+    #[test]
+    fn volume_changes_ramp_from_wherever_the_fade_is() {
+        let dir = TempDir::new("volume-ramp");
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+        let mut jumps = new_state(&dir);
+        assert_eq!(jumps.change_volume(30, now), 30);
+        assert_eq!(jumps.tick_volume_ramp(now), None);
+
+        let file = FileConfig { volume_fade_ms: Some(200), ..FileConfig::default() };
+        let mut state = AppState::new(Arc::new(test_util::config(dir.path(), file)));
+        state.volume = 70;
+        assert_eq!(state.change_volume(30, now), 70);
+        assert_eq!(state.tick_volume_ramp(now + ms(100)), Some(50));
+        // Changing course mid-fade starts from the level reached so far
+        assert_eq!(state.change_volume(90, now + ms(100)), 50);
+        assert_eq!(state.tick_volume_ramp(now + ms(200)), Some(70));
+        assert_eq!(state.tick_volume_ramp(now + ms(300)), Some(90));
+        assert_eq!(state.tick_volume_ramp(now + ms(400)), None);
+        assert_eq!(state.volume, 90);
+    }
+
+    #[test]
+    fn track_gap_holds_auto_advance_once_per_ending() {
+        let dir = TempDir::new("track-gap");
+        let now = Instant::now();
+        assert!(!new_state(&dir).hold_for_gap(now));
+
+        let file = FileConfig { track_gap_secs: Some(2.0), ..FileConfig::default() };
+        let mut state = AppState::new(Arc::new(test_util::config(dir.path(), file)));
+        assert!(state.hold_for_gap(now));
+        assert!(state.hold_for_gap(now + Duration::from_secs(1)));
+        assert!(!state.hold_for_gap(now + Duration::from_secs(2)));
+        // The next ending waits out a fresh gap
+        let later = now + Duration::from_secs(10);
+        assert!(state.hold_for_gap(later));
+        assert!(!state.hold_for_gap(later + Duration::from_secs(3)));
+    }
+}
@@ -18,7 +18,6 @@ pub fn new_shared_buffer() -> SharedBuffer {
 pub struct AudioFeatures {
     pub rms: f32,
     pub bass: f32,
-    #[allow(dead_code)]
     pub mid: f32,
     pub treble: f32,
     pub is_beat: bool,
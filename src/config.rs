@@ -1,37 +1,952 @@
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use ratatui::style::Color;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::downloader;
+use crate::keymap::{KeyMap, KeySpec};
+use crate::log_throttle;
 
 pub struct Config {
-    pub api_key: String,
+    /// The provider's key variable (`ANTHROPIC_API_KEY` or `OPENAI_API_KEY`)
+    /// or `api_key` first, then any `api_keys`. Never empty: an `openai`
+    /// provider without keys gets one empty key, sent as no auth at all.
+    pub api_keys: Vec<String>,
     pub model: String,
+    /// Which chat API `base_url` speaks.
+    pub provider: Provider,
+    /// Endpoint root without a trailing slash, e.g. `https://api.anthropic.com/v1`.
+    pub base_url: String,
+    /// `~/.vibeplayer`: log, session and default library location.
+    pub base_dir: PathBuf,
+    /// Re-prompt the model once when it answers without calling any tool.
+    pub retry_empty_tool_calls: bool,
+    /// Play the top search result for plain input instead of asking the model.
+    pub direct_search: bool,
+    /// Add downloads to the library. When off, only songs saved to a
+    /// playlist are kept; other downloads are deleted on quit, or on the
+    /// next start when a run that didn't quit cleanly had autosaved them.
+    pub persist_downloads: bool,
     pub cache_dir: PathBuf,
     pub library_path: PathBuf,
+    pub session_path: PathBuf,
+    /// Starting volume when there's no previous session to take it from.
     pub default_volume: u8,
+    /// Time volume changes take to fade in; zero means instant.
+    pub volume_fade: Duration,
+    /// Artist shown (and hidden in "title - artist" formatting) when the
+    /// real artist isn't known.
+    pub unknown_artist: String,
+    /// Start with the input bar focused instead of normal mode.
+    pub start_in_input_mode: bool,
+    /// In normal mode, a printable key with no binding of its own enters the
+    /// input bar and types itself.
+    pub type_to_input: bool,
+    /// What Tab does while typing in the input bar.
+    pub tab_action: TabAction,
+    /// Show the wave visualizer on startup (toggle at runtime with `h`).
+    pub show_visualizer: bool,
+    /// Accessibility: replace the wave with a calm level meter and stop
+    /// other animations (spinners).
+    pub reduce_motion: bool,
+    /// Briefly brighten panel borders on each detected beat.
+    pub beat_flash: bool,
+    /// Keep running without playback when no audio output can be opened,
+    /// e.g. to browse or warm the cache over SSH. Off means fail at startup.
+    pub allow_no_audio: bool,
+    /// Fixed seed for shuffle order, for reproducible runs. Random if unset.
+    pub shuffle_seed: Option<u64>,
+    pub layout: LayoutConfig,
+    pub theme: Theme,
+    pub keys: KeyMap,
+    /// Cap on library entries; least recently played metadata is evicted
+    /// beyond it. `None` means unlimited.
+    pub max_library_entries: Option<usize>,
+    /// Cap on the total size of cached downloads; least recently played
+    /// songs (file and entry) are evicted beyond it. `None` means unlimited.
+    pub max_cache_bytes: Option<u64>,
+    /// How many previous tracks `N` can step back through.
+    pub history_limit: usize,
+    /// Songs queued from a pasted playlist link; the rest are left out.
+    pub max_playlist_items: usize,
+    /// How often library changes and the session are written out. `None`
+    /// (`autosave_secs = 0`) writes the library on every change instead.
+    pub autosave_interval: Option<Duration>,
+    /// Silence inserted between tracks when auto-advancing.
+    pub track_gap: Duration,
+    /// How long auto-advance fades one track into the next; zero cuts
+    /// straight over. Always zero when `track_gap` is set.
+    pub crossfade: Duration,
+    /// Cap on auto-advance replays of one track; `None` means no cap.
+    pub replay_limit: Option<ReplayLimit>,
+    /// Format songs are downloaded in, one of `downloader::AUDIO_FORMATS`.
+    /// The agent can still pick another for a single song.
+    pub audio_format: String,
+    /// yt-dlp `--audio-quality`: a VBR level from 0 (best) to 10, or a
+    /// bitrate like `192K`.
+    pub audio_quality: String,
+    /// The yt-dlp executable: a name looked up on `PATH` or a path to it.
+    pub yt_dlp_path: PathBuf,
+    /// How long a yt-dlp run may take before it's killed; for downloads, how
+    /// long one may go without progress, plus the track's length while it's
+    /// converting. `None` (`yt_dlp_timeout_secs = 0`) waits forever.
+    pub yt_dlp_timeout: Option<Duration>,
+    /// Netscape-format cookies file passed to yt-dlp as `--cookies`, for
+    /// age-restricted and members-only videos.
+    pub cookies_file: Option<PathBuf>,
+    /// Browser to read cookies from (`--cookies-from-browser`), e.g.
+    /// `firefox`. Ignored when `cookies_file` is set.
+    pub cookies_from_browser: Option<String>,
+    /// Extra flags passed to every yt-dlp run, after vibeplayer's own and
+    /// before the URL. Anything in `downloader::RESERVED_FLAGS`/`RESERVED_OPTIONS`
+    /// is dropped.
+    pub yt_dlp_extra_args: Vec<String>,
+    /// Once a noisy log line has hit its burst limit, log only one in this
+    /// many (`1` logs everything).
+    pub log_sample_every: u32,
+    /// `host:port` to stream audio features to external visualizers as
+    /// JSON lines; off when unset.
+    pub feature_stream_addr: Option<String>,
+    /// Problems found while loading that didn't stop startup; logged and
+    /// shown once the UI is up.
+    pub warnings: Vec<String>,
+}
+
+/// At most `max_plays` auto-advance plays of one track per `window`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayLimit {
+    pub max_plays: u32,
+    pub window: Duration,
+}
+
+/// Window used when `max_replays` is set without `replay_window_mins`.
+pub const DEFAULT_REPLAY_WINDOW_MINS: u64 = 60;
+
+/// Tab in the input bar: leave for normal mode, or complete library titles
+/// and artists (Esc still leaves).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TabAction {
+    #[default]
+    ToggleMode,
+    Autocomplete,
+}
+
+/// Chat API the agent talks to. `openai` covers anything OpenAI-compatible,
+/// such as a local Ollama or llama.cpp server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Provider {
+    #[default]
+    Anthropic,
+    #[serde(rename = "openai")]
+    OpenAi,
+}
+
+impl Provider {
+    fn default_base_url(self) -> &'static str {
+        match self {
+            Provider::Anthropic => "https://api.anthropic.com/v1",
+            Provider::OpenAi => "https://api.openai.com/v1",
+        }
+    }
+
+    fn key_variable(self) -> &'static str {
+        match self {
+            Provider::Anthropic => "ANTHROPIC_API_KEY",
+            Provider::OpenAi => "OPENAI_API_KEY",
+        }
+    }
+}
+
+pub const DEFAULT_MODEL: &str = "claude-sonnet-4-5-20250929";
+
+pub const DEFAULT_UNKNOWN_ARTIST: &str = "Unknown";
+
+pub const DEFAULT_HISTORY_LIMIT: usize = 50;
+
+pub const DEFAULT_AUTOSAVE_SECS: u64 = 10;
+
+pub const DEFAULT_YT_DLP_TIMEOUT_SECS: u64 = 120;
+
+pub const DEFAULT_MAX_PLAYLIST_ITEMS: usize = 50;
+
+/// Smallest share (in percent) any panel may be given.
+pub const MIN_PANEL_PERCENT: u16 = 10;
+
+/// Below this terminal size the layout is replaced by a "too small" notice.
+pub const DEFAULT_MIN_WIDTH: u16 = 60;
+pub const DEFAULT_MIN_HEIGHT: u16 = 16;
+
+/// Panel split percentages. Each pair sums to 100.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayoutConfig {
+    /// Visualizer + now playing (left) vs. library + queue (right)
+    pub visualizer_percent: u16,
+    pub sidebar_percent: u16,
+    /// Library (top) vs. queue (bottom) within the sidebar
+    pub library_percent: u16,
+    pub queue_percent: u16,
+    /// Smallest terminal the layout is drawn in
+    pub min_width: u16,
+    pub min_height: u16,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            visualizer_percent: 65,
+            sidebar_percent: 35,
+            library_percent: 50,
+            queue_percent: 50,
+            min_width: DEFAULT_MIN_WIDTH,
+            min_height: DEFAULT_MIN_HEIGHT,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LayoutFileConfig {
+    pub visualizer: Option<u16>,
+    pub sidebar: Option<u16>,
+    pub library: Option<u16>,
+    pub queue: Option<u16>,
+    pub min_width: Option<u16>,
+    pub min_height: Option<u16>,
+}
+
+impl LayoutConfig {
+    fn from_file(file: Option<&LayoutFileConfig>, warnings: &mut Vec<String>) -> Self {
+        let default = Self::default();
+        let Some(file) = file else {
+            return default;
+        };
+        let (visualizer_percent, sidebar_percent) = split_percent(
+            "visualizer/sidebar",
+            file.visualizer,
+            file.sidebar,
+            (default.visualizer_percent, default.sidebar_percent),
+            warnings,
+        );
+        let (library_percent, queue_percent) = split_percent(
+            "library/queue",
+            file.library,
+            file.queue,
+            (default.library_percent, default.queue_percent),
+            warnings,
+        );
+        Self {
+            visualizer_percent,
+            sidebar_percent,
+            library_percent,
+            queue_percent,
+            min_width: file.min_width.unwrap_or(default.min_width),
+            min_height: file.min_height.unwrap_or(default.min_height),
+        }
+    }
+}
+
+/// Every color the UI draws with. A built-in theme is one struct literal in
+/// `Theme::preset`; to add one, copy `neon`, change the colors and give it a
+/// name there. `[theme]` picks a preset with `name` and can override the
+/// queue status colors on top of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    /// Focused panel titles, the selected row, agent replies and status-bar
+    /// indicators (volume, shuffle, repeat).
+    pub accent: Color,
+    /// The input box when typing, its title and the `INPUT` badge.
+    pub accent_alt: Color,
+    /// Song titles and other primary text.
+    pub text: Color,
+    /// Hints, numbering, artists and anything secondary.
+    pub muted: Color,
+    /// Unfocused panel titles, key hints and in-progress messages.
+    pub title: Color,
+    /// Text drawn on a colored badge (`CONTROLS`, `ERROR`).
+    pub badge_text: Color,
+    pub border_focused: Color,
+    pub border_unfocused: Color,
+    /// Played part of the progress bar; the rest uses `muted`.
+    pub progress_fill: Color,
+    /// Play/pause marker, the input prompt and calm-mode level bars.
+    pub elapsed: Color,
+    pub warning: Color,
+    pub error: Color,
+    /// Visualizer wave color when quiet and at full level; it fades between
+    /// the two with loudness. `wave_beat` is added on beats.
+    pub wave_quiet: (u8, u8, u8),
+    pub wave_loud: (u8, u8, u8),
+    pub wave_beat: (u8, u8, u8),
+    pub queued: Color,
+    pub downloading: Color,
+    pub ready: Color,
+    pub playing: Color,
+    pub played: Color,
+    /// Show a symbol next to each queue status so it reads without color.
+    pub status_symbols: bool,
+}
+
+/// Colors are names (`yellow`, `light_blue`) or `#rrggbb`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ThemeFileConfig {
+    /// Built-in theme to start from; see `THEME_NAMES`.
+    pub name: Option<String>,
+    pub queued: Option<String>,
+    pub downloading: Option<String>,
+    pub ready: Option<String>,
+    pub playing: Option<String>,
+    pub played: Option<String>,
+    pub status_symbols: Option<bool>,
+}
+
+/// Names accepted by `[theme] name`.
+pub const THEME_NAMES: &[&str] = &["neon", "mono", "solarized"];
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::preset("neon").expect("neon theme exists")
+    }
+}
+
+impl Theme {
+    pub fn preset(name: &str) -> Option<Self> {
+        let theme = match name {
+            "neon" => Self {
+                accent: Color::Cyan,
+                accent_alt: Color::Magenta,
+                text: Color::White,
+                muted: Color::DarkGray,
+                title: Color::Yellow,
+                badge_text: Color::Black,
+                border_focused: Color::Cyan,
+                border_unfocused: Color::DarkGray,
+                progress_fill: Color::Magenta,
+                elapsed: Color::Green,
+                warning: Color::Yellow,
+                error: Color::Red,
+                wave_quiet: (0, 80, 24),
+                wave_loud: (0, 255, 96),
+                wave_beat: (60, 0, 0),
+                queued: Color::DarkGray,
+                downloading: Color::Yellow,
+                ready: Color::Green,
+                playing: Color::Magenta,
+                played: Color::DarkGray,
+                status_symbols: false,
+            },
+            "mono" => Self {
+                accent: Color::White,
+                accent_alt: Color::White,
+                text: Color::Gray,
+                muted: Color::DarkGray,
+                title: Color::Gray,
+                badge_text: Color::Black,
+                border_focused: Color::White,
+                border_unfocused: Color::DarkGray,
+                progress_fill: Color::White,
+                elapsed: Color::Gray,
+                warning: Color::White,
+                error: Color::White,
+                wave_quiet: (70, 70, 70),
+                wave_loud: (230, 230, 230),
+                wave_beat: (25, 25, 25),
+                queued: Color::DarkGray,
+                downloading: Color::Gray,
+                ready: Color::Gray,
+                playing: Color::White,
+                played: Color::DarkGray,
+                // Status can't be told apart by color here
+                status_symbols: true,
+            },
+            "solarized" => Self {
+                accent: Color::Rgb(0x26, 0x8b, 0xd2),
+                accent_alt: Color::Rgb(0x6c, 0x71, 0xc4),
+                text: Color::Rgb(0x93, 0xa1, 0xa1),
+                muted: Color::Rgb(0x58, 0x6e, 0x75),
+                title: Color::Rgb(0xb5, 0x89, 0x00),
+                badge_text: Color::Rgb(0x00, 0x2b, 0x36),
+                border_focused: Color::Rgb(0x26, 0x8b, 0xd2),
+                border_unfocused: Color::Rgb(0x58, 0x6e, 0x75),
+                progress_fill: Color::Rgb(0xd3, 0x36, 0x82),
+                elapsed: Color::Rgb(0x85, 0x99, 0x00),
+                warning: Color::Rgb(0xcb, 0x4b, 0x16),
+                error: Color::Rgb(0xdc, 0x32, 0x2f),
+                wave_quiet: (0x07, 0x36, 0x42),
+                wave_loud: (0x2a, 0xa1, 0x98),
+                wave_beat: (0x50, 0x20, 0x00),
+                queued: Color::Rgb(0x58, 0x6e, 0x75),
+                downloading: Color::Rgb(0xb5, 0x89, 0x00),
+                ready: Color::Rgb(0x85, 0x99, 0x00),
+                playing: Color::Rgb(0xd3, 0x36, 0x82),
+                played: Color::Rgb(0x58, 0x6e, 0x75),
+                status_symbols: false,
+            },
+            _ => return None,
+        };
+        Some(theme)
+    }
+
+    fn from_file(file: Option<&ThemeFileConfig>, warnings: &mut Vec<String>) -> Self {
+        let Some(file) = file else {
+            return Self::default();
+        };
+        let base = match file.name.as_deref() {
+            None => Self::default(),
+            Some(name) => Self::preset(name).unwrap_or_else(|| {
+                warnings.push(format!(
+                    "theme {:?} not found (available: {}), using neon",
+                    name,
+                    THEME_NAMES.join(", ")
+                ));
+                Self::default()
+            }),
+        };
+        let mut color = |name: &str, value: &Option<String>, fallback: Color| match value {
+            None => fallback,
+            Some(v) => v.parse().unwrap_or_else(|_| {
+                warnings.push(format!("theme {} color {:?} not recognized, using default", name, v));
+                fallback
+            }),
+        };
+        Self {
+            queued: color("queued", &file.queued, base.queued),
+            downloading: color("downloading", &file.downloading, base.downloading),
+            ready: color("ready", &file.ready, base.ready),
+            playing: color("playing", &file.playing, base.playing),
+            played: color("played", &file.played, base.played),
+            status_symbols: file.status_symbols.unwrap_or(base.status_symbols),
+            ..base
+        }
+    }
+}
+
+/// Resolve one side-by-side split. A single value implies its complement;
+/// anything that doesn't add up to 100 or starves a panel falls back to
+/// `default` with a warning.
+fn split_percent(
+    name: &str,
+    first: Option<u16>,
+    second: Option<u16>,
+    default: (u16, u16),
+    warnings: &mut Vec<String>,
+) -> (u16, u16) {
+    let (a, b) = match (first, second) {
+        (None, None) => return default,
+        (Some(a), None) => (a, 100u16.saturating_sub(a)),
+        (None, Some(b)) => (100u16.saturating_sub(b), b),
+        (Some(a), Some(b)) => (a, b),
+    };
+    if a as u32 + b as u32 == 100 && a >= MIN_PANEL_PERCENT && b >= MIN_PANEL_PERCENT {
+        (a, b)
+    } else {
+        warnings.push(format!(
+            "layout {} split {}/{} must sum to 100 with each at least {}%, using {}/{}",
+            name, a, b, MIN_PANEL_PERCENT, default.0, default.1
+        ));
+        default
+    }
+}
+
+/// One of `downloader::AUDIO_FORMATS`. Anything else is an error rather than
+/// a warning: every song would download in it and then fail to play.
+fn audio_format(format: Option<String>) -> Result<String> {
+    let Some(format) = format.map(|f| f.trim().to_lowercase()) else {
+        return Ok(downloader::DEFAULT_AUDIO_FORMAT.to_string());
+    };
+    if downloader::AUDIO_FORMATS.contains(&format.as_str()) {
+        return Ok(format);
+    }
+    let reason = match format.as_str() {
+        "opus" => "opus can't be played back".to_string(),
+        "m4a" => "m4a needs vibeplayer built with --features symphonia".to_string(),
+        _ => format!("expected one of {}", downloader::AUDIO_FORMATS.join(", ")),
+    };
+    anyhow::bail!("audio_format {} in config.toml isn't usable: {}", format, reason)
+}
+
+/// A VBR level 0-10 or a bitrate such as `128K`, as `--audio-quality` takes.
+fn audio_quality(quality: Option<String>, warnings: &mut Vec<String>) -> String {
+    let default = downloader::DEFAULT_AUDIO_QUALITY.to_string();
+    let Some(quality) = quality.map(|q| q.trim().to_string()) else {
+        return default;
+    };
+    let level = quality.parse::<u8>().is_ok_and(|level| level <= 10);
+    let bitrate = quality
+        .strip_suffix(['K', 'k'])
+        .is_some_and(|rate| !rate.is_empty() && rate.chars().all(|c| c.is_ascii_digit()));
+    if level || bitrate {
+        quality
+    } else {
+        warnings.push(format!(
+            "audio_quality {} should be 0-10 or a bitrate like 192K, using {}",
+            quality, default
+        ));
+        default
+    }
+}
+
+/// The replay cap, if `max_replays` is set. Zero would stop auto-advance
+/// from ever playing anything, so it's ignored.
+fn replay_limit(
+    max_replays: Option<u32>,
+    window_mins: Option<u64>,
+    warnings: &mut Vec<String>,
+) -> Option<ReplayLimit> {
+    let max_plays = max_replays?;
+    if max_plays == 0 {
+        warnings.push("max_replays 0 would never let a track play, ignoring it".to_string());
+        return None;
+    }
+    let mins = window_mins.unwrap_or(DEFAULT_REPLAY_WINDOW_MINS);
+    Some(ReplayLimit {
+        max_plays,
+        window: Duration::from_secs(mins.saturating_mul(60)),
+    })
+}
+
+/// A duration setting in seconds, 0 when unset or invalid.
+fn secs_setting(key: &str, secs: Option<f64>, warnings: &mut Vec<String>) -> Duration {
+    match secs {
+        None => Duration::ZERO,
+        Some(s) if s.is_finite() && s >= 0.0 => Duration::from_secs_f64(s),
+        Some(s) => {
+            warnings.push(format!("{} {} must be a non-negative number, using 0", key, s));
+            Duration::ZERO
+        }
+    }
+}
+
+/// Drop extra yt-dlp args that would clash with the ones vibeplayer relies
+/// on, along with the value that follows a dropped flag.
+fn extra_args(args: Vec<String>, warnings: &mut Vec<String>) -> Vec<String> {
+    let mut kept = Vec::new();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, _)) => (flag, true),
+            None => (arg.as_str(), false),
+        };
+        let is_option = downloader::RESERVED_OPTIONS.contains(&flag);
+        if !is_option && !downloader::RESERVED_FLAGS.contains(&flag) {
+            kept.push(arg);
+            continue;
+        }
+        warnings.push(format!(
+            "yt_dlp_extra_args: {} is managed by vibeplayer, ignoring it",
+            flag
+        ));
+        if is_option && !inline_value {
+            iter.next();
+        }
+    }
+    kept
+}
+
+/// On-disk representation of `~/.vibeplayer/config.toml`. Every field is
+/// optional so a partial file only overrides what it mentions.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FileConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// More keys to switch to when one is rate limited or rejected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_keys: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<Provider>,
+    /// Defaults to the provider's public endpoint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_volume: Option<u8>,
+    /// `~/` is expanded for both paths.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub library_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_empty_tool_calls: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direct_search: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persist_downloads: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unknown_artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_in_input_mode: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub type_to_input: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tab_action: Option<TabAction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_visualizer: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reduce_motion: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub beat_flash: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_no_audio: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shuffle_seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_library_entries: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_cache_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_limit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub autosave_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_playlist_items: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track_gap_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crossfade_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume_fade_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_replays: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replay_window_mins: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub yt_dlp_extra_args: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_quality: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub yt_dlp_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub yt_dlp_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cookies_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cookies_from_browser: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_sample_every: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feature_stream_addr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<LayoutFileConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<ThemeFileConfig>,
+    /// Action name to key or keys, e.g. `skip = "n"`, `volume_up = ["+", "="]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<HashMap<String, KeySpec>>,
+}
+
+impl FileConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = std::fs::read_to_string(path).context("Failed to read config file")?;
+        toml::from_str(&data).map_err(|e| {
+            let line = e.span().map(|span| data[..span.start].matches('\n').count() + 1);
+            match line {
+                Some(line) => anyhow::anyhow!("{}:{}: {}", path.display(), line, e.message()),
+                None => anyhow::anyhow!("{}: {}", path.display(), e.message()),
+            }
+            .context("Failed to parse config file")
+        })
+    }
+
+    /// Write the file readable by its owner only, since it holds API keys.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        use std::io::Write;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).context("Failed to create config directory")?;
+        }
+        let data = toml::to_string_pretty(self).context("Failed to serialize config")?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(0o600);
+            // The mode only applies to a new file; tighten an existing one
+            // before the key goes in
+            if path.exists() {
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+                    .context("Failed to restrict config file permissions")?;
+            }
+        }
+        let mut file = options.open(path).context("Failed to write config file")?;
+        write!(file, "# vibeplayer configuration\n\n{}", data)
+            .context("Failed to write config file")?;
+        Ok(())
+    }
+}
+
+/// Resolve a configured path, expanding a leading `~/`.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Root directory for everything vibeplayer persists (`~/.vibeplayer`).
+pub fn base_dir() -> Result<PathBuf> {
+    Ok(dirs::home_dir()
+        .context("Could not find home directory")?
+        .join(".vibeplayer"))
+}
+
+pub fn config_path() -> Result<PathBuf> {
+    Ok(base_dir()?.join("config.toml"))
+}
+
+/// Check that `dir` accepts writes by creating and removing a scratch file.
+pub fn probe_writable(dir: &Path) -> Result<()> {
+    let probe = dir.join(".write-probe");
+    std::fs::write(&probe, b"ok")
+        .with_context(|| format!("{} is not writable", dir.display()))?;
+    std::fs::remove_file(&probe).context("Failed to remove write probe")?;
+    Ok(())
+}
+
+/// First run = no config file and no library yet.
+pub fn is_first_run() -> Result<bool> {
+    let base = base_dir()?;
+    Ok(!base.join("config.toml").exists() && !base.join("library.json").exists())
 }
 
 impl Config {
+    /// Directory holding the log and session; what `O` opens.
+    pub fn data_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
     pub fn load() -> Result<Self> {
-        let api_key = std::env::var("ANTHROPIC_API_KEY")
-            .context("ANTHROPIC_API_KEY environment variable not set")?;
+        let file = FileConfig::load(&config_path()?)?;
+        let env_key = std::env::var(file.provider.unwrap_or_default().key_variable()).ok();
+        Self::from_file(file, env_key, base_dir()?)
+    }
 
-        let cache_dir = dirs::home_dir()
-            .context("Could not find home directory")?
-            .join(".vibeplayer")
-            .join("cache");
+    /// Settings from `file`, with `env_key` (from the provider's key
+    /// variable) ahead of its keys and everything else kept in `base_dir`
+    /// unless it says otherwise.
+    pub fn from_file(file: FileConfig, env_key: Option<String>, base_dir: PathBuf) -> Result<Self> {
+        let mut warnings = Vec::new();
+
+        let provider = file.provider.unwrap_or_default();
+        let mut api_keys: Vec<String> = Vec::new();
+        let configured = env_key
+            .or(file.api_key)
+            .into_iter()
+            .chain(file.api_keys.unwrap_or_default());
+        for key in configured {
+            let key = key.trim().to_string();
+            if !key.is_empty() && !api_keys.contains(&key) {
+                api_keys.push(key);
+            }
+        }
+        if api_keys.is_empty() && provider == Provider::OpenAi {
+            // Local servers usually don't want one
+            api_keys.push(String::new());
+        }
+        anyhow::ensure!(
+            !api_keys.is_empty(),
+            "{} environment variable not set and no api_key in config.toml",
+            provider.key_variable()
+        );
+        let model = match file.model.map(|m| m.trim().to_string()).filter(|m| !m.is_empty()) {
+            Some(model) => model,
+            None if provider == Provider::Anthropic => DEFAULT_MODEL.to_string(),
+            None => anyhow::bail!("provider openai needs a model in config.toml"),
+        };
+        let base_url = file
+            .base_url
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| provider.default_base_url().to_string());
+
+        let cache_dir = file
+            .cache_dir
+            .as_deref()
+            .map(expand_home)
+            .unwrap_or_else(|| base_dir.join("cache"));
 
         std::fs::create_dir_all(&cache_dir)
             .context("Failed to create cache directory")?;
+        // Keep going read-only: cached songs still play, only new downloads
+        // and library changes will fail
+        if let Err(e) = probe_writable(&cache_dir) {
+            warnings.push(format!(
+                "{:#}; downloads and library changes will fail until it's fixed",
+                e
+            ));
+        }
+
+        let library_path = file
+            .library_path
+            .as_deref()
+            .map(expand_home)
+            .unwrap_or_else(|| base_dir.join("library.json"));
+        let session_path = base_dir.join("session.json");
 
-        let library_path = cache_dir.parent()
-            .unwrap_or(&cache_dir)
-            .join("library.json");
+        let default_volume = match file.default_volume {
+            Some(v) if v > 100 => {
+                warnings.push(format!("default_volume {} is over 100, using 100", v));
+                100
+            }
+            Some(v) => v,
+            None => 70,
+        };
+
+        let cookies_file = file.cookies_file.as_deref().map(expand_home);
+        if cookies_file.as_ref().is_some_and(|path| !path.is_file()) {
+            warnings.push(format!(
+                "cookies_file {} doesn't exist; age-restricted videos will fail",
+                file.cookies_file.as_deref().unwrap_or_default()
+            ));
+        }
+        let mut cookies_from_browser = file.cookies_from_browser.filter(|b| !b.trim().is_empty());
+        if cookies_file.is_some() && cookies_from_browser.is_some() {
+            warnings.push("cookies_from_browser is ignored while cookies_file is set".to_string());
+            cookies_from_browser = None;
+        }
+
+        let track_gap = secs_setting("track_gap_secs", file.track_gap_secs, &mut warnings);
+        let mut crossfade = secs_setting("crossfade_secs", file.crossfade_secs, &mut warnings);
+        if !crossfade.is_zero() && !track_gap.is_zero() {
+            warnings.push("crossfade_secs is ignored while track_gap_secs is set".to_string());
+            crossfade = Duration::ZERO;
+        }
 
         Ok(Self {
-            api_key,
-            model: "claude-sonnet-4-5-20250929".to_string(),
+            api_keys,
+            model,
+            provider,
+            base_url,
+            base_dir,
+            retry_empty_tool_calls: file.retry_empty_tool_calls.unwrap_or(true),
+            direct_search: file.direct_search.unwrap_or(false),
+            persist_downloads: file.persist_downloads.unwrap_or(true),
             cache_dir,
             library_path,
-            default_volume: 70,
+            session_path,
+            default_volume,
+            volume_fade: Duration::from_millis(file.volume_fade_ms.unwrap_or(0)),
+            unknown_artist: file
+                .unknown_artist
+                .unwrap_or_else(|| DEFAULT_UNKNOWN_ARTIST.to_string()),
+            start_in_input_mode: file.start_in_input_mode.unwrap_or(false),
+            type_to_input: file.type_to_input.unwrap_or(false),
+            tab_action: file.tab_action.unwrap_or_default(),
+            show_visualizer: file.show_visualizer.unwrap_or(true),
+            reduce_motion: file.reduce_motion.unwrap_or(false),
+            beat_flash: file.beat_flash.unwrap_or(false),
+            allow_no_audio: file.allow_no_audio.unwrap_or(true),
+            shuffle_seed: file.shuffle_seed,
+            layout: LayoutConfig::from_file(file.layout.as_ref(), &mut warnings),
+            theme: Theme::from_file(file.theme.as_ref(), &mut warnings),
+            keys: KeyMap::from_file(file.keys.as_ref(), &mut warnings),
+            max_library_entries: file.max_library_entries,
+            max_cache_bytes: file.max_cache_bytes,
+            history_limit: file.history_limit.unwrap_or(DEFAULT_HISTORY_LIMIT),
+            max_playlist_items: file
+                .max_playlist_items
+                .unwrap_or(DEFAULT_MAX_PLAYLIST_ITEMS)
+                .max(1),
+            autosave_interval: match file.autosave_secs.unwrap_or(DEFAULT_AUTOSAVE_SECS) {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            track_gap,
+            crossfade,
+            replay_limit: replay_limit(file.max_replays, file.replay_window_mins, &mut warnings),
+            log_sample_every: file
+                .log_sample_every
+                .unwrap_or(log_throttle::DEFAULT_SAMPLE_EVERY),
+            feature_stream_addr: file.feature_stream_addr,
+            audio_format: audio_format(file.audio_format)?,
+            audio_quality: audio_quality(file.audio_quality, &mut warnings),
+            yt_dlp_path: file
+                .yt_dlp_path
+                .as_deref()
+                .map(expand_home)
+                .unwrap_or_else(|| PathBuf::from("yt-dlp")),
+            yt_dlp_timeout: match file.yt_dlp_timeout_secs.unwrap_or(DEFAULT_YT_DLP_TIMEOUT_SECS) {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            cookies_file,
+            cookies_from_browser,
+            yt_dlp_extra_args: extra_args(file.yt_dlp_extra_args.unwrap_or_default(), &mut warnings),
+            warnings,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, TempDir};
+
+    #[test]
+    fn replay_limit_ignores_zero_and_saturates_the_window() {
+        let mut warnings = Vec::new();
+        assert_eq!(replay_limit(None, Some(5), &mut warnings), None);
+        assert_eq!(replay_limit(Some(0), None, &mut warnings), None);
+        assert_eq!(warnings.len(), 1);
+
+        let limit = replay_limit(Some(2), None, &mut warnings).unwrap();
+        assert_eq!(limit.max_plays, 2);
+        assert_eq!(limit.window, Duration::from_secs(60 * DEFAULT_REPLAY_WINDOW_MINS));
+        let limit = replay_limit(Some(2), Some(u64::MAX), &mut warnings).unwrap();
+        assert_eq!(limit.window, Duration::from_secs(u64::MAX));
+    }
+
+    #[test]
+    fn audio_format_accepts_only_playable_formats() {
+        assert_eq!(audio_format(None).unwrap(), downloader::DEFAULT_AUDIO_FORMAT);
+        assert_eq!(audio_format(Some(" FLAC ".to_string())).unwrap(), "flac");
+        let opus = audio_format(Some("opus".to_string())).unwrap_err();
+        assert!(opus.to_string().contains("can't be played back"), "{}", opus);
+        assert!(audio_format(Some("ogg".to_string())).is_err());
+        assert_eq!(
+            audio_format(Some("m4a".to_string())).is_ok(),
+            cfg!(feature = "symphonia")
+        );
+    }
+
+    #[test]
+    fn data_dir_is_where_the_session_and_default_cache_live() {
+        let dir = TempDir::new("data-dir");
+        let config = test_util::config(dir.path(), FileConfig::default());
+        assert_eq!(config.data_dir(), dir.path());
+        assert_eq!(config.session_path.parent(), Some(dir.path()));
+        assert_eq!(config.cache_dir, dir.path().join("cache"));
+
+        // A cache kept elsewhere doesn't move it
+        let elsewhere = TempDir::new("data-dir-cache");
+        let file = FileConfig {
+            cache_dir: Some(elsewhere.path().display().to_string()),
+            ..FileConfig::default()
+        };
+        let config = test_util::config(dir.path(), file);
+        assert_eq!(config.data_dir(), dir.path());
+        assert_eq!(config.cache_dir, elsewhere.path());
+    }
+
+    #[test]
+    fn probe_writable_cleans_up_and_reports_the_directory() {
+        let dir = TempDir::new("probe-writable");
+        probe_writable(dir.path()).unwrap();
+        assert!(!dir.path().join(".write-probe").exists());
+
+        // A directory squatting on the probe's name fails the write even
+        // when the tests run as root, which ignores read-only permissions
+        std::fs::create_dir(dir.path().join(".write-probe")).unwrap();
+        let err = probe_writable(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("is not writable"), "{}", err);
+        assert!(err.to_string().contains(&dir.path().display().to_string()));
+    }
+}
@@ -1,9 +1,349 @@
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 use tokio::process::Command;
 use tracing::{debug, error, info, warn};
 
 use crate::config::Config;
+use crate::log_throttle::LogThrottle;
+
+/// Raw output and per-result lines add up fast when the agent fans out
+/// searches; log the first few per minute and sample the rest.
+static SEARCH_RAW_LOG: LogThrottle = LogThrottle::new(3, Duration::from_secs(60));
+static SEARCH_RESULT_LOG: LogThrottle = LogThrottle::new(15, Duration::from_secs(60));
+
+/// Audio format used when neither the config nor the download picks one.
+pub const DEFAULT_AUDIO_FORMAT: &str = "mp3";
+
+/// `--audio-quality` when the config doesn't set one: VBR level 5 of 0 (best)
+/// to 10 (worst).
+pub const DEFAULT_AUDIO_QUALITY: &str = "5";
+
+/// Formats yt-dlp extracts to a file whose extension matches the format name
+/// and that the player can decode. m4a (AAC) needs the symphonia feature;
+/// opus never plays, so it isn't offered.
+#[cfg(feature = "symphonia")]
+pub const AUDIO_FORMATS: &[&str] = &["mp3", "m4a", "flac", "wav"];
+#[cfg(not(feature = "symphonia"))]
+pub const AUDIO_FORMATS: &[&str] = &["mp3", "flac", "wav"];
+
+/// Length of the clip fetched by `download_preview`.
+pub const PREVIEW_SECS: f64 = 20.0;
+
+/// yt-dlp releases older than this likely have broken YouTube extractors.
+const STALE_VERSION_DAYS: i64 = 90;
+
+/// stderr fragments that mean YouTube changed under yt-dlp, which is almost
+/// always fixed by updating it.
+const EXTRACTOR_FAILURE_PATTERNS: &[&str] = &[
+    "Unable to extract",
+    "Signature extraction failed",
+    "nsig extraction failed",
+    "Failed to extract any player response",
+    "Some formats may be missing",
+    "HTTP Error 403: Forbidden",
+    "please report this issue on",
+];
+
+const UPDATE_HINT: &str = "YouTube changed something; try updating yt-dlp (`yt-dlp -U`)";
+
+/// stderr fragments for videos that need a signed-in account: age gates,
+/// members-only uploads, bot checks.
+const LOGIN_REQUIRED_PATTERNS: &[&str] = &[
+    "Sign in to confirm your age",
+    "Sign in to confirm you",
+    "members-only content",
+    "available to this channel's members",
+    "Use --cookies",
+];
+
+const COOKIES_HINT: &str =
+    "this video needs a signed-in account; set cookies_from_browser or cookies_file in config.toml";
+
+pub const DISK_FULL_HINT: &str = "the disk holding the cache is full; free up some space";
+pub const READ_ONLY_HINT: &str = "the cache directory is on a read-only filesystem";
+
+/// A targeted hint for known failure modes in yt-dlp's stderr.
+pub fn failure_hint(stderr: &str) -> Option<&'static str> {
+    if stderr.contains("No space left on device") {
+        return Some(DISK_FULL_HINT);
+    }
+    if stderr.contains("Read-only file system") {
+        return Some(READ_ONLY_HINT);
+    }
+    if LOGIN_REQUIRED_PATTERNS.iter().any(|p| stderr.contains(p)) {
+        return Some(COOKIES_HINT);
+    }
+    EXTRACTOR_FAILURE_PATTERNS
+        .iter()
+        .any(|p| stderr.contains(p))
+        .then_some(UPDATE_HINT)
+}
+
+/// One line on why a download failed, for the queue: a known hint, else
+/// yt-dlp's `ERROR:` line, else the first line of the error.
+pub fn failure_reason(err: &anyhow::Error) -> String {
+    let message = format!("{:#}", err);
+    if let Some(hint) = storage_hint(err).or_else(|| failure_hint(&message)) {
+        return hint.to_string();
+    }
+    message
+        .lines()
+        .find_map(|line| line.split_once("ERROR:").map(|(_, rest)| rest.trim().to_string()))
+        .unwrap_or_else(|| message.lines().next().unwrap_or_default().to_string())
+}
+
+/// Storage hint for an error that came from the cache filesystem, whether
+/// it surfaced through yt-dlp's stderr or one of our own writes.
+pub fn storage_hint(err: &anyhow::Error) -> Option<&'static str> {
+    for cause in err.chain() {
+        if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+            match io.kind() {
+                std::io::ErrorKind::StorageFull => return Some(DISK_FULL_HINT),
+                std::io::ErrorKind::ReadOnlyFilesystem => return Some(READ_ONLY_HINT),
+                _ => {}
+            }
+        }
+    }
+    let message = err.to_string();
+    [DISK_FULL_HINT, READ_ONLY_HINT]
+        .into_iter()
+        .find(|hint| message.contains(hint))
+}
+
+/// Error for a failed yt-dlp run, leading with the hint (if any) so it's the
+/// first thing the status bar shows.
+fn yt_dlp_error(what: &str, stderr: &str) -> anyhow::Error {
+    match failure_hint(stderr) {
+        Some(hint) => anyhow::anyhow!("{}: {}\n{}", what, hint, stderr),
+        None => anyhow::anyhow!("{}: {}", what, stderr),
+    }
+}
+
+/// Age in days of a date-based yt-dlp version like `2024.08.06` (nightlies
+/// append a build number, which is ignored).
+pub fn version_age_days(version: &str, today: NaiveDate) -> Option<i64> {
+    let mut parts = version.trim().split('.');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    let released = NaiveDate::from_ymd_opt(year, month, day)?;
+    Some((today - released).num_days())
+}
+
+/// A yt-dlp command, killed if its future is dropped.
+fn yt_dlp(config: &Config) -> Command {
+    let mut command = Command::new(&config.yt_dlp_path);
+    command.kill_on_drop(true);
+    command
+}
+
+/// Context for a yt-dlp that couldn't be started at all.
+fn run_error(config: &Config) -> String {
+    format!("Failed to run {} (is yt-dlp installed?)", config.yt_dlp_path.display())
+}
+
+/// A yt-dlp run killed for hanging past `config.yt_dlp_timeout`. Kept as its
+/// own type so callers can tell it from yt-dlp refusing the URL.
+#[derive(Debug)]
+pub struct TimedOut {
+    pub after: Duration,
+}
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "yt-dlp timed out after {}s", self.after.as_secs())
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+pub fn is_timeout(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.is::<TimedOut>())
+}
+
+/// Wait for a yt-dlp `output()`, up to the configured timeout. On expiry the
+/// future is dropped, and with it the child, which `kill_on_drop` kills.
+async fn finish(
+    config: &Config,
+    output: impl Future<Output = std::io::Result<Output>>,
+) -> Result<Output> {
+    let output = match config.yt_dlp_timeout {
+        Some(limit) => tokio::time::timeout(limit, output)
+            .await
+            .map_err(|_| TimedOut { after: limit })?,
+        None => output.await,
+    };
+    output.with_context(|| run_error(config))
+}
+
+/// Check the installed yt-dlp. Fails if it can't be run; otherwise returns
+/// a warning if it looks stale.
+pub async fn check_version(config: &Config) -> Result<Option<String>> {
+    let not_found = |e: &anyhow::Error| {
+        e.chain()
+            .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+            .any(|io| io.kind() == std::io::ErrorKind::NotFound)
+    };
+    let output = match finish(config, yt_dlp(config).arg("--version").output()).await {
+        Ok(output) => output,
+        Err(e) if not_found(&e) => anyhow::bail!(
+            "yt-dlp not found at {}: install it (https://github.com/yt-dlp/yt-dlp#installation) \
+             or set yt_dlp_path in config.toml",
+            config.yt_dlp_path.display()
+        ),
+        Err(e) => return Err(e),
+    };
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let Some(age) = version_age_days(&version, chrono::Local::now().date_naive()) else {
+        return Ok(None);
+    };
+    info!(%version, age_days = age, "yt-dlp version");
+    Ok((age > STALE_VERSION_DAYS).then(|| {
+        format!(
+            "yt-dlp {} is {} days old; run `yt-dlp -U` if downloads fail",
+            version, age
+        )
+    }))
+}
+
+/// Switches vibeplayer sets itself; user-supplied extra args may not repeat
+/// or override them.
+pub const RESERVED_FLAGS: &[&str] = &[
+    "-x",
+    "--extract-audio",
+    "--no-download",
+    "--flat-playlist",
+    "--force-overwrites",
+];
+
+/// Options (taking a value) vibeplayer sets itself or parses the output of.
+pub const RESERVED_OPTIONS: &[&str] = &[
+    "--audio-format",
+    "-o",
+    "--output",
+    "--print",
+    "-O",
+    "--download-sections",
+];
+
+/// Full yt-dlp argument list: our own args, then cookies and the user's
+/// extra args from `config`, then the URL or search target last.
+pub fn build_args(args: &[&str], config: &Config, target: &str) -> Vec<String> {
+    let cookies = match (&config.cookies_file, &config.cookies_from_browser) {
+        (Some(file), _) => vec!["--cookies".to_string(), file.display().to_string()],
+        (None, Some(browser)) => vec!["--cookies-from-browser".to_string(), browser.clone()],
+        (None, None) => Vec::new(),
+    };
+    args.iter()
+        .map(|a| a.to_string())
+        .chain(cookies)
+        .chain(config.yt_dlp_extra_args.iter().cloned())
+        .chain(std::iter::once(target.to_string()))
+        .collect()
+}
+
+/// A YouTube link `play_url` can take: a video (`watch?v=`, `youtu.be/`,
+/// `shorts/`, `live/`) or a playlist page, on youtube.com, m.youtube.com or
+/// music.youtube.com.
+pub fn is_youtube_url(input: &str) -> bool {
+    is_playlist_url(input) || is_youtube_video_url(input)
+}
+
+/// Host (without `www.`) and path of an http(s) link, if `input` is one.
+fn split_url(input: &str) -> Option<(&str, &str)> {
+    let input = input.trim();
+    let rest = input
+        .strip_prefix("https://")
+        .or_else(|| input.strip_prefix("http://"))?;
+    if rest.contains(char::is_whitespace) {
+        return None;
+    }
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    Some((host.strip_prefix("www.").unwrap_or(host), path))
+}
+
+/// A YouTube playlist page (`playlist?list=`). A `watch` link that also
+/// carries a `list` is a video, and plays just that video.
+pub fn is_playlist_url(input: &str) -> bool {
+    let Some((host, path)) = split_url(input) else {
+        return false;
+    };
+    matches!(host, "youtube.com" | "m.youtube.com" | "music.youtube.com")
+        && path.strip_prefix("playlist?").is_some_and(|query| {
+            query
+                .split('&')
+                .any(|param| param.strip_prefix("list=").is_some_and(|id| !id.is_empty()))
+        })
+}
+
+fn is_youtube_video_url(input: &str) -> bool {
+    let Some((host, path)) = split_url(input) else {
+        return false;
+    };
+    let has_id = |s: &str| s.split(['?', '#', '/']).next().is_some_and(|id| !id.is_empty());
+    match host {
+        "youtu.be" => has_id(path),
+        "youtube.com" | "m.youtube.com" | "music.youtube.com" => {
+            if let Some(query) = path.strip_prefix("watch?") {
+                query.split('&').any(|param| param.strip_prefix("v=").is_some_and(has_id))
+            } else {
+                path.strip_prefix("shorts/")
+                    .or_else(|| path.strip_prefix("live/"))
+                    .is_some_and(has_id)
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Smaller than any real audio file; anything under this is the leftover of
+/// an interrupted download.
+const MIN_CACHED_FILE_BYTES: u64 = 4096;
+
+/// Whether `path` holds a usable cached download, not a missing, empty or
+/// truncated-to-nothing file.
+pub fn is_valid_cached_file(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() >= MIN_CACHED_FILE_BYTES)
+}
+
+pub fn cache_file_name(video_id: &str, format: &str) -> String {
+    format!("{}.{}", video_id, format)
+}
+
+/// Format of a usable cached copy of `video_id`, preferring the configured
+/// one. Lets downloads made before `audio_format` changed keep resolving.
+fn cached_format(video_id: &str, config: &Config) -> Option<&'static str> {
+    let configured = AUDIO_FORMATS.iter().filter(|f| **f == config.audio_format);
+    let others = AUDIO_FORMATS.iter().filter(|f| **f != config.audio_format);
+    configured
+        .chain(others)
+        .copied()
+        .find(|f| is_valid_cached_file(&config.cache_dir.join(cache_file_name(video_id, f))))
+}
+
+/// Cover images are converted to this, next to the audio.
+const THUMBNAIL_FORMAT: &str = "jpg";
+
+pub fn thumbnail_file_name(video_id: &str) -> String {
+    cache_file_name(video_id, THUMBNAIL_FORMAT)
+}
+
+/// `--download-sections` spec for a `PREVIEW_SECS` window around the middle
+/// of the track, or its start when the duration is unknown or too short.
+pub fn preview_section(duration_secs: Option<f64>) -> String {
+    let start = match duration_secs {
+        Some(d) if d > PREVIEW_SECS => ((d - PREVIEW_SECS) / 2.0).floor(),
+        _ => 0.0,
+    };
+    format!("*{}-{}", start, start + PREVIEW_SECS)
+}
 
 #[derive(Debug, Clone)]
 pub struct SearchResult {
@@ -13,18 +353,22 @@ pub struct SearchResult {
 }
 
 /// Quick title fetch — faster than full metadata since we only need one field.
-pub async fn get_title(url: &str) -> Result<String> {
+#[allow(dead_code)]
+pub async fn get_title(url: &str, config: &Config) -> Result<String> {
     info!(%url, "fetching title via yt-dlp");
-    let output = Command::new("yt-dlp")
-        .args(["--print", "%(title)s", "--no-download", "--no-playlist", url])
-        .output()
-        .await
-        .context("Failed to run yt-dlp")?;
+    let output = yt_dlp(config)
+        .args(build_args(
+            &["--print", "%(title)s", "--no-download", "--no-playlist"],
+            config,
+            url,
+        ))
+        .output();
+    let output = finish(config, output).await?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
         error!(%url, %stderr, "yt-dlp get_title failed");
-        anyhow::bail!("yt-dlp failed: {}", stderr);
+        return Err(yt_dlp_error("yt-dlp failed", &stderr));
     }
 
     let title = String::from_utf8_lossy(&output.stdout).trim().to_string();
@@ -32,8 +376,53 @@ pub async fn get_title(url: &str) -> Result<String> {
     Ok(title)
 }
 
-pub async fn download_song(url: &str, config: &Config) -> Result<(PathBuf, SongMeta)> {
-    info!(%url, "starting song download");
+/// Marks our `--progress-template` lines among yt-dlp's other output.
+const PROGRESS_PREFIX: &str = "vibeplayer-progress";
+
+/// yt-dlp's line as ffmpeg starts converting the download, which it does
+/// without printing anything.
+const EXTRACT_AUDIO_PREFIX: &str = "[ExtractAudio]";
+
+/// How long a download may go quiet. Converting to the audio format says
+/// nothing until done, so then it gets the track's length on top: ffmpeg
+/// would have to run slower than real time to hit it.
+fn quiet_limit(limit: Duration, converting: bool, duration_secs: f64) -> Duration {
+    if converting {
+        limit + Duration::try_from_secs_f64(duration_secs).unwrap_or_default()
+    } else {
+        limit
+    }
+}
+
+/// Download fraction (0–1) from a progress line: downloaded bytes, then the
+/// total and the estimated total, either of which may be `NA`.
+fn parse_progress(line: &str) -> Option<f32> {
+    let mut fields = line.strip_prefix(PROGRESS_PREFIX)?.split_whitespace();
+    let downloaded: f64 = fields.next()?.parse().ok()?;
+    let total = fields
+        .filter_map(|f| f.parse::<f64>().ok())
+        .find(|t| *t > 0.0)?;
+    Some((downloaded / total).clamp(0.0, 1.0) as f32)
+}
+
+/// Download a song into the cache. `format` overrides `config.audio_format`
+/// for this song only; without one, a copy already cached in another format
+/// (from before the configured format changed) is used as is.
+/// `on_progress` gets the download fraction as yt-dlp reports it; it restarts
+/// for each stream, so callers should smooth it.
+pub async fn download_song(
+    url: &str,
+    config: &Config,
+    format: Option<&str>,
+    on_progress: impl Fn(f32),
+) -> Result<(PathBuf, SongMeta)> {
+    info!(%url, ?format, "starting song download");
+    if let Some(fmt) = format {
+        if !AUDIO_FORMATS.contains(&fmt) {
+            anyhow::bail!("Unsupported audio format: {} (expected one of {})", fmt, AUDIO_FORMATS.join(", "));
+        }
+    }
+    let mut audio_format = format.unwrap_or(&config.audio_format);
     let output_template = config
         .cache_dir
         .join("%(id)s.%(ext)s")
@@ -42,20 +431,19 @@ pub async fn download_song(url: &str, config: &Config) -> Result<(PathBuf, SongM
 
     // First get metadata
     info!(%url, "fetching metadata");
-    let meta_output = Command::new("yt-dlp")
-        .args([
-            "--print", "%(title)s\n%(uploader)s\n%(duration)s\n%(id)s",
-            "--no-download",
+    let meta_output = yt_dlp(config)
+        .args(build_args(
+            &["--print", "%(title)s\n%(uploader)s\n%(duration)s\n%(id)s\n%(chapters)j\n%(album)s", "--no-download"],
+            config,
             url,
-        ])
-        .output()
-        .await
-        .context("Failed to run yt-dlp (is it installed?)")?;
+        ))
+        .output();
+    let meta_output = finish(config, meta_output).await?;
 
     if !meta_output.status.success() {
         let stderr = String::from_utf8_lossy(&meta_output.stderr);
         error!(%url, %stderr, "yt-dlp metadata fetch failed");
-        anyhow::bail!("yt-dlp metadata failed: {}", stderr);
+        return Err(yt_dlp_error("yt-dlp metadata failed", &stderr));
     }
 
     let meta_str = String::from_utf8_lossy(&meta_output.stdout);
@@ -63,45 +451,125 @@ pub async fn download_song(url: &str, config: &Config) -> Result<(PathBuf, SongM
     debug!(%url, ?meta_lines, "raw metadata lines");
 
     let title = meta_lines.first().unwrap_or(&"Unknown").to_string();
-    let artist = meta_lines.get(1).unwrap_or(&"Unknown").to_string();
+    // yt-dlp prints "NA" for fields the extractor couldn't fill
+    let artist = meta_lines
+        .get(1)
+        .filter(|a| !a.is_empty() && **a != "NA")
+        .map(|a| a.to_string())
+        .unwrap_or_else(|| config.unknown_artist.clone());
     let duration_secs: f64 = meta_lines
         .get(2)
         .and_then(|s| s.parse().ok())
         .unwrap_or(0.0);
     let video_id = meta_lines.get(3).unwrap_or(&"unknown").to_string();
+    let chapters = meta_lines.get(4).map(|l| parse_chapters(l)).unwrap_or_default();
+    let album = meta_lines
+        .get(5)
+        .filter(|a| !a.is_empty() && **a != "NA")
+        .map(|a| a.to_string());
 
-    info!(%title, %artist, %video_id, duration_secs, "metadata parsed");
+    info!(%title, %artist, ?album, %video_id, duration_secs, chapters = chapters.len(), "metadata parsed");
 
-    let file_path = config.cache_dir.join(format!("{}.mp3", video_id));
+    if format.is_none() {
+        audio_format = cached_format(&video_id, config).unwrap_or(audio_format);
+    }
+    let file_path = config.cache_dir.join(cache_file_name(&video_id, audio_format));
 
     // Skip download if already cached
-    if file_path.exists() {
+    if is_valid_cached_file(&file_path) {
         info!(path = %file_path.display(), "using cached file");
     } else {
+        // yt-dlp would treat a leftover stub as already downloaded
+        if file_path.exists() {
+            warn!(path = %file_path.display(), "discarding truncated cache file");
+            let _ = std::fs::remove_file(&file_path);
+        }
         info!(%url, path = %file_path.display(), "downloading audio");
-        let dl_output = Command::new("yt-dlp")
-            .args([
-                "-x",
-                "--audio-format",
-                "mp3",
-                "--audio-quality",
-                "5",
-                "-o",
-                &output_template,
-                "--no-playlist",
+        let progress_template = format!(
+            "download:{} %(progress.downloaded_bytes)s %(progress.total_bytes)s \
+             %(progress.total_bytes_estimate)s",
+            PROGRESS_PREFIX
+        );
+        let mut child = yt_dlp(config)
+            .args(build_args(
+                &[
+                    "-x",
+                    "--audio-format",
+                    audio_format,
+                    "--audio-quality",
+                    &config.audio_quality,
+                    "-o",
+                    &output_template,
+                    "--no-playlist",
+                    "--write-thumbnail",
+                    "--convert-thumbnails",
+                    THUMBNAIL_FORMAT,
+                    "--newline",
+                    "--progress-template",
+                    &progress_template,
+                ],
+                config,
                 url,
-            ])
-            .output()
-            .await
-            .context("yt-dlp download failed")?;
+            ))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| run_error(config))?;
+
+        // Drain stderr alongside stdout so a chatty yt-dlp can't block on it
+        let mut stderr_pipe = child.stderr.take().context("yt-dlp stderr not captured")?;
+        let stderr_task = tokio::spawn(async move {
+            let mut buf = Vec::new();
+            let _ = stderr_pipe.read_to_end(&mut buf).await;
+            buf
+        });
+        let stdout = child.stdout.take().context("yt-dlp stdout not captured")?;
+        let mut lines = BufReader::new(stdout).lines();
+        let mut converting = false;
+        loop {
+            // A download can rightly take a while, so the timeout is on going
+            // quiet: progress lines keep coming while it's getting anywhere
+            let next = lines.next_line();
+            let limit =
+                config.yt_dlp_timeout.map(|limit| quiet_limit(limit, converting, duration_secs));
+            let line = match limit {
+                Some(limit) => match tokio::time::timeout(limit, next).await {
+                    Ok(line) => line,
+                    Err(_) => {
+                        warn!(%url, ?limit, "yt-dlp stalled, killing it");
+                        if let Err(e) = child.kill().await {
+                            warn!(?e, "failed to kill yt-dlp");
+                        }
+                        return Err(TimedOut { after: limit }.into());
+                    }
+                },
+                None => next.await,
+            };
+            let Some(line) = line.context("Failed to read yt-dlp output")? else {
+                break;
+            };
+            if let Some(fraction) = parse_progress(&line) {
+                on_progress(fraction);
+                converting = fraction >= 1.0;
+            } else if line.starts_with(EXTRACT_AUDIO_PREFIX) {
+                converting = true;
+            }
+        }
+        let status = child.wait().await.context("yt-dlp download failed")?;
+        let stderr_bytes = stderr_task.await.unwrap_or_default();
 
-        if !dl_output.status.success() {
-            let stderr = String::from_utf8_lossy(&dl_output.stderr);
+        if !status.success() {
+            let stderr = String::from_utf8_lossy(&stderr_bytes);
             error!(%url, %stderr, "yt-dlp download failed");
-            anyhow::bail!("yt-dlp failed: {}", stderr);
+            return Err(yt_dlp_error("yt-dlp failed", &stderr));
         }
         info!(path = %file_path.display(), "download complete");
     }
+    let thumbnail = Some(thumbnail_file_name(&video_id))
+        .filter(|name| config.cache_dir.join(name).is_file());
+    if thumbnail.is_none() {
+        debug!(%video_id, "no thumbnail saved");
+    }
 
     Ok((
         file_path,
@@ -110,36 +578,111 @@ pub async fn download_song(url: &str, config: &Config) -> Result<(PathBuf, SongM
             artist,
             duration_secs,
             video_id,
+            audio_format: format.map(String::from),
+            extension: audio_format.to_string(),
+            chapters,
+            album,
+            thumbnail,
         },
     ))
 }
 
-pub async fn search_youtube(query: &str, count: u32) -> Result<Vec<SearchResult>> {
+/// Download only a short preview clip into the temp dir. The caller owns the
+/// returned file and is expected to delete it once it's been opened.
+pub async fn download_preview(
+    url: &str,
+    duration_secs: Option<f64>,
+    config: &Config,
+) -> Result<PathBuf> {
+    // Unique per preview, so one still being fetched isn't overwritten or
+    // deleted by the next
+    static NEXT_PREVIEW: AtomicU64 = AtomicU64::new(0);
+    let n = NEXT_PREVIEW.fetch_add(1, Ordering::Relaxed);
+    let name = format!("vibeplayer-preview-{}-{}", std::process::id(), n);
+    let base = std::env::temp_dir().join(name);
+    let path = base.with_extension("mp3");
+    let output_template = format!("{}.%(ext)s", base.display());
+    let section = preview_section(duration_secs);
+    info!(%url, %section, "downloading preview clip");
+
+    let output = yt_dlp(config)
+        .args(build_args(
+            &[
+                "-x",
+                "--audio-format",
+                "mp3",
+                "--download-sections",
+                &section,
+                "--force-overwrites",
+                "-o",
+                &output_template,
+                "--no-playlist",
+            ],
+            config,
+            url,
+        ))
+        .output();
+    let output = finish(config, output).await?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!(%url, %stderr, "yt-dlp preview download failed");
+        let _ = std::fs::remove_file(&path);
+        return Err(yt_dlp_error("yt-dlp preview failed", &stderr));
+    }
+
+    info!(path = %path.display(), "preview clip ready");
+    Ok(path)
+}
+
+pub async fn search_youtube(
+    query: &str,
+    count: u32,
+    config: &Config,
+) -> Result<Vec<SearchResult>> {
     let search_query = format!("ytsearch{}:{}", count, query);
     info!(%search_query, "searching YouTube");
 
-    let output = Command::new("yt-dlp")
-        .args([
-            "--print",
-            "%(title)s\t%(webpage_url)s\t%(duration)s",
-            "--no-download",
-            "--flat-playlist",
+    let output = yt_dlp(config)
+        .args(build_args(
+            &[
+                "--print",
+                "%(title)s\t%(webpage_url)s\t%(duration)s",
+                "--no-download",
+                "--flat-playlist",
+            ],
+            config,
             &search_query,
-        ])
-        .output()
-        .await
-        .context("yt-dlp search failed")?;
+        ))
+        .output();
+    let output = finish(config, output).await?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
         error!(%search_query, %stderr, "yt-dlp search failed");
-        anyhow::bail!("yt-dlp search failed: {}", stderr);
+        return Err(yt_dlp_error("yt-dlp search failed", &stderr));
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
-    debug!(%search_query, raw_output = %stdout, "search raw output");
+    if SEARCH_RAW_LOG.allow() {
+        debug!(%search_query, raw_output = %stdout, "search raw output");
+    }
+
+    let results = parse_result_lines(&stdout);
+
+    info!(%search_query, result_count = results.len(), "search complete");
+    for (i, r) in results.iter().enumerate() {
+        if SEARCH_RESULT_LOG.allow() {
+            debug!(index = i, title = %r.title, url = %r.url, "search result");
+        }
+    }
+
+    Ok(results)
+}
 
-    let results: Vec<SearchResult> = stdout
+/// `title<TAB>url<TAB>duration` lines, as printed for searches and playlists.
+fn parse_result_lines(stdout: &str) -> Vec<SearchResult> {
+    stdout
         .trim()
         .lines()
         .filter_map(|line| {
@@ -155,20 +698,315 @@ pub async fn search_youtube(query: &str, count: u32) -> Result<Vec<SearchResult>
                 None
             }
         })
-        .collect();
+        .collect()
+}
 
-    info!(%search_query, result_count = results.len(), "search complete");
-    for (i, r) in results.iter().enumerate() {
-        debug!(index = i, title = %r.title, url = %r.url, "search result");
+/// The first `limit` videos of a playlist, in playlist order. Only lists
+/// them; nothing is downloaded.
+pub async fn expand_playlist(
+    url: &str,
+    limit: usize,
+    config: &Config,
+) -> Result<Vec<SearchResult>> {
+    info!(%url, limit, "expanding playlist");
+    let limit = limit.to_string();
+    let output = yt_dlp(config)
+        .args(build_args(
+            &[
+                "--print",
+                "%(title)s\t%(url)s\t%(duration)s",
+                "--flat-playlist",
+                "--playlist-end",
+                &limit,
+            ],
+            config,
+            url,
+        ))
+        .output();
+    let output = finish(config, output).await?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!(%url, %stderr, "yt-dlp playlist listing failed");
+        return Err(yt_dlp_error("yt-dlp playlist listing failed", &stderr));
     }
 
+    let results = parse_result_lines(&String::from_utf8_lossy(&output.stdout));
+    info!(%url, count = results.len(), "playlist expanded");
     Ok(results)
 }
 
+/// A YouTube Music search for albums matching `query`, as a link yt-dlp can
+/// list.
+pub fn album_search_url(query: &str) -> String {
+    let mut url =
+        reqwest::Url::parse_with_params("https://music.youtube.com/search", &[("q", query)])
+            .expect("album search base URL is valid");
+    url.set_fragment(Some("albums"));
+    url.to_string()
+}
+
+/// Link to the best album match for `query` on YouTube Music, for
+/// `expand_playlist` to list its tracks. `None` when nothing matches.
+pub async fn find_album(query: &str, config: &Config) -> Result<Option<String>> {
+    let search_url = album_search_url(query);
+    info!(%search_url, "searching for album");
+    let output = yt_dlp(config)
+        .args(build_args(
+            &["--print", "%(url)s", "--flat-playlist", "--playlist-end", "1"],
+            config,
+            &search_url,
+        ))
+        .output();
+    let output = finish(config, output).await?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!(%search_url, %stderr, "yt-dlp album search failed");
+        return Err(yt_dlp_error("yt-dlp album search failed", &stderr));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let album = stdout.lines().map(str::trim).find(|l| !l.is_empty()).map(String::from);
+    info!(%search_url, ?album, "album search complete");
+    Ok(album)
+}
+
+/// A chapter marker from the video's metadata.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chapter {
+    pub start_secs: f64,
+    pub title: String,
+}
+
+/// yt-dlp's `chapters` field as printed with `%(chapters)j`.
+#[derive(Deserialize)]
+struct RawChapter {
+    start_time: f64,
+    #[serde(default)]
+    title: String,
+}
+
+/// Parse the `%(chapters)j` line; `null`/`NA`/garbage all mean no chapters.
+fn parse_chapters(line: &str) -> Vec<Chapter> {
+    let mut chapters: Vec<Chapter> = serde_json::from_str::<Option<Vec<RawChapter>>>(line)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|c| Chapter {
+            start_secs: c.start_time,
+            title: c.title,
+        })
+        .collect();
+    chapters.sort_by(|a, b| a.start_secs.total_cmp(&b.start_secs));
+    chapters
+}
+
 #[derive(Debug, Clone)]
 pub struct SongMeta {
     pub title: String,
     pub artist: String,
     pub duration_secs: f64,
     pub video_id: String,
+    /// Per-song format override; `None` means `config.audio_format`.
+    pub audio_format: Option<String>,
+    /// Format the cached file is actually in
+    pub extension: String,
+    pub chapters: Vec<Chapter>,
+    /// Album name, when the extractor knows it (mostly YouTube Music)
+    pub album: Option<String>,
+    /// Cover image file name in the cache dir, if yt-dlp saved one
+    pub thumbnail: Option<String>,
+}
+
+impl SongMeta {
+    pub fn file_name(&self) -> String {
+        cache_file_name(&self.video_id, &self.extension)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FileConfig;
+    use crate::test_util::{self, TempDir};
+
+    #[test]
+    fn extractor_errors_suggest_updating_yt_dlp() {
+        let stderr = "WARNING: [youtube] abc123: nsig extraction failed\n\
+                      ERROR: [youtube] abc123: Unable to extract uploader id";
+        assert_eq!(failure_hint(stderr), Some(UPDATE_HINT));
+        let err = yt_dlp_error("yt-dlp failed", stderr);
+        assert!(format!("{:#}", err).starts_with(&format!("yt-dlp failed: {}", UPDATE_HINT)));
+        assert_eq!(failure_reason(&err), UPDATE_HINT);
+
+        let unavailable = "ERROR: [youtube] abc123: Video unavailable";
+        assert_eq!(failure_hint(unavailable), None);
+        let err = yt_dlp_error("yt-dlp failed", unavailable);
+        assert_eq!(failure_reason(&err), "[youtube] abc123: Video unavailable");
+    }
+
+    #[test]
+    fn stale_versions_are_measured_in_days() {
+        let today = NaiveDate::from_ymd_opt(2024, 11, 4).unwrap();
+        assert_eq!(version_age_days("2024.08.06", today), Some(90));
+        assert_eq!(version_age_days("2024.10.22.232822\n", today), Some(13));
+        assert_eq!(version_age_days("unknown", today), None);
+    }
+
+    #[test]
+    fn converting_gets_the_track_length_on_top() {
+        let limit = Duration::from_secs(5);
+        assert_eq!(quiet_limit(limit, false, 200.0), limit);
+        assert_eq!(quiet_limit(limit, true, 200.0), Duration::from_secs(205));
+        // Unknown lengths don't extend it
+        assert_eq!(quiet_limit(limit, true, 0.0), limit);
+        assert_eq!(quiet_limit(limit, true, f64::INFINITY), limit);
+    }
+
+    #[tokio::test]
+    async fn quiet_conversion_outlasts_the_stall_timeout() {
+        let dir = TempDir::new("slow-convert");
+        let script = r#"
+case "$*" in *--no-download*) printf 'Title\nArtist\n200\nabc123\nNA\nNA\n'; exit 0;; esac
+while [ $# -gt 0 ]; do
+    case "$1" in -o) out=$2; shift;; esac
+    shift
+done
+echo "vibeplayer-progress 8192 8192 NA"
+echo "[ExtractAudio] Destination: abc123.mp3"
+sleep 2
+head -c 8192 /dev/zero > "$(echo "$out" | sed "s/%(id)s/abc123/; s/%(ext)s/mp3/")"
+"#;
+        let yt_dlp = test_util::fake_yt_dlp(dir.path(), script);
+        let file = FileConfig {
+            yt_dlp_path: Some(yt_dlp.display().to_string()),
+            yt_dlp_timeout_secs: Some(1),
+            ..FileConfig::default()
+        };
+        let config = test_util::config(dir.path(), file);
+
+        let url = "https://www.youtube.com/watch?v=abc123";
+        let (path, _) = download_song(url, &config, None, |_| {}).await.unwrap();
+        assert!(is_valid_cached_file(&path));
+    }
+
+    #[test]
+    fn youtube_links_are_told_apart_from_other_input() {
+        for url in [
+            "https://www.youtube.com/watch?v=abc123",
+            "https://m.youtube.com/watch?feature=share&v=abc123",
+            "http://music.youtube.com/watch?v=abc123&list=PL1",
+            "https://youtu.be/abc123?t=42",
+            "https://youtube.com/shorts/abc123",
+            "https://www.youtube.com/live/abc123",
+            "https://www.youtube.com/playlist?list=PL1",
+        ] {
+            assert!(is_youtube_url(url), "{}", url);
+        }
+        for input in [
+            "https://www.youtube.com/watch?v=",
+            "https://www.youtube.com/watch?list=PL1",
+            "https://youtu.be/",
+            "https://www.youtube.com/playlist?list=",
+            "https://youtube.com.evil.example/watch?v=abc123",
+            "https://www.youtube.com/watch?v=abc123 and more",
+            "youtube.com/watch?v=abc123",
+            "lofi beats",
+        ] {
+            assert!(!is_youtube_url(input), "{}", input);
+        }
+        // A video link in a playlist plays just the video
+        assert!(!is_playlist_url("https://www.youtube.com/watch?v=abc123&list=PL1"));
+        assert!(is_playlist_url("https://www.youtube.com/playlist?list=PL1"));
+    }
+
+    #[test]
+    fn only_full_sized_files_count_as_cached() {
+        let dir = TempDir::new("valid-cache");
+        let path = dir.path().join("abc123.mp3");
+        assert!(!is_valid_cached_file(&path));
+        std::fs::write(&path, b"").unwrap();
+        assert!(!is_valid_cached_file(&path));
+        std::fs::write(&path, vec![0; MIN_CACHED_FILE_BYTES as usize - 1]).unwrap();
+        assert!(!is_valid_cached_file(&path));
+        std::fs::write(&path, vec![0; MIN_CACHED_FILE_BYTES as usize]).unwrap();
+        assert!(is_valid_cached_file(&path));
+        // A directory by that name is no download either
+        assert!(!is_valid_cached_file(dir.path()));
+    }
+
+    #[test]
+    fn preview_section_centers_the_window() {
+        assert_eq!(preview_section(Some(200.0)), "*90-110");
+        assert_eq!(preview_section(Some(201.0)), "*90-110");
+        assert_eq!(preview_section(Some(10.0)), "*0-20");
+        assert_eq!(preview_section(None), "*0-20");
+    }
+
+    #[tokio::test]
+    async fn previews_get_their_own_files() {
+        let dir = TempDir::new("preview");
+        // Writes its arguments to the file named by `-o`
+        let script = r#"
+args="$*"
+while [ $# -gt 0 ]; do case "$1" in -o) out=$2; shift;; esac; shift; done
+echo "$args" > "$(echo "$out" | sed 's/%(ext)s/mp3/')"
+"#;
+        let file = FileConfig {
+            yt_dlp_path: Some(test_util::fake_yt_dlp(dir.path(), script).display().to_string()),
+            ..FileConfig::default()
+        };
+        let config = test_util::config(dir.path(), file);
+        let url = "https://www.youtube.com/watch?v=abc123";
+
+        let first = download_preview(url, Some(200.0), &config).await.unwrap();
+        let second = download_preview(url, None, &config).await.unwrap();
+        assert_ne!(first, second);
+        let args = std::fs::read_to_string(&first).unwrap();
+        assert!(args.contains("--download-sections *90-110"), "{}", args);
+        let _ = std::fs::remove_file(first);
+        let _ = std::fs::remove_file(second);
+    }
+
+    #[tokio::test]
+    async fn extra_args_reach_every_call_just_before_the_target() {
+        let dir = TempDir::new("extra-args");
+        let log = dir.path().join("args.log");
+        // Logs each call's arguments on a line, then answers like the real thing
+        let script = format!(
+            r#"
+printf '%s\n' "$*" | tr '\n' ' ' >> "{0}"; echo >> "{0}"
+case "$*" in
+    *--flat-playlist*) printf 'Found\thttps://www.youtube.com/watch?v=abc123\t200\n'; exit 0;;
+    *--no-download*) printf 'Title\nArtist\n200\nabc123\nNA\nNA\n'; exit 0;;
+esac
+while [ $# -gt 0 ]; do case "$1" in -o) out=$2; shift;; esac; shift; done
+head -c 8192 /dev/zero > "$(echo "$out" | sed "s/%(id)s/abc123/; s/%(ext)s/mp3/")"
+"#,
+            log.display()
+        );
+        let file = FileConfig {
+            yt_dlp_path: Some(test_util::fake_yt_dlp(dir.path(), &script).display().to_string()),
+            yt_dlp_extra_args: Some(vec!["--proxy".to_string(), "socks5://proxy:1080".to_string()]),
+            ..FileConfig::default()
+        };
+        let config = test_util::config(dir.path(), file);
+        let url = "https://www.youtube.com/watch?v=abc123";
+
+        let results = search_youtube("lofi", 1, &config).await.unwrap();
+        assert_eq!(results[0].url, url);
+        download_song(url, &config, None, |_| {}).await.unwrap();
+
+        let calls = std::fs::read_to_string(&log).unwrap();
+        let calls: Vec<&str> = calls.lines().map(str::trim_end).collect();
+        // The search, then metadata and the download itself
+        assert_eq!(calls.len(), 3, "{:?}", calls);
+        assert!(calls[0].ends_with("--proxy socks5://proxy:1080 ytsearch1:lofi"), "{}", calls[0]);
+        for call in &calls[1..] {
+            let expected = format!("--proxy socks5://proxy:1080 {}", url);
+            assert!(call.ends_with(&expected), "{}", call);
+        }
+    }
 }
@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use crate::audio_analysis::AudioFeatures;
+
+/// Frames buffered per client; a client that falls further behind skips
+/// ahead instead of holding anything up.
+const CLIENT_BACKLOG: usize = 64;
+
+/// One JSON line sent to external visualizers per analysis tick.
+#[derive(Debug, Serialize)]
+pub struct FeatureFrame {
+    pub rms: f32,
+    pub bass: f32,
+    pub mid: f32,
+    pub treble: f32,
+    pub beat: bool,
+}
+
+impl From<&AudioFeatures> for FeatureFrame {
+    fn from(f: &AudioFeatures) -> Self {
+        Self {
+            rms: f.rms,
+            bass: f.bass,
+            mid: f.mid,
+            treble: f.treble,
+            beat: f.is_beat,
+        }
+    }
+}
+
+/// Streams `AudioFeatures` as JSON lines to any TCP client that connects.
+/// Publishing never blocks: frames go through a broadcast channel and each
+/// client is served by its own task.
+pub struct FeatureStream {
+    tx: broadcast::Sender<String>,
+}
+
+impl FeatureStream {
+    pub async fn bind(addr: &str) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen for feature stream on {}", addr))?;
+        info!(%addr, "feature stream listening");
+        let (tx, _) = broadcast::channel(CLIENT_BACKLOG);
+
+        let accept_tx = tx.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, peer) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        warn!(?e, "feature stream accept failed");
+                        continue;
+                    }
+                };
+                info!(%peer, "feature stream client connected");
+                let mut rx = accept_tx.subscribe();
+                tokio::spawn(async move {
+                    loop {
+                        let line: String = match rx.recv().await {
+                            Ok(line) => line,
+                            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                                debug!(%peer, skipped, "feature stream client lagging");
+                                continue;
+                            }
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        };
+                        if socket.write_all(line.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                    info!(%peer, "feature stream client disconnected");
+                });
+            }
+        });
+
+        Ok(Self { tx })
+    }
+
+    pub fn has_clients(&self) -> bool {
+        self.tx.receiver_count() > 0
+    }
+
+    pub fn publish(&self, features: &AudioFeatures) {
+        if !self.has_clients() {
+            return;
+        }
+        match serde_json::to_string(&FeatureFrame::from(features)) {
+            // Only fails when nobody is subscribed, which is fine
+            Ok(json) => {
+                let _ = self.tx.send(json + "\n");
+            }
+            Err(e) => warn!(?e, "failed to serialize feature frame"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_keep_their_wire_names() {
+        let features =
+            AudioFeatures { rms: 0.5, bass: 0.25, mid: 0.125, treble: 1.0, is_beat: true };
+        let json = serde_json::to_value(FeatureFrame::from(&features)).unwrap();
+        let expected = serde_json::json!({
+            "rms": 0.5,
+            "bass": 0.25,
+            "mid": 0.125,
+            "treble": 1.0,
+            "beat": true,
+        });
+        assert_eq!(json, expected);
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+use crossterm::event::KeyCode;
+use serde::{Deserialize, Serialize};
+
+/// Something a normal-mode key can be bound to. Navigation (arrows, Space,
+/// Tab, Enter, Esc) stays fixed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    FocusInput,
+    Quit,
+    PlayPause,
+    Skip,
+    Previous,
+    SeekForward,
+    SeekBackward,
+    Restart,
+    NextChapter,
+    PreviousChapter,
+    JumpToLive,
+    VolumeUp,
+    VolumeDown,
+    ToggleMute,
+    SpeedUp,
+    SpeedDown,
+    ToggleShuffle,
+    CycleRepeat,
+    ClearQueue,
+    RemoveFromQueue,
+    MoveQueuedUp,
+    MoveQueuedDown,
+    SmoothQueue,
+    QueueLibrary,
+    ToggleFavorite,
+    Preview,
+    DownloadAll,
+    TogglePlaylists,
+    CycleLibrarySort,
+    FilterLibrary,
+    CycleSplit,
+    GrowVisualizer,
+    ShrinkVisualizer,
+    ToggleVisualizer,
+    CycleWaveStyle,
+    CycleOutput,
+    CopyNowPlaying,
+    OpenDataDir,
+    RetryFailure,
+    DismissFailures,
+}
+
+/// Config name and default keys for every action, in the order the
+/// defaults are applied.
+const DEFAULTS: &[(&str, Action, &[&str])] = &[
+    ("focus_input", Action::FocusInput, &["i"]),
+    ("quit", Action::Quit, &["q"]),
+    ("play_pause", Action::PlayPause, &["p"]),
+    ("skip", Action::Skip, &["n"]),
+    ("previous", Action::Previous, &["N"]),
+    ("seek_forward", Action::SeekForward, &["f"]),
+    ("seek_backward", Action::SeekBackward, &["b"]),
+    ("restart", Action::Restart, &["0"]),
+    ("next_chapter", Action::NextChapter, &["}"]),
+    ("previous_chapter", Action::PreviousChapter, &["{"]),
+    ("jump_to_live", Action::JumpToLive, &["l"]),
+    ("volume_up", Action::VolumeUp, &["+", "="]),
+    ("volume_down", Action::VolumeDown, &["-"]),
+    ("toggle_mute", Action::ToggleMute, &["m"]),
+    // [ and ] already resize the visualizer
+    ("speed_up", Action::SpeedUp, &[">"]),
+    ("speed_down", Action::SpeedDown, &["<"]),
+    ("toggle_shuffle", Action::ToggleShuffle, &["s"]),
+    ("cycle_repeat", Action::CycleRepeat, &["r"]),
+    ("clear_queue", Action::ClearQueue, &["c"]),
+    ("remove_from_queue", Action::RemoveFromQueue, &["x", "delete"]),
+    ("move_queued_up", Action::MoveQueuedUp, &["K"]),
+    ("move_queued_down", Action::MoveQueuedDown, &["J"]),
+    ("smooth_queue", Action::SmoothQueue, &["S"]),
+    ("queue_library", Action::QueueLibrary, &["A"]),
+    ("toggle_favorite", Action::ToggleFavorite, &["L", "*"]),
+    ("preview", Action::Preview, &["v"]),
+    ("download_all", Action::DownloadAll, &["D"]),
+    ("toggle_playlists", Action::TogglePlaylists, &["P"]),
+    ("cycle_library_sort", Action::CycleLibrarySort, &["g"]),
+    // Outside the library songs it enters the input bar instead
+    ("filter_library", Action::FilterLibrary, &["/"]),
+    ("cycle_split", Action::CycleSplit, &["t"]),
+    ("grow_visualizer", Action::GrowVisualizer, &["]"]),
+    ("shrink_visualizer", Action::ShrinkVisualizer, &["["]),
+    ("toggle_visualizer", Action::ToggleVisualizer, &["h"]),
+    ("cycle_wave_style", Action::CycleWaveStyle, &["w"]),
+    ("cycle_output", Action::CycleOutput, &["o"]),
+    ("copy_now_playing", Action::CopyNowPlaying, &["y"]),
+    ("open_data_dir", Action::OpenDataDir, &["O"]),
+    ("retry_failure", Action::RetryFailure, &["R"]),
+    ("dismiss_failures", Action::DismissFailures, &["X"]),
+];
+
+/// A `[keys]` entry: one key or several.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeySpec {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeySpec {
+    fn keys(&self) -> &[String] {
+        match self {
+            KeySpec::One(key) => std::slice::from_ref(key),
+            KeySpec::Many(keys) => keys,
+        }
+    }
+}
+
+/// Normal-mode key bindings: the defaults with `[keys]` overrides applied.
+#[derive(Debug, Clone)]
+pub struct KeyMap {
+    bindings: HashMap<KeyCode, Action>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self::from_file(None, &mut Vec::new())
+    }
+}
+
+impl KeyMap {
+    /// An action listed in `[keys]` replaces all of its default keys.
+    /// Unknown actions, unparseable keys and keys claimed twice are skipped
+    /// with a warning; a clash with a default keeps the configured binding
+    /// and warns that the default lost its key.
+    pub fn from_file(file: Option<&HashMap<String, KeySpec>>, warnings: &mut Vec<String>) -> Self {
+        let overrides = file.cloned().unwrap_or_default();
+        for name in overrides.keys() {
+            if !DEFAULTS.iter().any(|(n, _, _)| n == name) {
+                warnings.push(format!("keys: unknown action {:?}", name));
+            }
+        }
+
+        let mut bindings = HashMap::new();
+        // Configured bindings first so they win over defaults they clash with
+        let mut names: Vec<&String> = overrides.keys().collect();
+        names.sort();
+        for name in names {
+            let Some((_, action, _)) = DEFAULTS.iter().find(|(n, _, _)| n == name) else {
+                continue;
+            };
+            let mut bound = false;
+            for key in overrides[name].keys() {
+                let Some(code) = parse_key(key) else {
+                    warnings.push(format!("keys: {} has unrecognized key {:?}", name, key));
+                    continue;
+                };
+                match bindings.get(&code) {
+                    Some(other) if other != action => warnings.push(format!(
+                        "keys: {:?} is bound to both {} and {}, keeping {}",
+                        key,
+                        action_name(*other),
+                        name,
+                        action_name(*other)
+                    )),
+                    _ => {
+                        bindings.insert(code, *action);
+                        bound = true;
+                    }
+                }
+            }
+            if !bound {
+                warnings.push(format!("keys: {} is left without a key", name));
+            }
+        }
+        for (name, action, keys) in DEFAULTS {
+            if overrides.contains_key(*name) {
+                continue;
+            }
+            let mut bound = false;
+            for code in keys.iter().filter_map(|key| parse_key(key)) {
+                match bindings.get(&code) {
+                    Some(other) => warnings.push(format!(
+                        "keys: {} took {:?} from {}",
+                        action_name(*other),
+                        key_label(code),
+                        name
+                    )),
+                    None => {
+                        bindings.insert(code, *action);
+                        bound = true;
+                    }
+                }
+            }
+            if !bound {
+                warnings.push(format!("keys: {} is left without a key", name));
+            }
+        }
+        Self { bindings }
+    }
+
+    pub fn action(&self, code: KeyCode) -> Option<Action> {
+        self.bindings.get(&code).copied()
+    }
+
+    /// Display label for the first key bound to `action`, for hints.
+    pub fn label(&self, action: Action) -> String {
+        let mut keys: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|(_, a)| **a == action)
+            .map(|(code, _)| key_label(*code))
+            .collect();
+        keys.sort();
+        keys.into_iter().next().unwrap_or_else(|| "-".to_string())
+    }
+}
+
+fn action_name(action: Action) -> &'static str {
+    DEFAULTS
+        .iter()
+        .find(|(_, a, _)| *a == action)
+        .map_or("?", |(name, _, _)| name)
+}
+
+/// A single character (`"p"`, `"+"`), or a named key (`"space"`, `"delete"`,
+/// `"backspace"`, `"f1"`–`"f12"`).
+fn parse_key(key: &str) -> Option<KeyCode> {
+    let mut chars = key.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(c));
+    }
+    let lower = key.to_ascii_lowercase();
+    match lower.as_str() {
+        "space" => Some(KeyCode::Char(' ')),
+        "delete" | "del" => Some(KeyCode::Delete),
+        "backspace" => Some(KeyCode::Backspace),
+        "insert" => Some(KeyCode::Insert),
+        "home" => Some(KeyCode::Home),
+        "end" => Some(KeyCode::End),
+        "pageup" => Some(KeyCode::PageUp),
+        "pagedown" => Some(KeyCode::PageDown),
+        _ => {
+            let n: u8 = lower.strip_prefix('f')?.parse().ok()?;
+            (1..=12).contains(&n).then_some(KeyCode::F(n))
+        }
+    }
+}
+
+fn key_label(code: KeyCode) -> String {
+    match code {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slash_filters_and_i_focuses_the_input() {
+        let keys = KeyMap::default();
+        assert_eq!(keys.action(KeyCode::Char('/')), Some(Action::FilterLibrary));
+        assert_eq!(keys.action(KeyCode::Char('i')), Some(Action::FocusInput));
+    }
+
+    #[test]
+    fn filter_key_can_be_rebound() {
+        let file = HashMap::from([("filter_library".to_string(), KeySpec::One("F".to_string()))]);
+        let mut warnings = Vec::new();
+        let keys = KeyMap::from_file(Some(&file), &mut warnings);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(keys.action(KeyCode::Char('F')), Some(Action::FilterLibrary));
+        assert_eq!(keys.action(KeyCode::Char('/')), None);
+    }
+
+    fn from_pairs(pairs: &[(&str, &[&str])]) -> (KeyMap, Vec<String>) {
+        let file = pairs
+            .iter()
+            .map(|(name, keys)| {
+                let keys = keys.iter().map(|key| key.to_string()).collect();
+                (name.to_string(), KeySpec::Many(keys))
+            })
+            .collect();
+        let mut warnings = Vec::new();
+        let keys = KeyMap::from_file(Some(&file), &mut warnings);
+        (keys, warnings)
+    }
+
+    #[test]
+    fn defaults_bind_every_action_without_warnings() {
+        let mut warnings = Vec::new();
+        let keys = KeyMap::from_file(None, &mut warnings);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        for (_, action, _) in DEFAULTS {
+            assert!(keys.bindings.values().any(|a| a == action), "{:?}", action);
+        }
+    }
+
+    #[test]
+    fn unknown_actions_and_unrecognized_keys_warn() {
+        let (keys, warnings) = from_pairs(&[("dance", &["d"]), ("skip", &["ctrl+n", "k"])]);
+        assert_eq!(
+            warnings,
+            ["keys: unknown action \"dance\"", "keys: skip has unrecognized key \"ctrl+n\""]
+        );
+        assert_eq!(keys.action(KeyCode::Char('d')), None);
+        assert_eq!(keys.action(KeyCode::Char('k')), Some(Action::Skip));
+    }
+
+    #[test]
+    fn taking_a_default_key_warns_and_names_the_unbound_action() {
+        let (keys, warnings) = from_pairs(&[("skip", &["p"])]);
+        assert_eq!(
+            warnings,
+            ["keys: skip took \"p\" from play_pause", "keys: play_pause is left without a key"]
+        );
+        assert_eq!(keys.action(KeyCode::Char('p')), Some(Action::Skip));
+        assert!(!keys.bindings.values().any(|a| *a == Action::PlayPause));
+
+        // An action with another default key keeps that one
+        let (keys, warnings) = from_pairs(&[("skip", &["="])]);
+        assert_eq!(warnings, ["keys: skip took \"=\" from volume_up"]);
+        assert_eq!(keys.label(Action::VolumeUp), "+");
+    }
+
+    #[test]
+    fn a_key_configured_twice_stays_with_the_first_action() {
+        let (keys, warnings) = from_pairs(&[("quit", &["z"]), ("skip", &["z"])]);
+        assert_eq!(
+            warnings,
+            [
+                "keys: \"z\" is bound to both quit and skip, keeping quit",
+                "keys: skip is left without a key",
+            ]
+        );
+        assert_eq!(keys.action(KeyCode::Char('z')), Some(Action::Quit));
+    }
+}
@@ -1,7 +1,26 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use tracing::{debug, info};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::downloader::Chapter;
+use crate::log_throttle::LogThrottle;
+
+/// Every play and download saves the library; don't log each one.
+static SAVE_LOG: LogThrottle = LogThrottle::new(5, Duration::from_secs(60));
+
+/// Without deferred saving, changes this soon after a save wait for
+/// `flush_coalesced` rather than each rewriting the file, so a burst of
+/// finished downloads costs a couple of writes.
+const SAVE_COALESCE: Duration = Duration::from_secs(1);
+
+/// `flush_coalesced` waits this long to retry after a failed save, doubling
+/// with each failure in a row up to `SAVE_RETRY_MAX`, so a full disk isn't
+/// hit (and reported) on every tick.
+const SAVE_RETRY: Duration = Duration::from_secs(1);
+const SAVE_RETRY_MAX: Duration = Duration::from_secs(60);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LibraryEntry {
@@ -12,60 +31,530 @@ pub struct LibraryEntry {
     pub duration_secs: f64,
     pub file_path: String,
     pub downloaded_at: String,
+    /// Format override used for this download, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_format: Option<String>,
+    /// When this entry was last played (RFC 3339); `None` if never.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_played: Option<String>,
+    /// Times this entry started playing.
+    #[serde(default)]
+    pub play_count: u32,
+    #[serde(default)]
+    pub favorite: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chapters: Vec<Chapter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    /// Cover image file name in the cache dir
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+}
+
+impl LibraryEntry {
+    /// Recency used for eviction: last play, or the download if never played.
+    /// Both are UTC RFC 3339 strings, so they order lexicographically.
+    fn last_used(&self) -> &str {
+        self.last_played.as_deref().unwrap_or(&self.downloaded_at)
+    }
+
+    pub fn thumbnail_path(&self, cache_dir: &Path) -> Option<PathBuf> {
+        self.thumbnail.as_ref().map(|name| cache_dir.join(name))
+    }
+}
+
+/// Delete a cached file; one that's already gone counts as deleted.
+fn remove_cached_file(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// On-disk shape of the library file.
+#[derive(Deserialize)]
+struct LibraryFile {
+    entries: Vec<LibraryEntry>,
+    #[serde(default)]
+    playlists: HashMap<String, Vec<String>>,
+}
+
+/// `LibraryFile` borrowed from a `Library` for saving.
+#[derive(Serialize)]
+struct LibraryFileRef<'a> {
+    entries: &'a [LibraryEntry],
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    playlists: &'a HashMap<String, Vec<String>>,
+}
+
+/// Library files from before playlists were a bare list of entries.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredLibrary {
+    Current(LibraryFile),
+    Legacy(Vec<LibraryEntry>),
 }
 
 #[derive(Debug)]
 pub struct Library {
     entries: Vec<LibraryEntry>,
+    /// Playlist name to video ids, in play order. Ids may outlive their
+    /// entry (eviction) or cached file; loading skips those.
+    playlists: HashMap<String, Vec<String>>,
     path: PathBuf,
+    /// Cap on the number of entries; least recently used metadata is dropped
+    /// beyond it. Cached files are left alone.
+    max_entries: Option<usize>,
+    /// Leave writing to the autosave instead of saving on every change
+    deferred: bool,
+    /// Changed since the last save
+    dirty: bool,
+    last_saved: Option<Instant>,
+    /// Failed `flush_coalesced` saves in a row, and when it may try again
+    save_failures: u32,
+    retry_at: Option<Instant>,
 }
 
 impl Library {
-    pub fn load(path: PathBuf) -> Result<Self> {
-        let entries = if path.exists() {
+    /// With `deferred`, changes are only written by `save_if_dirty` (the
+    /// autosave) or `save`; otherwise changes are written straight away, or
+    /// by `flush_coalesced` if one was just written.
+    pub fn load(path: PathBuf, max_entries: Option<usize>, deferred: bool) -> Result<Self> {
+        let (entries, playlists) = if path.exists() {
             let data = std::fs::read_to_string(&path)
                 .context("Failed to read library file")?;
-            let entries: Vec<LibraryEntry> = serde_json::from_str(&data)
+            let stored: StoredLibrary = serde_json::from_str(&data)
                 .context("Failed to parse library JSON")?;
-            info!(count = entries.len(), "library loaded from disk");
-            entries
+            let (entries, playlists) = match stored {
+                StoredLibrary::Current(file) => (file.entries, file.playlists),
+                StoredLibrary::Legacy(entries) => (entries, HashMap::new()),
+            };
+            info!(count = entries.len(), playlists = playlists.len(), "library loaded from disk");
+            (entries, playlists)
         } else {
             debug!(path = %path.display(), "library file not found, starting empty");
-            Vec::new()
+            (Vec::new(), HashMap::new())
         };
 
-        Ok(Self { entries, path })
+        let mut library = Self {
+            entries,
+            playlists,
+            path,
+            max_entries,
+            deferred,
+            dirty: false,
+            last_saved: None,
+            save_failures: 0,
+            retry_at: None,
+        };
+        let evicted = library.evict_excess(None);
+        if !evicted.is_empty() {
+            library.save()?;
+        }
+        Ok(library)
     }
 
-    pub fn save(&self) -> Result<()> {
+    pub fn save(&mut self) -> Result<()> {
         if let Some(parent) = self.path.parent() {
             std::fs::create_dir_all(parent)
                 .context("Failed to create library directory")?;
         }
-        let data = serde_json::to_string_pretty(&self.entries)
+        let file = LibraryFileRef {
+            entries: &self.entries,
+            playlists: &self.playlists,
+        };
+        let data = serde_json::to_string_pretty(&file)
             .context("Failed to serialize library")?;
-        std::fs::write(&self.path, data)
+        // Write beside the file and rename over it, so a crash mid-write
+        // leaves the previous library rather than half of this one
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, data)
             .context("Failed to write library file")?;
-        debug!(path = %self.path.display(), count = self.entries.len(), "library saved");
+        std::fs::rename(&tmp, &self.path)
+            .context("Failed to replace library file")?;
+        self.dirty = false;
+        self.last_saved = Some(Instant::now());
+        if SAVE_LOG.allow() {
+            debug!(path = %self.path.display(), count = self.entries.len(), "library saved");
+        }
         Ok(())
     }
 
-    pub fn add(&mut self, entry: LibraryEntry) -> Result<()> {
+    /// Write out changes held back by deferred saving. Returns whether there
+    /// were any.
+    pub fn save_if_dirty(&mut self) -> Result<bool> {
+        if !self.dirty {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Write out changes held back by `SAVE_COALESCE`, once it has passed,
+    /// or left by a failed save, once its retry backoff has. Returns whether
+    /// there were any. Does nothing with deferred saving; the autosave covers
+    /// that.
+    pub fn flush_coalesced(&mut self) -> Result<bool> {
+        let now = Instant::now();
+        let waiting = self.last_saved.is_some_and(|t| now.duration_since(t) < SAVE_COALESCE)
+            || self.retry_at.is_some_and(|t| now < t);
+        if self.deferred || waiting {
+            return Ok(false);
+        }
+        match self.save_if_dirty() {
+            Ok(saved) => {
+                self.save_failures = 0;
+                self.retry_at = None;
+                Ok(saved)
+            }
+            Err(e) => {
+                let backoff = SAVE_RETRY.saturating_mul(1 << self.save_failures.min(6));
+                self.save_failures += 1;
+                self.retry_at = Some(now + backoff.min(SAVE_RETRY_MAX));
+                Err(e)
+            }
+        }
+    }
+
+    /// Persist a change now, or mark it for the next autosave or coalesced
+    /// save. A change that fails to save stays marked, for
+    /// `flush_coalesced` to retry.
+    fn changed(&mut self) -> Result<()> {
+        self.dirty = true;
+        let recent = self.last_saved.is_some_and(|t| t.elapsed() < SAVE_COALESCE);
+        if self.deferred || recent || self.retry_at.is_some() {
+            Ok(())
+        } else {
+            self.save()
+        }
+    }
+
+    /// Add or update an entry. Returns the entries evicted to stay under the
+    /// cap; the new entry itself is never one of them.
+    pub fn add(&mut self, entry: LibraryEntry) -> Result<Vec<LibraryEntry>> {
+        let video_id = entry.video_id.clone();
         if let Some(existing) = self.entries.iter_mut().find(|e| e.video_id == entry.video_id) {
             info!(video_id = %entry.video_id, "updating existing library entry");
+            let last_played = existing.last_played.take();
+            let (play_count, favorite) = (existing.play_count, existing.favorite);
             *existing = entry;
+            existing.last_played = last_played;
+            existing.play_count = play_count;
+            existing.favorite = favorite;
         } else {
             info!(video_id = %entry.video_id, title = %entry.title, "adding new library entry");
             self.entries.push(entry);
         }
-        self.save()
+        let evicted = self.evict_excess(Some(&video_id));
+        self.changed()?;
+        Ok(evicted)
+    }
+
+    /// Record that the entry for `url` just started playing.
+    pub fn mark_played(&mut self, url: &str) -> Result<()> {
+        let Some(entry) = self.entries.iter_mut().find(|e| e.url == url) else {
+            return Ok(());
+        };
+        entry.last_played = Some(chrono::Utc::now().to_rfc3339());
+        entry.play_count = entry.play_count.saturating_add(1);
+        debug!(%url, plays = entry.play_count, "marked library entry played");
+        self.changed()
+    }
+
+    /// Mark the entry for `url` as a favorite or not. Returns `false` if
+    /// there's no such entry.
+    pub fn set_favorite(&mut self, url: &str, favorite: bool) -> Result<bool> {
+        let Some(entry) = self.entries.iter_mut().find(|e| e.url == url) else {
+            return Ok(false);
+        };
+        if entry.favorite != favorite {
+            entry.favorite = favorite;
+            info!(%url, favorite, "set library entry favorite");
+            self.changed()?;
+        }
+        Ok(true)
+    }
+
+    /// Drop least recently used entries until the cap is met, sparing `keep`.
+    fn evict_excess(&mut self, keep: Option<&str>) -> Vec<LibraryEntry> {
+        let Some(max) = self.max_entries else {
+            return Vec::new();
+        };
+        let mut evicted = Vec::new();
+        while self.entries.len() > max {
+            let oldest = self
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(_, e)| Some(e.video_id.as_str()) != keep)
+                .min_by(|(_, a), (_, b)| a.last_used().cmp(b.last_used()))
+                .map(|(i, _)| i);
+            let Some(i) = oldest else { break };
+            let entry = self.entries.remove(i);
+            info!(video_id = %entry.video_id, title = %entry.title, max, "evicted library entry");
+            evicted.push(entry);
+        }
+        evicted
+    }
+
+    /// Delete least recently used cached files, and their entries, until the
+    /// files under `cache_dir` total at most `max_bytes`. Entries whose url
+    /// is in `keep` (the playing song, a fresh download) are never evicted,
+    /// nor are ones whose file can't be deleted. Returns the evicted entries.
+    pub fn evict_to_fit(
+        &mut self,
+        cache_dir: &Path,
+        max_bytes: u64,
+        keep: &[&str],
+    ) -> Result<Vec<LibraryEntry>> {
+        let file_size = |entry: &LibraryEntry| {
+            std::fs::metadata(cache_dir.join(&entry.file_path)).map_or(0, |m| m.len())
+        };
+        let mut total: u64 = self.entries.iter().map(file_size).sum();
+        if total <= max_bytes {
+            return Ok(Vec::new());
+        }
+
+        let mut evicted = Vec::new();
+        let mut undeletable: Vec<String> = Vec::new();
+        while total > max_bytes {
+            let oldest = self
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(_, e)| !keep.contains(&e.url.as_str()))
+                .filter(|(_, e)| !undeletable.contains(&e.video_id))
+                .min_by(|(_, a), (_, b)| a.last_used().cmp(b.last_used()))
+                .map(|(i, _)| i);
+            let Some(i) = oldest else { break };
+            let size = file_size(&self.entries[i]);
+            // An entry whose file stays behind would leave it untracked, and
+            // the cache no smaller
+            let path = cache_dir.join(&self.entries[i].file_path);
+            if let Err(e) = remove_cached_file(&path) {
+                warn!(path = %path.display(), ?e, "failed to delete cached file, keeping it");
+                undeletable.push(self.entries[i].video_id.clone());
+                continue;
+            }
+            let entry = self.entries.remove(i);
+            if let Some(path) = entry.thumbnail_path(cache_dir) {
+                if let Err(e) = remove_cached_file(&path) {
+                    warn!(path = %path.display(), ?e, "failed to delete cached thumbnail");
+                }
+            }
+            total = total.saturating_sub(size);
+            info!(video_id = %entry.video_id, title = %entry.title, size, "evicted cached song");
+            evicted.push(entry);
+        }
+        if total > max_bytes {
+            warn!(total, max_bytes, "cache still over its cap; the rest is in use");
+        }
+        if !evicted.is_empty() {
+            self.changed()?;
+        }
+        Ok(evicted)
+    }
+
+    /// Create an empty playlist. Returns `false` if the name is taken.
+    pub fn create_playlist(&mut self, name: &str) -> Result<bool> {
+        if self.playlists.contains_key(name) {
+            return Ok(false);
+        }
+        info!(%name, "created playlist");
+        self.playlists.insert(name.to_string(), Vec::new());
+        self.changed()?;
+        Ok(true)
+    }
+
+    /// Append `video_id` to the playlist, creating it if needed. Returns
+    /// `false` if it was already there.
+    pub fn add_to_playlist(&mut self, name: &str, video_id: &str) -> Result<bool> {
+        let ids = self.playlists.entry(name.to_string()).or_default();
+        if ids.iter().any(|id| id == video_id) {
+            return Ok(false);
+        }
+        ids.push(video_id.to_string());
+        info!(%name, %video_id, "added to playlist");
+        self.changed()?;
+        Ok(true)
+    }
+
+    /// Returns `false` if the playlist didn't contain `video_id`.
+    pub fn remove_from_playlist(&mut self, name: &str, video_id: &str) -> Result<bool> {
+        let Some(ids) = self.playlists.get_mut(name) else {
+            return Ok(false);
+        };
+        let before = ids.len();
+        ids.retain(|id| id != video_id);
+        if ids.len() == before {
+            return Ok(false);
+        }
+        info!(%name, %video_id, "removed from playlist");
+        self.changed()?;
+        Ok(true)
+    }
+
+    pub fn playlist(&self, name: &str) -> Option<&[String]> {
+        self.playlists.get(name).map(Vec::as_slice)
+    }
+
+    /// Playlist names with their lengths, sorted by name.
+    pub fn playlist_summaries(&self) -> Vec<(String, usize)> {
+        let mut summaries: Vec<(String, usize)> = self
+            .playlists
+            .iter()
+            .map(|(name, ids)| (name.clone(), ids.len()))
+            .collect();
+        summaries.sort();
+        summaries
+    }
+
+    pub fn find_by_video_id(&self, video_id: &str) -> Option<&LibraryEntry> {
+        self.entries.iter().find(|e| e.video_id == video_id)
     }
 
     pub fn find_by_url(&self, url: &str) -> Option<&LibraryEntry> {
         self.entries.iter().find(|e| e.url == url)
     }
 
+    /// Up to `n` played entries, most plays first; ties go to the more
+    /// recently played.
+    pub fn most_played(&self, n: usize) -> Vec<&LibraryEntry> {
+        let mut played: Vec<&LibraryEntry> =
+            self.entries.iter().filter(|e| e.play_count > 0).collect();
+        played.sort_by(|a, b| {
+            b.play_count.cmp(&a.play_count).then_with(|| b.last_played.cmp(&a.last_played))
+        });
+        played.truncate(n);
+        played
+    }
+
     pub fn entries(&self) -> &[LibraryEntry] {
         &self.entries
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{library_entry, TempDir};
+
+    /// Library under `dir` with a cached file of `size` bytes per entry,
+    /// least recently used first.
+    fn cached_library(dir: &Path, sizes: &[(&str, usize)]) -> Library {
+        let mut library = Library::load(dir.join("library.json"), None, false).unwrap();
+        for (i, (video_id, size)) in sizes.iter().enumerate() {
+            let mut entry = library_entry(video_id);
+            entry.last_played = Some(format!("2024-01-0{}T00:00:00+00:00", i + 1));
+            std::fs::write(dir.join(&entry.file_path), vec![0u8; *size]).unwrap();
+            library.add(entry).unwrap();
+        }
+        library
+    }
+
+    fn ids(entries: &[LibraryEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.video_id.as_str()).collect()
+    }
+
+    #[test]
+    fn adding_past_the_cap_evicts_the_least_recently_used() {
+        let dir = TempDir::new("max-entries");
+        let mut library = Library::load(dir.path().join("library.json"), Some(2), false).unwrap();
+        let mut a = library_entry("a");
+        a.downloaded_at = "2024-01-01T00:00:00+00:00".to_string();
+        let mut b = library_entry("b");
+        b.downloaded_at = "2024-02-01T00:00:00+00:00".to_string();
+        assert!(library.add(a).unwrap().is_empty());
+        assert!(library.add(b).unwrap().is_empty());
+        // Playing "a" makes "b" the oldest
+        library.mark_played(&library_entry("a").url).unwrap();
+
+        let mut c = library_entry("c");
+        c.downloaded_at = "2023-01-01T00:00:00+00:00".to_string();
+        // The new entry stays even though it looks oldest
+        assert_eq!(ids(&library.add(c).unwrap()), ["b"]);
+        assert_eq!(ids(library.entries()), ["a", "c"]);
+    }
+
+    #[test]
+    fn evict_to_fit_drops_least_recently_used_files() {
+        let dir = TempDir::new("evict-lru");
+        let mut library = cached_library(dir.path(), &[("a", 100), ("b", 100), ("c", 100)]);
+
+        let evicted = library.evict_to_fit(dir.path(), 150, &[]).unwrap();
+        assert_eq!(ids(&evicted), ["a", "b"]);
+        assert_eq!(ids(library.entries()), ["c"]);
+        assert!(!dir.path().join("a.mp3").exists());
+        assert!(dir.path().join("c.mp3").exists());
+
+        // Already under the cap
+        assert!(library.evict_to_fit(dir.path(), 150, &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn evict_to_fit_spares_kept_songs() {
+        let dir = TempDir::new("evict-keep");
+        let mut library = cached_library(dir.path(), &[("a", 100), ("b", 100), ("c", 100)]);
+        let keep = library_entry("a").url;
+
+        let evicted = library.evict_to_fit(dir.path(), 150, &[&keep]).unwrap();
+        assert_eq!(ids(&evicted), ["b", "c"]);
+        assert_eq!(ids(library.entries()), ["a"]);
+    }
+
+    #[test]
+    fn evict_to_fit_keeps_entries_whose_file_wont_delete() {
+        let dir = TempDir::new("evict-stuck");
+        let mut library = cached_library(dir.path(), &[("b", 100), ("c", 100)]);
+        // A directory where the file should be can't be removed as one
+        let mut stuck = library_entry("a");
+        stuck.last_played = Some("2023-01-01T00:00:00+00:00".to_string());
+        std::fs::create_dir(dir.path().join(&stuck.file_path)).unwrap();
+        let stuck_size = std::fs::metadata(dir.path().join(&stuck.file_path)).unwrap().len();
+        library.add(stuck).unwrap();
+
+        let evicted = library.evict_to_fit(dir.path(), stuck_size + 150, &[]).unwrap();
+        assert_eq!(ids(&evicted), ["b"]);
+        assert_eq!(ids(library.entries()), ["c", "a"]);
+        assert!(dir.path().join("a.mp3").is_dir());
+    }
+
+    #[test]
+    fn playlists_are_created_and_edited_by_video_id() {
+        let dir = TempDir::new("playlists");
+        let path = dir.path().join("library.json");
+        let mut library = Library::load(path.clone(), None, false).unwrap();
+
+        assert!(library.create_playlist("road trip").unwrap());
+        assert!(!library.create_playlist("road trip").unwrap());
+        assert!(library.add_to_playlist("road trip", "a").unwrap());
+        assert!(library.add_to_playlist("road trip", "b").unwrap());
+        assert!(library.remove_from_playlist("road trip", "a").unwrap());
+        assert!(!library.remove_from_playlist("road trip", "a").unwrap());
+        assert!(!library.remove_from_playlist("missing", "b").unwrap());
+
+        library.save_if_dirty().unwrap();
+        let reloaded = Library::load(path, None, false).unwrap();
+        assert_eq!(reloaded.playlist("road trip").unwrap(), ["b"]);
+    }
+
+    #[test]
+    fn failed_flush_waits_before_retrying() {
+        let dir = TempDir::new("flush-retry");
+        // A file where the library's directory should be makes every save fail
+        let blocker = dir.path().join("blocker");
+        std::fs::write(&blocker, "").unwrap();
+        let mut library = Library::load(blocker.join("library.json"), None, false).unwrap();
+
+        assert!(library.add(library_entry("a")).is_err());
+        assert!(library.flush_coalesced().is_err());
+        // Still dirty, but backing off instead of failing again every tick
+        assert!(!library.flush_coalesced().unwrap());
+        assert!(library.dirty);
+
+        std::fs::remove_file(&blocker).unwrap();
+        library.retry_at = Some(Instant::now());
+        assert!(library.flush_coalesced().unwrap());
+        assert_eq!(library.save_failures, 0);
+    }
+}
@@ -0,0 +1,405 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::{Config, Provider};
+
+/// Anthropic API version sent with every Messages request.
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+const MAX_TOKENS: u32 = 1024;
+
+/// A chat message in the shape the agent thinks in; each provider turns it
+/// into its own wire format.
+pub enum Message {
+    User(String),
+    Assistant {
+        text: Option<String>,
+        tool_calls: Vec<ToolUse>,
+    },
+    /// Outcomes of the previous assistant turn's tool calls, followed by
+    /// `prompt` from the user.
+    ToolResults {
+        results: Vec<ToolResult>,
+        prompt: String,
+    },
+}
+
+/// A tool call from the model; `id` pairs it with its result.
+#[derive(Debug, Clone)]
+pub struct ToolUse {
+    pub id: String,
+    pub name: String,
+    pub input: Value,
+}
+
+pub struct ToolResult {
+    pub id: String,
+    pub content: String,
+    pub is_error: bool,
+}
+
+/// What the model answered: its text, if any, and the tools it called.
+pub struct Reply {
+    pub text: Option<String>,
+    pub tool_calls: Vec<ToolUse>,
+}
+
+/// One chat API with tool calling. Only the wire format differs between
+/// providers; sending, retries and key rotation stay with the agent.
+pub trait LlmProvider: Send + Sync {
+    /// Shown in logs and errors.
+    fn name(&self) -> &'static str;
+
+    /// A POST to the chat endpoint, authenticated with `api_key` unless it's
+    /// empty.
+    fn request(&self, client: &reqwest::Client, api_key: &str) -> reqwest::RequestBuilder;
+
+    /// Request body. `tools` are in Anthropic's shape, as `tool_definitions`
+    /// writes them. With `text_only` the model may not call any tool.
+    fn body(
+        &self,
+        model: &str,
+        system: &str,
+        tools: &Value,
+        messages: &[Message],
+        text_only: bool,
+    ) -> Value;
+
+    fn parse(&self, raw: &str) -> Result<Reply>;
+}
+
+/// The provider `config` selects, talking to its `base_url`.
+pub fn provider(config: &Config) -> Box<dyn LlmProvider> {
+    let base_url = config.base_url.clone();
+    match config.provider {
+        Provider::Anthropic => Box::new(Anthropic { base_url }),
+        Provider::OpenAi => Box::new(OpenAi { base_url }),
+    }
+}
+
+/// Non-empty text parts joined into one reply.
+fn join_text<'a>(parts: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    let texts: Vec<&str> = parts.into_iter().map(str::trim).filter(|t| !t.is_empty()).collect();
+    (!texts.is_empty()).then(|| texts.join(" "))
+}
+
+/// Anthropic's Messages API.
+struct Anthropic {
+    base_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicResponse {
+    content: Vec<ContentBlock>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum ContentBlock {
+    #[serde(rename = "text")]
+    Text { text: String },
+    #[serde(rename = "tool_use")]
+    ToolUse { id: String, name: String, input: Value },
+    /// Thinking and anything newer; never acted on
+    #[serde(other)]
+    Other,
+}
+
+impl Anthropic {
+    fn message(message: &Message) -> Value {
+        match message {
+            Message::User(text) => json!({ "role": "user", "content": text }),
+            Message::Assistant { text, tool_calls } => {
+                let mut content: Vec<Value> = text
+                    .iter()
+                    .map(|text| json!({ "type": "text", "text": text }))
+                    .collect();
+                content.extend(tool_calls.iter().map(|call| {
+                    json!({
+                        "type": "tool_use",
+                        "id": call.id,
+                        "name": call.name,
+                        "input": call.input,
+                    })
+                }));
+                json!({ "role": "assistant", "content": content })
+            }
+            Message::ToolResults { results, prompt } => {
+                let mut content: Vec<Value> = results
+                    .iter()
+                    .map(|result| {
+                        json!({
+                            "type": "tool_result",
+                            "tool_use_id": result.id,
+                            "content": result.content,
+                            "is_error": result.is_error,
+                        })
+                    })
+                    .collect();
+                content.push(json!({ "type": "text", "text": prompt }));
+                json!({ "role": "user", "content": content })
+            }
+        }
+    }
+}
+
+impl LlmProvider for Anthropic {
+    fn name(&self) -> &'static str {
+        "Claude API"
+    }
+
+    fn request(&self, client: &reqwest::Client, api_key: &str) -> reqwest::RequestBuilder {
+        client
+            .post(format!("{}/messages", self.base_url))
+            .header("x-api-key", api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .header("content-type", "application/json")
+    }
+
+    fn body(
+        &self,
+        model: &str,
+        system: &str,
+        tools: &Value,
+        messages: &[Message],
+        text_only: bool,
+    ) -> Value {
+        let mut body = json!({
+            "model": model,
+            "max_tokens": MAX_TOKENS,
+            "system": system,
+            "tools": tools,
+            "messages": messages.iter().map(Self::message).collect::<Vec<_>>(),
+        });
+        if text_only {
+            body["tool_choice"] = json!({ "type": "none" });
+        }
+        body
+    }
+
+    fn parse(&self, raw: &str) -> Result<Reply> {
+        let resp: AnthropicResponse =
+            serde_json::from_str(raw).context("Failed to parse API response JSON")?;
+        let mut texts = Vec::new();
+        let mut tool_calls = Vec::new();
+        for block in resp.content {
+            match block {
+                ContentBlock::Text { text } => texts.push(text),
+                ContentBlock::ToolUse { id, name, input } => {
+                    tool_calls.push(ToolUse { id, name, input })
+                }
+                ContentBlock::Other => {}
+            }
+        }
+        Ok(Reply {
+            text: join_text(texts.iter().map(String::as_str)),
+            tool_calls,
+        })
+    }
+}
+
+/// OpenAI-style chat completions with function calling, as served by OpenAI
+/// and by local servers such as Ollama or llama.cpp.
+struct OpenAi {
+    base_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    message: ChatMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    #[serde(default)]
+    content: Option<String>,
+    /// Some servers send `null` rather than leaving it out
+    #[serde(default)]
+    tool_calls: Option<Vec<FunctionCall>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FunctionCall {
+    /// Some local servers leave it out
+    #[serde(default)]
+    id: String,
+    function: Function,
+}
+
+#[derive(Debug, Deserialize)]
+struct Function {
+    name: String,
+    /// JSON encoded as a string
+    #[serde(default)]
+    arguments: String,
+}
+
+impl OpenAi {
+    /// Anthropic's `{name, description, input_schema}` as a function tool.
+    fn tool(tool: &Value) -> Value {
+        json!({
+            "type": "function",
+            "function": {
+                "name": tool["name"],
+                "description": tool["description"],
+                "parameters": tool["input_schema"],
+            }
+        })
+    }
+
+    /// One message per tool result, so this returns several.
+    fn messages(message: &Message) -> Vec<Value> {
+        match message {
+            Message::User(text) => vec![json!({ "role": "user", "content": text })],
+            Message::Assistant { text, tool_calls } => {
+                let mut message = json!({ "role": "assistant", "content": text });
+                if !tool_calls.is_empty() {
+                    let calls: Vec<Value> = tool_calls
+                        .iter()
+                        .map(|call| {
+                            json!({
+                                "id": call.id,
+                                "type": "function",
+                                "function": {
+                                    "name": call.name,
+                                    "arguments": call.input.to_string(),
+                                }
+                            })
+                        })
+                        .collect();
+                    message["tool_calls"] = json!(calls);
+                }
+                vec![message]
+            }
+            Message::ToolResults { results, prompt } => {
+                let mut messages: Vec<Value> = results
+                    .iter()
+                    .map(|result| {
+                        // There's no error flag, so say it in the content
+                        let content = if result.is_error {
+                            format!("error: {}", result.content)
+                        } else {
+                            result.content.clone()
+                        };
+                        json!({ "role": "tool", "tool_call_id": result.id, "content": content })
+                    })
+                    .collect();
+                messages.push(json!({ "role": "user", "content": prompt }));
+                messages
+            }
+        }
+    }
+}
+
+impl LlmProvider for OpenAi {
+    fn name(&self) -> &'static str {
+        "chat completions API"
+    }
+
+    fn request(&self, client: &reqwest::Client, api_key: &str) -> reqwest::RequestBuilder {
+        let request = client
+            .post(format!("{}/chat/completions", self.base_url))
+            .header("content-type", "application/json");
+        if api_key.is_empty() {
+            request
+        } else {
+            request.bearer_auth(api_key)
+        }
+    }
+
+    fn body(
+        &self,
+        model: &str,
+        system: &str,
+        tools: &Value,
+        messages: &[Message],
+        text_only: bool,
+    ) -> Value {
+        let mut wire = vec![json!({ "role": "system", "content": system })];
+        wire.extend(messages.iter().flat_map(Self::messages));
+        let tools: Vec<Value> = tools
+            .as_array()
+            .map(|tools| tools.iter().map(Self::tool).collect())
+            .unwrap_or_default();
+        let mut body = json!({
+            "model": model,
+            "max_tokens": MAX_TOKENS,
+            "tools": tools,
+            "messages": wire,
+        });
+        if text_only {
+            body["tool_choice"] = json!("none");
+        }
+        body
+    }
+
+    fn parse(&self, raw: &str) -> Result<Reply> {
+        let resp: ChatResponse =
+            serde_json::from_str(raw).context("Failed to parse API response JSON")?;
+        let message = resp
+            .choices
+            .into_iter()
+            .next()
+            .context("API response has no choices")?
+            .message;
+        let tool_calls = message
+            .tool_calls
+            .unwrap_or_default()
+            .into_iter()
+            .enumerate()
+            .map(|(i, call)| {
+                let arguments = call.function.arguments.trim();
+                let input = if arguments.is_empty() {
+                    json!({})
+                } else {
+                    serde_json::from_str(arguments).with_context(|| {
+                        format!("{} arguments aren't JSON: {}", call.function.name, arguments)
+                    })?
+                };
+                let id = if call.id.is_empty() { format!("call_{}", i) } else { call.id };
+                Ok(ToolUse { id, name: call.function.name, input })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Reply {
+            text: join_text(message.content.as_deref()),
+            tool_calls,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn openai() -> OpenAi {
+        OpenAi { base_url: String::new() }
+    }
+
+    #[test]
+    fn openai_reply_with_null_tool_calls_is_text_only() {
+        let raw = r#"{"choices":[{"message":{"content":"Hi there","tool_calls":null}}]}"#;
+        let reply = openai().parse(raw).unwrap();
+        assert_eq!(reply.text.as_deref(), Some("Hi there"));
+        assert!(reply.tool_calls.is_empty());
+    }
+
+    #[test]
+    fn openai_tool_calls_without_ids_get_numbered() {
+        let raw = r#"{"choices":[{"message":{"content":null,"tool_calls":[
+            {"function":{"name":"skip","arguments":""}},
+            {"id":"x","function":{"name":"set_volume","arguments":"{\"level\":40}"}}
+        ]}}]}"#;
+        let reply = openai().parse(raw).unwrap();
+        assert_eq!(reply.text, None);
+        assert_eq!(reply.tool_calls[0].id, "call_0");
+        assert_eq!(reply.tool_calls[0].input, json!({}));
+        assert_eq!(reply.tool_calls[1].id, "x");
+        assert_eq!(reply.tool_calls[1].input, json!({ "level": 40 }));
+    }
+}
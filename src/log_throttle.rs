@@ -0,0 +1,86 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default for `Config::log_sample_every`.
+pub const DEFAULT_SAMPLE_EVERY: u32 = 10;
+
+/// Past the burst, one event in this many is logged; `1` logs everything,
+/// `0` nothing. Set once at startup from the config.
+static SAMPLE_EVERY: AtomicU32 = AtomicU32::new(DEFAULT_SAMPLE_EVERY);
+
+pub fn set_sample_every(n: u32) {
+    SAMPLE_EVERY.store(n, Ordering::Relaxed);
+}
+
+/// Rate limit for one high-frequency log site: the first `burst` events in
+/// each `window` are logged, after that only every `SAMPLE_EVERY`th.
+/// Meant to live in a `static` next to the call site.
+pub struct LogThrottle {
+    burst: u32,
+    window: Duration,
+    state: Mutex<Window>,
+}
+
+struct Window {
+    start: Option<Instant>,
+    count: u32,
+}
+
+impl LogThrottle {
+    pub const fn new(burst: u32, window: Duration) -> Self {
+        Self {
+            burst,
+            window,
+            state: Mutex::new(Window {
+                start: None,
+                count: 0,
+            }),
+        }
+    }
+
+    /// Whether this occurrence should be logged.
+    pub fn allow(&self) -> bool {
+        self.allow_at(Instant::now(), SAMPLE_EVERY.load(Ordering::Relaxed))
+    }
+
+    fn allow_at(&self, now: Instant, sample_every: u32) -> bool {
+        let mut w = self.state.lock().unwrap();
+        if w.start.is_none_or(|start| now.duration_since(start) >= self.window) {
+            w.start = Some(now);
+            w.count = 0;
+        }
+        w.count = w.count.saturating_add(1);
+        if w.count <= self.burst {
+            return true;
+        }
+        sample_every > 0 && (w.count - self.burst).is_multiple_of(sample_every)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_then_samples_until_the_window_resets() {
+        let throttle = LogThrottle::new(2, Duration::from_secs(1));
+        let now = Instant::now();
+        let allowed: Vec<bool> = (0..8).map(|_| throttle.allow_at(now, 3)).collect();
+        assert_eq!(allowed, [true, true, false, false, true, false, false, true]);
+
+        // A new window gets a fresh burst
+        let later = now + Duration::from_secs(1);
+        assert!(throttle.allow_at(later, 3));
+        assert!(throttle.allow_at(later, 3));
+        assert!(!throttle.allow_at(later, 3));
+    }
+
+    #[test]
+    fn sampling_zero_logs_only_the_burst() {
+        let throttle = LogThrottle::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+        assert!(throttle.allow_at(now, 0));
+        assert!((0..100).all(|_| !throttle.allow_at(now, 0)));
+    }
+}
@@ -1,38 +1,53 @@
 mod agent;
+mod api_keys;
 mod app;
 mod audio_analysis;
 mod config;
 mod downloader;
+mod feature_stream;
+mod keymap;
 mod library;
+mod llm;
+mod log_throttle;
 mod player;
+mod session;
+mod setup;
+#[cfg(test)]
+mod test_util;
 mod ui;
 
+use std::collections::HashSet;
 use std::io;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use crossterm::event::{
-    self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, KeyModifiers,
-    MouseButton, MouseEventKind,
+    self, DisableFocusChange, DisableMouseCapture, EnableFocusChange, EnableMouseCapture, Event,
+    KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEventKind,
 };
-use crossterm::execute;
+use crossterm::{cursor, execute};
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
 };
 use ratatui::backend::CrosstermBackend;
 use ratatui::Terminal;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-use app::{AgentStatus, AppState, FocusedPanel, InputMode, NowPlaying, PlayerCommand, Song, SongStatus};
-use config::Config;
+use app::{
+    AgentStatus, AppState, ConfirmAction, Failure, FocusedPanel, InputMode, LibraryView,
+    PlayerCommand, RetryAction, Song, SongStatus,
+};
+use config::{Config, TabAction};
+use keymap::Action;
+use player::Playback;
 
 fn setup_logging(config: &Config) {
     use tracing_subscriber::fmt;
     use tracing_subscriber::EnvFilter;
 
-    let log_path = config.cache_dir.parent().unwrap_or(&config.cache_dir);
-    let file_appender = tracing_appender::rolling::never(log_path, "vibeplayer.log");
+    let file_appender = tracing_appender::rolling::never(config.data_dir(), "vibeplayer.log");
 
     let filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new("vibeplayer=debug"));
@@ -46,30 +61,67 @@ fn setup_logging(config: &Config) {
         .init();
 }
 
+/// Put the terminal back the way the shell expects it. Safe to call more
+/// than once, and from the panic hook.
+fn restore_terminal() -> io::Result<()> {
+    restore_terminal_on(&mut io::stdout())
+}
+
+fn restore_terminal_on(out: &mut impl io::Write) -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(
+        out,
+        DisableFocusChange,
+        DisableMouseCapture,
+        LeaveAlternateScreen,
+        cursor::Show
+    )
+}
+
+/// A panic while the TUI is up would leave the shell in raw mode on the
+/// alternate screen; restore it before the default hook prints the message.
+/// Only the main thread draws, so panics in background tasks leave the
+/// terminal alone and are just logged.
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        error!(%info, "panic");
+        if std::thread::current().name() == Some("main") {
+            let _ = restore_terminal();
+        }
+        default_hook(info);
+    }));
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    // First run without a key: walk the user through creating config.toml
+    if std::env::var("ANTHROPIC_API_KEY").is_err() && config::is_first_run()? && !setup::run()? {
+        println!("Setup cancelled.");
+        return Ok(());
+    }
+
     let config = Arc::new(Config::load()?);
 
     setup_logging(&config);
+    log_throttle::set_sample_every(config.log_sample_every);
     info!("vibeplayer starting up");
     info!(cache_dir = %config.cache_dir.display(), model = %config.model, "config loaded");
+    for warning in &config.warnings {
+        warn!(%warning, "config warning");
+    }
 
+    install_panic_hook();
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture, EnableFocusChange)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
     info!("TUI initialized, entering main loop");
     let result = run_app(&mut terminal, config).await;
 
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        DisableMouseCapture,
-        LeaveAlternateScreen
-    )?;
-    terminal.show_cursor()?;
+    restore_terminal()?;
 
     if let Err(ref e) = result {
         error!(?e, "app exited with error");
@@ -85,47 +137,134 @@ async fn run_app(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
     config: Arc<Config>,
 ) -> Result<()> {
-    let lib = library::Library::load(config.library_path.clone())?;
+    let lib = library::Library::load(
+        config.library_path.clone(),
+        config.max_library_entries,
+        config.autosave_interval.is_some(),
+    )?;
     let library = Arc::new(Mutex::new(lib));
     info!(path = %config.library_path.display(), "library loaded");
 
-    let state = Arc::new(Mutex::new(AppState::new()));
+    let state = Arc::new(Mutex::new(AppState::new(config.clone())));
+    if let Some(warning) = config.warnings.first() {
+        state.lock().unwrap().report_warning(format!("Config: {}", warning));
+    }
 
-    // Populate library panel with previously downloaded entries
     {
+        let state = state.clone();
+        let config = config.clone();
+        tokio::spawn(async move {
+            match downloader::check_version(&config).await {
+                Ok(Some(warning)) => {
+                    warn!(%warning, "yt-dlp looks outdated");
+                    state.lock().unwrap().report_warning(warning);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    error!(?e, "yt-dlp unavailable");
+                    state.lock().unwrap().report_error(format!("{:#}", e));
+                }
+            }
+        });
+    }
+
+    let last_session = session::Session::load(&config.session_path);
+    if let Some(ref session) = last_session {
+        info!(?session, "previous session loaded");
         let lib = library.lock().unwrap();
+        remove_unsaved_leftovers(&lib, &config.cache_dir, &session.unsaved_files);
+        drop(lib);
         let mut s = state.lock().unwrap();
-        for entry in lib.entries() {
-            let cached_path = config.cache_dir.join(&entry.file_path);
-            if cached_path.exists() {
-                let mut song = Song::new_queued(&entry.title, &entry.artist, &entry.url);
-                song.file_path = Some(cached_path);
-                song.duration = Some(Duration::from_secs_f64(entry.duration_secs));
-                song.status = SongStatus::Ready;
-                s.library.push(song);
-            }
-        }
-        info!(count = s.library.len(), "restored songs to library panel");
+        s.volume = session.volume.min(100);
+        s.repeat = session.repeat;
+        s.shuffle = session.shuffle;
+        s.sidebar_split = session.sidebar_split;
+        s.visualizer_percent = session
+            .visualizer_percent
+            .clamp(app::MIN_VISUALIZER_PERCENT, app::MAX_VISUALIZER_PERCENT);
+    }
+
+    // Populate library panel with previously downloaded entries
+    {
+        let mut s = state.lock().unwrap();
+        let lib = library.lock().unwrap();
+        let cache_dir = &config.cache_dir;
+        s.library.extend(lib.entries().iter().filter_map(|entry| Song::cached(entry, cache_dir)));
+        s.playlists = lib.playlist_summaries();
+        info!(count = s.library.len(), playlists = s.playlists.len(), "restored songs to library panel");
     }
 
-    let agent = Arc::new(agent::Agent::new(config.clone(), library));
-    let mut player = player::Player::new()?;
-    player.set_volume(config.default_volume);
-    info!(volume = config.default_volume, "player initialized");
+    // Offer the previous queue and track back, once the library is known
+    let mut resume = last_session.and_then(|session| resume_plan(&session, &library, &config));
+    if let Some(ref plan) = resume {
+        let songs = plan.queue.len() + usize::from(plan.current.is_some());
+        info!(songs, "previous session can be resumed");
+        state.lock().unwrap().pending_confirm = Some(ConfirmAction::ResumeSession { songs });
+    }
+
+    let agent = Arc::new(agent::Agent::new(config.clone(), library.clone()));
+    let mut player = output_or_null(player::Player::new(), &state, &config)?;
+    // Opened on first copy and kept alive: on X11 the contents vanish with it
+    let mut clipboard: Option<arboard::Clipboard> = None;
+    // The last session's volume, if there was one
+    let volume = state.lock().unwrap().volume;
+    player.set_volume(volume);
+    info!(volume, "player initialized");
+
+    let feature_stream = match config.feature_stream_addr {
+        Some(ref addr) => match feature_stream::FeatureStream::bind(addr).await {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                error!(?e, "feature stream unavailable");
+                state.lock().unwrap().report_warning(format!("{:#}", e));
+                None
+            }
+        },
+        None => None,
+    };
 
     let tick_rate = Duration::from_millis(16); // ~60fps for smooth wave
+    let mut autosave = config
+        .autosave_interval
+        .map(|interval| session::AutosaveTimer::new(interval, Instant::now()));
+    // Song the upcoming queue was last prefetched for
+    let mut prefetched_for: Option<String> = None;
+    // Whether the sink volume is lowered by a stop-after-current fade
+    let mut fading_out = false;
+    let unfocused_tick_rate = Duration::from_millis(250);
+    // However the loop ends, an error and a panic included, state is saved
+    let _flush = ShutdownFlush {
+        state: state.clone(),
+        library: library.clone(),
+        config: config.clone(),
+    };
 
     loop {
         // Update audio features and matrix rain
         {
-            let audio_features = player.get_audio_features();
             let mut s = state.lock().unwrap();
-            s.audio_features = audio_features;
+            // External visualizers keep getting frames while we're unfocused
+            let streaming = feature_stream.as_ref().is_some_and(|f| f.has_clients())
+                && s.current.is_some()
+                && !s.paused;
+            if streaming || s.should_animate() {
+                s.audio_features = player.get_audio_features();
+            } else if s.current.is_none() || s.paused {
+                // The analyzer still holds the last samples; show silence instead
+                s.audio_features = Default::default();
+            }
+            if streaming {
+                if let Some(ref stream) = feature_stream {
+                    stream.publish(&s.audio_features);
+                }
+            }
+            if s.should_animate() {
+                let audio_features = s.audio_features;
 
-            if s.current.is_some() {
                 let size = terminal.size().unwrap_or_default();
-                // Approximate visualizer inner area (65% width, minus borders)
-                let vis_width = ((size.width as f32 * 0.65) as usize).saturating_sub(2);
+                // Approximate visualizer inner area (configured width, minus borders)
+                let vis_share = config.layout.visualizer_percent as f32 / 100.0;
+                let vis_width = ((size.width as f32 * vis_share) as usize).saturating_sub(2);
                 let vis_height = size.height.saturating_sub(8) as usize; // minus input, now_playing, status
                 s.matrix_rain.resize(vis_width.max(1), vis_height.max(1));
                 s.matrix_rain.update(&audio_features);
@@ -138,14 +277,43 @@ async fn run_app(
             if s.current.is_some() {
                 s.playback_position = player.get_position();
             }
+            player.advance_crossfade();
+            if let Some(level) = s.tick_volume_ramp(Instant::now()) {
+                player.set_volume(level);
+            }
+            // The stop fade overrides the volume until it ends or is disarmed
+            if let Some(level) = s.stop_fade_level() {
+                player.set_volume(level);
+                fading_out = true;
+            } else if fading_out {
+                player.set_volume(s.volume);
+                fading_out = false;
+            }
         }
 
         // Draw
         {
             let mut s = state.lock().unwrap();
+            s.ui_tick = s.ui_tick.wrapping_add(1);
+            s.expire_error(Instant::now());
+            s.update_request();
             terminal.draw(|f| ui::draw(f, &mut s))?;
         }
 
+        if let Some(ref mut timer) = autosave {
+            let now = Instant::now();
+            if timer.due(now) {
+                autosave_now(&state, &library, &config);
+                timer.saved(now);
+            }
+        } else {
+            let saved = library.lock().unwrap().flush_coalesced();
+            if let Err(e) = saved {
+                warn!(?e, "failed to save library");
+                report_library_failure(&state, &e);
+            }
+        }
+
         // Process pending player commands from agent
         {
             let commands: Vec<PlayerCommand> = {
@@ -159,105 +327,267 @@ async fn run_app(
 
             for cmd in commands {
                 match cmd {
-                    PlayerCommand::PlayFile { path, title, artist, url, duration_secs } => {
+                    PlayerCommand::PlayFile {
+                        generation,
+                        path,
+                        title,
+                        artist,
+                        url,
+                        duration_secs,
+                        chapters,
+                        thumbnail,
+                    } => {
+                        // Commands run here one at a time, so the only way two
+                        // plays collide is an older request landing late
+                        if state.lock().unwrap().play_superseded(generation) {
+                            info!(%url, generation, "ignoring superseded play request");
+                            continue;
+                        }
                         info!(%url, %title, "playing downloaded file");
-                        player.play_file(&path, Some(duration_secs))?;
-                        let mut s = state.lock().unwrap();
                         let mut song = Song::new_queued(&title, &artist, &url);
-                        song.file_path = Some(path);
+                        song.file_path = Some(path.clone());
                         song.duration = Some(Duration::from_secs_f64(duration_secs));
-                        s.current = Some(NowPlaying {
-                            song,
-                            started_at: Instant::now(),
-                            paused_elapsed: Duration::ZERO,
-                            paused_at: None,
-                        });
-                        s.paused = false;
+                        song.chapters = chapters;
+                        song.thumbnail = thumbnail;
+                        if let Err(e) = player.play_file(&path, Some(duration_secs)) {
+                            error!(?e, %url, "failed to play downloaded file");
+                            let mut s = state.lock().unwrap();
+                            s.report_error(format!("{:#}", e));
+                            s.record_failure(title, format!("{:#}", e), RetryAction::Play { song });
+                            continue;
+                        }
+                        let mut s = state.lock().unwrap();
+                        mark_played(&library, &mut s, &url);
+                        s.start_playing(song);
+                    }
+                    PlayerCommand::PlayPreview { generation, path, title, url } => {
+                        if state.lock().unwrap().play_superseded(generation) {
+                            info!(%url, generation, "ignoring superseded preview");
+                            if let Err(e) = std::fs::remove_file(&path) {
+                                warn!(?e, path = %path.display(), "failed to remove preview clip");
+                            }
+                            continue;
+                        }
+                        info!(%url, %title, "playing preview clip");
+                        let played = player.play_file(&path, Some(downloader::PREVIEW_SECS));
+                        // The decoder keeps the file open, so the clip can be removed now
+                        if let Err(e) = std::fs::remove_file(&path) {
+                            warn!(?e, path = %path.display(), "failed to remove preview clip");
+                        }
+                        if let Err(e) = played {
+                            error!(?e, %url, "failed to play preview clip");
+                            state.lock().unwrap().report_error(format!("{:#}", e));
+                            continue;
+                        }
+                        let mut s = state.lock().unwrap();
+                        let mut song = Song::new_queued(
+                            &format!("Preview: {}", title),
+                            &config.unknown_artist,
+                            &url,
+                        );
+                        song.duration = Some(Duration::from_secs_f64(downloader::PREVIEW_SECS));
+                        s.start_preview(song);
+                        s.status_message = None;
                     }
                     PlayerCommand::Skip => {
                         info!("skip requested");
                         player.stop();
-                        state.lock().unwrap().current = None;
+                        state.lock().unwrap().skip_current();
+                    }
+                    PlayerCommand::Previous => {
+                        info!("previous track requested");
+                        let mut s = state.lock().unwrap();
+                        play_previous(player.as_mut(), &library, &mut s);
                     }
                     PlayerCommand::Pause => {
                         info!("pause requested");
                         player.pause();
-                        state.lock().unwrap().paused = true;
+                        state.lock().unwrap().set_paused(true, Instant::now());
                     }
                     PlayerCommand::Resume => {
                         info!("resume requested");
                         player.resume();
-                        state.lock().unwrap().paused = false;
+                        state.lock().unwrap().set_paused(false, Instant::now());
                     }
                     PlayerCommand::SetVolume(level) => {
                         info!(level, "volume change");
-                        player.set_volume(level);
-                        state.lock().unwrap().volume = level;
+                        let now_level = state.lock().unwrap().change_volume(level, Instant::now());
+                        player.set_volume(now_level);
+                    }
+                    PlayerCommand::Mute(mute) => {
+                        info!(mute, "mute requested");
+                        if let Some(level) = state.lock().unwrap().set_muted(mute, Instant::now()) {
+                            player.set_volume(level);
+                        }
+                    }
+                    PlayerCommand::QueueLibrary { shuffle } => {
+                        info!(shuffle, "queue library requested");
+                        let mut s = state.lock().unwrap();
+                        queue_library(player.as_mut(), &library, &mut s, shuffle);
+                    }
+                    PlayerCommand::Seek(position) => {
+                        let mut s = state.lock().unwrap();
+                        let Some(ref np) = s.current else {
+                            debug!("seek requested with nothing playing, ignoring");
+                            continue;
+                        };
+                        let position = match np.song.known_duration() {
+                            Some(duration) => position.min(duration),
+                            None => position,
+                        };
+                        info!(?position, "seek requested");
+                        player.seek(position);
+                        // Don't wait a tick for the player to report it
+                        s.playback_position = position;
+                    }
+                    PlayerCommand::SetSpeed(speed) => {
+                        info!(speed, "speed change");
+                        player.set_speed(state.lock().unwrap().set_speed(speed));
                     }
                 }
             }
         }
 
-        // Auto-advance: if current song stream ended, play next from queue
+        // Auto-advance: if current song stream ended, or is close enough to
+        // the end to crossfade, play next from queue
         {
-            let should_advance = {
-                let s = state.lock().unwrap();
-                s.current.is_some() && player.is_empty()
+            let (should_advance, crossfade) = {
+                let mut s = state.lock().unwrap();
+                let ended = s.current.is_some() && player.is_empty();
+                if !ended {
+                    s.gap_until = None;
+                }
+                let crossfade = if ended { None } else { s.crossfade_due() };
+                (crossfade.is_some() || ended && !s.hold_for_gap(Instant::now()), crossfade)
             };
 
             if should_advance {
-                let next = state.lock().unwrap().next_ready_song();
+                if state.lock().unwrap().stop_if_armed() {
+                    info!("stopping after current track as requested");
+                    continue;
+                }
+                let halted = {
+                    let mut s = state.lock().unwrap();
+                    // A track that played out has waited through the gap
+                    // since; that isn't play time
+                    let gap = if crossfade.is_none() { s.config.track_gap } else { Duration::ZERO };
+                    let played = s
+                        .current
+                        .as_ref()
+                        .map(|np| np.elapsed().saturating_sub(gap))
+                        .unwrap_or_default();
+                    if s.advance_guard.record(played) {
+                        s.advance_guard.reset();
+                        s.current = None;
+                        s.report_error("Stopped: tracks keep ending right after they start");
+                        true
+                    } else {
+                        false
+                    }
+                };
+                if halted {
+                    warn!("rapid auto-advance detected, halting playback");
+                    continue;
+                }
+
+                let (next, repeat) = {
+                    let mut s = state.lock().unwrap();
+                    (s.next_auto_song(), s.repeat)
+                };
                 if let Some(song) = next {
                     if let Some(ref path) = song.file_path {
-                        info!(title = %song.title, url = %song.url, "auto-advancing to next song");
+                        info!(title = %song.title, url = %song.url, repeat = repeat.label(), "auto-advancing to next song");
                         let dur = song.duration.map(|d| d.as_secs_f64());
-                        player.play_file(path, dur)?;
+                        let started = match crossfade {
+                            Some(fade) => player.crossfade_to(path, dur, fade),
+                            None => player.play_file(path, dur),
+                        };
+                        if let Err(e) = started {
+                            error!(?e, url = %song.url, "failed to play next song");
+                            // Don't leave the outgoing track playing unseen
+                            player.stop();
+                            let mut s = state.lock().unwrap();
+                            s.current = None;
+                            s.report_error(format!("{:#}", e));
+                            let title = song.title.clone();
+                            s.record_failure(title, format!("{:#}", e), RetryAction::Play { song });
+                            continue;
+                        }
                         let mut s = state.lock().unwrap();
-                        s.current = Some(NowPlaying {
-                            song,
-                            started_at: Instant::now(),
-                            paused_elapsed: Duration::ZERO,
-                            paused_at: None,
-                        });
-                        s.paused = false;
+                        mark_played(&library, &mut s, &song.url);
+                        s.start_playing(song);
                     } else {
                         info!(title = %song.title, "song not downloaded yet, skipping");
                     }
                 } else {
                     info!("queue empty, stopping playback");
-                    state.lock().unwrap().current = None;
+                    player.stop();
+                    let mut s = state.lock().unwrap();
+                    s.current = None;
+                    // Ready songs left behind were all passed over
+                    if s.queue.iter().any(|song| song.status == SongStatus::Ready) {
+                        warn!("every ready song is over its replay limit");
+                        s.report_warning("Stopped: every queued song has hit max_replays for now");
+                    }
                 }
             }
         }
 
+        // Once a song starts, make sure the next few are on their way
+        let started_url = {
+            let s = state.lock().unwrap();
+            s.current
+                .as_ref()
+                .map(|np| &np.song.url)
+                .filter(|url| Some(*url) != prefetched_for.as_ref())
+                .cloned()
+        };
+        if let Some(url) = started_url {
+            let started = agent.prefetch_next(&state);
+            if started > 0 {
+                debug!(started, "prefetching after track start");
+            }
+            prefetched_for = Some(url);
+        }
+
         // Handle input events
-        if event::poll(tick_rate)? {
+        let poll_timeout = if state.lock().unwrap().terminal_focused {
+            tick_rate
+        } else {
+            unfocused_tick_rate
+        };
+        if event::poll(poll_timeout)? {
             let ev = event::read()?;
 
-            // Mouse click on progress bar → seek
+            if let Event::FocusGained | Event::FocusLost = ev {
+                let focused = matches!(ev, Event::FocusGained);
+                debug!(focused, "terminal focus changed");
+                state.lock().unwrap().terminal_focused = focused;
+                continue;
+            }
+
+            // Mouse on progress bar: hover previews the target, click seeks.
+            // Shift switches to fine seeking relative to the playhead.
             if let Event::Mouse(mouse) = ev {
-                if mouse.kind == MouseEventKind::Down(MouseButton::Left) {
-                    let s = state.lock().unwrap();
-                    if let (Some((bar_row, col_start, col_end)), Some(ref np)) =
-                        (s.progress_bar_area, &s.current)
-                    {
-                        if mouse.row == bar_row
-                            && mouse.column >= col_start
-                            && mouse.column < col_end
-                        {
-                            let duration = np.song.duration.unwrap_or(Duration::ZERO);
-                            if duration > Duration::ZERO {
-                                let frac = (mouse.column - col_start) as f64
-                                    / (col_end - col_start) as f64;
-                                let position = Duration::from_secs_f64(
-                                    frac * duration.as_secs_f64(),
-                                );
-                                drop(s);
-                                info!(?position, "user: mouse seek");
-                                player.seek(position);
+                let mut s = state.lock().unwrap();
+                let fine = mouse.modifiers.contains(KeyModifiers::SHIFT);
+                let target = s.seek_target_at(mouse.column, mouse.row, fine);
+                match mouse.kind {
+                    MouseEventKind::Moved => s.seek_hover = target,
+                    MouseEventKind::Down(MouseButton::Left) => {
+                        if let Some(position) = target {
+                            drop(s);
+                            info!(?position, fine, "user: mouse seek");
+                            player.seek(position);
+                        } else if let Some(row) = s.failure_row_at(mouse.column, mouse.row) {
+                            let failure = s.take_failure(row);
+                            drop(s);
+                            if let Some(failure) = failure {
+                                retry_failure(failure, &agent, &state, player.as_mut(), &library);
                             }
                         }
                     }
+                    _ => {}
                 }
                 continue;
             }
@@ -269,6 +599,505 @@ async fn run_app(
 
                 let in_edit_mode = state.lock().unwrap().input.mode == InputMode::Editing;
 
+                // A pending confirmation takes the next key: `y` runs it,
+                // anything else cancels
+                let confirm = state.lock().unwrap().pending_confirm.take();
+                if let Some(action) = confirm {
+                    if key.code == KeyCode::Char('y') {
+                        let mut s = state.lock().unwrap();
+                        match action {
+                            ConfirmAction::ClearQueue => {
+                                let removed = s.clear_queue();
+                                info!(removed, "user: cleared queue");
+                                s.agent_reply = Some(format!("Cleared {} songs from the queue", removed));
+                            }
+                            ConfirmAction::ResumeSession { .. } => {
+                                if let Some(plan) = resume.take() {
+                                    resume_session(player.as_mut(), &library, &mut s, plan);
+                                }
+                            }
+                        }
+                    } else {
+                        debug!(?action, "user: confirmation cancelled");
+                    }
+                    continue;
+                }
+
+                // While the library filter is being typed it takes the keys;
+                // Up/Down walk what it lists
+                let filtering =
+                    state.lock().unwrap().library_filter.as_ref().is_some_and(|f| f.editing);
+                let modified = key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+                if filtering && !modified {
+                    let mut s = state.lock().unwrap();
+                    let Some(filter) = s.library_filter.as_mut() else {
+                        continue;
+                    };
+                    match key.code {
+                        KeyCode::Char(c) => filter.input.insert(c),
+                        KeyCode::Backspace => filter.input.backspace(),
+                        KeyCode::Enter if filter.input.text.trim().is_empty() => {
+                            s.library_filter = None;
+                        }
+                        KeyCode::Enter => {
+                            debug!(filter = %filter.input.text, "user: apply library filter");
+                            filter.editing = false;
+                        }
+                        KeyCode::Esc => {
+                            debug!("user: clear library filter");
+                            s.library_filter = None;
+                        }
+                        KeyCode::Up => s.move_cursor_up(),
+                        KeyCode::Down => s.move_cursor_down(),
+                        _ => {}
+                    }
+                    s.snap_library_cursor();
+                    continue;
+                }
+
+                // Bindable normal-mode keys go through the keymap; Shift+Up/Down
+                // always move the selected queue entry
+                let action = if in_edit_mode
+                    || key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+                {
+                    None
+                } else if key.modifiers.contains(KeyModifiers::SHIFT) && key.code == KeyCode::Up {
+                    Some(Action::MoveQueuedUp)
+                } else if key.modifiers.contains(KeyModifiers::SHIFT) && key.code == KeyCode::Down {
+                    Some(Action::MoveQueuedDown)
+                } else {
+                    config.keys.action(key.code)
+                };
+                if let Some(action) = action {
+                    match action {
+                        Action::FocusInput => {
+                            debug!("user: enter editing mode");
+                            state.lock().unwrap().input.mode = InputMode::Editing;
+                        }
+
+                        Action::FilterLibrary => {
+                            let mut s = state.lock().unwrap();
+                            let in_library = s.focused_panel == FocusedPanel::Library
+                                && s.library_view == LibraryView::Songs;
+                            if in_library {
+                                debug!("user: open library filter");
+                                let filter = s.library_filter.get_or_insert_with(Default::default);
+                                filter.editing = true;
+                            } else {
+                                debug!("user: enter editing mode");
+                                s.input.mode = InputMode::Editing;
+                            }
+                        }
+
+                        Action::Quit => {
+                            info!("user: quit");
+                            state.lock().unwrap().should_quit = true;
+                        }
+
+                        Action::PlayPause => {
+                            let mut s = state.lock().unwrap();
+                            let paused = !s.paused;
+                            s.set_paused(paused, Instant::now());
+                            if s.paused {
+                                info!("user: pause");
+                                player.pause();
+                            } else {
+                                info!("user: resume");
+                                player.resume();
+                            }
+                        }
+
+                        Action::Skip => {
+                            info!("user: skip/next");
+                            player.stop();
+                            state.lock().unwrap().skip_current();
+                        }
+
+                        Action::Previous => {
+                            info!("user: previous track");
+                            let mut s = state.lock().unwrap();
+                            play_previous(player.as_mut(), &library, &mut s);
+                        }
+
+                        Action::SeekForward => {
+                            let s = state.lock().unwrap();
+                            if s.current.is_some() {
+                                let pos = s.playback_position + Duration::from_secs(10);
+                                drop(s);
+                                info!(?pos, "user: seek forward 10s");
+                                player.seek(pos);
+                            }
+                        }
+
+                        Action::SeekBackward => {
+                            let s = state.lock().unwrap();
+                            if s.current.is_some() {
+                                let pos = s.playback_position.saturating_sub(Duration::from_secs(10));
+                                drop(s);
+                                info!(?pos, "user: seek backward 10s");
+                                player.seek(pos);
+                            }
+                        }
+
+                        Action::ClearQueue => {
+                            let mut s = state.lock().unwrap();
+                            if !s.queue.is_empty() {
+                                s.pending_confirm = Some(ConfirmAction::ClearQueue);
+                            }
+                        }
+
+                        Action::NextChapter | Action::PreviousChapter => {
+                            let s = state.lock().unwrap();
+                            let Some(ref np) = s.current else {
+                                continue;
+                            };
+                            let target = if action == Action::NextChapter {
+                                app::next_chapter(&np.song.chapters, s.playback_position)
+                            } else {
+                                app::previous_chapter(&np.song.chapters, s.playback_position)
+                            };
+                            drop(s);
+                            if let Some(pos) = target {
+                                info!(?pos, "user: chapter skip");
+                                player.seek(pos);
+                            }
+                        }
+
+                        Action::ToggleShuffle => {
+                            let mut s = state.lock().unwrap();
+                            s.shuffle = !s.shuffle;
+                            info!(shuffle = s.shuffle, "user: toggle shuffle");
+                        }
+
+                        Action::CycleRepeat => {
+                            // On a failed queue item the same key retries its download
+                            let failed = {
+                                let s = state.lock().unwrap();
+                                s.queue
+                                    .get(s.queue_cursor)
+                                    .filter(|_| s.focused_panel == FocusedPanel::Queue)
+                                    .filter(|song| matches!(song.status, SongStatus::Failed(_)))
+                                    .map(|song| song.url.clone())
+                            };
+                            if let Some(url) = failed {
+                                info!(%url, "user: retry download");
+                                agent.retry_download(&url, &state);
+                            } else {
+                                let mut s = state.lock().unwrap();
+                                s.repeat = s.repeat.cycle();
+                                info!(mode = s.repeat.label(), "user: repeat mode");
+                            }
+                        }
+
+                        Action::CopyNowPlaying => {
+                            let mut s = state.lock().unwrap();
+                            let position = s.playback_position;
+                            let Some(text) = s.current.as_ref().map(|np| np.share_text(position)) else {
+                                continue;
+                            };
+                            if clipboard.is_none() {
+                                clipboard = arboard::Clipboard::new()
+                                    .map_err(|e| warn!(?e, "clipboard unavailable"))
+                                    .ok();
+                            }
+                            let copied = clipboard.as_mut().map(|c| c.set_text(text.clone()));
+                            match copied {
+                                Some(Ok(())) => {
+                                    info!(%text, "user: copied now playing");
+                                    s.status_message = Some("Copied now playing to clipboard".to_string());
+                                }
+                                Some(Err(e)) => {
+                                    warn!(?e, "failed to copy to clipboard");
+                                    s.report_warning("Couldn't copy to the clipboard");
+                                }
+                                None => s.report_warning("No clipboard available"),
+                            }
+                        }
+
+                        Action::OpenDataDir => {
+                            let dir = config.data_dir().to_path_buf();
+                            info!(dir = %dir.display(), "user: open data directory");
+                            let state_clone = state.clone();
+                            // xdg-open and friends can be slow; keep them off the UI thread
+                            tokio::task::spawn_blocking(move || {
+                                if let Err(e) = opener::open(&dir) {
+                                    warn!(?e, "failed to open file manager");
+                                    state_clone.lock().unwrap().report_warning(format!(
+                                        "Couldn't open a file manager; logs and cache are in {}",
+                                        dir.display()
+                                    ));
+                                }
+                            });
+                        }
+
+                        Action::JumpToLive => {
+                            let duration = state
+                                .lock()
+                                .unwrap()
+                                .current
+                                .as_ref()
+                                .and_then(|np| np.song.known_duration());
+                            if let Some(pos) = app::live_edge_position(duration, app::LIVE_EDGE_BUFFER) {
+                                info!(?pos, "user: jump to live edge");
+                                player.seek(pos);
+                            }
+                        }
+
+                        Action::Restart => {
+                            restart_track(player.as_ref(), &mut state.lock().unwrap());
+                        }
+
+                        Action::Preview => {
+                            let mut s = state.lock().unwrap();
+                            if s.focused_panel != FocusedPanel::Queue {
+                                continue;
+                            }
+                            let Some(song) = s.queue.get(s.queue_cursor).cloned() else {
+                                continue;
+                            };
+                            info!(title = %song.title, url = %song.url, "user: preview queued song");
+                            s.status_message = Some(format!("Loading preview of {}...", song.title));
+                            let generation = s.begin_play_request();
+                            let state_clone = state.clone();
+                            let config = config.clone();
+                            tokio::spawn(async move {
+                                let duration = song.duration.map(|d| d.as_secs_f64());
+                                match downloader::download_preview(&song.url, duration, &config).await {
+                                    Ok(path) => {
+                                        state_clone.lock().unwrap().pending_commands.push(
+                                            PlayerCommand::PlayPreview {
+                                                generation,
+                                                path,
+                                                title: song.title,
+                                                url: song.url,
+                                            },
+                                        );
+                                    }
+                                    Err(e) => {
+                                        error!(url = %song.url, ?e, "preview failed");
+                                        let mut s = state_clone.lock().unwrap();
+                                        s.status_message = None;
+                                        s.report_error(format!("Preview error: {}", e));
+                                    }
+                                }
+                            });
+                        }
+
+                        Action::SmoothQueue => {
+                            if state.lock().unwrap().queue.len() < 2 {
+                                continue;
+                            }
+                            info!("user: smooth queue");
+                            ask_agent(&agent, &state, SMOOTH_QUEUE_REQUEST.to_string());
+                        }
+
+                        Action::QueueLibrary => {
+                            let mut s = state.lock().unwrap();
+                            info!("user: queue library");
+                            queue_library(player.as_mut(), &library, &mut s, false);
+                        }
+
+                        Action::RetryFailure => {
+                            let failure = state.lock().unwrap().take_failure(0);
+                            if let Some(failure) = failure {
+                                retry_failure(failure, &agent, &state, player.as_mut(), &library);
+                            }
+                        }
+
+                        Action::DismissFailures => {
+                            let mut s = state.lock().unwrap();
+                            info!(count = s.failures.len(), "user: dismiss failures");
+                            s.failures.clear();
+                        }
+
+                        Action::DownloadAll => {
+                            info!("user: download all for offline");
+                            if agent.warm_cache(&state).is_none() {
+                                state.lock().unwrap().status_message =
+                                    Some("Offline download already running".to_string());
+                            }
+                        }
+
+                        Action::RemoveFromQueue => {
+                            let mut s = state.lock().unwrap();
+                            if s.focused_panel == FocusedPanel::Queue {
+                                if let Some(song) = s.remove_selected_queued() {
+                                    info!(title = %song.title, url = %song.url, "user: remove from queue");
+                                }
+                            }
+                        }
+
+                        Action::ToggleFavorite => {
+                            let mut s = state.lock().unwrap();
+                            let Some(song) = s.focused_song() else {
+                                continue;
+                            };
+                            let (url, title, favorite) =
+                                (song.url.clone(), song.title.clone(), !song.favorite);
+                            let saved = library.lock().unwrap().set_favorite(&url, favorite);
+                            match saved {
+                                Ok(true) => {
+                                    info!(%title, favorite, "user: toggle favorite");
+                                    s.mark_favorite(&url, favorite);
+                                    s.status_message = Some(if favorite {
+                                        format!("Added {} to favorites", title)
+                                    } else {
+                                        format!("Removed {} from favorites", title)
+                                    });
+                                }
+                                Ok(false) => {
+                                    s.report_warning("Only downloaded songs can be favorites")
+                                }
+                                Err(e) => {
+                                    warn!(?e, %url, "failed to save favorite");
+                                    drop(s);
+                                    report_library_failure(&state, &e);
+                                }
+                            }
+                        }
+
+                        Action::CycleLibrarySort => {
+                            let mut s = state.lock().unwrap();
+                            s.library_sort = s.library_sort.next();
+                            s.library_view = LibraryView::Songs;
+                            s.focused_panel = FocusedPanel::Library;
+                            debug!(sort = s.library_sort.label(), "user: cycle library sort");
+                        }
+
+                        Action::TogglePlaylists => {
+                            let mut s = state.lock().unwrap();
+                            s.library_view = match s.library_view {
+                                LibraryView::Songs => LibraryView::Playlists,
+                                LibraryView::Playlists => LibraryView::Songs,
+                            };
+                            s.focused_panel = FocusedPanel::Library;
+                            debug!(view = ?s.library_view, "user: toggle library view");
+                        }
+
+                        Action::CycleSplit => {
+                            let mut s = state.lock().unwrap();
+                            s.sidebar_split = s.sidebar_split.next();
+                            debug!(split = ?s.sidebar_split, "user: cycle sidebar split");
+                        }
+
+                        Action::GrowVisualizer | Action::ShrinkVisualizer => {
+                            let mut s = state.lock().unwrap();
+                            s.resize_visualizer(action == Action::GrowVisualizer);
+                            debug!(percent = s.visualizer_percent, "user: resize visualizer");
+                        }
+
+                        Action::CycleOutput => {
+                            let devices = player::output_devices();
+                            let Some(next) = player::next_device(player.device_name(), &devices)
+                                .map(String::from)
+                            else {
+                                state.lock().unwrap().report_warning("No other audio output device found");
+                                continue;
+                            };
+                            info!(device = %next, "user: cycle output device");
+                            let mut s = state.lock().unwrap();
+                            if let Err(e) = player.switch_device(&next) {
+                                error!(?e, "failed to switch output device");
+                                s.report_error(format!("Output switch failed: {:#}", e));
+                                continue;
+                            }
+                            player.set_volume(s.volume);
+                            // Pick the current track back up where it was on the new device
+                            let resume = s.current.as_ref().and_then(|np| {
+                                np.song.file_path.clone().map(|path| (path, np.song.duration))
+                            });
+                            match resume {
+                                Some((path, dur)) => {
+                                    let position = s.playback_position;
+                                    match player.play_file(&path, dur.map(|d| d.as_secs_f64())) {
+                                        Ok(()) => {
+                                            player.seek(position);
+                                            if s.paused {
+                                                player.pause();
+                                            }
+                                        }
+                                        Err(e) => {
+                                            error!(?e, "failed to resume after output switch");
+                                            s.current = None;
+                                        }
+                                    }
+                                }
+                                None => s.current = None,
+                            }
+                        }
+
+                        Action::ToggleVisualizer => {
+                            let mut s = state.lock().unwrap();
+                            s.visualizer_hidden = !s.visualizer_hidden;
+                            debug!(hidden = s.visualizer_hidden, "user: toggle visualizer");
+                        }
+
+                        Action::CycleWaveStyle => {
+                            let mut s = state.lock().unwrap();
+                            s.wave_style = s.wave_style.next();
+                            debug!(style = ?s.wave_style, "user: cycle wave style");
+                        }
+
+                        Action::VolumeUp => {
+                            let mut s = state.lock().unwrap();
+                            // From the pre-mute level, so it unmutes rather than starting at 0
+                            let target = (s.audible_volume() + 5).min(100);
+                            debug!(volume = target, "user: volume up");
+                            player.set_volume(s.change_volume(target, Instant::now()));
+                        }
+
+                        Action::VolumeDown => {
+                            let mut s = state.lock().unwrap();
+                            let target = s.audible_volume().saturating_sub(5);
+                            debug!(volume = target, "user: volume down");
+                            player.set_volume(s.change_volume(target, Instant::now()));
+                        }
+
+                        Action::SpeedUp | Action::SpeedDown => {
+                            let mut s = state.lock().unwrap();
+                            let step = match action {
+                                Action::SpeedUp => app::SPEED_STEP,
+                                _ => -app::SPEED_STEP,
+                            };
+                            let target = s.speed + step;
+                            let speed = s.set_speed(target);
+                            debug!(speed, "user: change speed");
+                            player.set_speed(speed);
+                        }
+
+                        Action::ToggleMute => {
+                            let mut s = state.lock().unwrap();
+                            let mute = s.muted_from.is_none();
+                            info!(mute, "user: toggle mute");
+                            if let Some(level) = s.set_muted(mute, Instant::now()) {
+                                player.set_volume(level);
+                            }
+                        }
+
+                        Action::MoveQueuedUp | Action::MoveQueuedDown => {
+                            let mut s = state.lock().unwrap();
+                            if s.focused_panel != FocusedPanel::Queue {
+                                if action == Action::MoveQueuedUp {
+                                    s.move_cursor_up();
+                                } else {
+                                    s.move_cursor_down();
+                                }
+                                continue;
+                            }
+                            let from = s.queue_cursor;
+                            let to = match action {
+                                Action::MoveQueuedUp => from.checked_sub(1),
+                                _ => Some(from + 1),
+                            };
+                            if let Some(to) = to.filter(|&to| s.move_queued(from, to)) {
+                                s.queue_cursor = to;
+                                debug!(from, to, "user: move queue entry");
+                            }
+                        }
+                    }
+                    continue;
+                }
+
                 match key.code {
                     KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                         info!("user: Ctrl+C quit");
@@ -280,19 +1109,7 @@ async fn run_app(
                         let input_text = state.lock().unwrap().input.submit();
                         if !input_text.is_empty() {
                             info!(%input_text, "user submitted input");
-                            let agent = agent.clone();
-                            let state_clone = state.clone();
-                            tokio::spawn(async move {
-                                if let Err(e) =
-                                    agent.handle_input(&input_text, &state_clone).await
-                                {
-                                    error!(?e, "agent error");
-                                    let mut s = state_clone.lock().unwrap();
-                                    s.agent_status = AgentStatus::Idle;
-                                    s.status_message =
-                                        Some(format!("Agent error: {}", e));
-                                }
-                            });
+                            ask_agent(&agent, &state, input_text);
                         }
                     }
 
@@ -309,6 +1126,19 @@ async fn run_app(
                         state.lock().unwrap().input.mode = InputMode::Normal;
                     }
 
+                    KeyCode::Esc if state.lock().unwrap().library_filter.is_some() => {
+                        debug!("user: clear library filter");
+                        state.lock().unwrap().library_filter = None;
+                    }
+
+                    KeyCode::Tab if in_edit_mode && config.tab_action == TabAction::Autocomplete => {
+                        let mut s = state.lock().unwrap();
+                        let candidates = s.completion_candidates();
+                        if !s.input.autocomplete(&candidates) {
+                            debug!("user: Tab, no completions");
+                        }
+                    }
+
                     // Tab toggles between input and normal mode
                     KeyCode::Tab => {
                         let mut s = state.lock().unwrap();
@@ -324,69 +1154,6 @@ async fn run_app(
                         };
                     }
 
-                    // Normal mode — '/' or 'i' also enters input
-                    KeyCode::Char('i') | KeyCode::Char('/') if !in_edit_mode => {
-                        debug!("user: enter editing mode");
-                        state.lock().unwrap().input.mode = InputMode::Editing;
-                    }
-
-                    KeyCode::Char('q') if !in_edit_mode => {
-                        info!("user: q quit");
-                        state.lock().unwrap().should_quit = true;
-                    }
-
-                    KeyCode::Char('p') if !in_edit_mode => {
-                        let mut s = state.lock().unwrap();
-                        s.paused = !s.paused;
-                        if s.paused {
-                            info!("user: pause");
-                            player.pause();
-                        } else {
-                            info!("user: resume");
-                            player.resume();
-                        }
-                    }
-
-                    KeyCode::Char('n') if !in_edit_mode => {
-                        info!("user: skip/next");
-                        player.stop();
-                        state.lock().unwrap().current = None;
-                    }
-
-                    KeyCode::Char('f') if !in_edit_mode => {
-                        let s = state.lock().unwrap();
-                        if s.current.is_some() {
-                            let pos = s.playback_position + Duration::from_secs(10);
-                            drop(s);
-                            info!(?pos, "user: seek forward 10s");
-                            player.seek(pos);
-                        }
-                    }
-
-                    KeyCode::Char('b') if !in_edit_mode => {
-                        let s = state.lock().unwrap();
-                        if s.current.is_some() {
-                            let pos = s.playback_position.saturating_sub(Duration::from_secs(10));
-                            drop(s);
-                            info!(?pos, "user: seek backward 10s");
-                            player.seek(pos);
-                        }
-                    }
-
-                    KeyCode::Char('+') | KeyCode::Char('=') if !in_edit_mode => {
-                        let mut s = state.lock().unwrap();
-                        s.volume = (s.volume + 5).min(100);
-                        debug!(volume = s.volume, "user: volume up");
-                        player.set_volume(s.volume);
-                    }
-
-                    KeyCode::Char('-') if !in_edit_mode => {
-                        let mut s = state.lock().unwrap();
-                        s.volume = s.volume.saturating_sub(5);
-                        debug!(volume = s.volume, "user: volume down");
-                        player.set_volume(s.volume);
-                    }
-
                     KeyCode::Up if !in_edit_mode => {
                         state.lock().unwrap().move_cursor_up();
                     }
@@ -405,67 +1172,15 @@ async fn run_app(
 
                     KeyCode::Char(' ') if !in_edit_mode => {
                         let mut s = state.lock().unwrap();
-                        // Try to play selected song first
-                        let played = match s.focused_panel {
-                            FocusedPanel::Library => {
-                                let idx = s.library_cursor;
-                                if idx < s.library.len() && s.library[idx].status == SongStatus::Ready {
-                                    let song = s.library[idx].clone();
-                                    if let Some(ref path) = song.file_path {
-                                        info!(title = %song.title, "user: play from library");
-                                        let dur = song.duration.map(|d| d.as_secs_f64());
-                                        match player.play_file(path, dur) {
-                                            Ok(()) => {
-                                                s.current = Some(NowPlaying {
-                                                    song,
-                                                    started_at: Instant::now(),
-                                                    paused_elapsed: Duration::ZERO,
-                                                    paused_at: None,
-                                                });
-                                                s.paused = false;
-                                                true
-                                            }
-                                            Err(e) => { error!(?e, "failed to play file"); false }
-                                        }
-                                    } else { false }
-                                } else { false }
-                            }
-                            FocusedPanel::Queue => {
-                                let idx = s.queue_cursor;
-                                if idx < s.queue.len() && s.queue[idx].status == SongStatus::Ready {
-                                    let song = s.queue.remove(idx);
-                                    s.clamp_cursors();
-                                    if let Some(ref path) = song.file_path {
-                                        info!(title = %song.title, "user: play from queue");
-                                        let dur = song.duration.map(|d| d.as_secs_f64());
-                                        match player.play_file(path, dur) {
-                                            Ok(()) => {
-                                                s.current = Some(NowPlaying {
-                                                    song,
-                                                    started_at: Instant::now(),
-                                                    paused_elapsed: Duration::ZERO,
-                                                    paused_at: None,
-                                                });
-                                                s.paused = false;
-                                                true
-                                            }
-                                            Err(e) => { error!(?e, "failed to play file"); false }
-                                        }
-                                    } else { false }
-                                } else { false }
-                            }
-                        };
-                        // Fall back to pause/resume if no song was played
-                        if !played && s.current.is_some() {
-                            s.paused = !s.paused;
-                            if s.paused {
-                                info!("user: space pause");
-                                player.pause();
-                            } else {
-                                info!("user: space resume");
-                                player.resume();
-                            }
-                        }
+                        play_selected(player.as_mut(), &library, &config, &mut s);
+                    }
+
+                    // Any printable key not bound above starts typing a request
+                    KeyCode::Char(c) if !in_edit_mode && types_into_input(&config, &key) => {
+                        debug!(%c, "user: typing -> editing mode");
+                        let mut s = state.lock().unwrap();
+                        s.input.mode = InputMode::Editing;
+                        s.input.insert(c);
                     }
 
                     _ => {}
@@ -479,5 +1194,686 @@ async fn run_app(
         }
     }
 
+    info!("shutting down");
+    player.stop();
+    Ok(())
+}
+
+/// What the smooth-queue key asks the agent.
+const SMOOTH_QUEUE_REQUEST: &str =
+    "Reorder the queue for a smoother flow of tempo and energy, keeping the same songs";
+
+/// Hand `input` to the agent in the background, as if typed.
+fn ask_agent(agent: &Arc<agent::Agent>, state: &Arc<Mutex<AppState>>, input: String) {
+    let agent = agent.clone();
+    let state = state.clone();
+    tokio::spawn(async move {
+        if let Err(e) = agent.handle_input(&input, &state).await {
+            error!(?e, "agent error");
+            let mut s = state.lock().unwrap();
+            s.agent_status = AgentStatus::Idle;
+            s.request = None;
+            s.report_error(format!("Agent error: {}", e));
+            let reason = e.to_string();
+            s.record_failure(input.clone(), reason, RetryAction::AskAgent { input });
+        }
+    });
+}
+
+/// Run a failure's retry. Failing again puts it back in the tray.
+fn retry_failure(
+    failure: Failure,
+    agent: &Arc<agent::Agent>,
+    state: &Arc<Mutex<AppState>>,
+    player: &mut dyn Playback,
+    library: &Arc<Mutex<library::Library>>,
+) {
+    info!(item = %failure.item, reason = %failure.reason, "user: retry failure");
+    match failure.retry {
+        RetryAction::Download { url } => {
+            agent.retry_download(&url, state);
+        }
+        RetryAction::PlayUrl { url } => {
+            let agent = agent.clone();
+            let state = state.clone();
+            tokio::spawn(async move {
+                if let Err(e) = agent.play_url(&url, &state).await {
+                    error!(?e, %url, "retried play failed");
+                    let mut s = state.lock().unwrap();
+                    s.report_error(format!("Download error: {}", e));
+                    let reason = downloader::failure_reason(&e);
+                    s.record_failure(url.clone(), reason, RetryAction::PlayUrl { url });
+                }
+            });
+        }
+        RetryAction::AskAgent { input } => ask_agent(agent, state, input),
+        RetryAction::Play { song } => {
+            let mut s = state.lock().unwrap();
+            if let Err(e) = play_song(player, library, &mut s, song) {
+                error!(?e, "retried play failed");
+                s.report_error(format!("Couldn't play: {:#}", e));
+            }
+        }
+    }
+}
+
+/// Space: play the selected song, or load the selected playlist. With
+/// nothing to play it toggles pause instead.
+fn play_selected(
+    player: &mut dyn Playback,
+    library: &Arc<Mutex<library::Library>>,
+    config: &Config,
+    s: &mut AppState,
+) {
+    // Try to play selected song first. `None` means there was
+    // nothing to play; a failed attempt must not toggle pause.
+    let attempt = match s.focused_panel {
+        FocusedPanel::Library if s.library_view == LibraryView::Playlists => {
+            if let Some((name, _)) = s.playlists.get(s.playlist_cursor).cloned() {
+                let (queued, missing) = load_playlist(library, config, s, &name);
+                info!(%name, queued, missing, "user: load playlist");
+                s.status_message = Some(format!("Queued {} songs from {}", queued, name));
+                if missing > 0 {
+                    s.report_warning(format!(
+                        "{} songs in {} are no longer cached and were skipped",
+                        missing, name
+                    ));
+                }
+            }
+            // Loading a playlist never toggles pause
+            Some(Ok(()))
+        }
+        FocusedPanel::Library => {
+            match s.selected_library_song() {
+                Some(song) if song.status == SongStatus::Ready && song.file_path.is_some() => {
+                    let song = song.clone();
+                    info!(title = %song.title, "user: play from library");
+                    Some(play_song(player, library, s, song))
+                }
+                _ => None,
+            }
+        }
+        FocusedPanel::Queue => {
+            let idx = s.queue_cursor;
+            match s.queue.get(idx) {
+                Some(song) if song.status == SongStatus::Ready && song.file_path.is_some() => {
+                    let song = song.clone();
+                    info!(title = %song.title, "user: play from queue");
+                    let result = play_song(player, library, s, song);
+                    // Leave it queued if it couldn't be played
+                    if result.is_ok() {
+                        s.queue.remove(idx);
+                        s.clamp_cursors();
+                    }
+                    Some(result)
+                }
+                _ => None,
+            }
+        }
+    };
+    match attempt {
+        Some(Ok(())) => {}
+        Some(Err(e)) => {
+            error!(?e, "failed to play file");
+            s.report_error(format!("Couldn't play: {:#}", e));
+        }
+        // Fall back to pause/resume if there was nothing to play
+        None if s.current.is_some() => {
+            let paused = !s.paused;
+            s.set_paused(paused, Instant::now());
+            if s.paused {
+                info!("user: space pause");
+                player.pause();
+            } else {
+                info!("user: space resume");
+                player.resume();
+            }
+        }
+        None => {}
+    }
+}
+
+/// Start `song` (which must have a file) and make it the current track.
+/// On failure whatever was playing keeps playing.
+fn play_song(
+    player: &mut dyn Playback,
+    library: &Arc<Mutex<library::Library>>,
+    s: &mut AppState,
+    song: Song,
+) -> Result<()> {
+    let path = song.file_path.as_ref().context("Song has not been downloaded")?;
+    if let Err(e) = player.play_file(path, song.duration.map(|d| d.as_secs_f64())) {
+        let retry = RetryAction::Play { song: song.clone() };
+        s.record_failure(&song.title, format!("{:#}", e), retry);
+        return Err(e);
+    }
+    // Picked by the user: a pending agent play shouldn't replace it
+    s.begin_play_request();
+    mark_played(library, s, &song.url);
+    s.start_playing(song);
     Ok(())
 }
+
+/// What's left of the last session to resume: the queue, then the track that
+/// was playing and where it was. Songs whose files are gone are skipped.
+struct ResumePlan {
+    queue: Vec<Song>,
+    current: Option<(Song, Duration)>,
+}
+
+fn resume_plan(
+    session: &session::Session,
+    library: &Arc<Mutex<library::Library>>,
+    config: &Config,
+) -> Option<ResumePlan> {
+    let lib = library.lock().unwrap();
+    let restore =
+        |url: &str| lib.find_by_url(url).and_then(|entry| Song::cached(entry, &config.cache_dir));
+    let queue: Vec<Song> = session.queue_urls.iter().filter_map(|url| restore(url)).collect();
+    let current = session
+        .current_url
+        .as_deref()
+        .and_then(restore)
+        .map(|song| (song, Duration::from_secs_f64(session.position_secs.max(0.0))));
+    if queue.is_empty() && current.is_none() {
+        return None;
+    }
+    Some(ResumePlan { queue, current })
+}
+
+/// Queue what's left of the last session and pick the track back up where
+/// it was.
+fn resume_session(
+    player: &mut dyn Playback,
+    library: &Arc<Mutex<library::Library>>,
+    s: &mut AppState,
+    plan: ResumePlan,
+) {
+    info!(queued = plan.queue.len(), "user: resume last session");
+    s.queue.extend(plan.queue);
+    if let Some((song, position)) = plan.current {
+        match play_song(player, library, s, song) {
+            Ok(()) => player.seek(position),
+            Err(e) => {
+                error!(?e, "failed to resume last track");
+                s.report_error(format!("Couldn't play: {:#}", e));
+            }
+        }
+    }
+}
+
+/// Whether `key` in normal mode should open the input bar and type itself:
+/// `type_to_input` is on and it's a plain printable key with no binding.
+fn types_into_input(config: &Config, key: &KeyEvent) -> bool {
+    config.type_to_input
+        && matches!(key.code, KeyCode::Char(_))
+        && !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+        && config.keys.action(key.code).is_none()
+}
+
+/// Back to the start of the current track, if there is one.
+fn restart_track(player: &dyn Playback, s: &mut AppState) {
+    if s.restart_current() {
+        info!("user: restart track");
+        player.seek(Duration::ZERO);
+    }
+}
+
+/// Append a playlist's songs to the queue. Returns how many were queued and
+/// how many were skipped because their entry or cached file is gone.
+fn load_playlist(
+    library: &Arc<Mutex<library::Library>>,
+    config: &Config,
+    s: &mut AppState,
+    name: &str,
+) -> (usize, usize) {
+    let lib = library.lock().unwrap();
+    let ids = lib.playlist(name).unwrap_or_default();
+    let songs: Vec<Song> = ids
+        .iter()
+        .filter_map(|id| lib.find_by_video_id(id))
+        .filter_map(|entry| Song::cached(entry, &config.cache_dir))
+        .collect();
+    let missing = ids.len() - songs.len();
+    let queued = songs.len();
+    s.queue.extend(songs);
+    (queued, missing)
+}
+
+/// Queue every ready library song that isn't queued yet and, if nothing is
+/// playing, start the first of them.
+fn queue_library(
+    player: &mut dyn Playback,
+    library: &Arc<Mutex<library::Library>>,
+    s: &mut AppState,
+    shuffle: bool,
+) {
+    let queued = s.enqueue_library(shuffle);
+    info!(queued, shuffle, "queued library");
+    s.status_message = Some(format!(
+        "Queued {} song{} from the library",
+        queued,
+        if queued == 1 { "" } else { "s" }
+    ));
+    if s.current.is_some() {
+        return;
+    }
+    if let Some(song) = s.next_ready_song() {
+        if let Err(e) = play_song(player, library, s, song) {
+            error!(?e, "failed to play from library");
+            s.report_error(format!("Couldn't play: {:#}", e));
+        }
+    }
+}
+
+/// Go back to the previous track, if there is one.
+fn play_previous(
+    player: &mut dyn Playback,
+    library: &Arc<Mutex<library::Library>>,
+    s: &mut AppState,
+) {
+    let Some(song) = s.previous_song() else {
+        s.status_message = Some("No previous track".to_string());
+        return;
+    };
+    info!(title = %song.title, url = %song.url, "playing previous track");
+    // Taken so starting the previous track doesn't retire it into history
+    let outgoing = s.current.take();
+    match play_song(player, library, s, song) {
+        Ok(()) => s.stepped_back(outgoing),
+        Err(e) => {
+            // It keeps playing, and the history keeps its entry
+            s.current = outgoing;
+            error!(?e, "failed to play previous track");
+            s.report_error(format!("Couldn't play: {:#}", e));
+        }
+    }
+}
+
+/// Bump the play count of the library entry and its panel copy, and the
+/// entry's last-played time, used to pick what to evict. Takes the library
+/// lock under the state lock, the usual order.
+fn mark_played(library: &Arc<Mutex<library::Library>>, s: &mut AppState, url: &str) {
+    s.count_library_play(url);
+    if let Err(e) = library.lock().unwrap().mark_played(url) {
+        warn!(?e, %url, "failed to record play in library");
+    }
+}
+
+/// Write the session and any held-back library changes, so a crash loses at
+/// most one autosave interval.
+fn autosave_now(
+    state: &Arc<Mutex<AppState>>,
+    library: &Arc<Mutex<library::Library>>,
+    config: &Config,
+) {
+    let session = session::Session::from_state(&state.lock().unwrap());
+    if let Err(e) = session.save(&config.session_path) {
+        warn!(?e, "autosave: failed to save session");
+    }
+
+    let saved = library.lock().unwrap().save_if_dirty();
+    if let Err(e) = saved {
+        warn!(?e, "autosave: failed to save library");
+        report_library_failure(state, &e);
+    }
+}
+
+fn report_library_failure(state: &Arc<Mutex<AppState>>, e: &anyhow::Error) {
+    let reason = downloader::storage_hint(e)
+        .map(String::from)
+        .unwrap_or_else(|| e.to_string());
+    state.lock().unwrap().report_warning(format!("Couldn't save library: {}", reason));
+}
+
+/// The opened audio output, or a `NullPlayer` when it couldn't be opened
+/// and `allow_no_audio` lets us carry on without one.
+fn output_or_null(
+    opened: Result<player::Player>,
+    state: &Arc<Mutex<AppState>>,
+    config: &Config,
+) -> Result<Box<dyn Playback>> {
+    match opened {
+        Ok(player) => Ok(Box::new(player)),
+        Err(e) if config.allow_no_audio => {
+            warn!(?e, "audio output unavailable, continuing without playback");
+            let mut s = state.lock().unwrap();
+            s.audio_available = false;
+            s.report_warning(format!("{:#}; playback is disabled", e));
+            Ok(Box::new(player::NullPlayer))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Flushes everything persistent when the main loop is left, before the
+/// terminal is restored. Background tasks are dropped with the runtime
+/// afterwards; their yt-dlp children are spawned with `kill_on_drop` so
+/// nothing lingers.
+struct ShutdownFlush {
+    state: Arc<Mutex<AppState>>,
+    library: Arc<Mutex<library::Library>>,
+    config: Arc<Config>,
+}
+
+impl Drop for ShutdownFlush {
+    fn drop(&mut self) {
+        flush_state(&self.state, &self.library, &self.config);
+    }
+}
+
+/// Save the session and library and delete downloads nobody kept. Locks
+/// poisoned by a panic are used anyway: saving what's there beats losing it.
+fn flush_state(
+    state: &Arc<Mutex<AppState>>,
+    library: &Arc<Mutex<library::Library>>,
+    config: &Config,
+) {
+    // Downloads nobody saved (persist_downloads off) don't outlive the run.
+    // They go first so the session doesn't list them for the next start.
+    let unsaved: Vec<_> = state
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .unsaved_downloads
+        .drain()
+        .collect();
+    let lib = library.lock().unwrap_or_else(|e| e.into_inner());
+    for (url, entry) in unsaved {
+        if lib.entries().iter().any(|e| e.file_path == entry.file_path) {
+            continue;
+        }
+        let paths = std::iter::once(config.cache_dir.join(&entry.file_path))
+            .chain(entry.thumbnail_path(&config.cache_dir));
+        for path in paths {
+            remove_unsaved(&path);
+        }
+        debug!(%url, "deleted unsaved download");
+    }
+    drop(lib);
+
+    let session = session::Session::from_state(&state.lock().unwrap_or_else(|e| e.into_inner()));
+    if let Err(e) = session.save(&config.session_path) {
+        error!(?e, "failed to save session");
+    }
+
+    // Taking the lock waits out any save a download task is in the middle of
+    if let Err(e) = library.lock().unwrap_or_else(|e| e.into_inner()).save() {
+        error!(?e, "failed to save library");
+    }
+    info!("state flushed");
+}
+
+/// Delete the unsaved downloads the last session listed, left behind by a
+/// run that never got to quit cleanly. Files a library entry uses stay, as
+/// does anything that isn't a plain file name in `cache_dir`.
+fn remove_unsaved_leftovers(lib: &library::Library, cache_dir: &Path, files: &[String]) {
+    let used: HashSet<&str> = lib
+        .entries()
+        .iter()
+        .flat_map(|entry| std::iter::once(&entry.file_path).chain(&entry.thumbnail))
+        .map(String::as_str)
+        .collect();
+    let mut removed = 0;
+    for name in files {
+        let plain = Path::new(name).file_name().is_some_and(|n| n == name.as_str());
+        if !plain || used.contains(name.as_str()) {
+            continue;
+        }
+        let path = cache_dir.join(name);
+        if path.is_file() {
+            remove_unsaved(&path);
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        info!(removed, "deleted unsaved downloads left by an earlier run");
+    }
+}
+
+fn remove_unsaved(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != io::ErrorKind::NotFound {
+            warn!(path = %path.display(), ?e, "failed to delete unsaved download");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FileConfig;
+    use crate::test_util::{self, FakePlayer, PlayerCall, TempDir};
+
+    #[test]
+    fn missing_audio_output_falls_back_to_a_null_player() {
+        let dir = TempDir::new("null-player");
+        let file = FileConfig { allow_no_audio: Some(true), ..FileConfig::default() };
+        let config = Arc::new(test_util::config(dir.path(), file));
+        let state = Arc::new(Mutex::new(AppState::new(config.clone())));
+
+        let player = output_or_null(Err(anyhow::anyhow!("no device")), &state, &config).unwrap();
+        assert!(player.is_empty());
+        let s = state.lock().unwrap();
+        assert!(!s.audio_available);
+        assert!(s.error.is_some());
+    }
+
+    #[test]
+    fn missing_audio_output_is_fatal_unless_allowed() {
+        let dir = TempDir::new("no-audio");
+        let file = FileConfig { allow_no_audio: Some(false), ..FileConfig::default() };
+        let config = Arc::new(test_util::config(dir.path(), file));
+        let state = Arc::new(Mutex::new(AppState::new(config.clone())));
+
+        assert!(output_or_null(Err(anyhow::anyhow!("no device")), &state, &config).is_err());
+        assert!(state.lock().unwrap().audio_available);
+    }
+
+    fn new_state(dir: &TempDir) -> AppState {
+        AppState::new(Arc::new(test_util::config(dir.path(), FileConfig::default())))
+    }
+
+    #[test]
+    fn restoring_the_terminal_twice_is_harmless() {
+        let mut first = Vec::new();
+        restore_terminal_on(&mut first).unwrap();
+        let mut second = Vec::new();
+        restore_terminal_on(&mut second).unwrap();
+        assert_eq!(first, second);
+        let written = String::from_utf8(first).unwrap();
+        // Leaves the alternate screen and shows the cursor again
+        assert!(written.contains("\x1b[?1049l"), "{:?}", written);
+        assert!(written.contains("\x1b[?25h"), "{:?}", written);
+    }
+
+    #[test]
+    fn only_unbound_plain_keys_type_into_the_input() {
+        let dir = TempDir::new("type-to-input");
+        let file = FileConfig { type_to_input: Some(true), ..FileConfig::default() };
+        let config = test_util::config(dir.path(), file);
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+        let plain = |c| key(KeyCode::Char(c), KeyModifiers::NONE);
+
+        assert!(types_into_input(&config, &plain('z')));
+        assert!(types_into_input(&config, &key(KeyCode::Char('Z'), KeyModifiers::SHIFT)));
+        // Bound keys keep their action
+        assert!(!types_into_input(&config, &plain('q')));
+        assert!(!types_into_input(&config, &key(KeyCode::Char('z'), KeyModifiers::CONTROL)));
+        assert!(!types_into_input(&config, &key(KeyCode::F(2), KeyModifiers::NONE)));
+
+        let off = test_util::config(dir.path(), FileConfig::default());
+        assert!(!types_into_input(&off, &plain('z')));
+    }
+
+    #[test]
+    fn failed_space_play_leaves_pause_alone() {
+        let dir = TempDir::new("space-play-fails");
+        let config = Arc::new(test_util::config(dir.path(), FileConfig::default()));
+        let lib = library::Library::load(config.library_path.clone(), None, false).unwrap();
+        let library = Arc::new(Mutex::new(lib));
+        let mut s = AppState::new(config.clone());
+        s.start_playing(Song::new_queued("Playing", "Artist", "https://youtu.be/p"));
+        let mut broken = Song::new_queued("Broken", "Artist", "https://youtu.be/b");
+        broken.file_path = Some(dir.path().join("broken.mp3"));
+        broken.status = SongStatus::Ready;
+        s.queue.push(broken);
+        s.focused_panel = FocusedPanel::Queue;
+
+        let mut player = FakePlayer { fail_plays: true, ..FakePlayer::default() };
+        play_selected(&mut player, &library, &config, &mut s);
+        assert_eq!(player.calls(), [PlayerCall::Play(dir.path().join("broken.mp3"))]);
+        assert!(!s.paused);
+        assert!(s.error.is_some());
+        assert_eq!(s.current.as_ref().unwrap().song.title, "Playing");
+        // Left queued to try again
+        assert_eq!(s.queue.len(), 1);
+        assert_eq!(s.failures.len(), 1);
+
+        // With nothing playable selected, Space pauses
+        s.queue.clear();
+        play_selected(&mut player, &library, &config, &mut s);
+        assert!(s.paused);
+        assert_eq!(player.calls().last(), Some(&PlayerCall::Pause));
+    }
+
+    #[test]
+    fn resuming_restores_the_queue_track_and_position() {
+        let dir = TempDir::new("resume-session");
+        let config = Arc::new(test_util::config(dir.path(), FileConfig::default()));
+        let mut lib = library::Library::load(config.library_path.clone(), None, false).unwrap();
+        for id in ["queued", "playing"] {
+            let entry = test_util::library_entry(id);
+            std::fs::write(config.cache_dir.join(&entry.file_path), vec![0; 8192]).unwrap();
+            lib.add(entry).unwrap();
+        }
+        // In the library but its file is gone
+        lib.add(test_util::library_entry("gone")).unwrap();
+        let library = Arc::new(Mutex::new(lib));
+        let url = |id| test_util::library_entry(id).url;
+        let session = session::Session {
+            current_url: Some(url("playing")),
+            position_secs: 42.0,
+            queue_urls: vec![url("gone"), url("queued"), "https://youtu.be/unknown".to_string()],
+            ..session::Session::default()
+        };
+
+        let plan = resume_plan(&session, &library, &config).unwrap();
+        let mut s = AppState::new(config.clone());
+        let mut player = FakePlayer::default();
+        resume_session(&mut player, &library, &mut s, plan);
+
+        let playing = config.cache_dir.join("playing.mp3");
+        let seek = PlayerCall::Seek(Duration::from_secs(42));
+        assert_eq!(player.calls(), [PlayerCall::Play(playing), seek]);
+        assert_eq!(s.current.as_ref().unwrap().song.url, url("playing"));
+        let queued: Vec<&str> = s.queue.iter().map(|song| song.url.as_str()).collect();
+        assert_eq!(queued, [url("queued")]);
+
+        // Nothing left to resume: no offer at all
+        let stale =
+            session::Session { current_url: None, queue_urls: vec![url("gone")], ..session };
+        assert!(resume_plan(&stale, &library, &config).is_none());
+    }
+
+    #[test]
+    fn retrying_a_play_tries_the_file_again() {
+        let dir = TempDir::new("retry-play");
+        let config = Arc::new(test_util::config(dir.path(), FileConfig::default()));
+        let lib = library::Library::load(config.library_path.clone(), None, false).unwrap();
+        let library = Arc::new(Mutex::new(lib));
+        let agent = Arc::new(agent::Agent::new(config.clone(), library.clone()));
+        let state = Arc::new(Mutex::new(AppState::new(config.clone())));
+        let mut song = Song::new_queued("Song", "Artist", "https://youtu.be/s");
+        song.file_path = Some(dir.path().join("s.mp3"));
+        song.status = SongStatus::Ready;
+
+        let mut broken = FakePlayer { fail_plays: true, ..FakePlayer::default() };
+        play_song(&mut broken, &library, &mut state.lock().unwrap(), song).unwrap_err();
+        let failure = state.lock().unwrap().take_failure(0).unwrap();
+        // Failing again puts it back in the tray
+        retry_failure(failure, &agent, &state, &mut broken, &library);
+        let failure = state.lock().unwrap().take_failure(0).unwrap();
+        assert_eq!(failure.item, "Song");
+
+        let mut player = FakePlayer::default();
+        retry_failure(failure, &agent, &state, &mut player, &library);
+        assert_eq!(player.calls(), [PlayerCall::Play(dir.path().join("s.mp3"))]);
+        let s = state.lock().unwrap();
+        assert_eq!(s.current.as_ref().unwrap().song.title, "Song");
+        assert!(s.failures.is_empty());
+    }
+
+    #[test]
+    fn restart_seeks_to_the_start() {
+        let dir = TempDir::new("restart");
+        let mut s = new_state(&dir);
+        let player = FakePlayer::default();
+
+        restart_track(&player, &mut s);
+        assert!(player.calls().is_empty());
+
+        s.start_playing(Song::new_queued("Song", "Artist", "https://youtu.be/a"));
+        s.playback_position = Duration::from_secs(42);
+        restart_track(&player, &mut s);
+        assert_eq!(player.calls(), [PlayerCall::Seek(Duration::ZERO)]);
+        assert_eq!(s.playback_position, Duration::ZERO);
+    }
+
+    #[test]
+    fn shutdown_flush_writes_session_and_library() {
+        let dir = TempDir::new("shutdown-flush");
+        let config = Arc::new(test_util::config(dir.path(), FileConfig::default()));
+        let mut lib = library::Library::load(config.library_path.clone(), None, true).unwrap();
+        lib.add(test_util::library_entry("kept")).unwrap();
+        assert!(!config.library_path.exists());
+        let library = Arc::new(Mutex::new(lib));
+
+        let mut s = AppState::new(config.clone());
+        s.volume = 40;
+        s.queue.push(Song::new_queued("Queued", "Artist", "https://youtu.be/q"));
+        let unsaved = test_util::library_entry("unsaved");
+        let unsaved_path = config.cache_dir.join(&unsaved.file_path);
+        std::fs::write(&unsaved_path, b"audio").unwrap();
+        s.unsaved_downloads.insert(unsaved.url.clone(), unsaved);
+        // An autosave lists it in case the run never quits cleanly
+        assert_eq!(session::Session::from_state(&s).unsaved_files, ["unsaved.mp3"]);
+        let state = Arc::new(Mutex::new(s));
+
+        flush_state(&state, &library, &config);
+        let session = session::Session::load(&config.session_path).unwrap();
+        assert!(session.unsaved_files.is_empty());
+        assert_eq!(session.volume, 40);
+        assert_eq!(session.queue_urls, ["https://youtu.be/q"]);
+        let saved = library::Library::load(config.library_path.clone(), None, false).unwrap();
+        assert!(saved.find_by_video_id("kept").is_some());
+        assert!(!unsaved_path.exists());
+        assert!(state.lock().unwrap().unsaved_downloads.is_empty());
+    }
+
+    #[test]
+    fn only_listed_leftovers_no_entry_uses_are_deleted() {
+        let dir = TempDir::new("unsaved-leftovers");
+        let mut lib = library::Library::load(dir.path().join("library.json"), None, false).unwrap();
+        let mut kept = test_util::library_entry("kept");
+        kept.thumbnail = Some("kept.jpg".to_string());
+        lib.add(kept).unwrap();
+        let cache = dir.path().join("cache");
+        std::fs::create_dir_all(&cache).unwrap();
+        for name in ["kept.mp3", "kept.jpg", "stray.mp3", "stray.jpg", "mine.mp3"] {
+            std::fs::write(cache.join(name), b"x").unwrap();
+        }
+        std::fs::write(dir.path().join("outside.mp3"), b"x").unwrap();
+
+        let listed = ["kept.mp3", "stray.mp3", "stray.jpg", "gone.mp3", "../outside.mp3"]
+            .map(String::from);
+        remove_unsaved_leftovers(&lib, &cache, &listed);
+        let mut left: Vec<_> = std::fs::read_dir(&cache)
+            .unwrap()
+            .map(|item| item.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        // Files the session never listed are the user's, whatever they are
+        assert_eq!(left, ["kept.jpg", "kept.mp3", "mine.mp3"]);
+        assert!(dir.path().join("outside.mp3").exists());
+    }
+}
@@ -1,7 +1,9 @@
 use anyhow::{Context, Result};
-use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::{cpal, Decoder, OutputStream, OutputStreamHandle, Sink, Source};
 use std::fs::File;
 use std::io::BufReader;
+use std::cell::Cell;
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
@@ -9,12 +11,110 @@ use tracing::{info, warn};
 
 use crate::audio_analysis::{self, AudioAnalyzer, AudioFeatures};
 
+/// Context for a file none of the enabled decoders could open, pointing at
+/// the fix for formats we know are a problem.
+fn decode_error(path: &Path) -> String {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    match ext {
+        "opus" => "Failed to decode audio file: opus isn't supported, download as mp3 or m4a".to_string(),
+        "m4a" | "webm" if !cfg!(feature = "symphonia") => format!(
+            "Failed to decode audio file: {} needs vibeplayer built with --features symphonia",
+            ext
+        ),
+        _ => "Failed to decode audio file".to_string(),
+    }
+}
+
 pub struct Player {
     _stream: OutputStream,
     _stream_handle: OutputStreamHandle,
     sink: Arc<Sink>,
     pub duration: Option<Duration>,
     analyzer: Option<AudioAnalyzer>,
+    /// Name of the output device in use, if the host reported one
+    device_name: Option<String>,
+    /// Speed factor, reapplied to every new sink
+    speed: f32,
+    /// Where the track was when the speed last changed or it was seeked
+    anchor: Cell<SpeedAnchor>,
+    /// Volume as a sink level (0–1); crossfades scale it per sink
+    volume: f32,
+    crossfade: Option<Crossfade>,
+}
+
+/// Track position and the sink's played time at the same moment. The sink
+/// counts played time, so only what's played since then is scaled by the
+/// current speed; earlier playback keeps the speed it had.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct SpeedAnchor {
+    track: Duration,
+    played: Duration,
+}
+
+impl SpeedAnchor {
+    fn position(self, played: Duration, speed: f32) -> Duration {
+        self.track + played.saturating_sub(self.played).mul_f32(speed)
+    }
+}
+
+/// The previous track fading out under the one in `Player::sink`.
+struct Crossfade {
+    outgoing: Arc<Sink>,
+    length: Duration,
+}
+
+/// Names of the output devices the audio host currently offers.
+pub fn output_devices() -> Vec<String> {
+    match cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+        Err(e) => {
+            warn!(?e, "failed to enumerate output devices");
+            Vec::new()
+        }
+    }
+}
+
+/// The device after `current` in `devices`, wrapping around. Starts from the
+/// first device when `current` isn't listed; `None` if there's nowhere else
+/// to go.
+pub fn next_device<'a>(current: Option<&str>, devices: &'a [String]) -> Option<&'a str> {
+    let next = match current.and_then(|c| devices.iter().position(|d| d == c)) {
+        Some(i) => &devices[(i + 1) % devices.len()],
+        None => devices.first()?,
+    };
+    (Some(next.as_str()) != current).then_some(next.as_str())
+}
+
+/// What the main loop needs from an audio backend: `Player` when an output
+/// device opened, `NullPlayer` when none did.
+pub trait Playback {
+    fn device_name(&self) -> Option<&str>;
+    /// Reopen output on the named device. Whatever was playing is stopped;
+    /// the caller restarts it.
+    fn switch_device(&mut self, name: &str) -> Result<()>;
+    fn play_file(&mut self, path: &Path, duration_secs: Option<f64>) -> Result<()>;
+    /// Start `path` silent and fade it in over `fade` while the current
+    /// track fades out. `advance_crossfade` drives the fade.
+    fn crossfade_to(
+        &mut self,
+        path: &Path,
+        duration_secs: Option<f64>,
+        fade: Duration,
+    ) -> Result<()>;
+    /// Set both tracks' levels for how far the crossfade has got, and let go
+    /// of the outgoing one once it's done. Called every tick.
+    fn advance_crossfade(&mut self);
+    fn get_audio_features(&mut self) -> AudioFeatures;
+    fn pause(&self);
+    fn resume(&self);
+    fn set_volume(&mut self, volume: u8);
+    /// Play faster or slower (and higher or lower), 1.0 being normal.
+    fn set_speed(&mut self, speed: f32);
+    fn is_empty(&self) -> bool;
+    fn stop(&mut self);
+    /// Position in the track, whatever the speed.
+    fn get_position(&self) -> Duration;
+    fn seek(&self, position: Duration);
 }
 
 impl Player {
@@ -23,7 +123,10 @@ impl Player {
             OutputStream::try_default().context("Failed to open audio output")?;
         let sink = Sink::try_new(&stream_handle).context("Failed to create audio sink")?;
         let sink = Arc::new(sink);
-        info!("audio output initialized");
+        let device_name = cpal::default_host()
+            .default_output_device()
+            .and_then(|d| d.name().ok());
+        info!(?device_name, "audio output initialized");
 
         Ok(Self {
             _stream: stream,
@@ -31,77 +134,288 @@ impl Player {
             sink,
             duration: None,
             analyzer: None,
+            device_name,
+            speed: 1.0,
+            anchor: Cell::default(),
+            volume: 1.0,
+            crossfade: None,
         })
     }
 
-    fn new_sink(&mut self) -> Result<()> {
-        self.stop();
-        let sink =
-            Sink::try_new(&self._stream_handle).context("Failed to create audio sink")?;
-        self.sink = Arc::new(sink);
-        Ok(())
-    }
-
-    pub fn play_file(&mut self, path: &Path, duration_secs: Option<f64>) -> Result<()> {
-        info!(path = %path.display(), "playing file");
-        self.new_sink()?;
-
+    /// Decode `path`, failing before anything stops if it can't be played.
+    fn open(path: &Path) -> Result<Decoder<BufReader<File>>> {
         let file = BufReader::new(File::open(path).context("Failed to open audio file")?);
-        let source = Decoder::new(file).context("Failed to decode audio file")?;
+        // rodio tries each enabled decoder in turn, symphonia's last, and the
+        // result goes through AnalyzingSource whichever one took it
+        Decoder::new(file).with_context(|| decode_error(path))
+    }
 
+    /// Start `source` on the current sink, analyzing what it plays.
+    fn append(&mut self, source: Decoder<BufReader<File>>, duration_secs: Option<f64>) {
         let channels = source.channels();
         let sample_rate = source.sample_rate();
 
-        // Create shared buffer and wrap source with AnalyzingSource
+        // Create shared buffer and wrap source with AnalyzingSource. The sink
+        // applies speed after this, so the analyzer sees the decoded samples
+        // and keeps the file's sample rate: bands follow the recording rather
+        // than moving with the playback pitch
         let buffer = audio_analysis::new_shared_buffer();
         let analyzing_source =
             audio_analysis::AnalyzingSource::new(source.convert_samples::<f32>(), buffer.clone(), channels, sample_rate);
 
         self.analyzer = Some(AudioAnalyzer::new(buffer, sample_rate));
         self.sink.append(analyzing_source);
-        self.duration = duration_secs.map(|s| Duration::from_secs_f64(s));
+        self.anchor.set(SpeedAnchor::default());
+        self.duration = duration_secs.map(Duration::from_secs_f64);
+    }
+
+    /// Set the sinks to `volume`, split between them during a crossfade.
+    fn apply_volume(&self) {
+        match (self.crossfade_progress(), &self.crossfade) {
+            (Some(progress), Some(crossfade)) => {
+                crossfade.outgoing.set_volume(self.volume * (1.0 - progress));
+                self.sink.set_volume(self.volume * progress);
+            }
+            _ => self.sink.set_volume(self.volume),
+        }
+    }
+
+    fn end_crossfade(&mut self) {
+        if let Some(crossfade) = self.crossfade.take() {
+            crossfade.outgoing.stop();
+            self.sink.set_volume(self.volume);
+        }
+    }
+
+    /// Share of the crossfade done, 0–1. Measured in time the incoming track
+    /// has played, so pausing holds the fade where it is.
+    fn crossfade_progress(&self) -> Option<f32> {
+        let crossfade = self.crossfade.as_ref()?;
+        let played = self.sink.get_pos().as_secs_f32();
+        Some((played / crossfade.length.as_secs_f32()).min(1.0))
+    }
+
+    fn new_sink(&mut self) -> Result<()> {
+        self.stop();
+        let sink =
+            Sink::try_new(&self._stream_handle).context("Failed to create audio sink")?;
+        sink.set_speed(self.speed);
+        sink.set_volume(self.volume);
+        self.sink = Arc::new(sink);
+        Ok(())
+    }
+}
+
+impl Playback for Player {
+    fn device_name(&self) -> Option<&str> {
+        self.device_name.as_deref()
+    }
 
+    fn switch_device(&mut self, name: &str) -> Result<()> {
+        let device = cpal::default_host()
+            .output_devices()
+            .context("Failed to enumerate output devices")?
+            .find(|d| d.name().is_ok_and(|n| n == name))
+            .with_context(|| format!("Output device not found: {}", name))?;
+        let (stream, stream_handle) = OutputStream::try_from_device(&device)
+            .with_context(|| format!("Failed to open output device {}", name))?;
+
+        self.stop();
+        self._stream = stream;
+        self._stream_handle = stream_handle;
+        self.new_sink()?;
+        self.analyzer = None;
+        self.device_name = Some(name.to_string());
+        info!(device = %name, "switched audio output");
+        Ok(())
+    }
+
+    fn play_file(&mut self, path: &Path, duration_secs: Option<f64>) -> Result<()> {
+        info!(path = %path.display(), "playing file");
+        // Open and decode first so a bad file leaves the current track playing
+        let source = Self::open(path)?;
+        self.new_sink()?;
+        self.append(source, duration_secs);
+        Ok(())
+    }
+
+    fn crossfade_to(
+        &mut self,
+        path: &Path,
+        duration_secs: Option<f64>,
+        fade: Duration,
+    ) -> Result<()> {
+        info!(path = %path.display(), ?fade, "crossfading to file");
+        let source = Self::open(path)?;
+        // A crossfade still running when the next one starts is cut short
+        self.end_crossfade();
+        let sink =
+            Sink::try_new(&self._stream_handle).context("Failed to create audio sink")?;
+        sink.set_speed(self.speed);
+        sink.set_volume(0.0);
+        let outgoing = std::mem::replace(&mut self.sink, Arc::new(sink));
+        self.crossfade = Some(Crossfade { outgoing, length: fade });
+        self.append(source, duration_secs);
         Ok(())
     }
 
-    pub fn get_audio_features(&mut self) -> AudioFeatures {
+    fn advance_crossfade(&mut self) {
+        let Some(progress) = self.crossfade_progress() else {
+            return;
+        };
+        let outgoing_done = self.crossfade.as_ref().is_some_and(|c| c.outgoing.empty());
+        if progress >= 1.0 || outgoing_done {
+            self.end_crossfade();
+            return;
+        }
+        self.apply_volume();
+    }
+
+    fn get_audio_features(&mut self) -> AudioFeatures {
         match self.analyzer {
             Some(ref mut a) => a.analyze(),
             None => AudioFeatures::default(),
         }
     }
 
-    pub fn pause(&self) {
+    fn pause(&self) {
         self.sink.pause();
+        if let Some(ref crossfade) = self.crossfade {
+            crossfade.outgoing.pause();
+        }
     }
 
-    pub fn resume(&self) {
+    fn resume(&self) {
         self.sink.play();
+        if let Some(ref crossfade) = self.crossfade {
+            crossfade.outgoing.play();
+        }
     }
 
-    pub fn is_paused(&self) -> bool {
-        self.sink.is_paused()
+    fn set_volume(&mut self, volume: u8) {
+        self.volume = volume as f32 / 100.0;
+        self.apply_volume();
     }
 
-    pub fn set_volume(&self, volume: u8) {
-        self.sink.set_volume(volume as f32 / 100.0);
+    fn set_speed(&mut self, speed: f32) {
+        let played = self.sink.get_pos();
+        let track = self.anchor.get().position(played, self.speed);
+        self.anchor.set(SpeedAnchor { track, played });
+        self.speed = speed;
+        self.sink.set_speed(speed);
+        if let Some(crossfade) = &self.crossfade {
+            crossfade.outgoing.set_speed(speed);
+        }
     }
 
-    pub fn is_empty(&self) -> bool {
+    fn is_empty(&self) -> bool {
         self.sink.empty()
     }
 
-    pub fn stop(&mut self) {
+    fn stop(&mut self) {
+        self.end_crossfade();
         self.sink.stop();
     }
 
-    pub fn get_position(&self) -> Duration {
-        self.sink.get_pos()
+    fn get_position(&self) -> Duration {
+        self.anchor.get().position(self.sink.get_pos(), self.speed)
     }
 
-    pub fn seek(&self, position: Duration) {
-        if let Err(e) = self.sink.try_seek(position) {
-            warn!(?e, ?position, "seek failed");
+    // The sink seeks in played time at the current speed, and reports that
+    // as its position afterwards
+    fn seek(&self, position: Duration) {
+        let played = position.div_f32(self.speed);
+        match self.sink.try_seek(played) {
+            Ok(()) => self.anchor.set(SpeedAnchor { track: position, played }),
+            Err(e) => warn!(?e, ?position, "seek failed"),
         }
     }
 }
+
+/// Stand-in when no output device could be opened, so the agent, library
+/// and downloads stay usable. Playing anything fails with a clear error.
+pub struct NullPlayer;
+
+impl Playback for NullPlayer {
+    fn device_name(&self) -> Option<&str> {
+        None
+    }
+
+    fn switch_device(&mut self, _name: &str) -> Result<()> {
+        anyhow::bail!("No audio output available")
+    }
+
+    fn play_file(&mut self, _path: &Path, _duration_secs: Option<f64>) -> Result<()> {
+        anyhow::bail!("No audio output available")
+    }
+
+    fn crossfade_to(
+        &mut self,
+        _path: &Path,
+        _duration_secs: Option<f64>,
+        _fade: Duration,
+    ) -> Result<()> {
+        anyhow::bail!("No audio output available")
+    }
+
+    fn advance_crossfade(&mut self) {}
+
+    fn get_audio_features(&mut self) -> AudioFeatures {
+        AudioFeatures::default()
+    }
+
+    fn pause(&self) {}
+
+    fn resume(&self) {}
+
+    fn set_volume(&mut self, _volume: u8) {}
+
+    fn set_speed(&mut self, _speed: f32) {}
+
+    fn is_empty(&self) -> bool {
+        true
+    }
+
+    fn stop(&mut self) {}
+
+    fn get_position(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn seek(&self, _position: Duration) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn undecodable_files_name_the_fix_for_their_format() {
+        let dir = TempDir::new("decode-error");
+        let m4a = dir.path().join("abc123.m4a");
+        std::fs::write(&m4a, vec![0; 8192]).unwrap();
+        let Err(err) = Player::open(&m4a) else { panic!("decoded a file of zeros") };
+        assert_eq!(err.to_string(), decode_error(&m4a));
+        assert_eq!(
+            err.to_string().contains("--features symphonia"),
+            !cfg!(feature = "symphonia")
+        );
+
+        assert!(decode_error(Path::new("a.opus")).contains("download as mp3"));
+        assert_eq!(decode_error(Path::new("a.mp3")), "Failed to decode audio file");
+    }
+
+    #[test]
+    fn next_device_wraps_and_skips_staying_put() {
+        let devices: Vec<String> = ["a", "b", "c"].iter().map(|d| d.to_string()).collect();
+        assert_eq!(next_device(Some("a"), &devices), Some("b"));
+        assert_eq!(next_device(Some("c"), &devices), Some("a"));
+        assert_eq!(next_device(Some("gone"), &devices), Some("a"));
+        assert_eq!(next_device(None, &devices), Some("a"));
+
+        let only = vec!["a".to_string()];
+        assert_eq!(next_device(Some("a"), &only), None);
+        assert_eq!(next_device(None, &[]), None);
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::app::{AppState, RepeatMode, SidebarSplit, MAX_VISUALIZER_PERCENT};
+
+/// Decides when the periodic autosave is due.
+#[derive(Debug)]
+pub struct AutosaveTimer {
+    interval: Duration,
+    last_save: Instant,
+}
+
+impl AutosaveTimer {
+    pub fn new(interval: Duration, now: Instant) -> Self {
+        Self { interval, last_save: now }
+    }
+
+    pub fn due(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_save) >= self.interval
+    }
+
+    pub fn saved(&mut self, now: Instant) {
+        self.last_save = now;
+    }
+}
+
+/// Listening state that outlives a single run, written by the autosave and
+/// on shutdown.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Session {
+    pub volume: u8,
+    pub current_url: Option<String>,
+    pub position_secs: f64,
+    /// Queued song URLs in order, offered for resuming on the next start
+    #[serde(default)]
+    pub queue_urls: Vec<String>,
+    #[serde(default)]
+    pub sidebar_split: SidebarSplit,
+    #[serde(default = "default_visualizer_percent")]
+    pub visualizer_percent: u16,
+    #[serde(default)]
+    pub repeat: RepeatMode,
+    #[serde(default)]
+    pub shuffle: bool,
+    /// Cache file names of downloads kept out of the library, so a run that
+    /// never got to quit cleanly doesn't leave them behind for good
+    #[serde(default)]
+    pub unsaved_files: Vec<String>,
+}
+
+fn default_visualizer_percent() -> u16 {
+    MAX_VISUALIZER_PERCENT
+}
+
+impl Session {
+    pub fn from_state(state: &AppState) -> Self {
+        Self {
+            volume: state.audible_volume(),
+            current_url: state.current.as_ref().map(|np| np.song.url.clone()),
+            position_secs: state.playback_position.as_secs_f64(),
+            queue_urls: state.queue.iter().map(|song| song.url.clone()).collect(),
+            sidebar_split: state.sidebar_split,
+            visualizer_percent: state.visualizer_percent,
+            repeat: state.repeat,
+            shuffle: state.shuffle,
+            unsaved_files: state
+                .unsaved_downloads
+                .values()
+                .flat_map(|entry| std::iter::once(&entry.file_path).chain(&entry.thumbnail))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Load the last session. A missing or unreadable file just means there's
+    /// nothing to restore.
+    pub fn load(path: &Path) -> Option<Self> {
+        let data = std::fs::read_to_string(path).ok()?;
+        match serde_json::from_str(&data) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                warn!(?e, path = %path.display(), "ignoring unreadable session file");
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .context("Failed to create session directory")?;
+        }
+        let data = serde_json::to_string_pretty(self)
+            .context("Failed to serialize session")?;
+        std::fs::write(path, data)
+            .context("Failed to write session file")?;
+        debug!(path = %path.display(), "session saved");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn autosave_is_due_once_the_interval_has_passed() {
+        let start = Instant::now();
+        let mut timer = AutosaveTimer::new(Duration::from_secs(30), start);
+        assert!(!timer.due(start));
+        assert!(!timer.due(start + Duration::from_secs(29)));
+        assert!(timer.due(start + Duration::from_secs(30)));
+
+        // Saving restarts the interval
+        let saved_at = start + Duration::from_secs(31);
+        timer.saved(saved_at);
+        assert!(!timer.due(saved_at + Duration::from_secs(10)));
+        assert!(timer.due(saved_at + Duration::from_secs(45)));
+        // A clock reading from before the last save isn't due
+        assert!(!timer.due(start));
+    }
+}
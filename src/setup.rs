@@ -0,0 +1,136 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::CrosstermBackend;
+use ratatui::Terminal;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use crate::app::InputState;
+use crate::config::{self, FileConfig};
+use crate::ui;
+
+/// Values collected by the wizard, independent of how they were prompted for.
+#[derive(Debug, Clone)]
+pub struct SetupInputs {
+    pub api_key: String,
+}
+
+/// State rendered by `ui::setup::draw` while the wizard is running.
+pub struct SetupWizard {
+    pub input: InputState,
+    /// `Some(version)` if `yt-dlp --version` succeeded.
+    pub yt_dlp_version: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Write the initial `config.toml` from the collected inputs.
+pub fn write_initial_config(path: &Path, inputs: &SetupInputs) -> Result<()> {
+    let file = FileConfig {
+        api_key: Some(inputs.api_key.trim().to_string()),
+        ..Default::default()
+    };
+    file.save(path)
+}
+
+pub fn yt_dlp_version() -> Option<String> {
+    let output = Command::new("yt-dlp").arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Run the interactive first-run setup in its own terminal session.
+/// Returns `false` if the user cancelled.
+pub fn run() -> Result<bool> {
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let result = prompt(&mut terminal);
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+
+    match result? {
+        Some(inputs) => {
+            write_initial_config(&config::config_path()?, &inputs)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+fn prompt(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+) -> Result<Option<SetupInputs>> {
+    let mut wizard = SetupWizard {
+        input: InputState::default(),
+        yt_dlp_version: yt_dlp_version(),
+        error: None,
+    };
+
+    loop {
+        terminal.draw(|f| ui::setup::draw(f, &wizard))?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(None);
+            }
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Enter => {
+                let api_key = wizard.input.text.trim().to_string();
+                if api_key.is_empty() {
+                    wizard.error = Some("API key cannot be empty".to_string());
+                } else {
+                    return Ok(Some(SetupInputs { api_key }));
+                }
+            }
+            KeyCode::Char(c) => {
+                wizard.error = None;
+                wizard.input.insert(c);
+            }
+            KeyCode::Backspace => wizard.input.backspace(),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn initial_config_holds_the_trimmed_key_for_its_owner_only() {
+        let dir = TempDir::new("setup");
+        let path = dir.path().join("nested").join("config.toml");
+        let inputs = SetupInputs { api_key: "  sk-test  ".to_string() };
+
+        write_initial_config(&path, &inputs).unwrap();
+        let file = FileConfig::load(&path).unwrap();
+        assert_eq!(file.api_key.as_deref(), Some("sk-test"));
+        assert!(file.model.is_none());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
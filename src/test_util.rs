@@ -0,0 +1,244 @@
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::audio_analysis::AudioFeatures;
+use crate::config::{Config, FileConfig};
+use crate::library::LibraryEntry;
+use crate::player::Playback;
+
+/// A fresh directory under the system temp dir, removed on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let dir = format!("vibeplayer-test-{}-{}-{}", name, std::process::id(), n);
+        let path = std::env::temp_dir().join(dir);
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Config as loaded from `file`, keeping everything under `dir`.
+pub fn config(dir: &Path, file: FileConfig) -> Config {
+    Config::from_file(file, Some("test-key".to_string()), dir.to_path_buf()).unwrap()
+}
+
+/// A library entry for `video_id`, cached as `<video_id>.mp3`.
+pub fn library_entry(video_id: &str) -> LibraryEntry {
+    LibraryEntry {
+        video_id: video_id.to_string(),
+        title: format!("Song {}", video_id),
+        artist: "Artist".to_string(),
+        url: format!("https://www.youtube.com/watch?v={}", video_id),
+        duration_secs: 180.0,
+        file_path: format!("{}.mp3", video_id),
+        downloaded_at: "2024-01-01T00:00:00+00:00".to_string(),
+        audio_format: None,
+        last_played: None,
+        play_count: 0,
+        favorite: false,
+        chapters: Vec::new(),
+        album: None,
+        thumbnail: None,
+    }
+}
+
+/// An executable shell script standing in for yt-dlp, written into `dir`.
+pub fn fake_yt_dlp(dir: &Path, script: &str) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join("yt-dlp");
+    std::fs::write(&path, format!("#!/bin/sh\n{}", script)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+/// A request `FakeApi` received: header lines as sent, and the body.
+#[derive(Debug, Clone)]
+pub struct ApiRequest {
+    pub headers: Vec<String>,
+    pub body: serde_json::Value,
+}
+
+impl ApiRequest {
+    /// The value of header `name`, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+}
+
+/// A local HTTP server standing in for a model API. It answers requests
+/// with `responses` in order, as `(status, body)`, and records them.
+pub struct FakeApi {
+    pub base_url: String,
+    requests: Arc<Mutex<Vec<ApiRequest>>>,
+}
+
+impl FakeApi {
+    pub fn start(responses: Vec<(u16, String)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let Ok((stream, _)) = listener.accept() else { return };
+                let mut reader = BufReader::new(stream);
+                let mut headers = Vec::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end().to_string();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((key, value)) = line.split_once(':') {
+                        if key.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                    headers.push(line);
+                }
+                let mut raw = vec![0; length];
+                reader.read_exact(&mut raw).unwrap();
+                let body_json = serde_json::from_slice(&raw).unwrap_or_default();
+                recorded.lock().unwrap().push(ApiRequest { headers, body: body_json });
+
+                let response = format!(
+                    "HTTP/1.1 {} Fake\r\ncontent-type: application/json\r\n\
+                     content-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = reader.into_inner().write_all(response.as_bytes());
+            }
+        });
+        Self { base_url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<ApiRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// What a `FakePlayer` was asked to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerCall {
+    Play(PathBuf),
+    Crossfade(PathBuf),
+    Pause,
+    Resume,
+    Stop,
+    Seek(Duration),
+}
+
+/// A `Playback` that records its calls instead of making sound. Plays fail
+/// when `fail_plays` is set.
+#[derive(Default)]
+pub struct FakePlayer {
+    pub calls: RefCell<Vec<PlayerCall>>,
+    pub fail_plays: bool,
+    pub playing: bool,
+}
+
+impl FakePlayer {
+    pub fn calls(&self) -> Vec<PlayerCall> {
+        self.calls.borrow().clone()
+    }
+
+    fn record(&self, call: PlayerCall) {
+        self.calls.borrow_mut().push(call);
+    }
+
+    fn start(&mut self, call: PlayerCall) -> Result<()> {
+        self.record(call);
+        if self.fail_plays {
+            anyhow::bail!("Failed to decode audio file");
+        }
+        self.playing = true;
+        Ok(())
+    }
+}
+
+impl Playback for FakePlayer {
+    fn device_name(&self) -> Option<&str> {
+        Some("fake")
+    }
+
+    fn switch_device(&mut self, _name: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn play_file(&mut self, path: &Path, _duration_secs: Option<f64>) -> Result<()> {
+        self.start(PlayerCall::Play(path.to_path_buf()))
+    }
+
+    fn crossfade_to(
+        &mut self,
+        path: &Path,
+        _duration_secs: Option<f64>,
+        _fade: Duration,
+    ) -> Result<()> {
+        self.start(PlayerCall::Crossfade(path.to_path_buf()))
+    }
+
+    fn advance_crossfade(&mut self) {}
+
+    fn get_audio_features(&mut self) -> AudioFeatures {
+        AudioFeatures::default()
+    }
+
+    fn pause(&self) {
+        self.record(PlayerCall::Pause);
+    }
+
+    fn resume(&self) {
+        self.record(PlayerCall::Resume);
+    }
+
+    fn set_volume(&mut self, _volume: u8) {}
+
+    fn set_speed(&mut self, _speed: f32) {}
+
+    fn is_empty(&self) -> bool {
+        !self.playing
+    }
+
+    fn stop(&mut self) {
+        self.record(PlayerCall::Stop);
+        self.playing = false;
+    }
+
+    fn get_position(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn seek(&self, position: Duration) {
+        self.record(PlayerCall::Seek(position));
+    }
+}
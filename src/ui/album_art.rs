@@ -0,0 +1,84 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+use image::Rgb;
+use ratatui::style::{Color, Style};
+use ratatui::text::{Line, Span};
+use tracing::debug;
+
+/// Upper half block: foreground paints the top pixel, background the bottom.
+const HALF_BLOCK: &str = "\u{2580}";
+
+/// Cover art rendered as colored half blocks, two pixels per cell. Decoding
+/// and scaling only happen when the image or the space for it changes.
+#[derive(Default)]
+pub struct AlbumArt {
+    /// Thumbnail and cell size the cached lines were rendered for
+    key: Option<(PathBuf, u16, u16)>,
+    /// Empty when the image couldn't be loaded
+    lines: Vec<Line<'static>>,
+}
+
+impl AlbumArt {
+    /// Rows for `path` fitted into `width` x `height` cells, keeping its
+    /// aspect ratio. Empty if the image can't be read.
+    pub fn lines(&mut self, path: &Path, width: u16, height: u16) -> &[Line<'static>] {
+        let fresh = self
+            .key
+            .as_ref()
+            .is_some_and(|(p, w, h)| p == path && *w == width && *h == height);
+        if !fresh {
+            self.lines = render(path, width, height).unwrap_or_else(|e| {
+                debug!(path = %path.display(), ?e, "couldn't render album art");
+                Vec::new()
+            });
+            self.key = Some((path.to_path_buf(), width, height));
+        }
+        &self.lines
+    }
+}
+
+fn render(path: &Path, width: u16, height: u16) -> Result<Vec<Line<'static>>> {
+    let image = image::open(path).context("Failed to decode thumbnail")?.to_rgb8();
+    let (w, h) = fit(image.width(), image.height(), width as u32, height as u32 * 2);
+    if w == 0 || h == 0 {
+        return Ok(Vec::new());
+    }
+    let small = image::imageops::resize(&image, w, h, FilterType::Triangle);
+    let lines = (0..h)
+        .step_by(2)
+        .map(|y| {
+            let spans: Vec<Span> = (0..w)
+                .map(|x| {
+                    let top = rgb(small.get_pixel(x, y));
+                    let style = match (y + 1 < h).then(|| small.get_pixel(x, y + 1)) {
+                        Some(bottom) => Style::default().fg(top).bg(rgb(bottom)),
+                        None => Style::default().fg(top),
+                    };
+                    Span::styled(HALF_BLOCK, style)
+                })
+                .collect();
+            Line::from(spans)
+        })
+        .collect();
+    Ok(lines)
+}
+
+/// Largest size with the image's aspect ratio inside `max_w` x `max_h`.
+fn fit(img_w: u32, img_h: u32, max_w: u32, max_h: u32) -> (u32, u32) {
+    if img_w == 0 || img_h == 0 {
+        return (0, 0);
+    }
+    let by_height = (img_w as u64 * max_h as u64 / img_h as u64) as u32;
+    if by_height <= max_w {
+        (by_height, max_h)
+    } else {
+        (max_w, (img_h as u64 * max_w as u64 / img_w as u64) as u32)
+    }
+}
+
+fn rgb(pixel: &Rgb<u8>) -> Color {
+    let [r, g, b] = pixel.0;
+    Color::Rgb(r, g, b)
+}
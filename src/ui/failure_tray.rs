@@ -0,0 +1,70 @@
+use ratatui::layout::Rect;
+use ratatui::style::Style;
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Clear, Paragraph};
+use ratatui::Frame;
+
+use crate::app::AppState;
+use crate::config::Theme;
+use crate::keymap::Action;
+
+/// Failures listed at once; the tray keeps more than it shows.
+const TRAY_ROWS: usize = 4;
+const TRAY_MAX_WIDTH: u16 = 64;
+
+/// Recent failures, newest first, over the bottom-right corner of `area`.
+/// Hidden when there are none.
+pub fn draw(f: &mut Frame, area: Rect, state: &mut AppState, theme: &Theme) {
+    state.failure_tray_area = None;
+    if state.failures.is_empty() {
+        return;
+    }
+    let rows = state.failures.len().min(TRAY_ROWS) as u16;
+    let width = area.width.saturating_sub(2).min(TRAY_MAX_WIDTH);
+    let height = rows + 2;
+    if width < 20 || area.height < height {
+        return;
+    }
+    let tray = Rect {
+        x: area.x + area.width - width - 1,
+        y: area.y + area.height - height,
+        width,
+        height,
+    };
+
+    let keys = &state.config.keys;
+    let title = format!(
+        " FAILED \u{00b7} [{}] retry \u{00b7} [{}] dismiss ",
+        keys.label(Action::RetryFailure),
+        keys.label(Action::DismissFailures)
+    );
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.error))
+        .title(title)
+        .title_style(Style::default().fg(theme.error));
+    let inner = block.inner(tray);
+
+    let lines: Vec<Line> = state
+        .failures
+        .iter()
+        .rev()
+        .take(TRAY_ROWS)
+        .map(|failure| {
+            let lead = format!("{} ", failure.timestamp);
+            let item = format!("{}: ", super::truncate(&failure.item, 24));
+            let room = (inner.width as usize)
+                .saturating_sub(lead.chars().count() + item.chars().count());
+            Line::from(vec![
+                Span::styled(lead, Style::default().fg(theme.muted)),
+                Span::styled(item, Style::default().fg(theme.text)),
+                Span::styled(super::truncate(&failure.reason, room), Style::default().fg(theme.error)),
+            ])
+        })
+        .collect();
+
+    f.render_widget(Clear, tray);
+    f.render_widget(block, tray);
+    f.render_widget(Paragraph::new(lines), inner);
+    state.failure_tray_area = Some((inner.y, inner.x, inner.x + inner.width, rows));
+}
@@ -1,40 +1,41 @@
 use ratatui::layout::Rect;
-use ratatui::style::{Color, Style};
+use ratatui::style::Style;
 use ratatui::text::{Line, Span};
 use ratatui::widgets::{Block, Borders, Paragraph};
 use ratatui::Frame;
 
 use crate::app::{AgentStatus, AppState, InputMode};
+use crate::config::Theme;
 
-pub fn draw(f: &mut Frame, area: Rect, state: &AppState) {
+pub fn draw(f: &mut Frame, area: Rect, state: &AppState, theme: &Theme) {
     let is_focused = state.input.mode == InputMode::Editing;
 
     let agent_indicator = match &state.agent_status {
         AgentStatus::Idle if is_focused => {
-            Span::styled(" > ", Style::default().fg(Color::Green))
+            Span::styled(" > ", Style::default().fg(theme.elapsed))
         }
-        AgentStatus::Idle => Span::styled(" > ", Style::default().fg(Color::DarkGray)),
+        AgentStatus::Idle => Span::styled(" > ", Style::default().fg(theme.muted)),
         AgentStatus::Thinking => {
-            Span::styled(" * thinking... ", Style::default().fg(Color::Yellow))
+            Span::styled(" * thinking... ", Style::default().fg(theme.title))
         }
         AgentStatus::Acting(action) => {
-            Span::styled(format!(" * {}... ", action), Style::default().fg(Color::Cyan))
+            Span::styled(format!(" * {}... ", action), Style::default().fg(theme.accent))
         }
     };
 
     let input_text = if is_focused {
-        Span::styled(&state.input.text, Style::default().fg(Color::White))
+        Span::styled(&state.input.text, Style::default().fg(theme.text))
     } else if state.input.text.is_empty() {
         Span::styled(
             "press Tab to type, or use shortcuts below",
-            Style::default().fg(Color::DarkGray),
+            Style::default().fg(theme.muted),
         )
     } else {
-        Span::styled(&state.input.text, Style::default().fg(Color::DarkGray))
+        Span::styled(&state.input.text, Style::default().fg(theme.muted))
     };
 
     let cursor = if is_focused {
-        Span::styled("_", Style::default().fg(Color::White))
+        Span::styled("_", Style::default().fg(theme.text))
     } else {
         Span::raw("")
     };
@@ -42,16 +43,35 @@ pub fn draw(f: &mut Frame, area: Rect, state: &AppState) {
     let line = Line::from(vec![agent_indicator, input_text, cursor]);
 
     let border_color = if is_focused {
-        Color::Magenta
+        theme.accent_alt
     } else {
-        Color::DarkGray
+        theme.border_unfocused
     };
 
-    let block = Block::default()
+    let mut block = Block::default()
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(border_color))
+        .border_style(Style::default().fg(super::border_color(state, border_color)))
         .title(" vibeplayer ")
-        .title_style(Style::default().fg(Color::Magenta));
+        .title_style(Style::default().fg(theme.accent_alt));
+
+    if let (Some(request), Some(fraction)) = (&state.request, state.request_fraction()) {
+        let progress = format!(
+            " {} {} {:>3}% ",
+            request.phase.label(),
+            super::queue::progress_bar(fraction),
+            (fraction * 100.0).round() as u32
+        );
+        block = block.title(
+            Line::from(Span::styled(progress, Style::default().fg(theme.accent))).right_aligned(),
+        );
+    }
+
+    if let Some(ref reply) = state.agent_reply {
+        block = block.title_bottom(Line::from(Span::styled(
+            format!(" {} ", reply),
+            Style::default().fg(theme.accent),
+        )));
+    }
 
     let paragraph = Paragraph::new(line).block(block);
     f.render_widget(paragraph, area);
@@ -1,33 +1,61 @@
 use ratatui::layout::Rect;
-use ratatui::style::{Color, Style};
+use ratatui::style::Style;
 use ratatui::text::{Line, Span};
 use ratatui::widgets::{Block, Borders, Paragraph};
 use ratatui::Frame;
 
-use crate::app::AppState;
+use crate::app::{AppState, LibraryView};
+use crate::config::Theme;
 
-pub fn draw(f: &mut Frame, area: Rect, state: &AppState, is_focused: bool) {
-    let border_color = if is_focused { Color::Cyan } else { Color::DarkGray };
+/// Marks favorites after the title.
+const HEART: &str = " \u{2665}";
+
+pub fn draw(f: &mut Frame, area: Rect, state: &AppState, theme: &Theme, is_focused: bool) {
+    let border_color = if is_focused { theme.border_focused } else { theme.border_unfocused };
+    let title = match (state.library_view, &state.library_filter) {
+        (LibraryView::Songs, Some(filter)) => {
+            let cursor = if filter.editing { "_" } else { "" };
+            let sort = state.library_sort.label();
+            format!(" LIBRARY \u{00b7} {} /{}{} ", sort, filter.input.text, cursor)
+        }
+        (LibraryView::Songs, None) => {
+            format!(" LIBRARY \u{00b7} {} ", state.library_sort.label())
+        }
+        (LibraryView::Playlists, _) => " PLAYLISTS ".to_string(),
+    };
     let block = Block::default()
         .borders(Borders::LEFT)
-        .border_style(Style::default().fg(border_color))
-        .title(" LIBRARY ")
-        .title_style(Style::default().fg(if is_focused { Color::Cyan } else { Color::Yellow }));
+        .border_style(Style::default().fg(super::border_color(state, border_color)))
+        .title(title)
+        .title_style(Style::default().fg(if is_focused { theme.accent } else { theme.title }));
 
     let inner = block.inner(area);
     f.render_widget(block, area);
 
+    if state.library_view == LibraryView::Playlists {
+        draw_playlists(f, inner, state, theme, is_focused);
+        return;
+    }
+
     if state.library.is_empty() {
         let line = Line::from(Span::styled(
             "  no songs yet",
-            Style::default().fg(Color::DarkGray),
+            Style::default().fg(theme.muted),
         ));
         f.render_widget(Paragraph::new(line), inner);
         return;
     }
 
+    let listed = state.visible_library();
+    if listed.is_empty() {
+        let line = Line::from(Span::styled("  no matches", Style::default().fg(theme.muted)));
+        f.render_widget(Paragraph::new(line), inner);
+        return;
+    }
+
     let visible_height = inner.height as usize;
-    let cursor = state.library_cursor;
+    // Row of the cursor among the listed songs
+    let cursor = listed.iter().position(|&i| i == state.library_cursor).unwrap_or(0);
 
     // Scroll offset to keep cursor visible
     let scroll_offset = if cursor >= visible_height {
@@ -38,16 +66,12 @@ pub fn draw(f: &mut Frame, area: Rect, state: &AppState, is_focused: bool) {
 
     let mut lines = Vec::new();
 
-    for (i, song) in state
-        .library
-        .iter()
-        .enumerate()
-        .skip(scroll_offset)
-        .take(visible_height)
-    {
-        let is_selected = i == cursor;
+    for (row, &i) in listed.iter().enumerate().skip(scroll_offset).take(visible_height) {
+        let song = &state.library[i];
+        let is_selected = row == cursor;
 
-        let max_title = (inner.width as usize).saturating_sub(4);
+        let heart = if song.favorite { HEART } else { "" };
+        let max_title = (inner.width as usize).saturating_sub(4 + heart.chars().count());
         let title = if max_title > 3 && song.title.len() > max_title {
             format!("{}...", &song.title[..max_title - 3])
         } else {
@@ -57,13 +81,54 @@ pub fn draw(f: &mut Frame, area: Rect, state: &AppState, is_focused: bool) {
         let prefix = if is_selected { "> " } else { "  " };
 
         let style = if is_selected && is_focused {
-            Style::default().fg(Color::Cyan)
+            Style::default().fg(theme.accent)
         } else {
-            Style::default().fg(Color::White)
+            Style::default().fg(theme.text)
         };
 
-        lines.push(Line::from(Span::styled(format!("{}{}", prefix, title), style)));
+        lines.push(Line::from(vec![
+            Span::styled(format!("{}{}", prefix, title), style),
+            Span::styled(heart, Style::default().fg(theme.accent)),
+        ]));
     }
 
     f.render_widget(Paragraph::new(lines), inner);
 }
+
+fn draw_playlists(f: &mut Frame, inner: Rect, state: &AppState, theme: &Theme, is_focused: bool) {
+    if state.playlists.is_empty() {
+        let line = Line::from(Span::styled(
+            "  no playlists yet",
+            Style::default().fg(theme.muted),
+        ));
+        f.render_widget(Paragraph::new(line), inner);
+        return;
+    }
+
+    let visible_height = inner.height as usize;
+    let cursor = state.playlist_cursor;
+    let scroll_offset = (cursor + 1).saturating_sub(visible_height);
+
+    let lines: Vec<Line> = state
+        .playlists
+        .iter()
+        .enumerate()
+        .skip(scroll_offset)
+        .take(visible_height)
+        .map(|(i, (name, len))| {
+            let is_selected = i == cursor;
+            let prefix = if is_selected { "> " } else { "  " };
+            let style = if is_selected && is_focused {
+                Style::default().fg(theme.accent)
+            } else {
+                Style::default().fg(theme.text)
+            };
+            Line::from(vec![
+                Span::styled(format!("{}{}", prefix, name), style),
+                Span::styled(format!("  {}", len), Style::default().fg(theme.muted)),
+            ])
+        })
+        .collect();
+
+    f.render_widget(Paragraph::new(lines), inner);
+}
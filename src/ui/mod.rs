@@ -1,15 +1,122 @@
+pub mod album_art;
+mod failure_tray;
 mod input_bar;
 mod library_panel;
 mod now_playing;
 mod queue;
+pub mod setup;
 pub mod visualizer;
 
 use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::Color;
 use ratatui::Frame;
 
-use crate::app::{AppState, FocusedPanel};
+use crate::app::{AppState, FocusedPanel, SidebarSplit};
+use crate::config::{LayoutConfig, Theme};
+
+/// Left (visualizer + now playing) vs. right (library + queue) columns.
+pub fn column_constraints(layout: &LayoutConfig) -> [Constraint; 2] {
+    [
+        Constraint::Percentage(layout.visualizer_percent),
+        Constraint::Percentage(layout.sidebar_percent),
+    ]
+}
+
+/// Share of the sidebar given to the favored panel in a lopsided split.
+const HEAVY_SPLIT_PERCENT: u16 = 85;
+
+/// Library (top) vs. queue (bottom) within the sidebar.
+pub fn sidebar_constraints(layout: &LayoutConfig, split: SidebarSplit) -> [Constraint; 2] {
+    let (library, queue) = match split {
+        SidebarSplit::Balanced => (layout.library_percent, layout.queue_percent),
+        SidebarSplit::LibraryHeavy => (HEAVY_SPLIT_PERCENT, 100 - HEAVY_SPLIT_PERCENT),
+        SidebarSplit::QueueHeavy => (100 - HEAVY_SPLIT_PERCENT, HEAVY_SPLIT_PERCENT),
+    };
+    [Constraint::Percentage(library), Constraint::Percentage(queue)]
+}
+
+/// Panel border color, nudged one shade brighter on the frame a beat lands
+/// when `beat_flash` is on (never with `reduce_motion`).
+pub fn border_color(state: &AppState, base: Color) -> Color {
+    let flash = state.config.beat_flash
+        && !state.config.reduce_motion
+        && state.audio_features.is_beat
+        && state.current.is_some()
+        && !state.paused;
+    if !flash {
+        return base;
+    }
+    match base {
+        Color::DarkGray => Color::Gray,
+        Color::Cyan => Color::LightCyan,
+        Color::Magenta => Color::LightMagenta,
+        other => other,
+    }
+}
+
+/// `text` cut to `max` characters, ending in "..." when shortened.
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(max.saturating_sub(3)).collect();
+    format!("{}...", kept)
+}
+
+/// Rows the now-playing block needs: border, title, artist, progress bar.
+const NOW_PLAYING_HEIGHT: u16 = 4;
+
+/// Visualizer rows in a left column `total` rows tall: `percent` of it, but
+/// never so many that now playing drops below its minimum.
+pub fn visualizer_rows(total: u16, percent: u16) -> u16 {
+    let wanted = (total as u32 * percent.min(100) as u32 / 100) as u16;
+    wanted.min(total.saturating_sub(NOW_PLAYING_HEIGHT))
+}
+
+/// The configured minimum size, if `area` is smaller than it.
+fn too_small(area: Rect, layout: &LayoutConfig) -> Option<(u16, u16)> {
+    (area.width < layout.min_width || area.height < layout.min_height)
+        .then_some((layout.min_width, layout.min_height))
+}
+
+/// Centered notice drawn instead of the layout on a terminal below
+/// `min`. Redrawn every frame, so it goes away as soon as the user resizes.
+fn draw_too_small(f: &mut Frame, area: Rect, min: (u16, u16), theme: &Theme) {
+    use ratatui::layout::Alignment;
+    use ratatui::style::Style;
+    use ratatui::widgets::{Paragraph, Wrap};
+
+    let message = format!(
+        "Terminal too small (need at least {}x{}, have {}x{})",
+        min.0, min.1, area.width, area.height
+    );
+    // Rows the wrapped message takes, to center it vertically
+    let rows = (message.chars().count() as u16).div_ceil(area.width.max(1));
+    let top = area.height.saturating_sub(rows) / 2;
+    let message_area = Rect {
+        y: area.y + top,
+        height: area.height - top,
+        ..area
+    };
+    f.render_widget(
+        Paragraph::new(message)
+            .style(Style::default().fg(theme.warning))
+            .alignment(Alignment::Center)
+            .wrap(Wrap { trim: true }),
+        message_area,
+    );
+}
 
 pub fn draw(f: &mut Frame, state: &mut AppState) {
+    let config = state.config.clone();
+    let theme = &config.theme;
+    if let Some(min) = too_small(f.area(), &config.layout) {
+        // Nothing clickable is on screen
+        state.progress_bar_area = None;
+        state.failure_tray_area = None;
+        draw_too_small(f, f.area(), min, theme);
+        return;
+    }
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
@@ -20,49 +127,46 @@ pub fn draw(f: &mut Frame, state: &mut AppState) {
         .split(f.area());
 
     // Input bar
-    input_bar::draw(f, chunks[0], state);
+    input_bar::draw(f, chunks[0], state, theme);
 
     // Main content: visualizer + now_playing (left) | library + queue (right)
     let main_chunks = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([
-            Constraint::Percentage(65), // visualizer + now playing
-            Constraint::Percentage(35), // library + queue
-        ])
+        .constraints(column_constraints(&state.config.layout))
         .split(chunks[1]);
 
     // Left side: visualizer on top, now_playing on bottom
     let left_chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
-            Constraint::Min(6),    // visualizer
-            Constraint::Length(4), // now playing + progress
+            Constraint::Length(visualizer_rows(main_chunks[0].height, state.visualizer_percent)),
+            Constraint::Min(NOW_PLAYING_HEIGHT), // now playing + progress
         ])
         .split(main_chunks[0]);
 
-    visualizer::draw(f, left_chunks[0], state);
-    now_playing::draw(f, left_chunks[1], state);
+    visualizer::draw(f, left_chunks[0], state, theme);
+    now_playing::draw(f, left_chunks[1], state, theme);
+    failure_tray::draw(f, left_chunks[0], state, theme);
 
     // Right side: library (top) + queue (bottom)
     let right_chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Percentage(50), // library
-            Constraint::Percentage(50), // queue
-        ])
+        .constraints(sidebar_constraints(&state.config.layout, state.sidebar_split))
         .split(main_chunks[1]);
 
     let lib_focused = state.focused_panel == FocusedPanel::Library;
-    library_panel::draw(f, right_chunks[0], state, lib_focused);
-    queue::draw(f, right_chunks[1], state, !lib_focused);
+    library_panel::draw(f, right_chunks[0], state, theme, lib_focused);
+    queue::draw(f, right_chunks[1], state, theme, !lib_focused);
 
     // Status bar
-    draw_status_bar(f, chunks[2], state);
+    draw_status_bar(f, chunks[2], state, theme);
 }
 
-fn draw_status_bar(f: &mut Frame, area: Rect, state: &AppState) {
-    use crate::app::InputMode;
-    use ratatui::style::{Color, Style};
+fn draw_status_bar(f: &mut Frame, area: Rect, state: &AppState, theme: &Theme) {
+    use crate::app::{countdown_text, InputMode, RepeatMode, Severity, SongStatus};
+    use std::time::Instant;
+    use crate::keymap::Action;
+    use ratatui::style::Style;
     use ratatui::text::{Line, Span};
     use ratatui::widgets::Paragraph;
 
@@ -74,54 +178,285 @@ fn draw_status_bar(f: &mut Frame, area: Rect, state: &AppState) {
         "\u{2591}".repeat(vol_empty)
     );
 
-    let key = |k: &str| Span::styled(format!(" [{}]", k), Style::default().fg(Color::Yellow));
-    let label = |l: &str| Span::styled(format!(" {} ", l), Style::default().fg(Color::DarkGray));
+    let key = |k: &str| Span::styled(format!(" [{}]", k), Style::default().fg(theme.title));
+    let label = |l: &str| Span::styled(format!(" {} ", l), Style::default().fg(theme.muted));
 
     let mut spans = Vec::new();
 
-    match state.input.mode {
-        InputMode::Editing => {
-            spans.push(Span::styled(
-                " INPUT ",
-                Style::default().fg(Color::Black).bg(Color::Magenta),
-            ));
-            spans.push(key("Tab"));
-            spans.push(label("controls"));
-            spans.push(key("Esc"));
-            spans.push(label("controls"));
-            spans.push(key("Enter"));
-            spans.push(label("send"));
-        }
-        InputMode::Normal => {
-            spans.push(Span::styled(
-                " CONTROLS ",
-                Style::default().fg(Color::Black).bg(Color::Cyan),
-            ));
-            spans.push(key("Space"));
-            spans.push(label("play"));
-            spans.push(key("\u{2191}\u{2193}"));
-            spans.push(label("nav"));
-            spans.push(key("\u{2190}\u{2192}"));
-            spans.push(label("panel"));
-            spans.push(key("Tab"));
-            spans.push(label("input"));
-            spans.push(key("n"));
-            spans.push(label("next"));
-            spans.push(key("f/b"));
-            spans.push(label("seek"));
-            spans.push(key("+/-"));
-            spans.push(label("vol"));
-            spans.push(key("q"));
-            spans.push(label("quit"));
+    if let Some(prompt) = state.confirm_prompt() {
+        spans.push(Span::styled(
+            " CONFIRM ",
+            Style::default().fg(theme.badge_text).bg(theme.warning),
+        ));
+        spans.push(Span::styled(format!(" {}", prompt), Style::default().fg(theme.warning)));
+    } else if let Some(ref err) = state.error {
+        let (tag, color) = match err.severity {
+            Severity::Error => (" ERROR ", theme.error),
+            Severity::Warning => (" WARN ", theme.warning),
+        };
+        spans.push(Span::styled(tag, Style::default().fg(theme.badge_text).bg(color)));
+        spans.push(Span::styled(
+            format!(" {} ", err.timestamp),
+            Style::default().fg(theme.muted),
+        ));
+        // yt-dlp errors carry full stderr; the first line is the useful part
+        let first_line = err.message.lines().next().unwrap_or_default();
+        spans.push(Span::styled(first_line.to_string(), Style::default().fg(color)));
+    } else {
+        match state.input.mode {
+            InputMode::Editing => {
+                spans.push(Span::styled(
+                    " INPUT ",
+                    Style::default().fg(theme.badge_text).bg(theme.accent_alt),
+                ));
+                spans.push(key("Tab"));
+                spans.push(label("controls"));
+                spans.push(key("Esc"));
+                spans.push(label("controls"));
+                spans.push(key("Enter"));
+                spans.push(label("send"));
+            }
+            InputMode::Normal => {
+                spans.push(Span::styled(
+                    " CONTROLS ",
+                    Style::default().fg(theme.badge_text).bg(theme.accent),
+                ));
+                spans.push(key("Space"));
+                spans.push(label("play"));
+                spans.push(key("\u{2191}\u{2193}"));
+                spans.push(label("nav"));
+                spans.push(key("\u{2190}\u{2192}"));
+                spans.push(label("panel"));
+                spans.push(key("Tab"));
+                spans.push(label("input"));
+                let keys = &state.config.keys;
+                spans.push(key(&keys.label(Action::Skip)));
+                spans.push(label("next"));
+                spans.push(key(&format!(
+                    "{}/{}",
+                    keys.label(Action::SeekForward),
+                    keys.label(Action::SeekBackward)
+                )));
+                spans.push(label("seek"));
+                spans.push(key(&keys.label(Action::Restart)));
+                spans.push(label("restart"));
+                spans.push(key(&format!(
+                    "{}/{}",
+                    keys.label(Action::VolumeUp),
+                    keys.label(Action::VolumeDown)
+                )));
+                spans.push(label("vol"));
+                let failed_selected = state.focused_panel == FocusedPanel::Queue
+                    && state
+                        .queue
+                        .get(state.queue_cursor)
+                        .is_some_and(|song| matches!(song.status, SongStatus::Failed(_)));
+                if failed_selected {
+                    spans.push(key(&keys.label(Action::CycleRepeat)));
+                    spans.push(label("retry"));
+                }
+                spans.push(key(&keys.label(Action::Quit)));
+                spans.push(label("quit"));
+            }
         }
     }
 
-    spans.push(Span::raw("    vol "));
-    spans.push(Span::styled(vol_bar, Style::default().fg(Color::Cyan)));
-    spans.push(Span::styled(
-        format!(" {}%", state.volume),
-        Style::default().fg(Color::DarkGray),
-    ));
+    if let Some(progress) = state.cache_warm {
+        spans.push(Span::styled(
+            format!("    caching {}/{}", progress.done + progress.failed, progress.total),
+            Style::default().fg(theme.title),
+        ));
+    }
+
+    if let Some(ref backoff) = state.backoff {
+        spans.push(Span::styled(
+            format!("    {}", countdown_text(&backoff.reason, backoff.until, Instant::now())),
+            Style::default().fg(theme.warning),
+        ));
+    }
+
+    if state.visualizer_hidden && state.current.is_some() {
+        spans.push(Span::raw("    "));
+        spans.push(Span::styled(
+            visualizer::mini_meter(&state.audio_features),
+            Style::default().fg(theme.accent_alt),
+        ));
+    }
+
+    if state.shuffle {
+        spans.push(Span::styled("    \u{21c4} shuffle", Style::default().fg(theme.accent)));
+    }
+
+    if let Some(stop) = state.stop_after_current {
+        let fade = if stop.fade { " (fade)" } else { "" };
+        spans.push(Span::styled(
+            format!("    \u{25a0} stop after this{}", fade),
+            Style::default().fg(theme.accent),
+        ));
+    }
+
+    if state.repeat != RepeatMode::Off {
+        spans.push(Span::styled(
+            format!("    \u{21bb} {}", state.repeat.label()),
+            Style::default().fg(theme.accent),
+        ));
+    }
+
+    if state.audio_available && state.muted_from.is_some() {
+        spans.push(Span::raw("    vol "));
+        spans.push(Span::styled("MUTED", Style::default().fg(theme.warning)));
+    } else if state.audio_available {
+        spans.push(Span::raw("    vol "));
+        spans.push(Span::styled(vol_bar, Style::default().fg(theme.accent)));
+        spans.push(Span::styled(
+            format!(" {}%", state.volume),
+            Style::default().fg(theme.muted),
+        ));
+    } else {
+        spans.push(Span::styled("    no audio output", Style::default().fg(theme.error)));
+    }
 
     f.render_widget(Paragraph::new(Line::from(spans)), area);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    use super::*;
+    use crate::app::{Song, MAX_VISUALIZER_PERCENT, MIN_VISUALIZER_PERCENT};
+    use crate::config::{FileConfig, LayoutFileConfig};
+    use crate::test_util::{self, TempDir};
+
+    fn layout(dir: &TempDir, file: LayoutFileConfig) -> (LayoutConfig, Vec<String>) {
+        let file = FileConfig { layout: Some(file), ..FileConfig::default() };
+        let config = test_util::config(dir.path(), file);
+        (config.layout, config.warnings)
+    }
+
+    #[test]
+    fn configured_splits_become_percentages() {
+        let dir = TempDir::new("layout");
+        let (custom, warnings) = layout(
+            &dir,
+            LayoutFileConfig { visualizer: Some(70), library: Some(30), ..Default::default() },
+        );
+        assert!(warnings.is_empty());
+        assert_eq!(
+            column_constraints(&custom),
+            [Constraint::Percentage(70), Constraint::Percentage(30)]
+        );
+        assert_eq!(
+            sidebar_constraints(&custom, SidebarSplit::Balanced),
+            [Constraint::Percentage(30), Constraint::Percentage(70)]
+        );
+    }
+
+    #[test]
+    fn sidebar_split_cycles_through_its_constraints() {
+        let layout = LayoutConfig::default();
+        let mut split = SidebarSplit::Balanced;
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            seen.push(sidebar_constraints(&layout, split));
+            split = split.next();
+        }
+        let percent =
+            |library, queue| [Constraint::Percentage(library), Constraint::Percentage(queue)];
+        assert_eq!(seen, [percent(50, 50), percent(85, 15), percent(15, 85), percent(50, 50)]);
+    }
+
+    #[test]
+    fn invalid_splits_fall_back_to_the_defaults() {
+        let dir = TempDir::new("layout-invalid");
+        let default = LayoutConfig::default();
+        for file in [
+            LayoutFileConfig { visualizer: Some(70), sidebar: Some(40), ..Default::default() },
+            LayoutFileConfig { visualizer: Some(95), ..Default::default() },
+            LayoutFileConfig { sidebar: Some(120), ..Default::default() },
+        ] {
+            let (layout, warnings) = layout(&dir, file);
+            assert_eq!(column_constraints(&layout), column_constraints(&default));
+            assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        }
+    }
+
+    #[test]
+    fn visualizer_leaves_room_for_now_playing() {
+        assert_eq!(visualizer_rows(40, 50), 20);
+        assert_eq!(visualizer_rows(40, 100), 40 - NOW_PLAYING_HEIGHT);
+        assert_eq!(visualizer_rows(40, 250), 40 - NOW_PLAYING_HEIGHT);
+        // Too short for now playing alone: no visualizer at all
+        assert_eq!(visualizer_rows(3, 100), 0);
+    }
+
+    #[test]
+    fn resizing_stops_at_the_bounds() {
+        let dir = TempDir::new("resize-visualizer");
+        let config = test_util::config(dir.path(), FileConfig::default());
+        let mut state = AppState::new(Arc::new(config));
+        assert_eq!(state.visualizer_percent, MAX_VISUALIZER_PERCENT);
+        state.resize_visualizer(true);
+        assert_eq!(state.visualizer_percent, MAX_VISUALIZER_PERCENT);
+        for _ in 0..20 {
+            state.resize_visualizer(false);
+        }
+        assert_eq!(state.visualizer_percent, MIN_VISUALIZER_PERCENT);
+        state.resize_visualizer(true);
+        assert_eq!(state.visualizer_percent, MIN_VISUALIZER_PERCENT + 10);
+    }
+
+    #[test]
+    fn small_terminals_get_a_notice_instead_of_the_layout() {
+        let layout = LayoutConfig::default();
+        let (width, height) = (layout.min_width, layout.min_height);
+        assert_eq!(too_small(Rect::new(0, 0, width, height), &layout), None);
+        assert_eq!(too_small(Rect::new(0, 0, width - 1, 40), &layout), Some((width, height)));
+        assert_eq!(too_small(Rect::new(0, 0, 200, height - 1), &layout), Some((width, height)));
+
+        let dir = TempDir::new("too-small");
+        let config = test_util::config(dir.path(), FileConfig::default());
+        let mut state = AppState::new(Arc::new(config));
+        state.progress_bar_area = Some((0, 10, 5));
+        let mut terminal = Terminal::new(TestBackend::new(width - 1, 20)).unwrap();
+        let frame = terminal.draw(|f| draw(f, &mut state)).unwrap();
+        let buffer = frame.buffer;
+        let text: String = (0..buffer.area.height)
+            .map(|y| (0..buffer.area.width).map(|x| buffer[(x, y)].symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let expected = format!("need at least {}x{}, have {}x20", width, height, width - 1);
+        assert!(text.contains(&expected), "{:?}", text);
+        assert_eq!(state.progress_bar_area, None);
+    }
+
+    /// Playing, with a beat on this frame.
+    fn on_beat(dir: &TempDir, beat_flash: bool, reduce_motion: bool) -> AppState {
+        let file = FileConfig {
+            beat_flash: Some(beat_flash),
+            reduce_motion: Some(reduce_motion),
+            ..FileConfig::default()
+        };
+        let mut state = AppState::new(Arc::new(test_util::config(dir.path(), file)));
+        state.start_playing(Song::new_queued("Title", "Artist", "https://youtu.be/a"));
+        state.audio_features.is_beat = true;
+        state
+    }
+
+    #[test]
+    fn borders_brighten_on_a_beat_only_when_flashing() {
+        let dir = TempDir::new("beat-flash");
+        let mut state = on_beat(&dir, true, false);
+        assert_eq!(border_color(&state, Color::Cyan), Color::LightCyan);
+        assert_eq!(border_color(&state, Color::DarkGray), Color::Gray);
+        assert_eq!(border_color(&state, Color::Green), Color::Green);
+
+        state.audio_features.is_beat = false;
+        assert_eq!(border_color(&state, Color::Cyan), Color::Cyan);
+        assert_eq!(border_color(&on_beat(&dir, false, false), Color::Cyan), Color::Cyan);
+        assert_eq!(border_color(&on_beat(&dir, true, true), Color::Cyan), Color::Cyan);
+    }
+}
@@ -1,60 +1,88 @@
 use ratatui::layout::Rect;
-use ratatui::style::{Color, Modifier, Style};
+use ratatui::style::{Modifier, Style};
 use ratatui::text::{Line, Span};
 use ratatui::widgets::Paragraph;
 use ratatui::Frame;
+use std::ops::Range;
 use std::time::Duration;
 
 use crate::app::AppState;
+use crate::config::Theme;
 
-pub fn draw(f: &mut Frame, area: Rect, state: &mut AppState) {
+/// Title and progress bar.
+const TEXT_ROWS: u16 = 2;
+/// Cover art smaller than this isn't worth drawing.
+const MIN_ART_ROWS: u16 = 3;
+
+pub fn draw(f: &mut Frame, area: Rect, state: &mut AppState, theme: &Theme) {
+    let area = draw_album_art(f, area, state);
     let Some(ref np) = state.current else {
+        state.progress_bar_area = None;
         return;
     };
 
     let mut lines = Vec::new();
 
     // Song title - artist
-    let title_line = if np.song.artist.is_empty() {
-        Line::from(Span::styled(
+    let title_line = match np.song.known_artist(&state.config.unknown_artist) {
+        None => Line::from(Span::styled(
             format!("  {}", np.song.title),
             Style::default()
-                .fg(Color::White)
+                .fg(theme.text)
                 .add_modifier(Modifier::BOLD),
-        ))
-    } else {
-        Line::from(vec![
+        )),
+        Some(artist) => Line::from(vec![
             Span::styled(
                 format!("  {}", np.song.title),
                 Style::default()
-                    .fg(Color::White)
+                    .fg(theme.text)
                     .add_modifier(Modifier::BOLD),
             ),
             Span::styled(
-                format!(" - {}", np.song.artist),
-                Style::default().fg(Color::DarkGray),
+                format!(" - {}", artist),
+                Style::default().fg(theme.muted),
             ),
-        ])
+        ]),
     };
     lines.push(title_line);
 
-    // Progress bar
-    let duration = np.song.duration.unwrap_or(Duration::ZERO);
-    let elapsed = if duration > Duration::ZERO {
-        state.playback_position.min(duration)
+    let play_icon = if state.paused { "||" } else { ">>" };
+    let prefix = format!("  [{}] ", play_icon); // 7 chars
+    // Times stay in track time, so the bar and chapter marks line up at any
+    // speed; the factor is shown next to them
+    let speed = if state.speed != 1.0 {
+        format!(" {}x", state.speed)
     } else {
-        state.playback_position
+        String::new()
     };
-    let progress = if duration.as_secs() > 0 {
-        elapsed.as_secs_f64() / duration.as_secs_f64()
-    } else {
-        0.0
+
+    // Unknown length (metadata missing): elapsed only, nothing to click on
+    let Some(duration) = np.song.known_duration() else {
+        state.progress_bar_area = None;
+        lines.push(Line::from(vec![
+            Span::styled(prefix, Style::default().fg(theme.elapsed)),
+            Span::raw(format_duration(state.playback_position)),
+            Span::styled("  (unknown length)", Style::default().fg(theme.muted)),
+            Span::styled(speed, Style::default().fg(theme.accent)),
+        ]));
+        f.render_widget(Paragraph::new(lines), area);
+        return;
     };
 
-    let play_icon = if state.paused { "||" } else { ">>" };
-    let prefix = format!("  [{}] ", play_icon); // 7 chars
-    let time_str = format!(" {} / {}", format_duration(elapsed), format_duration(duration));
-    let overhead = prefix.len() + 1 + time_str.len(); // +1 for the dot
+    // Progress bar
+    let elapsed = state.playback_position.min(duration);
+    let progress = elapsed.as_secs_f64() / duration.as_secs_f64();
+
+    let time_str = match state.seek_hover {
+        Some(target) => format!(" \u{2192} {}", format_duration_precise(target)),
+        None => format!(" {} / {}", format_duration(elapsed), format_duration(duration)),
+    };
+    // Reserve room for the longer of the two time labels so the bar doesn't
+    // jump when the hover tooltip appears
+    let time_width = time_str.chars().count().max(
+        format!(" {} / {}", format_duration(elapsed), format_duration(duration)).len(),
+    );
+    let overhead = prefix.len() + 1 + time_width + speed.len(); // +1 for the dot
     let bar_width = (area.width as usize).saturating_sub(overhead);
     let filled = (progress * bar_width as f64).min(bar_width as f64) as usize;
     let empty = bar_width.saturating_sub(filled);
@@ -65,18 +93,25 @@ pub fn draw(f: &mut Frame, area: Rect, state: &mut AppState) {
     let bar_col_end = bar_col_start + bar_width as u16;
     state.progress_bar_area = Some((area.y + 1, bar_col_start, bar_col_end));
 
+    // Chapter starts (other than at 0:00) are marked on the bar
+    let markers: Vec<usize> = np
+        .song
+        .chapters
+        .iter()
+        .filter(|c| c.start_secs > 0.0)
+        .map(|c| (c.start_secs / duration.as_secs_f64() * bar_width as f64) as usize)
+        .collect();
+
     let progress_line = Line::from(vec![
-        Span::styled(prefix, Style::default().fg(Color::Green)),
-        Span::styled(
-            "\u{2501}".repeat(filled),
-            Style::default().fg(Color::Magenta),
-        ),
-        Span::styled("\u{25CF}", Style::default().fg(Color::White)),
+        Span::styled(prefix, Style::default().fg(theme.elapsed)),
+        Span::styled(bar_segment(0..filled, &markers), Style::default().fg(theme.progress_fill)),
+        Span::styled("\u{25CF}", Style::default().fg(theme.text)),
         Span::styled(
-            "\u{2501}".repeat(empty),
-            Style::default().fg(Color::DarkGray),
+            bar_segment(filled..filled + empty, &markers),
+            Style::default().fg(theme.muted),
         ),
         Span::raw(time_str),
+        Span::styled(speed, Style::default().fg(theme.accent)),
     ]);
     lines.push(progress_line);
 
@@ -84,9 +119,96 @@ pub fn draw(f: &mut Frame, area: Rect, state: &mut AppState) {
     f.render_widget(paragraph, area);
 }
 
+/// Draw the current track's cover above the text when there's room for it.
+/// Returns the area left for the text.
+fn draw_album_art(f: &mut Frame, area: Rect, state: &mut AppState) -> Rect {
+    let Some(path) = state.current.as_ref().and_then(|np| np.song.thumbnail.clone()) else {
+        return area;
+    };
+    let art_rows = area.height.saturating_sub(TEXT_ROWS);
+    if art_rows < MIN_ART_ROWS {
+        return area;
+    }
+    let art_area = Rect {
+        x: area.x + 2,
+        y: area.y,
+        width: area.width.saturating_sub(4),
+        height: art_rows,
+    };
+    let lines = state.album_art.lines(&path, art_area.width, art_area.height);
+    if lines.is_empty() {
+        return area;
+    }
+    f.render_widget(Paragraph::new(lines.to_vec()), art_area);
+    Rect {
+        y: area.y + art_rows,
+        height: TEXT_ROWS,
+        ..area
+    }
+}
+
+/// Bar cells for `cols`, with a tick wherever a chapter starts.
+fn bar_segment(cols: Range<usize>, markers: &[usize]) -> String {
+    cols.map(|col| if markers.contains(&col) { '\u{2542}' } else { '\u{2501}' })
+        .collect()
+}
+
+/// `m:ss.t` — for seek targets, where sub-second precision matters.
+fn format_duration_precise(d: Duration) -> String {
+    let tenths = d.as_millis() / 100;
+    format!("{}:{:02}.{}", tenths / 600, (tenths / 10) % 60, tenths % 10)
+}
+
 fn format_duration(d: Duration) -> String {
     let secs = d.as_secs();
     let mins = secs / 60;
     let secs = secs % 60;
     format!("{}:{:02}", mins, secs)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    use super::*;
+    use crate::app::Song;
+    use crate::config::FileConfig;
+    use crate::test_util::{self, TempDir};
+
+    /// Draw the panel for a track of `duration`, 65s in; returns its rows.
+    fn draw_at(state: &mut AppState, duration: Duration) -> Vec<String> {
+        let mut song = Song::new_queued("Title", "Artist", "https://youtu.be/a");
+        song.duration = Some(duration);
+        state.start_playing(song);
+        state.playback_position = Duration::from_secs(65);
+        let mut terminal = Terminal::new(TestBackend::new(40, 2)).unwrap();
+        let frame = terminal
+            .draw(|f| draw(f, f.area(), state, &Theme::default()))
+            .unwrap();
+        let buffer = frame.buffer;
+        (0..buffer.area.height)
+            .map(|y| (0..buffer.area.width).map(|x| buffer[(x, y)].symbol()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn zero_duration_shows_elapsed_only_and_cannot_be_clicked() {
+        let dir = TempDir::new("zero-duration");
+        let config = test_util::config(dir.path(), FileConfig::default());
+        let mut state = AppState::new(Arc::new(config));
+
+        let rows = draw_at(&mut state, Duration::ZERO);
+        assert_eq!(rows[1].trim_end(), "  [>>] 1:05  (unknown length)");
+        assert_eq!(state.progress_bar_area, None);
+        // Even a stale bar position can't seek into a track of no length
+        state.progress_bar_area = Some((1, 7, 30));
+        assert_eq!(state.seek_target_at(10, 1, false), None);
+
+        draw_at(&mut state, Duration::from_secs(200));
+        let (row, start, _) = state.progress_bar_area.unwrap();
+        assert!(state.seek_target_at(start, row, false).is_some());
+    }
+}
@@ -5,14 +5,50 @@ use ratatui::widgets::{Block, Borders, Paragraph};
 use ratatui::Frame;
 
 use crate::app::{AppState, SongStatus};
+use crate::config::Theme;
 
-pub fn draw(f: &mut Frame, area: Rect, state: &AppState, is_focused: bool) {
-    let border_color = if is_focused { Color::Cyan } else { Color::DarkGray };
+const SPINNER_FRAMES: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+/// Ticks each spinner frame stays on screen (~10fps at the 16ms main loop).
+const SPINNER_TICKS_PER_FRAME: u64 = 6;
+
+pub fn spinner_frame(tick: u64) -> char {
+    let idx = (tick / SPINNER_TICKS_PER_FRAME) % SPINNER_FRAMES.len() as u64;
+    SPINNER_FRAMES[idx as usize]
+}
+
+/// Cells in the inline download progress bar.
+const PROGRESS_BAR_WIDTH: usize = 8;
+
+pub(super) fn progress_bar(fraction: f32) -> String {
+    let filled = ((fraction.clamp(0.0, 1.0) * PROGRESS_BAR_WIDTH as f32).round() as usize)
+        .min(PROGRESS_BAR_WIDTH);
+    format!(
+        "{}{}",
+        "\u{2588}".repeat(filled),
+        "\u{2591}".repeat(PROGRESS_BAR_WIDTH - filled)
+    )
+}
+
+/// Symbol and color for a status under `theme`. The symbol is there even
+/// when the theme hides it, so callers decide whether to show it.
+pub fn status_look(theme: &Theme, status: &SongStatus) -> (&'static str, Color) {
+    match status {
+        SongStatus::Queued => ("\u{00b7}", theme.queued),
+        SongStatus::Downloading => ("\u{23f3}", theme.downloading),
+        SongStatus::Ready => ("\u{2713}", theme.ready),
+        SongStatus::Playing => ("\u{25b6}", theme.playing),
+        SongStatus::Played => ("\u{2022}", theme.played),
+        SongStatus::Failed(_) => ("\u{2717}", theme.error),
+    }
+}
+
+pub fn draw(f: &mut Frame, area: Rect, state: &AppState, theme: &Theme, is_focused: bool) {
+    let border_color = if is_focused { theme.border_focused } else { theme.border_unfocused };
     let block = Block::default()
         .borders(Borders::LEFT)
-        .border_style(Style::default().fg(border_color))
+        .border_style(Style::default().fg(super::border_color(state, border_color)))
         .title(" UP NEXT ")
-        .title_style(Style::default().fg(if is_focused { Color::Cyan } else { Color::Yellow }));
+        .title_style(Style::default().fg(if is_focused { theme.accent } else { theme.title }));
 
     let inner = block.inner(area);
     f.render_widget(block, area);
@@ -20,7 +56,7 @@ pub fn draw(f: &mut Frame, area: Rect, state: &AppState, is_focused: bool) {
     if state.queue.is_empty() {
         let line = Line::from(Span::styled(
             "  queue is empty",
-            Style::default().fg(Color::DarkGray),
+            Style::default().fg(theme.muted),
         ));
         f.render_widget(Paragraph::new(line), inner);
         return;
@@ -60,11 +96,11 @@ pub fn draw(f: &mut Frame, area: Rect, state: &AppState, is_focused: bool) {
 
         let prefix = if is_selected { "> " } else { "  " };
         let title_style = if is_selected && is_focused {
-            Style::default().fg(Color::Cyan)
+            Style::default().fg(theme.accent)
         } else {
-            Style::default().fg(Color::White)
+            Style::default().fg(theme.text)
         };
-        let num_style = Style::default().fg(Color::DarkGray);
+        let num_style = Style::default().fg(theme.muted);
 
         lines.push(Line::from(vec![
             Span::styled(format!("{}{}. ", prefix, i + 1), num_style),
@@ -72,12 +108,35 @@ pub fn draw(f: &mut Frame, area: Rect, state: &AppState, is_focused: bool) {
         ]));
 
         // Status line
-        let (status_text, status_color) = match song.status {
-            SongStatus::Queued => ("queued", Color::DarkGray),
-            SongStatus::Downloading => ("downloading...", Color::Yellow),
-            SongStatus::Ready => ("ready", Color::Green),
-            SongStatus::Playing => ("playing", Color::Magenta),
-            SongStatus::Played => ("played", Color::DarkGray),
+        let status_text = match song.status {
+            SongStatus::Queued => "queued".to_string(),
+            SongStatus::Downloading => {
+                let lead = if state.config.reduce_motion {
+                    '\u{2026}'
+                } else {
+                    spinner_frame(state.ui_tick)
+                };
+                match song.download_progress {
+                    Some(p) => {
+                        format!("{} downloading {} {:.0}%", lead, progress_bar(p), p * 100.0)
+                    }
+                    None => format!("{} downloading...", lead),
+                }
+            }
+            SongStatus::Ready => "ready".to_string(),
+            SongStatus::Playing => "playing".to_string(),
+            SongStatus::Played => "played".to_string(),
+            SongStatus::Failed(ref reason) => {
+                // "     ✗ failed: " leads the line
+                let room = (inner.width as usize).saturating_sub(15);
+                format!("failed: {}", super::truncate(reason, room))
+            }
+        };
+        let (symbol, status_color) = status_look(theme, &song.status);
+        let status_text = if theme.status_symbols {
+            format!("{} {}", symbol, status_text)
+        } else {
+            status_text
         };
 
         lines.push(Line::from(Span::styled(
@@ -94,3 +153,42 @@ pub fn draw(f: &mut Frame, area: Rect, state: &AppState, is_focused: bool) {
     let paragraph = Paragraph::new(lines);
     f.render_widget(paragraph, inner);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FileConfig, ThemeFileConfig};
+    use crate::test_util::{self, TempDir};
+
+    #[test]
+    fn status_colors_come_from_the_theme() {
+        let dir = TempDir::new("status-look");
+        let theme = ThemeFileConfig {
+            ready: Some("#102030".to_string()),
+            downloading: Some("light_blue".to_string()),
+            queued: Some("not-a-color".to_string()),
+            ..ThemeFileConfig::default()
+        };
+        let file = FileConfig { theme: Some(theme), ..FileConfig::default() };
+        let config = test_util::config(dir.path(), file);
+        let theme = &config.theme;
+
+        assert_eq!(status_look(theme, &SongStatus::Ready), ("\u{2713}", Color::Rgb(16, 32, 48)));
+        assert_eq!(status_look(theme, &SongStatus::Downloading).1, Color::LightBlue);
+        // Unrecognized colors keep the default, with a warning
+        assert_eq!(status_look(theme, &SongStatus::Queued).1, Theme::default().queued);
+        assert_eq!(config.warnings.len(), 1, "{:?}", config.warnings);
+        let failed = SongStatus::Failed("gone".to_string());
+        assert_eq!(status_look(theme, &failed).1, theme.error);
+    }
+
+    #[test]
+    fn spinner_holds_each_frame_then_wraps() {
+        assert_eq!(spinner_frame(0), SPINNER_FRAMES[0]);
+        assert_eq!(spinner_frame(SPINNER_TICKS_PER_FRAME - 1), SPINNER_FRAMES[0]);
+        assert_eq!(spinner_frame(SPINNER_TICKS_PER_FRAME), SPINNER_FRAMES[1]);
+        let cycle = SPINNER_TICKS_PER_FRAME * SPINNER_FRAMES.len() as u64;
+        assert_eq!(spinner_frame(cycle), SPINNER_FRAMES[0]);
+        assert_eq!(spinner_frame(u64::MAX), spinner_frame(u64::MAX % cycle));
+    }
+}
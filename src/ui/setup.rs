@@ -0,0 +1,78 @@
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::Frame;
+
+use crate::setup::SetupWizard;
+
+pub fn draw(f: &mut Frame, wizard: &SetupWizard) {
+    let area = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(0),
+            Constraint::Length(12),
+            Constraint::Min(0),
+        ])
+        .split(f.area())[1];
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Magenta))
+        .title(" vibeplayer setup ")
+        .title_style(Style::default().fg(Color::Magenta));
+
+    let yt_dlp_line = match wizard.yt_dlp_version {
+        Some(ref version) => Line::from(vec![
+            Span::styled("  yt-dlp: ", Style::default().fg(Color::DarkGray)),
+            Span::styled(format!("found ({})", version), Style::default().fg(Color::Green)),
+        ]),
+        None => Line::from(vec![
+            Span::styled("  yt-dlp: ", Style::default().fg(Color::DarkGray)),
+            Span::styled(
+                "not found — install it from https://github.com/yt-dlp/yt-dlp",
+                Style::default().fg(Color::Red),
+            ),
+        ]),
+    };
+
+    // Mask the key so it doesn't sit on screen in plain text
+    let masked: String = "\u{2022}".repeat(wizard.input.text.chars().count());
+
+    let mut lines = vec![
+        Line::from(""),
+        Line::from(Span::styled(
+            "  Welcome! Let's get you set up.",
+            Style::default().fg(Color::White).add_modifier(Modifier::BOLD),
+        )),
+        Line::from(""),
+        yt_dlp_line,
+        Line::from(""),
+        Line::from(Span::styled(
+            "  Anthropic API key:",
+            Style::default().fg(Color::DarkGray),
+        )),
+        Line::from(vec![
+            Span::styled("  > ", Style::default().fg(Color::Green)),
+            Span::styled(masked, Style::default().fg(Color::White)),
+            Span::styled("_", Style::default().fg(Color::White)),
+        ]),
+        Line::from(""),
+    ];
+
+    if let Some(ref err) = wizard.error {
+        lines.push(Line::from(Span::styled(
+            format!("  {}", err),
+            Style::default().fg(Color::Red),
+        )));
+    } else {
+        lines.push(Line::from(vec![
+            Span::styled("  [Enter]", Style::default().fg(Color::Yellow)),
+            Span::styled(" save  ", Style::default().fg(Color::DarkGray)),
+            Span::styled("[Esc]", Style::default().fg(Color::Yellow)),
+            Span::styled(" quit", Style::default().fg(Color::DarkGray)),
+        ]));
+    }
+
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
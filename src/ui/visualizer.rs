@@ -1,32 +1,164 @@
+use ratatui::buffer::Buffer;
 use ratatui::layout::Rect;
-use ratatui::style::{Color, Style};
+use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span};
 use ratatui::widgets::{Block, Borders, Paragraph};
 use ratatui::Frame;
 
-use crate::app::AppState;
+use crate::app::{AppState, WaveStyle};
 use crate::audio_analysis::AudioFeatures;
+use crate::config::Theme;
+use crate::keymap::Action;
 
 const BAR_CHARS: &[char] = &[' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
 
 // ---------------------------------------------------------------------------
-// MatrixRain — now just a tick counter for the wave animation
+// MatrixRain — phase of the wave animation
 // ---------------------------------------------------------------------------
 
 #[derive(Clone, Debug)]
 pub struct MatrixRain {
-    tick: u64,
+    phase: f64,
 }
 
 impl MatrixRain {
     pub fn new(_width: usize, _height: usize) -> Self {
-        Self { tick: 0 }
+        Self { phase: 0.0 }
     }
 
     pub fn resize(&mut self, _width: usize, _height: usize) {}
 
-    pub fn update(&mut self, _features: &AudioFeatures) {
-        self.tick = self.tick.wrapping_add(1);
+    /// Advance the wave; louder passages make it travel faster, silence
+    /// slows it to a crawl.
+    pub fn update(&mut self, features: &AudioFeatures) {
+        let speed = 0.02 + features.rms.clamp(0.0, 1.0) as f64 * 0.12;
+        self.phase += speed;
+    }
+}
+
+// ---------------------------------------------------------------------------
+// mini meter — compact status-bar stand-in when the visualizer is hidden
+// ---------------------------------------------------------------------------
+
+/// One cell per band (bass, mid, treble) plus overall level.
+pub fn mini_meter(features: &AudioFeatures) -> String {
+    [features.bass, features.mid, features.treble, features.rms]
+        .iter()
+        .map(|&level| {
+            let idx = (level.clamp(0.0, 1.0) * (BAR_CHARS.len() - 1) as f32).round() as usize;
+            BAR_CHARS[idx]
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// calm meter — reduce-motion stand-in for the wave
+// ---------------------------------------------------------------------------
+
+/// Horizontal level bars, one per band. Quantized to whole cells so they
+/// don't shimmer.
+fn draw_calm(f: &mut Frame, inner: Rect, feat: &AudioFeatures, theme: &Theme) {
+    let bands = [
+        ("bass ", feat.bass),
+        ("mid  ", feat.mid),
+        ("treb ", feat.treble),
+        ("level", feat.rms),
+    ];
+    let bar_width = (inner.width as usize).saturating_sub(10);
+    let lines: Vec<Line> = bands
+        .iter()
+        .map(|&(label, level)| {
+            let filled = (level.clamp(0.0, 1.0) * bar_width as f32).round() as usize;
+            Line::from(vec![
+                Span::styled(format!("  {} ", label), Style::default().fg(theme.muted)),
+                Span::styled("\u{2588}".repeat(filled), Style::default().fg(theme.elapsed)),
+                Span::styled(
+                    "\u{2591}".repeat(bar_width - filled),
+                    Style::default().fg(theme.muted),
+                ),
+            ])
+        })
+        .collect();
+
+    let height = (lines.len() as u16).min(inner.height);
+    let y = inner.y + inner.height.saturating_sub(height) / 2;
+    f.render_widget(Paragraph::new(lines), Rect::new(inner.x, y, inner.width, height));
+}
+
+// ---------------------------------------------------------------------------
+// wave styles
+// ---------------------------------------------------------------------------
+
+/// How one cell of the wave is drawn.
+#[derive(Debug, PartialEq)]
+struct WaveCell {
+    ch: char,
+    /// Drawn with colors swapped, so the empty part of a lower block becomes
+    /// the filled top of a bar hanging down
+    inverted: bool,
+    /// 0–1
+    brightness: f32,
+}
+
+/// The cell at `row_y` (0 = top) in a column whose wave value is `w`, or
+/// `None` if the wave doesn't reach it. `center` is half the height.
+fn wave_cell(
+    style: WaveStyle,
+    row_y: f64,
+    center: f64,
+    w: f64,
+    thickness: f64,
+) -> Option<WaveCell> {
+    let max_idx = (BAR_CHARS.len() - 1) as f64;
+    match style {
+        WaveStyle::Mirror => {
+            // Wave center is at `center + w`
+            let wave_center = center + w;
+            // Distance from this row to the wave center
+            let dist = (row_y - wave_center).abs();
+            if dist >= thickness + 1.0 {
+                return None;
+            }
+            // Within the wave band — compute sub-cell fill
+            let fill = (thickness - dist + 1.0).clamp(0.0, 1.0);
+            let char_idx = (fill * max_idx) as usize;
+            let ch = if row_y > wave_center {
+                // Below center: normal bars (▁▂▃... growing up)
+                BAR_CHARS[char_idx]
+            } else {
+                // Above center: inverted (█▇▆... growing down)
+                BAR_CHARS[BAR_CHARS.len() - 1 - char_idx]
+            };
+            // Brighter near center, dimmer at edges
+            let brightness = (1.0 - dist / (thickness + 1.0)) as f32;
+            Some(WaveCell { ch, inverted: false, brightness })
+        }
+        WaveStyle::Bottom | WaveStyle::Top => {
+            // Bar length in rows: the swing either way, over the full height
+            let height = center * 2.0;
+            let bar = (w.abs() * 2.0 + thickness).min(height);
+            // Rows from the edge the bar grows from
+            let from_edge = match style {
+                WaveStyle::Bottom => height - 1.0 - row_y,
+                _ => row_y,
+            };
+            if from_edge >= bar {
+                return None;
+            }
+            let fill = (bar - from_edge).clamp(0.0, 1.0);
+            let char_idx = (fill * max_idx) as usize;
+            // There are no upper eighth blocks, so a bar from the top is
+            // drawn as the complementary lower block inverted
+            let inverted = style == WaveStyle::Top;
+            let ch = if inverted {
+                BAR_CHARS[BAR_CHARS.len() - 1 - char_idx]
+            } else {
+                BAR_CHARS[char_idx]
+            };
+            // Brightest at the tip
+            let brightness = (0.4 + 0.6 * (from_edge + 1.0) / bar).min(1.0) as f32;
+            Some(WaveCell { ch, inverted, brightness })
+        }
     }
 }
 
@@ -34,14 +166,27 @@ impl MatrixRain {
 // draw
 // ---------------------------------------------------------------------------
 
-pub fn draw(f: &mut Frame, area: Rect, state: &AppState) {
+pub fn draw(f: &mut Frame, area: Rect, state: &AppState, theme: &Theme) {
     let block = Block::default()
         .borders(Borders::LEFT | Borders::RIGHT)
-        .border_style(Style::default().fg(Color::DarkGray));
+        .border_style(Style::default().fg(super::border_color(state, theme.border_unfocused)));
 
     let inner = block.inner(area);
     f.render_widget(block, area);
 
+    if state.visualizer_hidden && state.current.is_some() {
+        let msg = format!(
+            "visualizer hidden ({} to show)",
+            state.config.keys.label(Action::ToggleVisualizer)
+        );
+        let display_width = (msg.chars().count() as u16).min(inner.width);
+        let x = inner.x + (inner.width.saturating_sub(display_width)) / 2;
+        let line = Line::from(Span::styled(msg, Style::default().fg(theme.muted)));
+        let msg_area = Rect::new(x, inner.y + inner.height / 2, display_width, 1);
+        f.render_widget(Paragraph::new(line), msg_area);
+        return;
+    }
+
     if state.current.is_none() {
         let center_y = inner.height / 2;
         let msg = if let Some(ref status) = state.status_message {
@@ -50,9 +195,9 @@ pub fn draw(f: &mut Frame, area: Rect, state: &AppState) {
             "paste a link or describe a vibe to start"
         };
         let color = if state.status_message.is_some() {
-            Color::Yellow
+            theme.title
         } else {
-            Color::DarkGray
+            theme.muted
         };
         let display_width = (msg.len() as u16).min(inner.width);
         let x = inner.x + (inner.width.saturating_sub(display_width)) / 2;
@@ -62,21 +207,48 @@ pub fn draw(f: &mut Frame, area: Rect, state: &AppState) {
         return;
     }
 
-    let width = inner.width as usize;
-    let height = inner.height as usize;
-    if height == 0 || width == 0 {
+    if inner.height == 0 || inner.width == 0 {
         return;
     }
 
+    if state.config.reduce_motion {
+        draw_calm(f, inner, &state.audio_features, theme);
+        return;
+    }
+    draw_wave(f.buffer_mut(), inner, state, theme);
+}
+
+/// Write the wave straight into the buffer: a Span per cell meant a String
+/// allocation per cell per frame.
+fn draw_wave(buf: &mut Buffer, inner: Rect, state: &AppState, theme: &Theme) {
+    visit_wave(inner, state, theme, |x, y, cell| {
+        let target = &mut buf[(x, y)];
+        match cell {
+            Some((ch, style)) => target.set_char(ch).set_style(style),
+            None => target.set_char(' '),
+        };
+    });
+}
+
+/// Call `visit` with the position of every cell in `inner` and the glyph and
+/// style the wave gives it, or `None` where the wave doesn't reach.
+fn visit_wave(
+    inner: Rect,
+    state: &AppState,
+    theme: &Theme,
+    mut visit: impl FnMut(u16, u16, Option<(char, Style)>),
+) {
+    let width = inner.width as usize;
+    let height = inner.height as usize;
     let feat = &state.audio_features;
-    let t = state.matrix_rain.tick as f64 * 0.08;
+    let t = state.matrix_rain.phase;
 
     // Center line of the wave
     let center = height as f64 / 2.0;
 
     // Compute wave height for each column — multiple sine waves modulated by audio
     let mut wave = vec![0.0f64; width];
-    for col in 0..width {
+    for (col, w) in wave.iter_mut().enumerate() {
         let x = col as f64 / width as f64;
 
         // Base wave: slow sine, amplitude from bass
@@ -86,60 +258,197 @@ pub fn draw(f: &mut Frame, area: Rect, state: &AppState) {
         // High-frequency ripple from treble
         let w3 = (x * 18.0 + t * 2.5).sin() * feat.treble as f64 * center * 0.25;
 
-        wave[col] = w1 + w2 + w3;
+        *w = w1 + w2 + w3;
     }
 
-    // Color based on energy
-    let base_g: u8 = (80.0 + feat.rms * 175.0).min(255.0) as u8;
-    let base_b: u8 = (40.0 + feat.treble * 120.0).min(160.0) as u8;
-    let bright = feat.is_beat;
-
-    let mut lines = Vec::with_capacity(height);
+    // Color fades from the theme's quiet to loud color with energy
+    let level = feat.rms.clamp(0.0, 1.0);
+    let mix = |quiet: u8, loud: u8| quiet as f32 + (loud as f32 - quiet as f32) * level;
+    let (quiet, loud) = (theme.wave_quiet, theme.wave_loud);
+    let base = (mix(quiet.0, loud.0), mix(quiet.1, loud.1), mix(quiet.2, loud.2));
+    let beat = if feat.is_beat { theme.wave_beat } else { (0, 0, 0) };
 
+    // Wave has a thickness proportional to energy
+    let thickness = 0.8 + feat.rms as f64 * 2.0;
+    let style = state.wave_style;
     for row in 0..height {
-        let mut spans = Vec::with_capacity(width);
         let row_y = row as f64; // 0 = top
+        let y = inner.y + row as u16;
 
-        for col in 0..width {
-            // Wave center is at `center + wave[col]`
-            let wave_center = center + wave[col];
+        for (col, &w) in wave.iter().enumerate() {
+            let x = inner.x + col as u16;
+            let Some(wave) = wave_cell(style, row_y, center, w, thickness) else {
+                visit(x, y, None);
+                continue;
+            };
+            let shade =
+                |base: f32, beat: u8| ((base + beat as f32) * wave.brightness).min(255.0) as u8;
+            let r = shade(base.0, beat.0);
+            let g = shade(base.1, beat.1);
+            let b = shade(base.2, beat.2);
 
-            // Distance from this row to the wave center
-            let dist = (row_y - wave_center).abs();
-
-            // Wave has a thickness proportional to energy
-            let thickness = 0.8 + feat.rms as f64 * 2.0;
-
-            if dist < thickness + 1.0 {
-                // Within the wave band — compute sub-cell fill
-                let fill = ((thickness - dist + 1.0) / 1.0).clamp(0.0, 1.0);
-                let char_idx = (fill * (BAR_CHARS.len() - 1) as f64) as usize;
-                let ch = if row_y > wave_center {
-                    // Below center: normal bars (▁▂▃... growing up)
-                    BAR_CHARS[char_idx]
-                } else {
-                    // Above center: inverted (█▇▆... growing down)
-                    BAR_CHARS[BAR_CHARS.len() - 1 - char_idx]
-                };
-
-                // Color: brighter near center, dimmer at edges
-                let edge_fade = (1.0 - dist / (thickness + 1.0)) as f32;
-                let g = (base_g as f32 * edge_fade).min(255.0) as u8;
-                let b = (base_b as f32 * edge_fade * 0.6).min(255.0) as u8;
-                let r = if bright { (60.0 * edge_fade) as u8 } else { 0 };
-
-                spans.push(Span::styled(
-                    ch.to_string(),
-                    Style::default().fg(Color::Rgb(r, g, b)),
-                ));
-            } else {
-                spans.push(Span::raw(" "));
+            let mut cell_style = Style::default().fg(Color::Rgb(r, g, b));
+            if wave.inverted {
+                cell_style = cell_style.add_modifier(Modifier::REVERSED);
             }
+            visit(x, y, Some((wave.ch, cell_style)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use ratatui::backend::TestBackend;
+    use ratatui::widgets::Widget;
+    use ratatui::Terminal;
+
+    use super::*;
+    use crate::app::Song;
+    use crate::config::FileConfig;
+    use crate::keymap::KeySpec;
+    use crate::test_util::{self, TempDir};
+
+    /// A loud frame partway through the animation.
+    fn playing_state(dir: &TempDir, style: WaveStyle) -> AppState {
+        let config = test_util::config(dir.path(), FileConfig::default());
+        let mut state = AppState::new(Arc::new(config));
+        state.audio_features =
+            AudioFeatures { rms: 0.6, bass: 0.8, mid: 0.5, treble: 0.4, is_beat: true };
+        state.matrix_rain.phase = 1.3;
+        state.wave_style = style;
+        state
+    }
+
+    /// The wave as it was drawn before `draw_wave`: a Span per cell.
+    fn draw_wave_spans(buf: &mut Buffer, inner: Rect, state: &AppState, theme: &Theme) {
+        let mut lines = vec![Vec::new(); inner.height as usize];
+        visit_wave(inner, state, theme, |_, y, cell| {
+            lines[(y - inner.y) as usize].push(match cell {
+                Some((ch, style)) => Span::styled(ch.to_string(), style),
+                None => Span::raw(" "),
+            });
+        });
+        let lines: Vec<Line> = lines.into_iter().map(Line::from).collect();
+        Paragraph::new(lines).render(inner, buf);
+    }
+
+    #[test]
+    fn buffer_drawing_matches_span_drawing() {
+        let dir = TempDir::new("wave-buffer");
+        let area = Rect::new(0, 0, 40, 12);
+        let inner = Rect::new(1, 1, 38, 10);
+        for style in [WaveStyle::Mirror, WaveStyle::Bottom, WaveStyle::Top] {
+            let state = playing_state(&dir, style);
+            let mut direct = Buffer::empty(area);
+            draw_wave(&mut direct, inner, &state, &Theme::default());
+            let mut spans = Buffer::empty(area);
+            draw_wave_spans(&mut spans, inner, &state, &Theme::default());
+            assert_eq!(direct, spans, "{:?}", style);
         }
+    }
+
+    /// Frame cost of both ways of drawing on a large terminal. Run with
+    /// `cargo test --release bench_wave -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_wave_drawing() {
+        const FRAMES: u32 = 500;
+        let dir = TempDir::new("wave-bench");
+        let state = playing_state(&dir, WaveStyle::Mirror);
+        let theme = Theme::default();
+        let area = Rect::new(0, 0, 240, 70);
+        let mut buf = Buffer::empty(area);
+        let mut time = |draw: fn(&mut Buffer, Rect, &AppState, &Theme)| {
+            let start = Instant::now();
+            for _ in 0..FRAMES {
+                draw(&mut buf, area, &state, &theme);
+            }
+            start.elapsed() / FRAMES
+        };
+        let spans = time(draw_wave_spans);
+        let direct = time(draw_wave);
+        println!("{}x{} wave: spans {:?}/frame, buffer {:?}/frame", 240, 70, spans, direct);
+    }
 
-        lines.push(Line::from(spans));
+    /// Rows of a 10-row column the wave reaches.
+    fn filled_rows(style: WaveStyle, w: f64, thickness: f64) -> Vec<usize> {
+        (0..10).filter(|&row| wave_cell(style, row as f64, 5.0, w, thickness).is_some()).collect()
     }
 
-    let paragraph = Paragraph::new(lines);
-    f.render_widget(paragraph, inner);
+    #[test]
+    fn mini_meter_has_a_cell_per_band_and_level() {
+        let features =
+            AudioFeatures { rms: 0.5, bass: 1.0, mid: 0.0, treble: 0.25, is_beat: false };
+        assert_eq!(mini_meter(&features), "█ ▂▄");
+        // Out-of-range readings are clamped
+        let loud = AudioFeatures { rms: 2.0, bass: -1.0, ..features };
+        assert_eq!(mini_meter(&loud), "  ▂█");
+    }
+
+    #[test]
+    fn each_style_fills_its_own_rows() {
+        // Centered on row 6, a row either side
+        assert_eq!(filled_rows(WaveStyle::Mirror, 1.0, 1.0), [5, 6, 7]);
+        // A bar of 2 * 1 + 1 rows from the bottom, then from the top
+        assert_eq!(filled_rows(WaveStyle::Bottom, 1.0, 1.0), [7, 8, 9]);
+        assert_eq!(filled_rows(WaveStyle::Top, 1.0, 1.0), [0, 1, 2]);
+        // Swings either way make the same bar
+        assert_eq!(filled_rows(WaveStyle::Top, -1.0, 1.0), [0, 1, 2]);
+    }
+
+    #[test]
+    fn bar_tips_fill_from_the_bar_side() {
+        // A bar of 2.5 rows ends half way through its third row
+        let bottom = wave_cell(WaveStyle::Bottom, 7.0, 5.0, 0.75, 1.0).unwrap();
+        assert_eq!((bottom.ch, bottom.inverted), ('▄', false));
+        // Inverted, the lower half is background and the upper half the bar
+        let top = wave_cell(WaveStyle::Top, 2.0, 5.0, 0.75, 1.0).unwrap();
+        assert_eq!((top.ch, top.inverted), ('▄', true));
+        let body = wave_cell(WaveStyle::Top, 0.0, 5.0, 0.75, 1.0).unwrap();
+        assert_eq!(body.ch, ' ');
+    }
+
+    /// The whole panel at animation `phase`, with `reduce_motion` as given.
+    fn render(dir: &TempDir, reduce_motion: bool, phase: f64) -> Buffer {
+        let file = FileConfig { reduce_motion: Some(reduce_motion), ..FileConfig::default() };
+        let mut state = AppState::new(Arc::new(test_util::config(dir.path(), file)));
+        state.audio_features =
+            AudioFeatures { rms: 0.6, bass: 0.8, mid: 0.5, treble: 0.4, is_beat: false };
+        state.matrix_rain.phase = phase;
+        state.start_playing(Song::new_queued("Title", "Artist", "https://youtu.be/a"));
+        let mut terminal = Terminal::new(TestBackend::new(30, 8)).unwrap();
+        let frame = terminal.draw(|f| draw(f, f.area(), &state, &Theme::default())).unwrap();
+        frame.buffer.clone()
+    }
+
+    #[test]
+    fn reduce_motion_draws_a_still_meter() {
+        let dir = TempDir::new("reduce-motion");
+        let calm = render(&dir, true, 0.0);
+        assert_eq!(calm, render(&dir, true, 2.0));
+        let rows: Vec<String> = (0..calm.area.height)
+            .map(|y| (0..calm.area.width).map(|x| calm[(x, y)].symbol()).collect())
+            .collect();
+        assert!(rows.iter().any(|row| row.contains("bass")), "{:?}", rows);
+
+        assert_ne!(render(&dir, false, 0.0), render(&dir, false, 2.0));
+    }
+
+    #[test]
+    fn hidden_hint_names_the_bound_key() {
+        let dir = TempDir::new("hidden-hint");
+        let rebound = HashMap::from([("toggle_visualizer".to_string(), KeySpec::One("V".into()))]);
+        let file = FileConfig { keys: Some(rebound), ..FileConfig::default() };
+        let mut state = AppState::new(Arc::new(test_util::config(dir.path(), file)));
+        state.start_playing(Song::new_queued("Title", "Artist", "https://youtu.be/a"));
+        state.visualizer_hidden = true;
+        let mut terminal = Terminal::new(TestBackend::new(40, 5)).unwrap();
+        let frame = terminal.draw(|f| draw(f, f.area(), &state, &Theme::default())).unwrap();
+        let row: String = (0..40).map(|x| frame.buffer[(x, 2)].symbol()).collect();
+        assert!(row.contains("visualizer hidden (V to show)"), "{:?}", row);
+    }
 }